@@ -5,6 +5,7 @@ use super::attribute;
 use super::error;
 use super::interface;
 use super::object;
+use super::pkcs8;
 use super::{attr_element, err_rv};
 
 use attribute::{from_bool, from_bytes};
@@ -28,11 +29,31 @@ fn check_key_len(len: usize) -> KResult<()> {
     }
 }
 
-fn check_key_object(key: &Object, op: CK_ULONG) -> KResult<()> {
+/* XTS keys are two AES keys of the same size, which must differ as
+ * required by FIPS 140-3 IG C.I */
+fn check_xts_key(value: &[u8]) -> KResult<()> {
+    match value.len() {
+        32 | 64 => (),
+        _ => return err_rv!(CKR_KEY_SIZE_RANGE),
+    }
+    let (key1, key2) = value.split_at(value.len() / 2);
+    if key1 == key2 {
+        return err_rv!(CKR_ATTRIBUTE_VALUE_INVALID);
+    }
+    Ok(())
+}
+
+fn check_key_object(
+    key: &Object,
+    mech: CK_MECHANISM_TYPE,
+    op: CK_ULONG,
+) -> KResult<()> {
     match key.get_attr_as_ulong(CKA_CLASS)? {
-        CKO_SECRET_KEY => match key.get_attr_as_ulong(CKA_KEY_TYPE)? {
-            CKK_AES => (),
-            CKK_GENERIC_SECRET => (),
+        CKO_SECRET_KEY => match (mech, key.get_attr_as_ulong(CKA_KEY_TYPE)?) {
+            (CKM_AES_XTS, CKK_AES_XTS) => (),
+            (CKM_AES_XTS, _) => return err_rv!(CKR_KEY_TYPE_INCONSISTENT),
+            (_, CKK_AES) => (),
+            (_, CKK_GENERIC_SECRET) => (),
             _ => return err_rv!(CKR_KEY_TYPE_INCONSISTENT),
         },
         _ => return err_rv!(CKR_KEY_TYPE_INCONSISTENT),
@@ -51,12 +72,14 @@ fn check_key_object(key: &Object, op: CK_ULONG) -> KResult<()> {
 #[derive(Debug)]
 pub struct AesKeyTemplate {
     attributes: Vec<ObjectAttr>,
+    xts: bool,
 }
 
 impl AesKeyTemplate {
-    fn new() -> AesKeyTemplate {
+    fn new(xts: bool) -> AesKeyTemplate {
         let mut data: AesKeyTemplate = AesKeyTemplate {
             attributes: Vec::new(),
+            xts: xts,
         };
         data.attributes.append(&mut data.init_common_object_attrs());
         data.attributes
@@ -87,11 +110,28 @@ impl ObjectTemplate for AesKeyTemplate {
         let obj = self.default_object_create(template, false)?;
 
         let val = obj.get_attr_as_bytes(CKA_VALUE)?;
-        check_key_len(val.len())?;
+        if self.xts {
+            check_xts_key(val)?;
+        } else {
+            check_key_len(val.len())?;
+        }
 
         Ok(obj)
     }
 
+    fn unwrap(
+        &self,
+        template: &[CK_ATTRIBUTE],
+        data: &[u8],
+    ) -> KResult<Object> {
+        if self.xts {
+            check_xts_key(data)?;
+        } else {
+            check_key_len(data.len())?;
+        }
+        self.default_secret_key_unwrap(template, data)
+    }
+
     fn get_attributes(&self) -> &Vec<ObjectAttr> {
         &self.attributes
     }
@@ -110,7 +150,10 @@ impl SecretKeyTemplate for AesKeyTemplate {
 }
 
 static AES_KEY_TEMPLATE: Lazy<Box<dyn ObjectTemplate>> =
-    Lazy::new(|| Box::new(AesKeyTemplate::new()));
+    Lazy::new(|| Box::new(AesKeyTemplate::new(false)));
+
+static AES_XTS_KEY_TEMPLATE: Lazy<Box<dyn ObjectTemplate>> =
+    Lazy::new(|| Box::new(AesKeyTemplate::new(true)));
 
 #[derive(Debug)]
 struct AesMechanism {
@@ -130,10 +173,19 @@ impl Mechanism for AesMechanism {
         if self.info.flags & CKF_ENCRYPT != CKF_ENCRYPT {
             return err_rv!(CKR_MECHANISM_INVALID);
         }
-        match check_key_object(key, CKA_ENCRYPT) {
+        match check_key_object(key, mech.mechanism, CKA_ENCRYPT) {
             Ok(_) => (),
             Err(e) => return Err(e),
         }
+        match mech.mechanism {
+            CKM_AES_XTS => {
+                return Ok(Box::new(AesXtsOperation::encrypt_new(mech, key)?))
+            }
+            CKM_AES_CTS | KRYMECH_AES_CTS_CS1 | KRYMECH_AES_CTS_CS2 => {
+                return Ok(Box::new(AesCtsOperation::encrypt_new(mech, key)?))
+            }
+            _ => (),
+        }
         Ok(Box::new(AesOperation::encrypt_new(mech, key)?))
     }
 
@@ -145,44 +197,126 @@ impl Mechanism for AesMechanism {
         if self.info.flags & CKF_DECRYPT != CKF_DECRYPT {
             return err_rv!(CKR_MECHANISM_INVALID);
         }
-        match check_key_object(key, CKA_DECRYPT) {
+        match check_key_object(key, mech.mechanism, CKA_DECRYPT) {
             Ok(_) => (),
             Err(e) => return Err(e),
         }
+        match mech.mechanism {
+            CKM_AES_XTS => {
+                return Ok(Box::new(AesXtsOperation::decrypt_new(mech, key)?))
+            }
+            CKM_AES_CTS | KRYMECH_AES_CTS_CS1 | KRYMECH_AES_CTS_CS2 => {
+                return Ok(Box::new(AesCtsOperation::decrypt_new(mech, key)?))
+            }
+            _ => (),
+        }
         Ok(Box::new(AesOperation::decrypt_new(mech, key)?))
     }
 
+    fn msg_encryption_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> KResult<Box<dyn MsgEncryption>> {
+        if self.info.flags & CKF_MESSAGE_ENCRYPT != CKF_MESSAGE_ENCRYPT {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        match check_key_object(key, mech.mechanism, CKA_ENCRYPT) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        Ok(Box::new(AesMsgOperation::encrypt_new(mech, key)?))
+    }
+
+    fn msg_decryption_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> KResult<Box<dyn MsgDecryption>> {
+        if self.info.flags & CKF_MESSAGE_DECRYPT != CKF_MESSAGE_DECRYPT {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        match check_key_object(key, mech.mechanism, CKA_DECRYPT) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        Ok(Box::new(AesMsgOperation::decrypt_new(mech, key)?))
+    }
+
+    fn sign_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> KResult<Box<dyn Sign>> {
+        if self.info.flags & CKF_SIGN != CKF_SIGN {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        match check_key_object(key, mech.mechanism, CKA_SIGN) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        Ok(Box::new(AesMacOperation::init(mech, key)?))
+    }
+
+    fn verify_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> KResult<Box<dyn Verify>> {
+        if self.info.flags & CKF_VERIFY != CKF_VERIFY {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        match check_key_object(key, mech.mechanism, CKA_VERIFY) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        Ok(Box::new(AesMacOperation::init(mech, key)?))
+    }
+
     fn generate_key(
         &self,
         mech: &CK_MECHANISM,
         template: &[CK_ATTRIBUTE],
+        _: &ObjectTemplates,
     ) -> KResult<Object> {
-        if mech.mechanism != CKM_AES_KEY_GEN {
-            return err_rv!(CKR_MECHANISM_INVALID);
-        }
-        let mut key = AES_KEY_TEMPLATE.default_object_create(template, true)?;
+        let (ktype, ktemplate) = match mech.mechanism {
+            CKM_AES_KEY_GEN => (CKK_AES, &AES_KEY_TEMPLATE),
+            CKM_AES_XTS_KEY_GEN => (CKK_AES_XTS, &AES_XTS_KEY_TEMPLATE),
+            _ => return err_rv!(CKR_MECHANISM_INVALID),
+        };
+        let mut key = ktemplate.default_object_create(template, true)?;
         if !key.check_or_set_attr(attribute::from_ulong(
             CKA_CLASS,
             CKO_SECRET_KEY,
         ))? {
             return err_rv!(CKR_TEMPLATE_INCONSISTENT);
         }
-        if !key
-            .check_or_set_attr(attribute::from_ulong(CKA_KEY_TYPE, CKK_AES))?
-        {
+        if !key.check_or_set_attr(attribute::from_ulong(CKA_KEY_TYPE, ktype))? {
             return err_rv!(CKR_TEMPLATE_INCONSISTENT);
         }
 
         let value_len = key.get_attr_as_ulong(CKA_VALUE_LEN)? as usize;
-        check_key_len(value_len)?;
+        match ktype {
+            CKK_AES_XTS => match value_len {
+                32 | 64 => (),
+                _ => return err_rv!(CKR_KEY_SIZE_RANGE),
+            },
+            _ => check_key_len(value_len)?,
+        }
         key.del_attr(CKA_VALUE_LEN);
 
         let mut value: Vec<u8> = vec![0; value_len];
-        match super::CSPRNG
-            .with(|rng| rng.borrow_mut().generate_random(value.as_mut_slice()))
-        {
-            Ok(()) => (),
-            Err(e) => return Err(e),
+        loop {
+            match super::CSPRNG.with(|rng| {
+                rng.borrow_mut().generate_random(value.as_mut_slice())
+            }) {
+                Ok(()) => (),
+                Err(e) => return Err(e),
+            }
+            /* the two halves of an XTS key must differ */
+            if ktype != CKK_AES_XTS || check_xts_key(&value).is_ok() {
+                break;
+            }
         }
         key.set_attr(attribute::from_bytes(CKA_VALUE, value))?;
 
@@ -194,22 +328,17 @@ impl Mechanism for AesMechanism {
         mech: &CK_MECHANISM,
         wrapping_key: &Object,
         key: &Object,
+        _: &[&Object],
         data: CK_BYTE_PTR,
         data_len: CK_ULONG_PTR,
     ) -> KResult<()> {
         if self.info.flags & CKF_WRAP != CKF_WRAP {
             return err_rv!(CKR_MECHANISM_INVALID);
         }
-        match check_key_object(wrapping_key, CKA_WRAP) {
+        match check_key_object(wrapping_key, mech.mechanism, CKA_WRAP) {
             Ok(_) => (),
             Err(e) => return Err(e),
         }
-        /* FIXME: move to token/lib wrapper */
-        match check_key_object(key, CKA_EXTRACTABLE) {
-            Ok(_) => (),
-            Err(e) => return Err(e),
-        }
-        /* FIXME: deal with CKA_WRAP_WITH_TRUSTED */
         AesOperation::wrap(mech, wrapping_key, key, data, data_len)
     }
 
@@ -217,45 +346,215 @@ impl Mechanism for AesMechanism {
         &self,
         mech: &CK_MECHANISM,
         wrapping_key: &Object,
+        _: &[&Object],
         data: &[u8],
         template: &[CK_ATTRIBUTE],
+        objtemplates: &ObjectTemplates,
     ) -> KResult<Object> {
         if self.info.flags & CKF_UNWRAP != CKF_UNWRAP {
             return err_rv!(CKR_MECHANISM_INVALID);
         }
-        match check_key_object(wrapping_key, CKA_UNWRAP) {
+        match check_key_object(wrapping_key, mech.mechanism, CKA_UNWRAP) {
             Ok(_) => (),
             Err(e) => return Err(e),
         }
-        let value = AesOperation::unwrap(mech, wrapping_key, data)?;
+        let mut value = AesOperation::unwrap(mech, wrapping_key, data)?;
+        let key = objtemplates.unwrap_key_from_template(template, &value);
+        value.zeroize();
+        key
+    }
 
-        check_key_len(value.len())?;
+    fn derive_key(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+        _: &[&Object],
+        template: &[CK_ATTRIBUTE],
+        objtemplates: &ObjectTemplates,
+    ) -> KResult<Object> {
+        if self.info.flags & CKF_DERIVE != CKF_DERIVE {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        match check_key_object(key, mech.mechanism, CKA_DERIVE) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        let (encmech, data) = encrypt_data_params(mech)?;
+        /* the data is encrypted without padding */
+        if data.is_empty() || data.len() % 16 != 0 {
+            return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+        }
 
-        let mut key = AES_KEY_TEMPLATE.default_object_create(template, true)?;
-        if !key.check_or_set_attr(attribute::from_ulong(
-            CKA_CLASS,
-            CKO_SECRET_KEY,
-        ))? {
+        let mut obj = objtemplates.derive_key_from_template(key, template)?;
+
+        let keylen = match obj.get_attr_as_ulong(CKA_VALUE_LEN) {
+            Ok(n) => n as usize,
+            Err(e) => match e {
+                KError::NotFound(_) => data.len(),
+                _ => return Err(e),
+            },
+        };
+        if keylen == 0 || keylen > data.len() {
             return err_rv!(CKR_TEMPLATE_INCONSISTENT);
         }
-        if !key
-            .check_or_set_attr(attribute::from_ulong(CKA_KEY_TYPE, CKK_AES))?
+        if obj.get_attr_as_ulong(CKA_KEY_TYPE)? == CKK_AES
+            && check_key_len(keylen).is_err()
         {
             return err_rv!(CKR_TEMPLATE_INCONSISTENT);
         }
 
-        match key.get_attr_as_ulong(CKA_VALUE_LEN) {
-            Ok(len) => {
-                if len as usize != value.len() {
-                    return err_rv!(CKR_KEY_SIZE_RANGE);
-                }
+        let mut op = AesOperation::encrypt_new(&encmech, key)?;
+        let mut secret = vec![0u8; data.len()];
+        let mut len = secret.len() as CK_ULONG;
+        if let Err(e) = op.encrypt(data, secret.as_mut_ptr(), &mut len) {
+            secret.zeroize();
+            return Err(e);
+        }
+        obj.del_attr(CKA_VALUE_LEN);
+        obj.set_attr(from_bytes(CKA_VALUE, secret[..keylen].to_vec()))?;
+        secret.zeroize();
+
+        Ok(obj)
+    }
+}
+
+/* Returns the underlying encryption mechanism and the data to encrypt
+ * for the ENCRYPT_DATA key derivation mechanisms */
+fn encrypt_data_params(mech: &CK_MECHANISM) -> KResult<(CK_MECHANISM, &[u8])> {
+    match mech.mechanism {
+        CKM_AES_ECB_ENCRYPT_DATA => {
+            if mech.pParameter.is_null()
+                || mech.ulParameterLen as usize
+                    != ::std::mem::size_of::<CK_KEY_DERIVATION_STRING_DATA>()
+            {
+                return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+            }
+            let params = unsafe {
+                &*(mech.pParameter as *const CK_KEY_DERIVATION_STRING_DATA)
+            };
+            if params.pData.is_null() {
+                return err_rv!(CKR_MECHANISM_PARAM_INVALID);
             }
-            Err(_) => (),
+            Ok((
+                CK_MECHANISM {
+                    mechanism: CKM_AES_ECB,
+                    pParameter: std::ptr::null_mut(),
+                    ulParameterLen: 0,
+                },
+                unsafe {
+                    std::slice::from_raw_parts(
+                        params.pData,
+                        params.ulLen as usize,
+                    )
+                },
+            ))
         }
-        key.set_attr(attribute::from_bytes(CKA_VALUE, value))?;
+        CKM_AES_CBC_ENCRYPT_DATA => {
+            if mech.pParameter.is_null()
+                || mech.ulParameterLen as usize
+                    != ::std::mem::size_of::<CK_AES_CBC_ENCRYPT_DATA_PARAMS>()
+            {
+                return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+            }
+            let params = unsafe {
+                &*(mech.pParameter as *const CK_AES_CBC_ENCRYPT_DATA_PARAMS)
+            };
+            if params.pData.is_null() {
+                return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+            }
+            Ok((
+                CK_MECHANISM {
+                    mechanism: CKM_AES_CBC,
+                    pParameter: params.iv.as_ptr() as CK_VOID_PTR,
+                    ulParameterLen: params.iv.len() as CK_ULONG,
+                },
+                unsafe {
+                    std::slice::from_raw_parts(
+                        params.pData,
+                        params.length as usize,
+                    )
+                },
+            ))
+        }
+        _ => err_rv!(CKR_MECHANISM_INVALID),
+    }
+}
 
-        Ok(key)
+fn kwp_mechanism() -> CK_MECHANISM {
+    CK_MECHANISM {
+        mechanism: CKM_AES_KEY_WRAP_KWP,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    }
+}
+
+/* AES-KWP with a raw key, used by mechanisms that wrap keys with an
+ * ephemeral AES key */
+pub fn kwp_wrap(kek: &[u8], key: &Object) -> KResult<Vec<u8>> {
+    let mech = kwp_mechanism();
+    let mut wrapping_key = Object::new();
+    wrapping_key.set_attr(from_bytes(CKA_VALUE, kek.to_vec()))?;
+    let mut len: CK_ULONG = 0;
+    AesOperation::wrap(
+        &mech,
+        &wrapping_key,
+        key,
+        std::ptr::null_mut(),
+        &mut len,
+    )?;
+    let mut wrapped = vec![0u8; len as usize];
+    AesOperation::wrap(
+        &mech,
+        &wrapping_key,
+        key,
+        wrapped.as_mut_ptr(),
+        &mut len,
+    )?;
+    wrapped.truncate(len as usize);
+    Ok(wrapped)
+}
+
+/* AES-KWP pads the input to a multiple of the 8 byte semiblock and
+ * prepends one semiblock of integrity check value */
+pub fn kwp_len(len: usize) -> usize {
+    ((len + 7) / 8) * 8 + 8
+}
+
+pub fn kwp_wrap_len(key: &Object) -> KResult<usize> {
+    let keylen = match key.get_attr_as_ulong(CKA_CLASS)? {
+        CKO_SECRET_KEY => key.get_attr_as_bytes(CKA_VALUE)?.len(),
+        CKO_PRIVATE_KEY => {
+            let mut encoded = pkcs8::encode_private_key(key)?;
+            let len = encoded.len();
+            encoded.zeroize();
+            len
+        }
+        _ => return err_rv!(CKR_KEY_NOT_WRAPPABLE),
+    };
+    Ok(kwp_len(keylen))
+}
+
+pub fn kwp_unwrap(kek: &[u8], data: &[u8]) -> KResult<Vec<u8>> {
+    let mut wrapping_key = Object::new();
+    wrapping_key.set_attr(from_bytes(CKA_VALUE, kek.to_vec()))?;
+    AesOperation::unwrap(&kwp_mechanism(), &wrapping_key, data)
+}
+
+/* AES-CMAC with the full block as output, used as PRF by key derivation
+ * functions */
+pub fn cmac(key: &Object, data: &[&[u8]]) -> KResult<Vec<u8>> {
+    let mech = CK_MECHANISM {
+        mechanism: CKM_AES_CMAC,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let mut op = AesMacOperation::init(&mech, key)?;
+    for d in data {
+        op.update(d)?;
     }
+    let mut output = vec![0u8; AES_BLOCK_SIZE];
+    op.finalize(output.as_mut_slice())?;
+    Ok(output)
 }
 
 pub fn register(mechs: &mut Mechanisms, ot: &mut ObjectTemplates) {
@@ -352,6 +651,100 @@ pub fn register(mechs: &mut Mechanisms, ot: &mut ObjectTemplates) {
         }),
     );
 
+    mechs.add_mechanism(
+        CKM_AES_GCM,
+        Box::new(AesMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 16,
+                ulMaxKeySize: 32,
+                flags: CKF_ENCRYPT
+                    | CKF_DECRYPT
+                    | CKF_MESSAGE_ENCRYPT
+                    | CKF_MESSAGE_DECRYPT
+                    | CKF_MULTI_MESSAGE,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_AES_CCM,
+        Box::new(AesMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 16,
+                ulMaxKeySize: 32,
+                flags: CKF_ENCRYPT | CKF_DECRYPT,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_AES_CTS,
+        Box::new(AesMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 16,
+                ulMaxKeySize: 32,
+                flags: CKF_ENCRYPT | CKF_DECRYPT,
+            },
+        }),
+    );
+
+    /* the CS1 and CS2 ciphertext stealing variants of NIST SP 800-38A
+     * Addendum, PKCS#11 only defines CS3 */
+    mechs.add_mechanism(
+        KRYMECH_AES_CTS_CS1,
+        Box::new(AesMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 16,
+                ulMaxKeySize: 32,
+                flags: CKF_ENCRYPT | CKF_DECRYPT,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        KRYMECH_AES_CTS_CS2,
+        Box::new(AesMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 16,
+                ulMaxKeySize: 32,
+                flags: CKF_ENCRYPT | CKF_DECRYPT,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_AES_XTS,
+        Box::new(AesMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 32,
+                ulMaxKeySize: 64,
+                flags: CKF_ENCRYPT | CKF_DECRYPT,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_AES_KEY_WRAP,
+        Box::new(AesMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 16,
+                ulMaxKeySize: 32,
+                flags: CKF_WRAP | CKF_UNWRAP,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_AES_KEY_WRAP_KWP,
+        Box::new(AesMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 16,
+                ulMaxKeySize: 32,
+                flags: CKF_WRAP | CKF_UNWRAP,
+            },
+        }),
+    );
+
     mechs.add_mechanism(
         CKM_AES_KEY_GEN,
         Box::new(AesMechanism {
@@ -363,7 +756,81 @@ pub fn register(mechs: &mut Mechanisms, ot: &mut ObjectTemplates) {
         }),
     );
 
+    mechs.add_mechanism(
+        CKM_AES_XTS_KEY_GEN,
+        Box::new(AesMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 32,
+                ulMaxKeySize: 64,
+                flags: CKF_GENERATE,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_AES_CMAC,
+        Box::new(AesMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 16,
+                ulMaxKeySize: 32,
+                flags: CKF_SIGN
+                    | CKF_VERIFY
+                    | CKF_MESSAGE_SIGN
+                    | CKF_MESSAGE_VERIFY,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_AES_CMAC_GENERAL,
+        Box::new(AesMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 16,
+                ulMaxKeySize: 32,
+                flags: CKF_SIGN
+                    | CKF_VERIFY
+                    | CKF_MESSAGE_SIGN
+                    | CKF_MESSAGE_VERIFY,
+            },
+        }),
+    );
+
+    /* no message based operations as those would reuse the IV */
+    mechs.add_mechanism(
+        CKM_AES_GMAC,
+        Box::new(AesMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 16,
+                ulMaxKeySize: 32,
+                flags: CKF_SIGN | CKF_VERIFY,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_AES_ECB_ENCRYPT_DATA,
+        Box::new(AesMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 16,
+                ulMaxKeySize: 32,
+                flags: CKF_DERIVE,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_AES_CBC_ENCRYPT_DATA,
+        Box::new(AesMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 16,
+                ulMaxKeySize: 32,
+                flags: CKF_DERIVE,
+            },
+        }),
+    );
+
     ot.add_template(ObjectType::AesKey, &AES_KEY_TEMPLATE);
+    ot.add_template(ObjectType::AesXtsKey, &AES_XTS_KEY_TEMPLATE);
 }
 
 #[cfg(feature = "fips")]
@@ -18,6 +18,7 @@ pub enum AttrType {
     StringType,
     BytesType,
     DateType,
+    AttrArrayType,
     DenyType,
     IgnoreType,
 }
@@ -39,7 +40,7 @@ macro_rules! attrmap_element {
     };
 }
 
-static ATTRMAP: [Attrmap<'_>; 130] = [
+static ATTRMAP: [Attrmap<'_>; 134] = [
     attrmap_element!(CKA_CLASS; as NumType),
     attrmap_element!(CKA_TOKEN; as BoolType),
     attrmap_element!(CKA_PRIVATE; as BoolType),
@@ -106,9 +107,9 @@ static ATTRMAP: [Attrmap<'_>; 130] = [
     attrmap_element!(CKA_EC_POINT; as BytesType),
     attrmap_element!(CKA_ALWAYS_AUTHENTICATE; as BoolType),
     attrmap_element!(CKA_WRAP_WITH_TRUSTED; as BoolType),
-    attrmap_element!(CKA_WRAP_TEMPLATE; as BytesType),
-    attrmap_element!(CKA_UNWRAP_TEMPLATE; as BytesType),
-    attrmap_element!(CKA_DERIVE_TEMPLATE; as BytesType),
+    attrmap_element!(CKA_WRAP_TEMPLATE; as AttrArrayType),
+    attrmap_element!(CKA_UNWRAP_TEMPLATE; as AttrArrayType),
+    attrmap_element!(CKA_DERIVE_TEMPLATE; as AttrArrayType),
     attrmap_element!(CKA_OTP_FORMAT; as NumType),
     attrmap_element!(CKA_OTP_LENGTH; as NumType),
     attrmap_element!(CKA_OTP_TIME_INTERVAL; as NumType),
@@ -169,6 +170,10 @@ static ATTRMAP: [Attrmap<'_>; 130] = [
     attrmap_element!(CKA_HSS_LMS_TYPES; as BytesType),
     attrmap_element!(CKA_HSS_LMOTS_TYPES; as BytesType),
     attrmap_element!(CKA_HSS_KEYS_REMAINING; as NumType),
+    attrmap_element!(CKA_PARAMETER_SET; as NumType),
+    attrmap_element!(CKA_ENCAPSULATE; as BoolType),
+    attrmap_element!(CKA_DECAPSULATE; as BoolType),
+    attrmap_element!(CKA_SEED; as BytesType),
     attrmap_element!(KRYATTR_MAX_LOGIN_ATTEMPTS; as NumType),
 ];
 
@@ -196,6 +201,12 @@ impl Attribute {
         if self.ck_type != attr.type_ {
             return false;
         }
+        if self.attrtype == AttrType::AttrArrayType {
+            return match attr.to_attr_array() {
+                Ok(a) => attr_array_to_vec(a) == self.value,
+                Err(_) => false,
+            };
+        }
         match attr.to_buf() {
             Ok(buf) => buf == self.value,
             Err(_) => false,
@@ -296,6 +307,46 @@ impl Attribute {
         }
     }
 
+    pub fn to_attr_array(&self) -> KResult<Vec<Attribute>> {
+        let mut attrs = Vec::new();
+        let mut idx = 0;
+        while idx < self.value.len() {
+            if self.value.len() - idx < 16 {
+                return err_rv!(CKR_ATTRIBUTE_VALUE_INVALID);
+            }
+            let t = u64::from_ne_bytes(
+                self.value[idx..(idx + 8)].try_into().unwrap(),
+            ) as CK_ULONG;
+            let len = u64::from_ne_bytes(
+                self.value[(idx + 8)..(idx + 16)].try_into().unwrap(),
+            ) as usize;
+            idx += 16;
+            if self.value.len() - idx < len {
+                return err_rv!(CKR_ATTRIBUTE_VALUE_INVALID);
+            }
+            attrs.push(Attribute {
+                ck_type: t,
+                attrtype: attr_type_of(t)?,
+                value: self.value[idx..(idx + len)].to_vec(),
+            });
+            idx += len;
+        }
+        Ok(attrs)
+    }
+
+    fn to_attr_array_value(&self) -> Value {
+        match self.to_attr_array() {
+            Ok(attrs) => {
+                let mut map = serde_json::Map::new();
+                for a in attrs {
+                    map.insert(a.name(), a.json_value());
+                }
+                Value::Object(map)
+            }
+            Err(_) => Value::Null,
+        }
+    }
+
     pub fn json_value(&self) -> serde_json::Value {
         match self.attrtype {
             AttrType::BoolType => self.to_bool_value(),
@@ -303,6 +354,7 @@ impl Attribute {
             AttrType::StringType => self.to_string_value(),
             AttrType::BytesType => self.to_b64_string_value(),
             AttrType::DateType => self.to_date_value(),
+            AttrType::AttrArrayType => self.to_attr_array_value(),
             AttrType::IgnoreType => Value::Null,
             AttrType::DenyType => Value::Null,
         }
@@ -368,6 +420,34 @@ fn bytes_to_vec(val: Vec<u8>) -> Vec<u8> {
 }
 conversion_from_type! {make from_bytes; from_type_bytes; from_string_bytes; from Vec<u8>; as BytesType; via bytes_to_vec}
 
+fn attr_type_of(t: CK_ULONG) -> KResult<AttrType> {
+    for a in &ATTRMAP {
+        if a.id == t {
+            return match a.atype {
+                /* nested arrays are not supported */
+                AttrType::AttrArrayType | AttrType::DenyType => {
+                    err_rv!(CKR_ATTRIBUTE_VALUE_INVALID)
+                }
+                _ => Ok(a.atype),
+            };
+        }
+    }
+    err_rv!(CKR_ATTRIBUTE_TYPE_INVALID)
+}
+
+/* attribute arrays are stored as a sequence of type, length, value
+ * triplets, type and length are encoded as native endian u64 */
+fn attr_array_to_vec(val: Vec<Attribute>) -> Vec<u8> {
+    let mut v = Vec::new();
+    for a in val {
+        v.extend_from_slice(&(a.ck_type as u64).to_ne_bytes());
+        v.extend_from_slice(&(a.value.len() as u64).to_ne_bytes());
+        v.extend_from_slice(&a.value);
+    }
+    v
+}
+conversion_from_type! {make from_attr_array; from_type_attr_array; from_string_attr_array; from Vec<Attribute>; as AttrArrayType; via attr_array_to_vec}
+
 fn date_to_vec(val: CK_DATE) -> Vec<u8> {
     let mut v = Vec::with_capacity(8);
     v[0] = val.year[0];
@@ -487,6 +567,18 @@ pub fn from_value(s: String, v: &Value) -> KResult<Attribute> {
                     }
                     None => return err_rv!(CKR_ATTRIBUTE_VALUE_INVALID),
                 },
+                AttrType::AttrArrayType => match v.as_object() {
+                    Some(m) => {
+                        let mut attrs = Vec::with_capacity(m.len());
+                        for (name, val) in m {
+                            let attr = from_value(name.clone(), val)?;
+                            attr_type_of(attr.ck_type)?;
+                            attrs.push(attr);
+                        }
+                        return Ok(from_attr_array(a.id, attrs));
+                    }
+                    None => return err_rv!(CKR_ATTRIBUTE_VALUE_INVALID),
+                },
                 AttrType::DenyType => (),
                 AttrType::IgnoreType => (),
             }
@@ -549,6 +641,28 @@ impl CK_ATTRIBUTE {
         };
         vec_to_date_validate(buf.to_vec())
     }
+    pub fn to_attr_array(self) -> KResult<Vec<Attribute>> {
+        let size = std::mem::size_of::<CK_ATTRIBUTE>();
+        if self.ulValueLen as usize % size != 0 {
+            return err_rv!(CKR_ATTRIBUTE_VALUE_INVALID);
+        }
+        let count = self.ulValueLen as usize / size;
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        if self.pValue.is_null() {
+            return err_rv!(CKR_ATTRIBUTE_VALUE_INVALID);
+        }
+        let array: &[CK_ATTRIBUTE] = unsafe {
+            std::slice::from_raw_parts(self.pValue as *const _, count)
+        };
+        let mut attrs = Vec::with_capacity(count);
+        for a in array {
+            attr_type_of(a.type_)?;
+            attrs.push(a.to_attribute()?);
+        }
+        Ok(attrs)
+    }
 
     pub fn to_attribute(self) -> KResult<Attribute> {
         let mut atype = AttrType::DenyType;
@@ -566,6 +680,9 @@ impl CK_ATTRIBUTE {
             }
             AttrType::BytesType => Ok(from_bytes(self.type_, self.to_buf()?)),
             AttrType::DateType => Ok(from_date(self.type_, self.to_date()?)),
+            AttrType::AttrArrayType => {
+                Ok(from_attr_array(self.type_, self.to_attr_array()?))
+            }
             AttrType::DenyType => err_rv!(CKR_ATTRIBUTE_TYPE_INVALID),
             AttrType::IgnoreType => Ok(from_ignore(self.type_, None)),
         }
@@ -0,0 +1,341 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use super::attribute;
+use super::error;
+use super::interface;
+use super::object;
+use super::{attr_element, err_rv};
+
+use attribute::{from_bool, from_bytes};
+use error::{KError, KResult};
+use interface::*;
+use object::{
+    CommonKeyTemplate, OAFlags, Object, ObjectAttr, ObjectTemplate,
+    ObjectTemplates, ObjectType, SecretKeyTemplate,
+};
+
+use super::mechanism;
+use mechanism::*;
+
+use once_cell::sync::Lazy;
+use std::fmt::Debug;
+
+/* Both ChaCha20 and Poly1305 use 256 bit keys */
+const CHACHA20_KEY_SIZE: usize = 32;
+
+fn check_key_len(len: usize) -> KResult<()> {
+    match len {
+        CHACHA20_KEY_SIZE => Ok(()),
+        _ => err_rv!(CKR_KEY_SIZE_RANGE),
+    }
+}
+
+fn check_key_object(
+    key: &Object,
+    ktype: CK_KEY_TYPE,
+    op: CK_ULONG,
+) -> KResult<()> {
+    match key.get_attr_as_ulong(CKA_CLASS)? {
+        CKO_SECRET_KEY => {
+            if key.get_attr_as_ulong(CKA_KEY_TYPE)? != ktype {
+                return err_rv!(CKR_KEY_TYPE_INCONSISTENT);
+            }
+        }
+        _ => return err_rv!(CKR_KEY_TYPE_INCONSISTENT),
+    }
+    match key.get_attr_as_bool(op) {
+        Ok(avail) => {
+            if !avail {
+                return err_rv!(CKR_KEY_FUNCTION_NOT_PERMITTED);
+            }
+        }
+        Err(_) => return err_rv!(CKR_KEY_FUNCTION_NOT_PERMITTED),
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct ChaCha20KeyTemplate {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl ChaCha20KeyTemplate {
+    fn new() -> ChaCha20KeyTemplate {
+        let mut data: ChaCha20KeyTemplate = ChaCha20KeyTemplate {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes.append(&mut data.init_common_key_attrs());
+        data.attributes
+            .append(&mut data.init_common_secret_key_attrs());
+        data.attributes.push(attr_element!(CKA_VALUE; OAFlags::Defval | OAFlags::Sensitive | OAFlags::RequiredOnCreate | OAFlags::UnsettableOnGenerate | OAFlags::UnsettableOnUnwrap; from_bytes; val Vec::new()));
+        data.attributes.push(attr_element!(CKA_VALUE_LEN; OAFlags::UnsettableOnCreate; from_bytes; val Vec::new()));
+
+        /* default to private */
+        let private = attr_element!(CKA_PRIVATE; OAFlags::Defval | OAFlags::ChangeOnCopy; from_bool; val true);
+        match data
+            .attributes
+            .iter()
+            .position(|x| x.get_type() == CKA_PRIVATE)
+        {
+            Some(idx) => data.attributes[idx] = private,
+            None => data.attributes.push(private),
+        }
+
+        data
+    }
+}
+
+impl ObjectTemplate for ChaCha20KeyTemplate {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> KResult<Object> {
+        let obj = self.default_object_create(template, false)?;
+
+        let val = obj.get_attr_as_bytes(CKA_VALUE)?;
+        check_key_len(val.len())?;
+
+        Ok(obj)
+    }
+
+    fn unwrap(
+        &self,
+        template: &[CK_ATTRIBUTE],
+        data: &[u8],
+    ) -> KResult<Object> {
+        check_key_len(data.len())?;
+        self.default_secret_key_unwrap(template, data)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl CommonKeyTemplate for ChaCha20KeyTemplate {
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl SecretKeyTemplate for ChaCha20KeyTemplate {
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+/* ChaCha20 and Poly1305 keys have the same attributes, the key type is
+ * what keeps a key from being used with the other algorithm */
+static CHACHA20_KEY_TEMPLATE: Lazy<Box<dyn ObjectTemplate>> =
+    Lazy::new(|| Box::new(ChaCha20KeyTemplate::new()));
+
+static POLY1305_KEY_TEMPLATE: Lazy<Box<dyn ObjectTemplate>> =
+    Lazy::new(|| Box::new(ChaCha20KeyTemplate::new()));
+
+#[derive(Debug)]
+struct ChaCha20Mechanism {
+    info: CK_MECHANISM_INFO,
+}
+
+impl Mechanism for ChaCha20Mechanism {
+    fn info(&self) -> &CK_MECHANISM_INFO {
+        &self.info
+    }
+
+    fn encryption_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> KResult<Box<dyn Encryption>> {
+        if self.info.flags & CKF_ENCRYPT != CKF_ENCRYPT {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        match check_key_object(key, CKK_CHACHA20, CKA_ENCRYPT) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        Ok(Box::new(ChaCha20Operation::encrypt_new(mech, key)?))
+    }
+
+    fn decryption_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> KResult<Box<dyn Decryption>> {
+        if self.info.flags & CKF_DECRYPT != CKF_DECRYPT {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        match check_key_object(key, CKK_CHACHA20, CKA_DECRYPT) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        Ok(Box::new(ChaCha20Operation::decrypt_new(mech, key)?))
+    }
+
+    fn msg_encryption_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> KResult<Box<dyn MsgEncryption>> {
+        if self.info.flags & CKF_MESSAGE_ENCRYPT != CKF_MESSAGE_ENCRYPT {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        match check_key_object(key, CKK_CHACHA20, CKA_ENCRYPT) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        Ok(Box::new(ChaCha20MsgOperation::encrypt_new(mech, key)?))
+    }
+
+    fn msg_decryption_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> KResult<Box<dyn MsgDecryption>> {
+        if self.info.flags & CKF_MESSAGE_DECRYPT != CKF_MESSAGE_DECRYPT {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        match check_key_object(key, CKK_CHACHA20, CKA_DECRYPT) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        Ok(Box::new(ChaCha20MsgOperation::decrypt_new(mech, key)?))
+    }
+
+    fn sign_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> KResult<Box<dyn Sign>> {
+        if self.info.flags & CKF_SIGN != CKF_SIGN {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        match check_key_object(key, CKK_POLY1305, CKA_SIGN) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        Ok(Box::new(Poly1305Operation::init(mech, key)?))
+    }
+
+    fn verify_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> KResult<Box<dyn Verify>> {
+        if self.info.flags & CKF_VERIFY != CKF_VERIFY {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        match check_key_object(key, CKK_POLY1305, CKA_VERIFY) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        Ok(Box::new(Poly1305Operation::init(mech, key)?))
+    }
+
+    fn generate_key(
+        &self,
+        mech: &CK_MECHANISM,
+        template: &[CK_ATTRIBUTE],
+        _: &ObjectTemplates,
+    ) -> KResult<Object> {
+        let (ktype, ktemplate) = match mech.mechanism {
+            CKM_CHACHA20_KEY_GEN => (CKK_CHACHA20, &CHACHA20_KEY_TEMPLATE),
+            CKM_POLY1305_KEY_GEN => (CKK_POLY1305, &POLY1305_KEY_TEMPLATE),
+            _ => return err_rv!(CKR_MECHANISM_INVALID),
+        };
+        let mut key = ktemplate.default_object_create(template, true)?;
+        if !key.check_or_set_attr(attribute::from_ulong(
+            CKA_CLASS,
+            CKO_SECRET_KEY,
+        ))? {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        if !key.check_or_set_attr(attribute::from_ulong(CKA_KEY_TYPE, ktype))? {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+
+        /* the key size is fixed, CKA_VALUE_LEN is optional */
+        if let Ok(len) = key.get_attr_as_ulong(CKA_VALUE_LEN) {
+            check_key_len(len as usize)?;
+            key.del_attr(CKA_VALUE_LEN);
+        }
+
+        let mut value: Vec<u8> = vec![0; CHACHA20_KEY_SIZE];
+        match super::CSPRNG
+            .with(|rng| rng.borrow_mut().generate_random(value.as_mut_slice()))
+        {
+            Ok(()) => (),
+            Err(e) => return Err(e),
+        }
+        key.set_attr(attribute::from_bytes(CKA_VALUE, value))?;
+
+        Ok(key)
+    }
+}
+
+pub fn register(mechs: &mut Mechanisms, ot: &mut ObjectTemplates) {
+    mechs.add_mechanism(
+        CKM_CHACHA20_KEY_GEN,
+        Box::new(ChaCha20Mechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: CHACHA20_KEY_SIZE as CK_ULONG,
+                ulMaxKeySize: CHACHA20_KEY_SIZE as CK_ULONG,
+                flags: CKF_GENERATE,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_CHACHA20,
+        Box::new(ChaCha20Mechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: CHACHA20_KEY_SIZE as CK_ULONG,
+                ulMaxKeySize: CHACHA20_KEY_SIZE as CK_ULONG,
+                flags: CKF_ENCRYPT | CKF_DECRYPT,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_CHACHA20_POLY1305,
+        Box::new(ChaCha20Mechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: CHACHA20_KEY_SIZE as CK_ULONG,
+                ulMaxKeySize: CHACHA20_KEY_SIZE as CK_ULONG,
+                flags: CKF_ENCRYPT
+                    | CKF_DECRYPT
+                    | CKF_MESSAGE_ENCRYPT
+                    | CKF_MESSAGE_DECRYPT,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_POLY1305_KEY_GEN,
+        Box::new(ChaCha20Mechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: CHACHA20_KEY_SIZE as CK_ULONG,
+                ulMaxKeySize: CHACHA20_KEY_SIZE as CK_ULONG,
+                flags: CKF_GENERATE,
+            },
+        }),
+    );
+
+    /* Poly1305 keys are single use, so no message based operations */
+    mechs.add_mechanism(
+        CKM_POLY1305,
+        Box::new(ChaCha20Mechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: CHACHA20_KEY_SIZE as CK_ULONG,
+                ulMaxKeySize: CHACHA20_KEY_SIZE as CK_ULONG,
+                flags: CKF_SIGN | CKF_VERIFY,
+            },
+        }),
+    );
+
+    ot.add_template(ObjectType::ChaCha20Key, &CHACHA20_KEY_TEMPLATE);
+    ot.add_template(ObjectType::Poly1305Key, &POLY1305_KEY_TEMPLATE);
+}
+
+include!("ossl/chacha20.rs");
@@ -0,0 +1,105 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use zeroize::Zeroize;
+
+pub const DER_INTEGER_TAG: u8 = 0x02;
+pub const DER_OCTET_STRING_TAG: u8 = 0x04;
+pub const DER_NULL_TAG: u8 = 0x05;
+pub const DER_OID_TAG: u8 = 0x06;
+pub const DER_PRINTABLE_STRING_TAG: u8 = 0x13;
+pub const DER_SEQUENCE_TAG: u8 = 0x30;
+
+/* Minimal DER parser, splits the first element off the buffer and
+ * returns its tag, its body and the data that follows it */
+pub fn der_split(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    if data.len() < 2 {
+        return None;
+    }
+    let (len, off) = match data[1] {
+        l if l < 0x80 => (l as usize, 2),
+        /* long form, lengths are minimally encoded in up to 4 bytes */
+        l @ 0x81..=0x84 => {
+            let n = (l & 0x7f) as usize;
+            if data.len() < 2 + n || data[2] == 0 {
+                return None;
+            }
+            let l = data[2..2 + n]
+                .iter()
+                .fold(0usize, |acc, b| (acc << 8) | *b as usize);
+            if l < 0x80 {
+                return None;
+            }
+            (l, 2 + n)
+        }
+        _ => return None,
+    };
+    if data.len() - off < len {
+        return None;
+    }
+    Some((data[0], &data[off..off + len], &data[off + len..]))
+}
+
+/* Returns the body of the next element if it has the expected tag,
+ * and the data that follows it */
+pub fn der_next(tag: u8, data: &[u8]) -> Option<(&[u8], &[u8])> {
+    match der_split(data) {
+        Some((t, body, rest)) if t == tag => Some((body, rest)),
+        _ => None,
+    }
+}
+
+/* Returns the body of a single element with the expected tag that
+ * spans the whole buffer */
+pub fn der_unwrap(tag: u8, data: &[u8]) -> Option<&[u8]> {
+    match der_next(tag, data) {
+        Some((body, rest)) if rest.is_empty() => Some(body),
+        _ => None,
+    }
+}
+
+pub fn der_wrap(tag: u8, data: &[u8]) -> Vec<u8> {
+    let mut der = Vec::with_capacity(data.len() + 10);
+    der.push(tag);
+    match data.len() {
+        l if l < 0x80 => der.push(l as u8),
+        l => {
+            let bytes = l.to_be_bytes();
+            let skip = bytes.iter().take_while(|b| **b == 0).count();
+            der.push(0x80 | (bytes.len() - skip) as u8);
+            der.extend_from_slice(&bytes[skip..]);
+        }
+    }
+    der.extend_from_slice(data);
+    der
+}
+
+/* PKCS#11 big integers are unsigned big endian byte strings, while DER
+ * INTEGERs are signed and minimally encoded */
+pub fn der_wrap_uint(data: &[u8]) -> Vec<u8> {
+    let start = match data.iter().position(|b| *b != 0) {
+        Some(s) => s,
+        None => data.len(),
+    };
+    let mut int = Vec::with_capacity(data.len() - start + 1);
+    if start == data.len() || data[start] & 0x80 != 0 {
+        int.push(0);
+    }
+    int.extend_from_slice(&data[start..]);
+    let der = der_wrap(DER_INTEGER_TAG, &int);
+    int.zeroize();
+    der
+}
+
+/* Returns the next INTEGER as an unsigned big endian byte string,
+ * negative numbers are rejected */
+pub fn der_next_uint(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (int, rest) = der_next(DER_INTEGER_TAG, data)?;
+    if int.is_empty() || int[0] & 0x80 != 0 {
+        return None;
+    }
+    if int.len() > 1 && int[0] == 0 {
+        return Some((&int[1..], rest));
+    }
+    Some((int, rest))
+}
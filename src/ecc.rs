@@ -0,0 +1,130 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use super::der;
+use super::err_rv;
+use super::error;
+use super::interface;
+
+use der::*;
+use error::{KError, KResult};
+use interface::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EccCurve {
+    Ed25519,
+    Ed448,
+    X25519,
+    X448,
+}
+
+#[derive(Debug)]
+struct CurveInfo {
+    curve: EccCurve,
+    oid: &'static [u8],
+    name: &'static [u8],
+    ossl_name: &'static [u8],
+    key_type: CK_KEY_TYPE,
+    key_len: usize,
+}
+
+/* oid contains the DER encoded body of the curve Object Identifier,
+ * name contains the curve name as defined in the PKCS#11 spec for
+ * the PrintableString form of CKA_EC_PARAMS */
+static CURVES: [CurveInfo; 4] = [
+    CurveInfo {
+        curve: EccCurve::Ed25519,
+        oid: &[0x2B, 0x65, 0x70],
+        name: b"edwards25519",
+        ossl_name: b"ED25519\0",
+        key_type: CKK_EC_EDWARDS,
+        key_len: 32,
+    },
+    CurveInfo {
+        curve: EccCurve::Ed448,
+        oid: &[0x2B, 0x65, 0x71],
+        name: b"edwards448",
+        ossl_name: b"ED448\0",
+        key_type: CKK_EC_EDWARDS,
+        key_len: 57,
+    },
+    CurveInfo {
+        curve: EccCurve::X25519,
+        oid: &[0x2B, 0x65, 0x6E],
+        name: b"curve25519",
+        ossl_name: b"X25519\0",
+        key_type: CKK_EC_MONTGOMERY,
+        key_len: 32,
+    },
+    CurveInfo {
+        curve: EccCurve::X448,
+        oid: &[0x2B, 0x65, 0x6F],
+        name: b"curve448",
+        ossl_name: b"X448\0",
+        key_type: CKK_EC_MONTGOMERY,
+        key_len: 56,
+    },
+];
+
+fn curve_info(curve: EccCurve) -> &'static CurveInfo {
+    /* all curves are in the table */
+    CURVES.iter().find(|c| c.curve == curve).unwrap()
+}
+
+impl EccCurve {
+    pub fn from_ec_params(params: &[u8]) -> KResult<EccCurve> {
+        if let Some(oid) = der_unwrap(DER_OID_TAG, params) {
+            return match CURVES.iter().find(|c| c.oid == oid) {
+                Some(c) => Ok(c.curve),
+                None => err_rv!(CKR_CURVE_NOT_SUPPORTED),
+            };
+        }
+        if let Some(name) = der_unwrap(DER_PRINTABLE_STRING_TAG, params) {
+            return match CURVES.iter().find(|c| c.name == name) {
+                Some(c) => Ok(c.curve),
+                None => err_rv!(CKR_CURVE_NOT_SUPPORTED),
+            };
+        }
+        err_rv!(CKR_ATTRIBUTE_VALUE_INVALID)
+    }
+
+    pub fn to_ec_params(&self) -> Vec<u8> {
+        der_wrap(DER_OID_TAG, curve_info(*self).oid)
+    }
+
+    pub fn key_type(&self) -> CK_KEY_TYPE {
+        curve_info(*self).key_type
+    }
+
+    pub fn key_len(&self) -> usize {
+        curve_info(*self).key_len
+    }
+
+    /* NUL terminated name as known to OpenSSL */
+    pub fn ossl_name(&self) -> &'static [u8] {
+        curve_info(*self).ossl_name
+    }
+}
+
+/* Applications use both the raw and the DER encoded OCTET STRING forms
+ * of CKA_EC_POINT for Edwards and Montgomery curves, accept both on
+ * import and always return the DER encoding */
+pub fn ec_point_to_raw(curve: EccCurve, point: &[u8]) -> KResult<Vec<u8>> {
+    let len = curve.key_len();
+    if point.len() == len {
+        return Ok(point.to_vec());
+    }
+    match der_unwrap(DER_OCTET_STRING_TAG, point) {
+        Some(p) => {
+            if p.len() != len {
+                return err_rv!(CKR_ATTRIBUTE_VALUE_INVALID);
+            }
+            Ok(p.to_vec())
+        }
+        None => err_rv!(CKR_ATTRIBUTE_VALUE_INVALID),
+    }
+}
+
+pub fn raw_to_ec_point(raw: &[u8]) -> Vec<u8> {
+    der_wrap(DER_OCTET_STRING_TAG, raw)
+}
@@ -0,0 +1,402 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use super::attribute;
+use super::ecc;
+use super::error;
+use super::interface;
+use super::object;
+use super::{attr_element, bytes_attr_not_empty, err_rv};
+
+use attribute::{from_bool, from_bytes};
+use ecc::EccCurve;
+use error::{KError, KResult};
+use interface::*;
+use object::{
+    CommonKeyTemplate, OAFlags, Object, ObjectAttr, ObjectTemplate,
+    ObjectTemplates, ObjectType, PrivKeyTemplate, PubKeyTemplate,
+};
+
+use super::mechanism;
+use mechanism::*;
+
+use once_cell::sync::Lazy;
+use std::fmt::Debug;
+
+pub const MIN_EDDSA_SIZE_BITS: usize = 256;
+pub const MAX_EDDSA_SIZE_BITS: usize = 456;
+
+/* the context string is limited to 255 bytes by RFC 8032 */
+const MAX_EDDSA_CONTEXT_LEN: usize = 255;
+
+const ED25519_NAME: &[u8; 8] = b"Ed25519\0";
+const ED25519CTX_NAME: &[u8; 11] = b"Ed25519ctx\0";
+const ED25519PH_NAME: &[u8; 10] = b"Ed25519ph\0";
+const ED448_NAME: &[u8; 6] = b"Ed448\0";
+const ED448PH_NAME: &[u8; 8] = b"Ed448ph\0";
+
+#[derive(Debug)]
+pub struct EDDSAPubTemplate {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl EDDSAPubTemplate {
+    pub fn new() -> EDDSAPubTemplate {
+        let mut data: EDDSAPubTemplate = EDDSAPubTemplate {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes.append(&mut data.init_common_key_attrs());
+        data.attributes
+            .append(&mut data.init_common_public_key_attrs());
+        data.attributes.push(attr_element!(CKA_EC_PARAMS; OAFlags::AlwaysRequired | OAFlags::Unchangeable; from_bytes; val Vec::new()));
+        data.attributes.push(attr_element!(CKA_EC_POINT; OAFlags::RequiredOnCreate | OAFlags::UnsettableOnGenerate | OAFlags::Unchangeable; from_bytes; val Vec::new()));
+        data
+    }
+}
+
+impl ObjectTemplate for EDDSAPubTemplate {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> KResult<Object> {
+        let mut obj = self.default_object_create(template, false)?;
+
+        let curve =
+            EccCurve::from_ec_params(obj.get_attr_as_bytes(CKA_EC_PARAMS)?)?;
+        if curve.key_type() != CKK_EC_EDWARDS {
+            return err_rv!(CKR_ATTRIBUTE_VALUE_INVALID);
+        }
+        let point =
+            ecc::ec_point_to_raw(curve, obj.get_attr_as_bytes(CKA_EC_POINT)?)?;
+        /* always store the point in the DER encoding */
+        obj.set_attr(from_bytes(CKA_EC_POINT, ecc::raw_to_ec_point(&point)))?;
+
+        Ok(obj)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl CommonKeyTemplate for EDDSAPubTemplate {
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl PubKeyTemplate for EDDSAPubTemplate {
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+#[derive(Debug)]
+pub struct EDDSAPrivTemplate {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl EDDSAPrivTemplate {
+    pub fn new() -> EDDSAPrivTemplate {
+        let mut data: EDDSAPrivTemplate = EDDSAPrivTemplate {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes.append(&mut data.init_common_key_attrs());
+        data.attributes
+            .append(&mut data.init_common_private_key_attrs());
+        data.attributes.push(attr_element!(CKA_EC_PARAMS; OAFlags::RequiredOnCreate | OAFlags::Unchangeable; from_bytes; val Vec::new()));
+        data.attributes.push(attr_element!(CKA_VALUE; OAFlags::Sensitive | OAFlags::RequiredOnCreate | OAFlags::UnsettableOnGenerate | OAFlags::Unchangeable; from_bytes; val Vec::new()));
+
+        /* default to private */
+        let private = attr_element!(CKA_PRIVATE; OAFlags::Defval | OAFlags::ChangeOnCopy; from_bool; val true);
+        match data
+            .attributes
+            .iter()
+            .position(|x| x.get_type() == CKA_PRIVATE)
+        {
+            Some(idx) => data.attributes[idx] = private,
+            None => data.attributes.push(private),
+        }
+
+        data
+    }
+}
+
+impl ObjectTemplate for EDDSAPrivTemplate {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> KResult<Object> {
+        let obj = self.default_object_create(template, false)?;
+
+        let curve =
+            EccCurve::from_ec_params(obj.get_attr_as_bytes(CKA_EC_PARAMS)?)?;
+        if curve.key_type() != CKK_EC_EDWARDS {
+            return err_rv!(CKR_ATTRIBUTE_VALUE_INVALID);
+        }
+        bytes_attr_not_empty!(obj; CKA_VALUE);
+        if obj.get_attr_as_bytes(CKA_VALUE)?.len() != curve.key_len() {
+            return err_rv!(CKR_ATTRIBUTE_VALUE_INVALID);
+        }
+
+        Ok(obj)
+    }
+
+    fn unwrap(
+        &self,
+        template: &[CK_ATTRIBUTE],
+        data: &[u8],
+    ) -> KResult<Object> {
+        self.default_private_key_unwrap(template, data)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl CommonKeyTemplate for EDDSAPrivTemplate {
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl PrivKeyTemplate for EDDSAPrivTemplate {
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+static PUBLIC_KEY_TEMPLATE: Lazy<Box<dyn ObjectTemplate>> =
+    Lazy::new(|| Box::new(EDDSAPubTemplate::new()));
+
+static PRIVATE_KEY_TEMPLATE: Lazy<Box<dyn ObjectTemplate>> =
+    Lazy::new(|| Box::new(EDDSAPrivTemplate::new()));
+
+fn check_key_object(key: &Object, public: bool, op: CK_ULONG) -> KResult<()> {
+    match key.get_attr_as_ulong(CKA_CLASS)? {
+        CKO_PUBLIC_KEY => {
+            if !public {
+                return err_rv!(CKR_KEY_TYPE_INCONSISTENT);
+            }
+        }
+        CKO_PRIVATE_KEY => {
+            if public {
+                return err_rv!(CKR_KEY_TYPE_INCONSISTENT);
+            }
+        }
+        _ => return err_rv!(CKR_KEY_TYPE_INCONSISTENT),
+    }
+    match key.get_attr_as_ulong(CKA_KEY_TYPE)? {
+        CKK_EC_EDWARDS => (),
+        _ => return err_rv!(CKR_KEY_TYPE_INCONSISTENT),
+    }
+    match key.get_attr_as_bool(op) {
+        Ok(avail) => {
+            if !avail {
+                return err_rv!(CKR_KEY_FUNCTION_NOT_PERMITTED);
+            }
+        }
+        Err(_) => return err_rv!(CKR_KEY_FUNCTION_NOT_PERMITTED),
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+struct EddsaParams {
+    ph_flag: Option<bool>,
+    context_data: Vec<u8>,
+}
+
+fn parse_params(mech: &CK_MECHANISM, curve: EccCurve) -> KResult<EddsaParams> {
+    if mech.mechanism != CKM_EDDSA {
+        return err_rv!(CKR_MECHANISM_INVALID);
+    }
+    if mech.pParameter.is_null() {
+        if mech.ulParameterLen != 0 {
+            return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+        }
+        return Ok(EddsaParams {
+            ph_flag: None,
+            context_data: Vec::new(),
+        });
+    }
+    if mech.ulParameterLen as usize != ::std::mem::size_of::<CK_EDDSA_PARAMS>()
+    {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    let params = unsafe { &*(mech.pParameter as *const CK_EDDSA_PARAMS) };
+    let ctxlen = params.ulContextDataLen as usize;
+    if ctxlen > MAX_EDDSA_CONTEXT_LEN {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    let context_data = if ctxlen == 0 {
+        Vec::new()
+    } else {
+        if params.pContextData.is_null() {
+            return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+        }
+        unsafe {
+            std::slice::from_raw_parts(params.pContextData, ctxlen).to_vec()
+        }
+    };
+    let params = EddsaParams {
+        ph_flag: Some(params.phFlag != CK_FALSE),
+        context_data: context_data,
+    };
+    instance_name(curve, &params)?;
+    Ok(params)
+}
+
+fn instance_name(
+    curve: EccCurve,
+    params: &EddsaParams,
+) -> KResult<&'static [u8]> {
+    Ok(match curve {
+        EccCurve::Ed25519 => match params.ph_flag {
+            None => &ED25519_NAME[..],
+            Some(true) => &ED25519PH_NAME[..],
+            /* RFC 8032 forbids Ed25519ctx with an empty context */
+            Some(false) => {
+                if params.context_data.is_empty() {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+                &ED25519CTX_NAME[..]
+            }
+        },
+        EccCurve::Ed448 => match params.ph_flag {
+            Some(true) => &ED448PH_NAME[..],
+            _ => &ED448_NAME[..],
+        },
+        _ => return err_rv!(CKR_KEY_TYPE_INCONSISTENT),
+    })
+}
+
+#[derive(Debug)]
+struct EddsaMechanism {
+    info: CK_MECHANISM_INFO,
+}
+
+impl Mechanism for EddsaMechanism {
+    fn info(&self) -> &CK_MECHANISM_INFO {
+        &self.info
+    }
+
+    fn sign_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> KResult<Box<dyn Sign>> {
+        if self.info.flags & CKF_SIGN != CKF_SIGN {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        match check_key_object(key, false, CKA_SIGN) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        Ok(Box::new(EddsaOperation::sign_new(mech, key)?))
+    }
+
+    fn verify_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> KResult<Box<dyn Verify>> {
+        if self.info.flags & CKF_VERIFY != CKF_VERIFY {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        match check_key_object(key, true, CKA_VERIFY) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        Ok(Box::new(EddsaOperation::verify_new(mech, key)?))
+    }
+
+    fn generate_keypair(
+        &self,
+        mech: &CK_MECHANISM,
+        pubkey_template: &[CK_ATTRIBUTE],
+        prikey_template: &[CK_ATTRIBUTE],
+    ) -> KResult<(Object, Object)> {
+        if mech.mechanism != CKM_EC_EDWARDS_KEY_PAIR_GEN {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        let mut pubkey =
+            PUBLIC_KEY_TEMPLATE.default_object_create(pubkey_template, true)?;
+        if !pubkey.check_or_set_attr(attribute::from_ulong(
+            CKA_CLASS,
+            CKO_PUBLIC_KEY,
+        ))? {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        if !pubkey.check_or_set_attr(attribute::from_ulong(
+            CKA_KEY_TYPE,
+            CKK_EC_EDWARDS,
+        ))? {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        let ec_params = pubkey.get_attr_as_bytes(CKA_EC_PARAMS)?.clone();
+        let curve = EccCurve::from_ec_params(&ec_params)?;
+        if curve.key_type() != CKK_EC_EDWARDS {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+
+        let mut privkey = PRIVATE_KEY_TEMPLATE
+            .default_object_create(prikey_template, true)?;
+        if !privkey.check_or_set_attr(attribute::from_ulong(
+            CKA_CLASS,
+            CKO_PRIVATE_KEY,
+        ))? {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        if !privkey.check_or_set_attr(attribute::from_ulong(
+            CKA_KEY_TYPE,
+            CKK_EC_EDWARDS,
+        ))? {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        if !privkey.check_or_set_attr(from_bytes(CKA_EC_PARAMS, ec_params))? {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+
+        EddsaOperation::generate_keypair(curve, &mut pubkey, &mut privkey)?;
+
+        Ok((pubkey, privkey))
+    }
+}
+
+pub fn register(mechs: &mut Mechanisms, ot: &mut ObjectTemplates) {
+    mechs.add_mechanism(
+        CKM_EDDSA,
+        Box::new(EddsaMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: MIN_EDDSA_SIZE_BITS as CK_ULONG,
+                ulMaxKeySize: MAX_EDDSA_SIZE_BITS as CK_ULONG,
+                flags: CKF_SIGN
+                    | CKF_VERIFY
+                    | CKF_MESSAGE_SIGN
+                    | CKF_MESSAGE_VERIFY,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_EC_EDWARDS_KEY_PAIR_GEN,
+        Box::new(EddsaMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: MIN_EDDSA_SIZE_BITS as CK_ULONG,
+                ulMaxKeySize: MAX_EDDSA_SIZE_BITS as CK_ULONG,
+                flags: CKF_GENERATE_KEY_PAIR,
+            },
+        }),
+    );
+
+    ot.add_template(ObjectType::EDDSAPubKey, &PUBLIC_KEY_TEMPLATE);
+    ot.add_template(ObjectType::EDDSAPrivKey, &PRIVATE_KEY_TEMPLATE);
+}
+
+#[cfg(feature = "fips")]
+include!("ossl/eddsa.rs");
+
+#[cfg(not(feature = "fips"))]
+include!("ossl/eddsa.rs");
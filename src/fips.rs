@@ -808,6 +808,49 @@ impl ProviderSignatureCtx {
             }
         }
     }
+
+    pub fn digest_sign(
+        &mut self,
+        signature: &mut [u8],
+        tbs: &[u8],
+    ) -> KResult<()> {
+        unsafe {
+            match (*self.vtable).digest_sign {
+                Some(f) => {
+                    let mut siglen = signature.len();
+                    let siglen_ptr: *mut usize = &mut siglen;
+                    res_to_err!(f(
+                        self.ctx,
+                        signature.as_mut_ptr() as *mut c_uchar,
+                        siglen_ptr,
+                        signature.len(),
+                        tbs.as_ptr() as *const c_uchar,
+                        tbs.len()
+                    ))
+                }
+                None => err_rv!(CKR_DEVICE_ERROR),
+            }
+        }
+    }
+
+    pub fn digest_verify(
+        &mut self,
+        signature: &[u8],
+        tbs: &[u8],
+    ) -> KResult<()> {
+        unsafe {
+            match (*self.vtable).digest_verify {
+                Some(f) => res_to_err!(f(
+                    self.ctx,
+                    signature.as_ptr() as *const c_uchar,
+                    signature.len(),
+                    tbs.as_ptr() as *const c_uchar,
+                    tbs.len()
+                )),
+                None => err_rv!(CKR_DEVICE_ERROR),
+            }
+        }
+    }
 }
 
 unsafe impl Send for ProviderSignatureCtx {}
@@ -0,0 +1,282 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use super::attribute;
+use super::err_rv;
+use super::error;
+use super::hash;
+use super::hmac;
+use super::interface;
+use super::mechanism;
+use super::object;
+
+use attribute::from_bytes;
+use error::{KError, KResult};
+use interface::*;
+use mechanism::*;
+use object::{GenericSecretKeyMechanism, Object, ObjectTemplates};
+
+use std::fmt::Debug;
+use zeroize::Zeroize;
+
+/* HKDF takes generic secrets as input key material or salt key */
+fn check_key_type(key: &Object) -> KResult<()> {
+    if key.get_attr_as_ulong(CKA_CLASS)? != CKO_SECRET_KEY {
+        return err_rv!(CKR_KEY_TYPE_INCONSISTENT);
+    }
+    match key.get_attr_as_ulong(CKA_KEY_TYPE)? {
+        CKK_GENERIC_SECRET | CKK_HKDF => Ok(()),
+        _ => err_rv!(CKR_KEY_TYPE_INCONSISTENT),
+    }
+}
+
+fn check_key_object(key: &Object) -> KResult<()> {
+    check_key_type(key)?;
+    match key.get_attr_as_bool(CKA_DERIVE) {
+        Ok(avail) => {
+            if !avail {
+                return err_rv!(CKR_KEY_FUNCTION_NOT_PERMITTED);
+            }
+        }
+        Err(_) => return err_rv!(CKR_KEY_FUNCTION_NOT_PERMITTED),
+    }
+    Ok(())
+}
+
+fn hkdf_params(mech: &CK_MECHANISM) -> KResult<&CK_HKDF_PARAMS> {
+    if mech.pParameter.is_null()
+        || mech.ulParameterLen as usize
+            != ::std::mem::size_of::<CK_HKDF_PARAMS>()
+    {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    let params = unsafe { &*(mech.pParameter as *const CK_HKDF_PARAMS) };
+    if params.bExtract == CK_FALSE && params.bExpand == CK_FALSE {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    hmac::hash_mech_to_hmac_mech(params.prfHashMechanism)?;
+    /* the salt is only used in the extract step */
+    if params.bExtract != CK_FALSE {
+        match params.ulSaltType {
+            CKF_HKDF_SALT_NULL | CKF_HKDF_SALT_KEY => (),
+            CKF_HKDF_SALT_DATA => {
+                if params.pSalt.is_null() || params.ulSaltLen == 0 {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+            }
+            _ => return err_rv!(CKR_MECHANISM_PARAM_INVALID),
+        }
+    }
+    if params.pInfo.is_null() && params.ulInfoLen != 0 {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    Ok(params)
+}
+
+/* RFC 5869, 2.3 */
+fn hkdf_expand(
+    prf: CK_MECHANISM_TYPE,
+    prk: &[u8],
+    info: &[u8],
+    len: usize,
+) -> KResult<Vec<u8>> {
+    let mut okm: Vec<u8> = Vec::new();
+    let mut t: Vec<u8> = Vec::new();
+    let mut counter = 0u8;
+    while okm.len() < len {
+        counter += 1;
+        let next = hmac::hmac(prf, prk, &[t.as_slice(), info, &[counter]])?;
+        t.zeroize();
+        t = next;
+        okm.extend_from_slice(t.as_slice());
+    }
+    t.zeroize();
+    okm[len..].zeroize();
+    okm.truncate(len);
+    Ok(okm)
+}
+
+#[derive(Debug)]
+struct HKDFMechanism {
+    info: CK_MECHANISM_INFO,
+}
+
+impl HKDFMechanism {
+    /* CKM_HKDF_DATA outputs a data object, which has no CKA_VALUE_LEN
+     * attribute, so the requested length is taken out of the template */
+    fn data_object(
+        template: &[CK_ATTRIBUTE],
+        objtemplates: &ObjectTemplates,
+    ) -> KResult<(Object, Option<usize>)> {
+        let mut tmpl: Vec<CK_ATTRIBUTE> = Vec::with_capacity(template.len());
+        let mut len: Option<usize> = None;
+        for attr in template {
+            match attr.type_ {
+                CKA_CLASS => {
+                    if attr.to_ulong()? != CKO_DATA {
+                        return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+                    }
+                    tmpl.push(*attr);
+                }
+                CKA_VALUE_LEN => len = Some(attr.to_ulong()? as usize),
+                CKA_VALUE => return err_rv!(CKR_TEMPLATE_INCONSISTENT),
+                _ => tmpl.push(*attr),
+            }
+        }
+        Ok((objtemplates.create(tmpl.as_slice())?, len))
+    }
+}
+
+impl Mechanism for HKDFMechanism {
+    fn info(&self) -> &CK_MECHANISM_INFO {
+        &self.info
+    }
+
+    fn derive_key_objects(
+        &self,
+        mech: &CK_MECHANISM,
+    ) -> KResult<Vec<CK_OBJECT_HANDLE>> {
+        let params = hkdf_params(mech)?;
+        if params.bExtract != CK_FALSE && params.ulSaltType == CKF_HKDF_SALT_KEY
+        {
+            return Ok(vec![params.hSaltKey]);
+        }
+        Ok(Vec::new())
+    }
+
+    fn derive_key(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+        objects: &[&Object],
+        template: &[CK_ATTRIBUTE],
+        objtemplates: &ObjectTemplates,
+    ) -> KResult<Object> {
+        if self.info.flags & CKF_DERIVE != CKF_DERIVE {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        match check_key_object(key) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        let params = hkdf_params(mech)?;
+        let extract = params.bExtract != CK_FALSE;
+        let expand = params.bExpand != CK_FALSE;
+        let prf = params.prfHashMechanism;
+        let hashlen = hash::HashOperation::new(prf)?.hashlen();
+
+        let (mut obj, len) = match mech.mechanism {
+            CKM_HKDF_DERIVE => {
+                let mut obj =
+                    objtemplates.derive_key_from_template(key, template)?;
+                let len = match obj.get_attr_as_ulong(CKA_VALUE_LEN) {
+                    Ok(n) => Some(n as usize),
+                    Err(e) => match e {
+                        KError::NotFound(_) => None,
+                        _ => return Err(e),
+                    },
+                };
+                obj.del_attr(CKA_VALUE_LEN);
+                (obj, len)
+            }
+            CKM_HKDF_DATA => Self::data_object(template, objtemplates)?,
+            _ => return err_rv!(CKR_MECHANISM_INVALID),
+        };
+        let keylen = match len {
+            Some(n) => n,
+            None => {
+                if expand {
+                    return err_rv!(CKR_TEMPLATE_INCOMPLETE);
+                }
+                hashlen
+            }
+        };
+        /* the extract step alone always returns a full PRK, and expand
+         * can not output more than 255 blocks */
+        if keylen == 0
+            || (!expand && keylen != hashlen)
+            || keylen > 255 * hashlen
+        {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+
+        let ikm = key.get_attr_as_bytes(CKA_VALUE)?;
+        let mut prk = if extract {
+            let mut salt = match params.ulSaltType {
+                CKF_HKDF_SALT_NULL => vec![0u8; hashlen],
+                CKF_HKDF_SALT_DATA => unsafe {
+                    std::slice::from_raw_parts(
+                        params.pSalt,
+                        params.ulSaltLen as usize,
+                    )
+                }
+                .to_vec(),
+                CKF_HKDF_SALT_KEY => {
+                    if objects.len() != 1 {
+                        return err_rv!(CKR_GENERAL_ERROR);
+                    }
+                    if check_key_type(objects[0]).is_err() {
+                        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                    }
+                    objects[0].get_attr_as_bytes(CKA_VALUE)?.clone()
+                }
+                _ => return err_rv!(CKR_MECHANISM_PARAM_INVALID),
+            };
+            let prk = hmac::hmac(prf, salt.as_slice(), &[ikm.as_slice()]);
+            salt.zeroize();
+            prk?
+        } else {
+            ikm.clone()
+        };
+
+        let okm = if expand {
+            let info: &[u8] = if params.pInfo.is_null() {
+                &[]
+            } else {
+                unsafe {
+                    std::slice::from_raw_parts(
+                        params.pInfo,
+                        params.ulInfoLen as usize,
+                    )
+                }
+            };
+            let okm = hkdf_expand(prf, prk.as_slice(), info, keylen);
+            prk.zeroize();
+            okm?
+        } else {
+            prk
+        };
+        obj.set_attr(from_bytes(CKA_VALUE, okm))?;
+
+        Ok(obj)
+    }
+}
+
+pub fn register(mechs: &mut Mechanisms, _: &mut ObjectTemplates) {
+    mechs.add_mechanism(
+        CKM_HKDF_DERIVE,
+        Box::new(HKDFMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_DERIVE,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_HKDF_DATA,
+        Box::new(HKDFMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_DERIVE,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_HKDF_KEY_GEN,
+        Box::new(GenericSecretKeyMechanism::new(CKK_HKDF)),
+    );
+}
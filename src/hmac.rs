@@ -59,26 +59,76 @@ struct HMACMechanism {
     maxlen: usize,
 }
 
+/* HMAC mechanisms and the hash they are based on */
+const HMAC_HASH_MECHS: [(
+    CK_MECHANISM_TYPE,
+    CK_MECHANISM_TYPE,
+    CK_MECHANISM_TYPE,
+); 9] = [
+    (CKM_SHA_1_HMAC, CKM_SHA_1_HMAC_GENERAL, CKM_SHA_1),
+    (CKM_SHA224_HMAC, CKM_SHA224_HMAC_GENERAL, CKM_SHA224),
+    (CKM_SHA256_HMAC, CKM_SHA256_HMAC_GENERAL, CKM_SHA256),
+    (CKM_SHA384_HMAC, CKM_SHA384_HMAC_GENERAL, CKM_SHA384),
+    (CKM_SHA512_HMAC, CKM_SHA512_HMAC_GENERAL, CKM_SHA512),
+    (CKM_SHA3_224_HMAC, CKM_SHA3_224_HMAC_GENERAL, CKM_SHA3_224),
+    (CKM_SHA3_256_HMAC, CKM_SHA3_256_HMAC_GENERAL, CKM_SHA3_256),
+    (CKM_SHA3_384_HMAC, CKM_SHA3_384_HMAC_GENERAL, CKM_SHA3_384),
+    (CKM_SHA3_512_HMAC, CKM_SHA3_512_HMAC_GENERAL, CKM_SHA3_512),
+];
+
 impl HMACMechanism {
     fn hmac_mech_to_hash_mech(
         &self,
         hmac: CK_MECHANISM_TYPE,
     ) -> KResult<CK_MECHANISM_TYPE> {
-        Ok(match hmac {
-            CKM_SHA_1_HMAC | CKM_SHA_1_HMAC_GENERAL => CKM_SHA_1,
-            CKM_SHA224_HMAC | CKM_SHA224_HMAC_GENERAL => CKM_SHA224,
-            CKM_SHA256_HMAC | CKM_SHA256_HMAC_GENERAL => CKM_SHA256,
-            CKM_SHA384_HMAC | CKM_SHA384_HMAC_GENERAL => CKM_SHA384,
-            CKM_SHA512_HMAC | CKM_SHA512_HMAC_GENERAL => CKM_SHA512,
-            CKM_SHA3_224_HMAC | CKM_SHA3_224_HMAC_GENERAL => CKM_SHA3_224,
-            CKM_SHA3_256_HMAC | CKM_SHA3_256_HMAC_GENERAL => CKM_SHA3_256,
-            CKM_SHA3_384_HMAC | CKM_SHA3_384_HMAC_GENERAL => CKM_SHA3_384,
-            CKM_SHA3_512_HMAC | CKM_SHA3_512_HMAC_GENERAL => CKM_SHA3_512,
-            _ => return err_rv!(CKR_MECHANISM_INVALID),
-        })
+        match HMAC_HASH_MECHS.iter().find(|m| m.0 == hmac || m.1 == hmac) {
+            Some(m) => Ok(m.2),
+            None => err_rv!(CKR_MECHANISM_INVALID),
+        }
+    }
+}
+
+/* Maps a hash mechanism used as PRF by a KDF to the matching HMAC */
+pub fn hash_mech_to_hmac_mech(
+    hash: CK_MECHANISM_TYPE,
+) -> KResult<CK_MECHANISM_TYPE> {
+    match HMAC_HASH_MECHS.iter().find(|m| m.2 == hash) {
+        Some(m) => Ok(m.0),
+        None => err_rv!(CKR_MECHANISM_PARAM_INVALID),
     }
 }
 
+/* Maps a HMAC mechanism used as PRF by a KDF to the underlying hash */
+pub fn hmac_mech_to_hash_mech(
+    hmac: CK_MECHANISM_TYPE,
+) -> KResult<CK_MECHANISM_TYPE> {
+    match HMAC_HASH_MECHS.iter().find(|m| m.0 == hmac) {
+        Some(m) => Ok(m.2),
+        None => err_rv!(CKR_MECHANISM_PARAM_INVALID),
+    }
+}
+
+/* Computes HMAC(key, data[0] || data[1] || ...) with the full hash
+ * length as output, for use in key derivation functions */
+pub fn hmac(
+    hash: CK_MECHANISM_TYPE,
+    key: &[u8],
+    data: &[&[u8]],
+) -> KResult<Vec<u8>> {
+    let mut op = HMACOperation::init(
+        hash_mech_to_hmac_mech(hash)?,
+        hash,
+        key.to_vec(),
+        0,
+    )?;
+    for d in data {
+        op.update(d)?;
+    }
+    let mut output = vec![0u8; op.hashlen];
+    op.finalize(output.as_mut_slice())?;
+    Ok(output)
+}
+
 impl Mechanism for HMACMechanism {
     fn info(&self) -> &CK_MECHANISM_INFO {
         &self.info
@@ -196,7 +246,10 @@ pub fn register(mechs: &mut Mechanisms, _ot: &mut ObjectTemplates) {
                 info: CK_MECHANISM_INFO {
                     ulMinKeySize: 0,
                     ulMaxKeySize: 0,
-                    flags: CKF_SIGN | CKF_VERIFY,
+                    flags: CKF_SIGN
+                        | CKF_VERIFY
+                        | CKF_MESSAGE_SIGN
+                        | CKF_MESSAGE_VERIFY,
                 },
                 keytype: rs.1,
                 minlen: hashlen,
@@ -209,7 +262,10 @@ pub fn register(mechs: &mut Mechanisms, _ot: &mut ObjectTemplates) {
                 info: CK_MECHANISM_INFO {
                     ulMinKeySize: 0,
                     ulMaxKeySize: 0,
-                    flags: CKF_SIGN | CKF_VERIFY,
+                    flags: CKF_SIGN
+                        | CKF_VERIFY
+                        | CKF_MESSAGE_SIGN
+                        | CKF_MESSAGE_VERIFY,
                 },
                 keytype: rs.1,
                 minlen: 1,
@@ -350,6 +406,19 @@ impl MechOperation for HMACOperation {
     fn finalized(&self) -> bool {
         self.finalized
     }
+    fn reset(&mut self) -> KResult<()> {
+        /* restart from H((K0 ^ ipad) || .. ) */
+        match &mut self.inner {
+            Operation::Digest(op) => {
+                op.reset()?;
+                op.digest_update(self.ipad.as_slice())?;
+            }
+            _ => return err_rv!(CKR_GENERAL_ERROR),
+        }
+        self.finalized = false;
+        self.in_use = false;
+        Ok(())
+    }
 }
 
 impl Sign for HMACOperation {
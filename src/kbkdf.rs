@@ -0,0 +1,508 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use super::aes;
+use super::attribute;
+use super::err_rv;
+use super::error;
+use super::hash;
+use super::hmac;
+use super::interface;
+use super::mechanism;
+use super::object;
+
+use attribute::from_bytes;
+use error::{KError, KResult};
+use interface::*;
+use mechanism::*;
+use object::{Object, ObjectTemplates};
+
+use std::fmt::Debug;
+use zeroize::Zeroize;
+
+fn check_key_object(key: &Object, prf: CK_MECHANISM_TYPE) -> KResult<()> {
+    if key.get_attr_as_ulong(CKA_CLASS)? != CKO_SECRET_KEY {
+        return err_rv!(CKR_KEY_TYPE_INCONSISTENT);
+    }
+    let ktype = match prf {
+        CKM_AES_CMAC => CKK_AES,
+        _ => CKK_GENERIC_SECRET,
+    };
+    if key.get_attr_as_ulong(CKA_KEY_TYPE)? != ktype {
+        return err_rv!(CKR_KEY_TYPE_INCONSISTENT);
+    }
+    match key.get_attr_as_bool(CKA_DERIVE) {
+        Ok(avail) => {
+            if !avail {
+                return err_rv!(CKR_KEY_FUNCTION_NOT_PERMITTED);
+            }
+        }
+        Err(_) => return err_rv!(CKR_KEY_FUNCTION_NOT_PERMITTED),
+    }
+    Ok(())
+}
+
+fn param_slice<'a, T>(ptr: *const T, len: CK_ULONG) -> KResult<&'a [T]> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+    if ptr.is_null() {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    Ok(unsafe { std::slice::from_raw_parts(ptr, len as usize) })
+}
+
+/* Integer encoding as (little endian, width in bytes) */
+type Encoding = (bool, usize);
+
+fn encode(value: u64, enc: Encoding) -> Vec<u8> {
+    if enc.0 {
+        value.to_le_bytes()[..enc.1].to_vec()
+    } else {
+        value.to_be_bytes()[(8 - enc.1)..].to_vec()
+    }
+}
+
+fn fits(value: u64, enc: Encoding) -> bool {
+    enc.1 == 8 || value < (1u64 << (enc.1 * 8))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum DataParam<'a> {
+    /* the encoding is only used in counter mode, in the other modes the
+     * iteration variable is the output of the previous iteration */
+    Iteration(Option<Encoding>),
+    Counter(Encoding),
+    DkmLength(CK_SP800_108_DKM_LENGTH_METHOD, Encoding),
+    Bytes(&'a [u8]),
+}
+
+fn counter_format(param: &CK_PRF_DATA_PARAM) -> KResult<Encoding> {
+    if param.pValue.is_null()
+        || param.ulValueLen as usize
+            != ::std::mem::size_of::<CK_SP800_108_COUNTER_FORMAT>()
+    {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    let format =
+        unsafe { &*(param.pValue as *const CK_SP800_108_COUNTER_FORMAT) };
+    match format.ulWidthInBits {
+        8 | 16 | 24 | 32 => Ok((
+            format.bLittleEndian != CK_FALSE,
+            format.ulWidthInBits as usize / 8,
+        )),
+        _ => err_rv!(CKR_MECHANISM_PARAM_INVALID),
+    }
+}
+
+fn dkm_length_format(
+    param: &CK_PRF_DATA_PARAM,
+) -> KResult<(CK_SP800_108_DKM_LENGTH_METHOD, Encoding)> {
+    if param.pValue.is_null()
+        || param.ulValueLen as usize
+            != ::std::mem::size_of::<CK_SP800_108_DKM_LENGTH_FORMAT>()
+    {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    let format =
+        unsafe { &*(param.pValue as *const CK_SP800_108_DKM_LENGTH_FORMAT) };
+    match format.dkmLengthMethod {
+        CK_SP800_108_DKM_LENGTH_SUM_OF_KEYS
+        | CK_SP800_108_DKM_LENGTH_SUM_OF_SEGMENTS => (),
+        _ => return err_rv!(CKR_MECHANISM_PARAM_INVALID),
+    }
+    match format.ulWidthInBits {
+        8 | 16 | 24 | 32 | 40 | 48 | 56 | 64 => Ok((
+            format.dkmLengthMethod,
+            (
+                format.bLittleEndian != CK_FALSE,
+                format.ulWidthInBits as usize / 8,
+            ),
+        )),
+        _ => err_rv!(CKR_MECHANISM_PARAM_INVALID),
+    }
+}
+
+#[derive(Debug)]
+struct KdfParams<'a> {
+    prf: CK_MECHANISM_TYPE,
+    data: Vec<DataParam<'a>>,
+    iv: &'a [u8],
+    keys: &'a [CK_DERIVED_KEY],
+}
+
+fn kdf_params(mech: &CK_MECHANISM) -> KResult<KdfParams> {
+    if mech.pParameter.is_null() {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    let (prf, data_params, iv, keys) = match mech.mechanism {
+        CKM_SP800_108_COUNTER_KDF | CKM_SP800_108_DOUBLE_PIPELINE_KDF => {
+            if mech.ulParameterLen as usize
+                != ::std::mem::size_of::<CK_SP800_108_KDF_PARAMS>()
+            {
+                return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+            }
+            let params = unsafe {
+                &*(mech.pParameter as *const CK_SP800_108_KDF_PARAMS)
+            };
+            (
+                params.prfType,
+                param_slice(params.pDataParams, params.ulNumberOfDataParams)?,
+                &[] as &[u8],
+                param_slice(
+                    params.pAdditionalDerivedKeys,
+                    params.ulAdditionalDerivedKeys,
+                )?,
+            )
+        }
+        CKM_SP800_108_FEEDBACK_KDF => {
+            if mech.ulParameterLen as usize
+                != ::std::mem::size_of::<CK_SP800_108_FEEDBACK_KDF_PARAMS>()
+            {
+                return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+            }
+            let params = unsafe {
+                &*(mech.pParameter as *const CK_SP800_108_FEEDBACK_KDF_PARAMS)
+            };
+            (
+                params.prfType,
+                param_slice(params.pDataParams, params.ulNumberOfDataParams)?,
+                param_slice(params.pIV, params.ulIVLen)?,
+                param_slice(
+                    params.pAdditionalDerivedKeys,
+                    params.ulAdditionalDerivedKeys,
+                )?,
+            )
+        }
+        _ => return err_rv!(CKR_MECHANISM_INVALID),
+    };
+    if prf != CKM_AES_CMAC {
+        hmac::hmac_mech_to_hash_mech(prf)?;
+    }
+    let counter_mode = mech.mechanism == CKM_SP800_108_COUNTER_KDF;
+
+    let mut data: Vec<DataParam> = Vec::with_capacity(data_params.len());
+    let mut iteration = false;
+    let mut counter = false;
+    let mut dkm = false;
+    for param in data_params {
+        match param.type_ {
+            CK_SP800_108_ITERATION_VARIABLE => {
+                if iteration {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+                iteration = true;
+                if counter_mode {
+                    data.push(DataParam::Iteration(Some(counter_format(
+                        param,
+                    )?)));
+                } else {
+                    if !param.pValue.is_null() || param.ulValueLen != 0 {
+                        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                    }
+                    data.push(DataParam::Iteration(None));
+                }
+            }
+            CK_SP800_108_OPTIONAL_COUNTER => {
+                /* in counter mode the iteration variable is the counter */
+                if counter_mode || counter {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+                counter = true;
+                data.push(DataParam::Counter(counter_format(param)?));
+            }
+            CK_SP800_108_DKM_LENGTH => {
+                if dkm {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+                dkm = true;
+                let (method, enc) = dkm_length_format(param)?;
+                data.push(DataParam::DkmLength(method, enc));
+            }
+            CK_SP800_108_BYTE_ARRAY => {
+                data.push(DataParam::Bytes(param_slice(
+                    param.pValue as *const u8,
+                    param.ulValueLen,
+                )?));
+            }
+            _ => return err_rv!(CKR_MECHANISM_PARAM_INVALID),
+        }
+    }
+    if !iteration {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    for key in keys {
+        if key.phKey.is_null() {
+            return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+        }
+    }
+
+    Ok(KdfParams {
+        prf: prf,
+        data: data,
+        iv: iv,
+        keys: keys,
+    })
+}
+
+/* Creates a key object from the template and returns it along with the
+ * requested length, which must always be specified */
+fn derived_key(
+    key: &Object,
+    template: &[CK_ATTRIBUTE],
+    objtemplates: &ObjectTemplates,
+) -> KResult<(Object, usize)> {
+    let mut obj = objtemplates.derive_key_from_template(key, template)?;
+    let keylen = match obj.get_attr_as_ulong(CKA_VALUE_LEN) {
+        Ok(n) => n as usize,
+        Err(e) => match e {
+            KError::NotFound(_) => return err_rv!(CKR_TEMPLATE_INCOMPLETE),
+            _ => return Err(e),
+        },
+    };
+    if keylen == 0 {
+        return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+    }
+    if obj.get_attr_as_ulong(CKA_KEY_TYPE)? == CKK_AES {
+        match keylen {
+            16 | 24 | 32 => (),
+            _ => return err_rv!(CKR_TEMPLATE_INCONSISTENT),
+        }
+    }
+    obj.del_attr(CKA_VALUE_LEN);
+    Ok((obj, keylen))
+}
+
+#[derive(Debug)]
+struct KBKDFMechanism {
+    info: CK_MECHANISM_INFO,
+}
+
+impl KBKDFMechanism {
+    fn prf(
+        prf: CK_MECHANISM_TYPE,
+        key: &Object,
+        data: &[&[u8]],
+    ) -> KResult<Vec<u8>> {
+        match prf {
+            CKM_AES_CMAC => aes::cmac(key, data),
+            _ => hmac::hmac(
+                hmac::hmac_mech_to_hash_mech(prf)?,
+                key.get_attr_as_bytes(CKA_VALUE)?.as_slice(),
+                data,
+            ),
+        }
+    }
+
+    /* SP 800-108r1, Section 4 */
+    fn keystream(
+        mechanism: CK_MECHANISM_TYPE,
+        params: &KdfParams,
+        key: &Object,
+        len: usize,
+    ) -> KResult<Vec<u8>> {
+        let seglen = match params.prf {
+            CKM_AES_CMAC => 16,
+            _ => hash::HashOperation::new(hmac::hmac_mech_to_hash_mech(
+                params.prf,
+            )?)?
+            .hashlen(),
+        };
+        let segments = len.div_ceil(seglen);
+        let dkmlen = (len * 8) as u64;
+
+        let mut fixed: Vec<Vec<u8>> = Vec::with_capacity(params.data.len());
+        for param in &params.data {
+            match param {
+                DataParam::Iteration(Some(enc)) | DataParam::Counter(enc) => {
+                    if !fits(segments as u64, *enc) {
+                        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                    }
+                    fixed.push(Vec::new());
+                }
+                DataParam::Iteration(None) => fixed.push(Vec::new()),
+                DataParam::DkmLength(method, enc) => {
+                    let l = match *method {
+                        CK_SP800_108_DKM_LENGTH_SUM_OF_SEGMENTS => {
+                            (segments * seglen * 8) as u64
+                        }
+                        _ => dkmlen,
+                    };
+                    if !fits(l, *enc) {
+                        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                    }
+                    fixed.push(encode(l, *enc));
+                }
+                DataParam::Bytes(b) => fixed.push(b.to_vec()),
+            }
+        }
+
+        /* in double pipeline mode A(0) is the fixed input data, that is
+         * everything except the iteration variable and the counter */
+        let mut chain: Vec<u8> = match mechanism {
+            CKM_SP800_108_FEEDBACK_KDF => params.iv.to_vec(),
+            CKM_SP800_108_DOUBLE_PIPELINE_KDF => {
+                let mut a0: Vec<&[u8]> = Vec::new();
+                for (param, value) in params.data.iter().zip(fixed.iter()) {
+                    match param {
+                        DataParam::Iteration(_) | DataParam::Counter(_) => (),
+                        _ => a0.push(value.as_slice()),
+                    }
+                }
+                Self::prf(params.prf, key, a0.as_slice())?
+            }
+            _ => Vec::new(),
+        };
+
+        let mut dkm: Vec<u8> = Vec::with_capacity(segments * seglen);
+        for i in 1..=segments {
+            if mechanism == CKM_SP800_108_DOUBLE_PIPELINE_KDF && i > 1 {
+                let next = Self::prf(params.prf, key, &[chain.as_slice()])?;
+                chain.zeroize();
+                chain = next;
+            }
+            let mut counters: Vec<Vec<u8>> = Vec::new();
+            for param in &params.data {
+                match param {
+                    DataParam::Iteration(Some(enc))
+                    | DataParam::Counter(enc) => {
+                        counters.push(encode(i as u64, *enc))
+                    }
+                    _ => counters.push(Vec::new()),
+                }
+            }
+            let mut input: Vec<&[u8]> = Vec::with_capacity(params.data.len());
+            for (idx, param) in params.data.iter().enumerate() {
+                match param {
+                    DataParam::Iteration(None) => input.push(chain.as_slice()),
+                    DataParam::Iteration(Some(_)) | DataParam::Counter(_) => {
+                        input.push(counters[idx].as_slice())
+                    }
+                    _ => input.push(fixed[idx].as_slice()),
+                }
+            }
+            let mut segment = match Self::prf(params.prf, key, input.as_slice())
+            {
+                Ok(s) => s,
+                Err(e) => {
+                    chain.zeroize();
+                    dkm.zeroize();
+                    return Err(e);
+                }
+            };
+            dkm.extend_from_slice(segment.as_slice());
+            if mechanism == CKM_SP800_108_FEEDBACK_KDF {
+                chain.zeroize();
+                chain = segment;
+            } else {
+                segment.zeroize();
+            }
+        }
+        chain.zeroize();
+        dkm[len..].zeroize();
+        dkm.truncate(len);
+        Ok(dkm)
+    }
+}
+
+impl Mechanism for KBKDFMechanism {
+    fn info(&self) -> &CK_MECHANISM_INFO {
+        &self.info
+    }
+
+    fn derive_keys(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+        _: &[&Object],
+        template: &[CK_ATTRIBUTE],
+        objtemplates: &ObjectTemplates,
+    ) -> KResult<Vec<Object>> {
+        if self.info.flags & CKF_DERIVE != CKF_DERIVE {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        let params = kdf_params(mech)?;
+        match check_key_object(key, params.prf) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+
+        let mut keys: Vec<(Object, usize)> =
+            Vec::with_capacity(params.keys.len() + 1);
+        keys.push(derived_key(key, template, objtemplates)?);
+        for dk in params.keys {
+            let tmpl = param_slice(dk.pTemplate, dk.ulAttributeCount)?;
+            keys.push(derived_key(key, tmpl, objtemplates)?);
+        }
+
+        /* all keys are cut in order from the same key stream */
+        let len: usize = keys.iter().map(|k| k.1).sum();
+        let mut dkm = Self::keystream(mech.mechanism, &params, key, len)?;
+        let mut objs: Vec<Object> = Vec::with_capacity(keys.len());
+        let mut offset = 0;
+        for (mut obj, keylen) in keys {
+            let value = dkm[offset..(offset + keylen)].to_vec();
+            offset += keylen;
+            if let Err(e) = obj.set_attr(from_bytes(CKA_VALUE, value)) {
+                dkm.zeroize();
+                return Err(e);
+            }
+            objs.push(obj);
+        }
+        dkm.zeroize();
+
+        Ok(objs)
+    }
+
+    fn derive_key_handles(
+        &self,
+        mech: &CK_MECHANISM,
+        handles: &[CK_OBJECT_HANDLE],
+    ) -> KResult<CK_OBJECT_HANDLE> {
+        let params = kdf_params(mech)?;
+        if handles.len() != params.keys.len() + 1 {
+            return err_rv!(CKR_GENERAL_ERROR);
+        }
+        for (dk, handle) in params.keys.iter().zip(handles[1..].iter()) {
+            unsafe {
+                core::ptr::write(dk.phKey, *handle);
+            }
+        }
+        Ok(handles[0])
+    }
+}
+
+pub fn register(mechs: &mut Mechanisms, _: &mut ObjectTemplates) {
+    mechs.add_mechanism(
+        CKM_SP800_108_COUNTER_KDF,
+        Box::new(KBKDFMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_DERIVE,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_SP800_108_FEEDBACK_KDF,
+        Box::new(KBKDFMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_DERIVE,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_SP800_108_DOUBLE_PIPELINE_KDF,
+        Box::new(KBKDFMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_DERIVE,
+            },
+        }),
+    );
+}
@@ -28,7 +28,7 @@ mod token;
 
 use error::{KError, KResult};
 use interface::*;
-use mechanism::Operation;
+use mechanism::{MsgSign, MsgVerify, Operation, Sign, Verify};
 use rng::RNG;
 use session::Session;
 use slot::Slot;
@@ -42,10 +42,27 @@ mod fips;
 mod ossl;
 
 mod aes;
+#[cfg(not(feature = "fips"))]
+mod chacha20;
+mod der;
 mod drbg;
+mod ecc;
+mod eddsa;
 mod hash;
+mod hkdf;
 mod hmac;
+mod kbkdf;
+mod mldsa;
+mod mlkem;
+mod montgomery;
+mod pbkdf2;
+mod pkcs8;
 mod rsa;
+mod shamir;
+mod simplekdf;
+mod slhdsa;
+mod sshkdf;
+mod tls;
 
 macro_rules! err_to_rv {
     ($err:expr) => {
@@ -74,6 +91,16 @@ macro_rules! res_or_ret {
     };
 }
 
+macro_rules! bytes_to_slice {
+    ($ptr:expr, $len:expr) => {
+        if $len > 0 {
+            unsafe { std::slice::from_raw_parts($ptr, $len as usize) }
+        } else {
+            &[]
+        }
+    };
+}
+
 thread_local!(static CSPRNG: RefCell<RNG> = RefCell::new(RNG::new("HMAC DRBG SHA256").unwrap()));
 
 struct State {
@@ -845,7 +872,8 @@ extern "C" fn fn_encrypt(
         return CKR_OPERATION_NOT_INITIALIZED;
     }
     if encrypted_data.is_null() {
-        let encryption_len = res_or_ret!(operation.encryption_len());
+        let encryption_len =
+            res_or_ret!(operation.encryption_len(data_len as usize, true));
         unsafe {
             *pul_encrypted_data_len = encryption_len as CK_ULONG;
         }
@@ -875,7 +903,8 @@ extern "C" fn fn_encrypt_update(
         return CKR_OPERATION_NOT_INITIALIZED;
     }
     if encrypted_part.is_null() {
-        let encryption_len = res_or_ret!(operation.encryption_len());
+        let encryption_len =
+            res_or_ret!(operation.encryption_len(part_len as usize, false));
         unsafe {
             *pul_encrypted_part_len = encryption_len as CK_ULONG;
         }
@@ -907,7 +936,7 @@ extern "C" fn fn_encrypt_final(
         return CKR_OPERATION_NOT_INITIALIZED;
     }
     if last_encrypted_part.is_null() {
-        let encryption_len = res_or_ret!(operation.encryption_len());
+        let encryption_len = res_or_ret!(operation.encryption_len(0, true));
         unsafe {
             *pul_last_encrypted_part_len = encryption_len as CK_ULONG;
         }
@@ -957,7 +986,9 @@ extern "C" fn fn_decrypt(
         return CKR_OPERATION_NOT_INITIALIZED;
     }
     if data.is_null() {
-        let decryption_len = res_or_ret!(operation.decryption_len());
+        let decryption_len = res_or_ret!(
+            operation.decryption_len(encrypted_data_len as usize, true)
+        );
         unsafe {
             *pul_data_len = decryption_len as CK_ULONG;
         }
@@ -988,7 +1019,9 @@ extern "C" fn fn_decrypt_update(
         return CKR_OPERATION_NOT_INITIALIZED;
     }
     if part.is_null() {
-        let decryption_len = res_or_ret!(operation.decryption_len());
+        let decryption_len = res_or_ret!(
+            operation.decryption_len(encrypted_part_len as usize, false)
+        );
         unsafe {
             *pul_part_len = decryption_len as CK_ULONG;
         }
@@ -1017,7 +1050,7 @@ extern "C" fn fn_decrypt_final(
         return CKR_OPERATION_NOT_INITIALIZED;
     }
     if last_part.is_null() {
-        let decryption_len = res_or_ret!(operation.decryption_len());
+        let decryption_len = res_or_ret!(operation.decryption_len(0, true));
         unsafe {
             *pul_last_part_len = decryption_len as CK_ULONG;
         }
@@ -1487,7 +1520,7 @@ extern "C" fn fn_generate_key(
         return CKR_MECHANISM_INVALID;
     }
 
-    let result = mech.generate_key(data, tmpl);
+    let result = mech.generate_key(data, tmpl, token.get_object_templates());
     match result {
         Ok(obj) => {
             let kh = res_or_ret!(token.insert_object(s_handle, obj));
@@ -1562,36 +1595,217 @@ extern "C" fn fn_generate_key_pair(
 }
 
 extern "C" fn fn_wrap_key(
-    _session: CK_SESSION_HANDLE,
-    _mechanism: CK_MECHANISM_PTR,
-    _wrapping_key: CK_OBJECT_HANDLE,
-    _key: CK_OBJECT_HANDLE,
-    _wrapped_key: CK_BYTE_PTR,
-    _pul_wrapped_key_len: CK_ULONG_PTR,
+    s_handle: CK_SESSION_HANDLE,
+    mechanism: CK_MECHANISM_PTR,
+    wrapping_key_handle: CK_OBJECT_HANDLE,
+    key_handle: CK_OBJECT_HANDLE,
+    wrapped_key: CK_BYTE_PTR,
+    pul_wrapped_key_len: CK_ULONG_PTR,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    if mechanism.is_null() || pul_wrapped_key_len.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let rstate = global_rlock!(STATE);
+    let session = res_or_ret!(rstate.get_session(s_handle));
+    let token = res_or_ret!(rstate.get_token_from_slot(session.get_slot_id()));
+
+    let data: &CK_MECHANISM = unsafe { &*mechanism };
+    let mech = res_or_ret!(token.get_mech(data.mechanism));
+    if mech.info().flags & CKF_WRAP != CKF_WRAP {
+        return CKR_MECHANISM_INVALID;
+    }
+    let wrapping_key =
+        res_or_ret!(token.get_object_by_handle(wrapping_key_handle, true));
+    let key = res_or_ret!(token.get_object_by_handle(key_handle, true));
+
+    match wrapping_key.get_attr_as_bool(CKA_WRAP) {
+        Ok(true) => (),
+        _ => return CKR_KEY_FUNCTION_NOT_PERMITTED,
+    }
+    match key.get_attr_as_bool(CKA_EXTRACTABLE) {
+        Ok(true) => (),
+        _ => return CKR_KEY_UNEXTRACTABLE,
+    }
+    res_or_ret!(wrapping_key.check_wrap_policy(key));
+
+    let mut objects: Vec<&object::Object> = Vec::new();
+    for handle in res_or_ret!(mech.wrap_key_objects(data)) {
+        objects.push(res_or_ret!(token.get_object_by_handle(handle, true)));
+    }
+
+    ret_to_rv!(mech.wrap_key(
+        data,
+        wrapping_key,
+        key,
+        objects.as_slice(),
+        wrapped_key,
+        pul_wrapped_key_len
+    ))
 }
 extern "C" fn fn_unwrap_key(
-    _session: CK_SESSION_HANDLE,
-    _mechanism: CK_MECHANISM_PTR,
-    _unwrapping_key: CK_OBJECT_HANDLE,
-    _wrapped_key: CK_BYTE_PTR,
-    _wrapped_key_len: CK_ULONG,
-    _template: CK_ATTRIBUTE_PTR,
-    _attribute_count: CK_ULONG,
-    _ph_key: CK_OBJECT_HANDLE_PTR,
+    s_handle: CK_SESSION_HANDLE,
+    mechanism: CK_MECHANISM_PTR,
+    unwrapping_key_handle: CK_OBJECT_HANDLE,
+    wrapped_key: CK_BYTE_PTR,
+    wrapped_key_len: CK_ULONG,
+    template: CK_ATTRIBUTE_PTR,
+    attribute_count: CK_ULONG,
+    key_handle: CK_OBJECT_HANDLE_PTR,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    if mechanism.is_null() || wrapped_key.is_null() || key_handle.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let rstate = global_rlock!(STATE);
+    let session = res_or_ret!(rstate.get_session(s_handle));
+
+    let data: &CK_MECHANISM = unsafe { &*mechanism };
+    let tmpl: &[CK_ATTRIBUTE] = unsafe {
+        std::slice::from_raw_parts(template, attribute_count as usize)
+    };
+
+    let mut token =
+        res_or_ret!(rstate.get_token_from_slot_mut(session.get_slot_id()));
+
+    let mech = res_or_ret!(token.get_mech(data.mechanism));
+    if mech.info().flags & CKF_UNWRAP != CKF_UNWRAP {
+        return CKR_MECHANISM_INVALID;
+    }
+    let unwrapping_key =
+        res_or_ret!(token.get_object_by_handle(unwrapping_key_handle, true));
+    match unwrapping_key.get_attr_as_bool(CKA_UNWRAP) {
+        Ok(true) => (),
+        _ => return CKR_KEY_FUNCTION_NOT_PERMITTED,
+    }
+
+    /* attributes from the unwrapping key CKA_UNWRAP_TEMPLATE are forced
+     * on the unwrapped key and must not conflict with the caller's ones */
+    let unwrap_template = match unwrapping_key.get_attr(CKA_UNWRAP_TEMPLATE) {
+        Some(a) => res_or_ret!(a.to_attr_array()),
+        None => Vec::new(),
+    };
+    let mut full_tmpl: Vec<CK_ATTRIBUTE> = tmpl.to_vec();
+    for attr in unwrap_template.iter() {
+        match tmpl.iter().find(|a| a.type_ == attr.get_type()) {
+            Some(a) => {
+                if !attr.match_ck_attr(a) {
+                    return CKR_TEMPLATE_INCONSISTENT;
+                }
+            }
+            None => full_tmpl.push(CK_ATTRIBUTE {
+                type_: attr.get_type(),
+                pValue: attr.get_value().as_ptr() as *mut _,
+                ulValueLen: attr.get_value().len() as CK_ULONG,
+            }),
+        }
+    }
+    if !session.is_writable() {
+        fail_if_cka_token_true!(full_tmpl.as_slice());
+    }
+
+    let mut objects: Vec<&object::Object> = Vec::new();
+    for handle in res_or_ret!(mech.wrap_key_objects(data)) {
+        objects.push(res_or_ret!(token.get_object_by_handle(handle, true)));
+    }
+
+    let result = mech.unwrap_key(
+        data,
+        unwrapping_key,
+        objects.as_slice(),
+        bytes_to_slice!(wrapped_key, wrapped_key_len),
+        full_tmpl.as_slice(),
+        token.get_object_templates(),
+    );
+    match result {
+        Ok(obj) => {
+            let kh = res_or_ret!(token.insert_object(s_handle, obj));
+            unsafe {
+                core::ptr::write(key_handle as *mut _, kh);
+            }
+            CKR_OK
+        }
+        Err(e) => err_to_rv!(e),
+    }
 }
 extern "C" fn fn_derive_key(
-    _session: CK_SESSION_HANDLE,
-    _mechanism: CK_MECHANISM_PTR,
-    _base_key: CK_OBJECT_HANDLE,
-    _template: CK_ATTRIBUTE_PTR,
-    _attribute_count: CK_ULONG,
-    _ph_key: CK_OBJECT_HANDLE_PTR,
+    s_handle: CK_SESSION_HANDLE,
+    mechanism: CK_MECHANISM_PTR,
+    base_key: CK_OBJECT_HANDLE,
+    template: CK_ATTRIBUTE_PTR,
+    attribute_count: CK_ULONG,
+    key_handle: CK_OBJECT_HANDLE_PTR,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    if mechanism.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let rstate = global_rlock!(STATE);
+    let session = res_or_ret!(rstate.get_session(s_handle));
+
+    let data: &CK_MECHANISM = unsafe { &*mechanism };
+    /* only the TLS key and MAC derivation returns its handles through the
+     * mechanism parameters */
+    if key_handle.is_null() && data.mechanism != CKM_TLS12_KEY_AND_MAC_DERIVE {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let tmpl: &mut [CK_ATTRIBUTE] = unsafe {
+        std::slice::from_raw_parts_mut(template, attribute_count as usize)
+    };
+    if !session.is_writable() {
+        fail_if_cka_token_true!(&*tmpl);
+    }
+
+    let mut token =
+        res_or_ret!(rstate.get_token_from_slot_mut(session.get_slot_id()));
+
+    let mech = res_or_ret!(token.get_mech(data.mechanism));
+    if mech.info().flags & CKF_DERIVE != CKF_DERIVE {
+        return CKR_MECHANISM_INVALID;
+    }
+    let key = res_or_ret!(token.get_object_by_handle(base_key, true));
+    let mut objects: Vec<&object::Object> = Vec::new();
+    for handle in res_or_ret!(mech.derive_key_objects(data)) {
+        objects.push(res_or_ret!(token.get_object_by_handle(handle, true)));
+    }
+
+    let result = mech.derive_keys(
+        data,
+        key,
+        objects.as_slice(),
+        tmpl,
+        token.get_object_templates(),
+    );
+    let keys = match result {
+        Ok(k) => k,
+        Err(e) => return err_to_rv!(e),
+    };
+    if keys.is_empty() {
+        return CKR_GENERAL_ERROR;
+    }
+    /* additional keys are created from templates in the mechanism
+     * parameters, which have not been checked above */
+    if !session.is_writable() && keys.iter().any(|k| k.is_token()) {
+        return CKR_SESSION_READ_ONLY;
+    }
+    /* either all keys are stored or none is */
+    let mut handles: Vec<CK_OBJECT_HANDLE> = Vec::with_capacity(keys.len());
+    for obj in keys {
+        match token.insert_object(s_handle, obj) {
+            Ok(kh) => handles.push(kh),
+            Err(e) => {
+                for kh in handles {
+                    let _ = token.destroy_object(kh);
+                }
+                return err_to_rv!(e);
+            }
+        }
+    }
+    let mech = res_or_ret!(token.get_mech(data.mechanism));
+    let kh = res_or_ret!(mech.derive_key_handles(data, handles.as_slice()));
+    if !key_handle.is_null() {
+        unsafe {
+            core::ptr::write(key_handle as *mut _, kh);
+        }
+    }
+    CKR_OK
 }
 extern "C" fn fn_seed_random(
     _session: CK_SESSION_HANDLE,
@@ -1815,170 +2029,664 @@ extern "C" fn fn_session_cancel(
     CKR_FUNCTION_NOT_SUPPORTED
 }
 extern "C" fn fn_message_encrypt_init(
-    _session: CK_SESSION_HANDLE,
-    _mechanism: CK_MECHANISM_PTR,
-    _key: CK_OBJECT_HANDLE,
+    s_handle: CK_SESSION_HANDLE,
+    mechanism: CK_MECHANISM_PTR,
+    key: CK_OBJECT_HANDLE,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    check_op_empty_or_fail!(session; MsgEncryption; mechanism);
+    let data: &CK_MECHANISM = unsafe { &*mechanism };
+    let token = res_or_ret!(rstate.get_token_from_slot(session.get_slot_id()));
+    let obj = res_or_ret!(token.get_object_by_handle(key, true));
+    let mech = res_or_ret!(token.get_mech(data.mechanism));
+    if mech.info().flags & CKF_MESSAGE_ENCRYPT == CKF_MESSAGE_ENCRYPT {
+        let operation = res_or_ret!(mech.msg_encryption_new(data, obj));
+        session.set_operation(Operation::MsgEncryption(operation));
+        CKR_OK
+    } else {
+        CKR_MECHANISM_INVALID
+    }
 }
 extern "C" fn fn_encrypt_message(
-    _session: CK_SESSION_HANDLE,
-    _parameter: CK_VOID_PTR,
-    _parameter_len: CK_ULONG,
-    _associated_data: CK_BYTE_PTR,
-    _associated_data_len: CK_ULONG,
-    _plaintext: CK_BYTE_PTR,
-    _plaintext_len: CK_ULONG,
-    _ciphertext: CK_BYTE_PTR,
-    _pul_ciphertext_len: CK_ULONG_PTR,
-) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    s_handle: CK_SESSION_HANDLE,
+    parameter: CK_VOID_PTR,
+    parameter_len: CK_ULONG,
+    associated_data: CK_BYTE_PTR,
+    associated_data_len: CK_ULONG,
+    plaintext: CK_BYTE_PTR,
+    plaintext_len: CK_ULONG,
+    ciphertext: CK_BYTE_PTR,
+    pul_ciphertext_len: CK_ULONG_PTR,
+) -> CK_RV {
+    if parameter.is_null() || pul_ciphertext_len.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    if plaintext.is_null() && plaintext_len != 0 {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let operation = match session.get_operation_mut() {
+        Operation::MsgEncryption(op) => op,
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    };
+    if operation.finalized() {
+        return CKR_OPERATION_NOT_INITIALIZED;
+    }
+    if ciphertext.is_null() {
+        let encryption_len = res_or_ret!(
+            operation.msg_encryption_len(plaintext_len as usize, true)
+        );
+        unsafe {
+            *pul_ciphertext_len = encryption_len as CK_ULONG;
+        }
+        return CKR_OK;
+    }
+    let aad: &[u8] = bytes_to_slice!(associated_data, associated_data_len);
+    let plain: &[u8] = bytes_to_slice!(plaintext, plaintext_len);
+    ret_to_rv!(operation.msg_encrypt(
+        parameter,
+        parameter_len as usize,
+        aad,
+        plain,
+        ciphertext,
+        pul_ciphertext_len
+    ))
 }
 extern "C" fn fn_encrypt_message_begin(
-    _session: CK_SESSION_HANDLE,
-    _parameter: CK_VOID_PTR,
-    _parameter_len: CK_ULONG,
-    _associated_data: CK_BYTE_PTR,
-    _associated_data_len: CK_ULONG,
+    s_handle: CK_SESSION_HANDLE,
+    parameter: CK_VOID_PTR,
+    parameter_len: CK_ULONG,
+    associated_data: CK_BYTE_PTR,
+    associated_data_len: CK_ULONG,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    if parameter.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let operation = match session.get_operation_mut() {
+        Operation::MsgEncryption(op) => op,
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    };
+    if operation.finalized() {
+        return CKR_OPERATION_NOT_INITIALIZED;
+    }
+    let aad: &[u8] = bytes_to_slice!(associated_data, associated_data_len);
+    ret_to_rv!(operation.msg_encrypt_begin(
+        parameter,
+        parameter_len as usize,
+        aad
+    ))
 }
 extern "C" fn fn_encrypt_message_next(
-    _session: CK_SESSION_HANDLE,
-    _parameter: CK_VOID_PTR,
-    _parameter_len: CK_ULONG,
-    _plaintext_part: CK_BYTE_PTR,
-    _plaintext_part_len: CK_ULONG,
-    _ciphertext_part: CK_BYTE_PTR,
-    _pul_ciphertext_part_len: CK_ULONG_PTR,
-    _flags: CK_FLAGS,
+    s_handle: CK_SESSION_HANDLE,
+    parameter: CK_VOID_PTR,
+    parameter_len: CK_ULONG,
+    plaintext_part: CK_BYTE_PTR,
+    plaintext_part_len: CK_ULONG,
+    ciphertext_part: CK_BYTE_PTR,
+    pul_ciphertext_part_len: CK_ULONG_PTR,
+    flags: CK_FLAGS,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    if parameter.is_null() || pul_ciphertext_part_len.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    if plaintext_part.is_null() && plaintext_part_len != 0 {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let fin = flags & CKF_END_OF_MESSAGE == CKF_END_OF_MESSAGE;
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let operation = match session.get_operation_mut() {
+        Operation::MsgEncryption(op) => op,
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    };
+    if operation.finalized() {
+        return CKR_OPERATION_NOT_INITIALIZED;
+    }
+    if ciphertext_part.is_null() {
+        let encryption_len = res_or_ret!(
+            operation.msg_encryption_len(plaintext_part_len as usize, fin)
+        );
+        unsafe {
+            *pul_ciphertext_part_len = encryption_len as CK_ULONG;
+        }
+        return CKR_OK;
+    }
+    let plain: &[u8] = bytes_to_slice!(plaintext_part, plaintext_part_len);
+    if fin {
+        ret_to_rv!(operation.msg_encrypt_final(
+            parameter,
+            parameter_len as usize,
+            plain,
+            ciphertext_part,
+            pul_ciphertext_part_len
+        ))
+    } else {
+        ret_to_rv!(operation.msg_encrypt_next(
+            parameter,
+            parameter_len as usize,
+            plain,
+            ciphertext_part,
+            pul_ciphertext_part_len
+        ))
+    }
 }
-extern "C" fn fn_message_encrypt_final(_session: CK_SESSION_HANDLE) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+extern "C" fn fn_message_encrypt_final(s_handle: CK_SESSION_HANDLE) -> CK_RV {
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    match session.get_operation() {
+        Operation::MsgEncryption(_) => (),
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    }
+    session.set_operation(Operation::Empty);
+    CKR_OK
 }
 extern "C" fn fn_message_decrypt_init(
-    _session: CK_SESSION_HANDLE,
-    _mechanism: CK_MECHANISM_PTR,
-    _key: CK_OBJECT_HANDLE,
+    s_handle: CK_SESSION_HANDLE,
+    mechanism: CK_MECHANISM_PTR,
+    key: CK_OBJECT_HANDLE,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    check_op_empty_or_fail!(session; MsgDecryption; mechanism);
+    let data: &CK_MECHANISM = unsafe { &*mechanism };
+    let token = res_or_ret!(rstate.get_token_from_slot(session.get_slot_id()));
+    let obj = res_or_ret!(token.get_object_by_handle(key, true));
+    let mech = res_or_ret!(token.get_mech(data.mechanism));
+    if mech.info().flags & CKF_MESSAGE_DECRYPT == CKF_MESSAGE_DECRYPT {
+        let operation = res_or_ret!(mech.msg_decryption_new(data, obj));
+        session.set_operation(Operation::MsgDecryption(operation));
+        CKR_OK
+    } else {
+        CKR_MECHANISM_INVALID
+    }
 }
 extern "C" fn fn_decrypt_message(
-    _session: CK_SESSION_HANDLE,
-    _parameter: CK_VOID_PTR,
-    _parameter_len: CK_ULONG,
-    _associated_data: CK_BYTE_PTR,
-    _associated_data_len: CK_ULONG,
-    _ciphertext: CK_BYTE_PTR,
-    _ciphertext_len: CK_ULONG,
-    _plaintext: CK_BYTE_PTR,
-    _pul_plaintext_len: CK_ULONG_PTR,
-) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    s_handle: CK_SESSION_HANDLE,
+    parameter: CK_VOID_PTR,
+    parameter_len: CK_ULONG,
+    associated_data: CK_BYTE_PTR,
+    associated_data_len: CK_ULONG,
+    ciphertext: CK_BYTE_PTR,
+    ciphertext_len: CK_ULONG,
+    plaintext: CK_BYTE_PTR,
+    pul_plaintext_len: CK_ULONG_PTR,
+) -> CK_RV {
+    if parameter.is_null() || pul_plaintext_len.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    if ciphertext.is_null() && ciphertext_len != 0 {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let operation = match session.get_operation_mut() {
+        Operation::MsgDecryption(op) => op,
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    };
+    if operation.finalized() {
+        return CKR_OPERATION_NOT_INITIALIZED;
+    }
+    if plaintext.is_null() {
+        let decryption_len = res_or_ret!(
+            operation.msg_decryption_len(ciphertext_len as usize, true)
+        );
+        unsafe {
+            *pul_plaintext_len = decryption_len as CK_ULONG;
+        }
+        return CKR_OK;
+    }
+    let aad: &[u8] = bytes_to_slice!(associated_data, associated_data_len);
+    let cipher: &[u8] = bytes_to_slice!(ciphertext, ciphertext_len);
+    ret_to_rv!(operation.msg_decrypt(
+        parameter,
+        parameter_len as usize,
+        aad,
+        cipher,
+        plaintext,
+        pul_plaintext_len
+    ))
 }
 extern "C" fn fn_decrypt_message_begin(
-    _session: CK_SESSION_HANDLE,
-    _parameter: CK_VOID_PTR,
-    _parameter_len: CK_ULONG,
-    _associated_data: CK_BYTE_PTR,
-    _associated_data_len: CK_ULONG,
+    s_handle: CK_SESSION_HANDLE,
+    parameter: CK_VOID_PTR,
+    parameter_len: CK_ULONG,
+    associated_data: CK_BYTE_PTR,
+    associated_data_len: CK_ULONG,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
-}
-extern "C" fn fn_decrypt_message_next(
-    _session: CK_SESSION_HANDLE,
-    _parameter: CK_VOID_PTR,
-    _parameter_len: CK_ULONG,
-    _ciphertext_part: CK_BYTE_PTR,
-    _ciphertext_part_len: CK_ULONG,
-    _plaintext_part: CK_BYTE_PTR,
-    _pul_plaintext_part_len: CK_ULONG_PTR,
-    _flags: CK_FLAGS,
+    if parameter.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let operation = match session.get_operation_mut() {
+        Operation::MsgDecryption(op) => op,
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    };
+    if operation.finalized() {
+        return CKR_OPERATION_NOT_INITIALIZED;
+    }
+    let aad: &[u8] = bytes_to_slice!(associated_data, associated_data_len);
+    ret_to_rv!(operation.msg_decrypt_begin(
+        parameter,
+        parameter_len as usize,
+        aad
+    ))
+}
+extern "C" fn fn_decrypt_message_next(
+    s_handle: CK_SESSION_HANDLE,
+    parameter: CK_VOID_PTR,
+    parameter_len: CK_ULONG,
+    ciphertext_part: CK_BYTE_PTR,
+    ciphertext_part_len: CK_ULONG,
+    plaintext_part: CK_BYTE_PTR,
+    pul_plaintext_part_len: CK_ULONG_PTR,
+    flags: CK_FLAGS,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    if parameter.is_null() || pul_plaintext_part_len.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    if ciphertext_part.is_null() && ciphertext_part_len != 0 {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let fin = flags & CKF_END_OF_MESSAGE == CKF_END_OF_MESSAGE;
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let operation = match session.get_operation_mut() {
+        Operation::MsgDecryption(op) => op,
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    };
+    if operation.finalized() {
+        return CKR_OPERATION_NOT_INITIALIZED;
+    }
+    if plaintext_part.is_null() {
+        let decryption_len = res_or_ret!(
+            operation.msg_decryption_len(ciphertext_part_len as usize, fin)
+        );
+        unsafe {
+            *pul_plaintext_part_len = decryption_len as CK_ULONG;
+        }
+        return CKR_OK;
+    }
+    let cipher: &[u8] = bytes_to_slice!(ciphertext_part, ciphertext_part_len);
+    if fin {
+        ret_to_rv!(operation.msg_decrypt_final(
+            parameter,
+            parameter_len as usize,
+            cipher,
+            plaintext_part,
+            pul_plaintext_part_len
+        ))
+    } else {
+        ret_to_rv!(operation.msg_decrypt_next(
+            parameter,
+            parameter_len as usize,
+            cipher,
+            plaintext_part,
+            pul_plaintext_part_len
+        ))
+    }
 }
-extern "C" fn fn_message_decrypt_final(_session: CK_SESSION_HANDLE) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+extern "C" fn fn_message_decrypt_final(s_handle: CK_SESSION_HANDLE) -> CK_RV {
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    match session.get_operation() {
+        Operation::MsgDecryption(_) => (),
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    }
+    session.set_operation(Operation::Empty);
+    CKR_OK
+}
+fn msg_sign_op(
+    rstate: &State,
+    slot_id: CK_SLOT_ID,
+    operation: &MsgSign,
+    parameter: CK_VOID_PTR,
+    parameter_len: CK_ULONG,
+) -> KResult<Option<Box<dyn Sign>>> {
+    if parameter.is_null() {
+        return Ok(None);
+    }
+    let data = CK_MECHANISM {
+        mechanism: operation.mechanism(),
+        pParameter: parameter,
+        ulParameterLen: parameter_len,
+    };
+    let token = rstate.get_token_from_slot(slot_id)?;
+    let obj = token.get_object_by_handle(operation.key(), true)?;
+    let mech = token.get_mech(data.mechanism)?;
+    Ok(Some(mech.sign_new(&data, obj)?))
+}
+fn msg_sign_output(
+    op: &mut Box<dyn Sign>,
+    data: &[u8],
+    psignature: CK_BYTE_PTR,
+    pul_signature_len: CK_ULONG_PTR,
+) -> CK_RV {
+    let signature_len = res_or_ret!(op.signature_len());
+    if psignature.is_null() {
+        unsafe {
+            *pul_signature_len = signature_len as CK_ULONG;
+        }
+        return CKR_OK;
+    }
+    unsafe {
+        if *pul_signature_len < signature_len as CK_ULONG {
+            return CKR_BUFFER_TOO_SMALL;
+        }
+    }
+    let signature: &mut [u8] =
+        unsafe { std::slice::from_raw_parts_mut(psignature, signature_len) };
+    let ret = if op.in_use() {
+        match op.sign_update(data) {
+            Ok(()) => ret_to_rv!(op.sign_final(signature)),
+            Err(e) => err_to_rv!(e),
+        }
+    } else {
+        ret_to_rv!(op.sign(data, signature))
+    };
+    if ret == CKR_OK {
+        unsafe {
+            *pul_signature_len = signature_len as CK_ULONG;
+        }
+    }
+    ret
 }
 extern "C" fn fn_message_sign_init(
-    _session: CK_SESSION_HANDLE,
-    _mechanism: CK_MECHANISM_PTR,
-    _key: CK_OBJECT_HANDLE,
+    s_handle: CK_SESSION_HANDLE,
+    mechanism: CK_MECHANISM_PTR,
+    key: CK_OBJECT_HANDLE,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    check_op_empty_or_fail!(session; MsgSign; mechanism);
+    let data: &CK_MECHANISM = unsafe { &*mechanism };
+    let token = res_or_ret!(rstate.get_token_from_slot(session.get_slot_id()));
+    let obj = res_or_ret!(token.get_object_by_handle(key, true));
+    let mech = res_or_ret!(token.get_mech(data.mechanism));
+    if mech.info().flags & CKF_MESSAGE_SIGN == CKF_MESSAGE_SIGN {
+        let operation = res_or_ret!(mech.sign_new(data, obj));
+        session.set_operation(Operation::MsgSign(MsgSign::new(
+            data.mechanism,
+            key,
+            operation,
+        )));
+        CKR_OK
+    } else {
+        CKR_MECHANISM_INVALID
+    }
 }
 extern "C" fn fn_sign_message(
-    _session: CK_SESSION_HANDLE,
-    _parameter: CK_VOID_PTR,
-    _parameter_len: CK_ULONG,
-    _data: CK_BYTE_PTR,
-    _data_len: CK_ULONG,
-    _signature: CK_BYTE_PTR,
-    _pul_signature_len: CK_ULONG_PTR,
+    s_handle: CK_SESSION_HANDLE,
+    parameter: CK_VOID_PTR,
+    parameter_len: CK_ULONG,
+    pdata: CK_BYTE_PTR,
+    data_len: CK_ULONG,
+    psignature: CK_BYTE_PTR,
+    pul_signature_len: CK_ULONG_PTR,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    if pul_signature_len.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    if pdata.is_null() && data_len != 0 {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let slot_id = session.get_slot_id();
+    let operation = match session.get_operation_mut() {
+        Operation::MsgSign(op) => op,
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    };
+    let msgop = res_or_ret!(msg_sign_op(
+        &rstate,
+        slot_id,
+        operation,
+        parameter,
+        parameter_len
+    ));
+    res_or_ret!(operation.begin(msgop));
+    let data: &[u8] = bytes_to_slice!(pdata, data_len);
+    let ret = match operation.op() {
+        Ok(op) => msg_sign_output(op, data, psignature, pul_signature_len),
+        Err(e) => err_to_rv!(e),
+    };
+    operation.end();
+    ret
 }
 extern "C" fn fn_sign_message_begin(
-    _session: CK_SESSION_HANDLE,
-    _parameter: CK_VOID_PTR,
-    _parameter_len: CK_ULONG,
+    s_handle: CK_SESSION_HANDLE,
+    parameter: CK_VOID_PTR,
+    parameter_len: CK_ULONG,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let slot_id = session.get_slot_id();
+    let operation = match session.get_operation_mut() {
+        Operation::MsgSign(op) => op,
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    };
+    let msgop = res_or_ret!(msg_sign_op(
+        &rstate,
+        slot_id,
+        operation,
+        parameter,
+        parameter_len
+    ));
+    ret_to_rv!(operation.begin(msgop))
 }
 extern "C" fn fn_sign_message_next(
-    _session: CK_SESSION_HANDLE,
+    s_handle: CK_SESSION_HANDLE,
     _parameter: CK_VOID_PTR,
     _parameter_len: CK_ULONG,
-    _data: CK_BYTE_PTR,
-    _data_len: CK_ULONG,
-    _signature: CK_BYTE_PTR,
-    _pul_signature_len: CK_ULONG_PTR,
+    pdata: CK_BYTE_PTR,
+    data_len: CK_ULONG,
+    psignature: CK_BYTE_PTR,
+    pul_signature_len: CK_ULONG_PTR,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    if pdata.is_null() && data_len != 0 {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let operation = match session.get_operation_mut() {
+        Operation::MsgSign(op) => op,
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    };
+    let op = res_or_ret!(operation.op());
+    let data: &[u8] = bytes_to_slice!(pdata, data_len);
+    /* a NULL signature length marks a part that is not the last one */
+    let ret = if pul_signature_len.is_null() {
+        ret_to_rv!(op.sign_update(data))
+    } else {
+        let ret = msg_sign_output(op, data, psignature, pul_signature_len);
+        if ret == CKR_OK && !psignature.is_null() {
+            operation.end();
+        }
+        ret
+    };
+    if ret != CKR_OK && ret != CKR_BUFFER_TOO_SMALL {
+        operation.end();
+    }
+    ret
 }
-extern "C" fn fn_message_sign_final(_session: CK_SESSION_HANDLE) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+extern "C" fn fn_message_sign_final(s_handle: CK_SESSION_HANDLE) -> CK_RV {
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    match session.get_operation() {
+        Operation::MsgSign(_) => (),
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    }
+    session.set_operation(Operation::Empty);
+    CKR_OK
+}
+fn msg_verify_op(
+    rstate: &State,
+    slot_id: CK_SLOT_ID,
+    operation: &MsgVerify,
+    parameter: CK_VOID_PTR,
+    parameter_len: CK_ULONG,
+) -> KResult<Option<Box<dyn Verify>>> {
+    if parameter.is_null() {
+        return Ok(None);
+    }
+    let data = CK_MECHANISM {
+        mechanism: operation.mechanism(),
+        pParameter: parameter,
+        ulParameterLen: parameter_len,
+    };
+    let token = rstate.get_token_from_slot(slot_id)?;
+    let obj = token.get_object_by_handle(operation.key(), true)?;
+    let mech = token.get_mech(data.mechanism)?;
+    Ok(Some(mech.verify_new(&data, obj)?))
+}
+fn msg_verify_output(
+    op: &mut Box<dyn Verify>,
+    data: &[u8],
+    signature: &[u8],
+) -> CK_RV {
+    let signature_len = res_or_ret!(op.signature_len());
+    if signature.len() != signature_len {
+        return CKR_SIGNATURE_LEN_RANGE;
+    }
+    if op.in_use() {
+        match op.verify_update(data) {
+            Ok(()) => ret_to_rv!(op.verify_final(signature)),
+            Err(e) => err_to_rv!(e),
+        }
+    } else {
+        ret_to_rv!(op.verify(data, signature))
+    }
 }
 extern "C" fn fn_message_verify_init(
-    _session: CK_SESSION_HANDLE,
-    _mechanism: CK_MECHANISM_PTR,
-    _key: CK_OBJECT_HANDLE,
+    s_handle: CK_SESSION_HANDLE,
+    mechanism: CK_MECHANISM_PTR,
+    key: CK_OBJECT_HANDLE,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    check_op_empty_or_fail!(session; MsgVerify; mechanism);
+    let data: &CK_MECHANISM = unsafe { &*mechanism };
+    let token = res_or_ret!(rstate.get_token_from_slot(session.get_slot_id()));
+    let obj = res_or_ret!(token.get_object_by_handle(key, true));
+    let mech = res_or_ret!(token.get_mech(data.mechanism));
+    if mech.info().flags & CKF_MESSAGE_VERIFY == CKF_MESSAGE_VERIFY {
+        let operation = res_or_ret!(mech.verify_new(data, obj));
+        session.set_operation(Operation::MsgVerify(MsgVerify::new(
+            data.mechanism,
+            key,
+            operation,
+        )));
+        CKR_OK
+    } else {
+        CKR_MECHANISM_INVALID
+    }
 }
 extern "C" fn fn_verify_message(
-    _session: CK_SESSION_HANDLE,
-    _parameter: CK_VOID_PTR,
-    _parameter_len: CK_ULONG,
-    _data: CK_BYTE_PTR,
-    _data_len: CK_ULONG,
-    _signature: CK_BYTE_PTR,
-    _signature_len: CK_ULONG,
+    s_handle: CK_SESSION_HANDLE,
+    parameter: CK_VOID_PTR,
+    parameter_len: CK_ULONG,
+    pdata: CK_BYTE_PTR,
+    data_len: CK_ULONG,
+    psignature: CK_BYTE_PTR,
+    signature_len: CK_ULONG,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    if psignature.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    if pdata.is_null() && data_len != 0 {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let slot_id = session.get_slot_id();
+    let operation = match session.get_operation_mut() {
+        Operation::MsgVerify(op) => op,
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    };
+    let msgop = res_or_ret!(msg_verify_op(
+        &rstate,
+        slot_id,
+        operation,
+        parameter,
+        parameter_len
+    ));
+    res_or_ret!(operation.begin(msgop));
+    let data: &[u8] = bytes_to_slice!(pdata, data_len);
+    let signature: &[u8] = bytes_to_slice!(psignature, signature_len);
+    let ret = match operation.op() {
+        Ok(op) => msg_verify_output(op, data, signature),
+        Err(e) => err_to_rv!(e),
+    };
+    operation.end();
+    ret
 }
 extern "C" fn fn_verify_message_begin(
-    _session: CK_SESSION_HANDLE,
-    _parameter: CK_VOID_PTR,
-    _parameter_len: CK_ULONG,
+    s_handle: CK_SESSION_HANDLE,
+    parameter: CK_VOID_PTR,
+    parameter_len: CK_ULONG,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let slot_id = session.get_slot_id();
+    let operation = match session.get_operation_mut() {
+        Operation::MsgVerify(op) => op,
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    };
+    let msgop = res_or_ret!(msg_verify_op(
+        &rstate,
+        slot_id,
+        operation,
+        parameter,
+        parameter_len
+    ));
+    ret_to_rv!(operation.begin(msgop))
 }
 extern "C" fn fn_verify_message_next(
-    _session: CK_SESSION_HANDLE,
+    s_handle: CK_SESSION_HANDLE,
     _parameter: CK_VOID_PTR,
     _parameter_len: CK_ULONG,
-    _data: CK_BYTE_PTR,
-    _data_len: CK_ULONG,
-    _signature: CK_BYTE_PTR,
-    _signature_len: CK_ULONG,
+    pdata: CK_BYTE_PTR,
+    data_len: CK_ULONG,
+    psignature: CK_BYTE_PTR,
+    signature_len: CK_ULONG,
 ) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+    if pdata.is_null() && data_len != 0 {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    let operation = match session.get_operation_mut() {
+        Operation::MsgVerify(op) => op,
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    };
+    let op = res_or_ret!(operation.op());
+    let data: &[u8] = bytes_to_slice!(pdata, data_len);
+    /* a NULL signature marks a part that is not the last one */
+    if psignature.is_null() {
+        let ret = ret_to_rv!(op.verify_update(data));
+        if ret != CKR_OK {
+            operation.end();
+        }
+        return ret;
+    }
+    let signature: &[u8] = bytes_to_slice!(psignature, signature_len);
+    let ret = msg_verify_output(op, data, signature);
+    operation.end();
+    ret
 }
-extern "C" fn fn_message_verify_final(_session: CK_SESSION_HANDLE) -> CK_RV {
-    CKR_FUNCTION_NOT_SUPPORTED
+extern "C" fn fn_message_verify_final(s_handle: CK_SESSION_HANDLE) -> CK_RV {
+    let rstate = global_rlock!(STATE);
+    let mut session = res_or_ret!(rstate.get_session_mut(s_handle));
+    match session.get_operation() {
+        Operation::MsgVerify(_) => (),
+        _ => return CKR_OPERATION_NOT_INITIALIZED,
+    }
+    session.set_operation(Operation::Empty);
+    CKR_OK
 }
 
 pub static FNLIST_300: CK_FUNCTION_LIST_3_0 = CK_FUNCTION_LIST_3_0 {
@@ -2168,6 +2876,147 @@ pub extern "C" fn C_GetInterface(
     CKR_OK
 }
 
+// Additional 3.2 functions
+//
+// The 3.2 function list is not available in the 3.1 headers, so these
+// are only exported as symbols for now
+
+#[no_mangle]
+pub extern "C" fn C_EncapsulateKey(
+    s_handle: CK_SESSION_HANDLE,
+    mechanism: CK_MECHANISM_PTR,
+    public_key_handle: CK_OBJECT_HANDLE,
+    template: CK_ATTRIBUTE_PTR,
+    attribute_count: CK_ULONG,
+    ciphertext: CK_BYTE_PTR,
+    pul_ciphertext_len: CK_ULONG_PTR,
+    key_handle: CK_OBJECT_HANDLE_PTR,
+) -> CK_RV {
+    if mechanism.is_null()
+        || pul_ciphertext_len.is_null()
+        || (template.is_null() && attribute_count != 0)
+    {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let rstate = global_rlock!(STATE);
+    let session = res_or_ret!(rstate.get_session(s_handle));
+
+    let data: &CK_MECHANISM = unsafe { &*mechanism };
+    let tmpl: &[CK_ATTRIBUTE] = if template.is_null() {
+        &[]
+    } else {
+        unsafe {
+            std::slice::from_raw_parts(template, attribute_count as usize)
+        }
+    };
+
+    let mut token =
+        res_or_ret!(rstate.get_token_from_slot_mut(session.get_slot_id()));
+
+    let mech = res_or_ret!(token.get_mech(data.mechanism));
+    if mech.info().flags & CKF_ENCAPSULATE != CKF_ENCAPSULATE {
+        return CKR_MECHANISM_INVALID;
+    }
+    let key = res_or_ret!(token.get_object_by_handle(public_key_handle, true));
+
+    let len = res_or_ret!(mech.encapsulate_ciphertext_len(key));
+    if ciphertext.is_null() {
+        unsafe {
+            *pul_ciphertext_len = len as CK_ULONG;
+        }
+        return CKR_OK;
+    }
+    unsafe {
+        if (*pul_ciphertext_len as usize) < len {
+            *pul_ciphertext_len = len as CK_ULONG;
+            return CKR_BUFFER_TOO_SMALL;
+        }
+    }
+    if key_handle.is_null() {
+        return CKR_ARGUMENTS_BAD;
+    }
+    if !session.is_writable() {
+        fail_if_cka_token_true!(tmpl);
+    }
+
+    let output: &mut [u8] =
+        unsafe { std::slice::from_raw_parts_mut(ciphertext, len) };
+    let result =
+        mech.encapsulate(data, key, tmpl, token.get_object_templates(), output);
+    match result {
+        Ok((obj, outlen)) => {
+            let kh = res_or_ret!(token.insert_object(s_handle, obj));
+            unsafe {
+                *pul_ciphertext_len = outlen as CK_ULONG;
+                core::ptr::write(key_handle as *mut _, kh);
+            }
+            CKR_OK
+        }
+        Err(e) => err_to_rv!(e),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn C_DecapsulateKey(
+    s_handle: CK_SESSION_HANDLE,
+    mechanism: CK_MECHANISM_PTR,
+    private_key_handle: CK_OBJECT_HANDLE,
+    template: CK_ATTRIBUTE_PTR,
+    attribute_count: CK_ULONG,
+    ciphertext: CK_BYTE_PTR,
+    ciphertext_len: CK_ULONG,
+    key_handle: CK_OBJECT_HANDLE_PTR,
+) -> CK_RV {
+    if mechanism.is_null()
+        || ciphertext.is_null()
+        || key_handle.is_null()
+        || (template.is_null() && attribute_count != 0)
+    {
+        return CKR_ARGUMENTS_BAD;
+    }
+    let rstate = global_rlock!(STATE);
+    let session = res_or_ret!(rstate.get_session(s_handle));
+
+    let data: &CK_MECHANISM = unsafe { &*mechanism };
+    let tmpl: &[CK_ATTRIBUTE] = if template.is_null() {
+        &[]
+    } else {
+        unsafe {
+            std::slice::from_raw_parts(template, attribute_count as usize)
+        }
+    };
+    if !session.is_writable() {
+        fail_if_cka_token_true!(tmpl);
+    }
+
+    let mut token =
+        res_or_ret!(rstate.get_token_from_slot_mut(session.get_slot_id()));
+
+    let mech = res_or_ret!(token.get_mech(data.mechanism));
+    if mech.info().flags & CKF_DECAPSULATE != CKF_DECAPSULATE {
+        return CKR_MECHANISM_INVALID;
+    }
+    let key = res_or_ret!(token.get_object_by_handle(private_key_handle, true));
+
+    let result = mech.decapsulate(
+        data,
+        key,
+        tmpl,
+        token.get_object_templates(),
+        bytes_to_slice!(ciphertext, ciphertext_len),
+    );
+    match result {
+        Ok(obj) => {
+            let kh = res_or_ret!(token.insert_object(s_handle, obj));
+            unsafe {
+                core::ptr::write(key_handle as *mut _, kh);
+            }
+            CKR_OK
+        }
+        Err(e) => err_to_rv!(e),
+    }
+}
+
 #[cfg(feature = "fips")]
 #[no_mangle]
 pub extern "C" fn OSSL_provider_init(
@@ -29,6 +29,20 @@ pub trait Mechanism: Debug + Send + Sync {
     ) -> KResult<Box<dyn Decryption>> {
         err_rv!(CKR_MECHANISM_INVALID)
     }
+    fn msg_encryption_new(
+        &self,
+        _: &CK_MECHANISM,
+        _: &object::Object,
+    ) -> KResult<Box<dyn MsgEncryption>> {
+        err_rv!(CKR_MECHANISM_INVALID)
+    }
+    fn msg_decryption_new(
+        &self,
+        _: &CK_MECHANISM,
+        _: &object::Object,
+    ) -> KResult<Box<dyn MsgDecryption>> {
+        err_rv!(CKR_MECHANISM_INVALID)
+    }
     fn digest_new(&self, _: &CK_MECHANISM) -> KResult<Box<dyn Digest>> {
         err_rv!(CKR_MECHANISM_INVALID)
     }
@@ -51,6 +65,7 @@ pub trait Mechanism: Debug + Send + Sync {
         &self,
         _: &CK_MECHANISM,
         _: &[CK_ATTRIBUTE],
+        _: &object::ObjectTemplates,
     ) -> KResult<Object> {
         err_rv!(CKR_MECHANISM_INVALID)
     }
@@ -64,11 +79,20 @@ pub trait Mechanism: Debug + Send + Sync {
         err_rv!(CKR_MECHANISM_INVALID)
     }
 
+    /* Same as derive_key_objects(), for wrap_key() and unwrap_key() */
+    fn wrap_key_objects(
+        &self,
+        _: &CK_MECHANISM,
+    ) -> KResult<Vec<CK_OBJECT_HANDLE>> {
+        Ok(Vec::new())
+    }
+
     fn wrap_key(
         &self,
         _: &CK_MECHANISM,
         _: &object::Object,
         _: &object::Object,
+        _: &[&object::Object],
         _: CK_BYTE_PTR,
         _: CK_ULONG_PTR,
     ) -> KResult<()> {
@@ -79,8 +103,92 @@ pub trait Mechanism: Debug + Send + Sync {
         &self,
         _: &CK_MECHANISM,
         _: &object::Object,
+        _: &[&object::Object],
         _: &[u8],
         _: &[CK_ATTRIBUTE],
+        _: &object::ObjectTemplates,
+    ) -> KResult<Object> {
+        err_rv!(CKR_MECHANISM_INVALID)
+    }
+
+    /* Handles of any additional key objects referenced by the mechanism
+     * parameters, these are looked up by the caller and handed to
+     * derive_key() in the same order */
+    fn derive_key_objects(
+        &self,
+        _: &CK_MECHANISM,
+    ) -> KResult<Vec<CK_OBJECT_HANDLE>> {
+        Ok(Vec::new())
+    }
+
+    fn derive_key(
+        &self,
+        _: &CK_MECHANISM,
+        _: &object::Object,
+        _: &[&object::Object],
+        _: &[CK_ATTRIBUTE],
+        _: &object::ObjectTemplates,
+    ) -> KResult<Object> {
+        err_rv!(CKR_MECHANISM_INVALID)
+    }
+
+    /* Mechanisms that can output more than one key return all of them
+     * here, the first one is the key returned to the caller */
+    fn derive_keys(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &object::Object,
+        objects: &[&object::Object],
+        template: &[CK_ATTRIBUTE],
+        objtemplates: &object::ObjectTemplates,
+    ) -> KResult<Vec<Object>> {
+        Ok(vec![self.derive_key(
+            mech,
+            key,
+            objects,
+            template,
+            objtemplates,
+        )?])
+    }
+
+    /* Receives the handles of all the keys returned by derive_keys(), in
+     * the same order, so they can be stored in the mechanism parameters,
+     * and returns the handle to hand back to the caller, if any */
+    fn derive_key_handles(
+        &self,
+        _: &CK_MECHANISM,
+        handles: &[CK_OBJECT_HANDLE],
+    ) -> KResult<CK_OBJECT_HANDLE> {
+        match handles.first() {
+            Some(h) => Ok(*h),
+            None => err_rv!(CKR_GENERAL_ERROR),
+        }
+    }
+
+    fn encapsulate_ciphertext_len(&self, _: &object::Object) -> KResult<usize> {
+        err_rv!(CKR_MECHANISM_INVALID)
+    }
+
+    /* Returns the new key and the length of the ciphertext written
+     * in the output buffer */
+    fn encapsulate(
+        &self,
+        _: &CK_MECHANISM,
+        _: &object::Object,
+        _: &[CK_ATTRIBUTE],
+        _: &object::ObjectTemplates,
+        _: &mut [u8],
+    ) -> KResult<(Object, usize)> {
+        err_rv!(CKR_MECHANISM_INVALID)
+    }
+
+    fn decapsulate(
+        &self,
+        _: &CK_MECHANISM,
+        _: &object::Object,
+        _: &[CK_ATTRIBUTE],
+        _: &object::ObjectTemplates,
+        _: &[u8],
     ) -> KResult<Object> {
         err_rv!(CKR_MECHANISM_INVALID)
     }
@@ -162,7 +270,7 @@ pub trait Encryption: MechOperation {
     ) -> KResult<()> {
         err_rv!(CKR_GENERAL_ERROR)
     }
-    fn encryption_len(&self) -> KResult<usize> {
+    fn encryption_len(&self, _data_len: usize, _fin: bool) -> KResult<usize> {
         err_rv!(CKR_GENERAL_ERROR)
     }
 }
@@ -191,7 +299,105 @@ pub trait Decryption: MechOperation {
     ) -> KResult<()> {
         err_rv!(CKR_GENERAL_ERROR)
     }
-    fn decryption_len(&self) -> KResult<usize> {
+    fn decryption_len(&self, _data_len: usize, _fin: bool) -> KResult<usize> {
+        err_rv!(CKR_GENERAL_ERROR)
+    }
+}
+
+pub trait MsgEncryption: MechOperation {
+    fn msg_encrypt(
+        &mut self,
+        _param: CK_VOID_PTR,
+        _paramlen: usize,
+        _aad: &[u8],
+        _plain: &[u8],
+        _cipher: CK_BYTE_PTR,
+        _cipher_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        err_rv!(CKR_GENERAL_ERROR)
+    }
+    fn msg_encrypt_begin(
+        &mut self,
+        _param: CK_VOID_PTR,
+        _paramlen: usize,
+        _aad: &[u8],
+    ) -> KResult<()> {
+        err_rv!(CKR_GENERAL_ERROR)
+    }
+    fn msg_encrypt_next(
+        &mut self,
+        _param: CK_VOID_PTR,
+        _paramlen: usize,
+        _plain: &[u8],
+        _cipher: CK_BYTE_PTR,
+        _cipher_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        err_rv!(CKR_GENERAL_ERROR)
+    }
+    fn msg_encrypt_final(
+        &mut self,
+        _param: CK_VOID_PTR,
+        _paramlen: usize,
+        _plain: &[u8],
+        _cipher: CK_BYTE_PTR,
+        _cipher_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        err_rv!(CKR_GENERAL_ERROR)
+    }
+    fn msg_encryption_len(
+        &self,
+        _data_len: usize,
+        _fin: bool,
+    ) -> KResult<usize> {
+        err_rv!(CKR_GENERAL_ERROR)
+    }
+}
+
+pub trait MsgDecryption: MechOperation {
+    fn msg_decrypt(
+        &mut self,
+        _param: CK_VOID_PTR,
+        _paramlen: usize,
+        _aad: &[u8],
+        _cipher: &[u8],
+        _plain: CK_BYTE_PTR,
+        _plain_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        err_rv!(CKR_GENERAL_ERROR)
+    }
+    fn msg_decrypt_begin(
+        &mut self,
+        _param: CK_VOID_PTR,
+        _paramlen: usize,
+        _aad: &[u8],
+    ) -> KResult<()> {
+        err_rv!(CKR_GENERAL_ERROR)
+    }
+    fn msg_decrypt_next(
+        &mut self,
+        _param: CK_VOID_PTR,
+        _paramlen: usize,
+        _cipher: &[u8],
+        _plain: CK_BYTE_PTR,
+        _plain_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        err_rv!(CKR_GENERAL_ERROR)
+    }
+    fn msg_decrypt_final(
+        &mut self,
+        _param: CK_VOID_PTR,
+        _paramlen: usize,
+        _cipher: &[u8],
+        _plain: CK_BYTE_PTR,
+        _plain_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        err_rv!(CKR_GENERAL_ERROR)
+    }
+    fn msg_decryption_len(
+        &self,
+        _data_len: usize,
+        _fin: bool,
+    ) -> KResult<usize> {
         err_rv!(CKR_GENERAL_ERROR)
     }
 }
@@ -249,15 +455,151 @@ pub trait Verify: MechOperation {
     }
 }
 
+/* Message based signatures are implemented on top of the regular Sign
+ * and Verify operations: the operation created at init time is reset
+ * between messages, while messages that carry their own parameters get
+ * a fresh operation that is discarded once the message is done */
+#[derive(Debug)]
+pub struct MsgSign {
+    mech: CK_MECHANISM_TYPE,
+    key: CK_OBJECT_HANDLE,
+    base: Box<dyn Sign>,
+    msgop: Option<Box<dyn Sign>>,
+    active: bool,
+}
+
+impl MsgSign {
+    pub fn new(
+        mech: CK_MECHANISM_TYPE,
+        key: CK_OBJECT_HANDLE,
+        op: Box<dyn Sign>,
+    ) -> MsgSign {
+        MsgSign {
+            mech: mech,
+            key: key,
+            base: op,
+            msgop: None,
+            active: false,
+        }
+    }
+
+    pub fn mechanism(&self) -> CK_MECHANISM_TYPE {
+        self.mech
+    }
+
+    pub fn key(&self) -> CK_OBJECT_HANDLE {
+        self.key
+    }
+
+    pub fn active(&self) -> bool {
+        self.active
+    }
+
+    pub fn begin(&mut self, op: Option<Box<dyn Sign>>) -> KResult<()> {
+        if self.active {
+            return err_rv!(CKR_OPERATION_ACTIVE);
+        }
+        if op.is_none() && (self.base.in_use() || self.base.finalized()) {
+            self.base.reset()?;
+        }
+        self.msgop = op;
+        self.active = true;
+        Ok(())
+    }
+
+    pub fn op(&mut self) -> KResult<&mut Box<dyn Sign>> {
+        if !self.active {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        match &mut self.msgop {
+            Some(op) => Ok(op),
+            None => Ok(&mut self.base),
+        }
+    }
+
+    pub fn end(&mut self) {
+        self.msgop = None;
+        self.active = false;
+    }
+}
+
+#[derive(Debug)]
+pub struct MsgVerify {
+    mech: CK_MECHANISM_TYPE,
+    key: CK_OBJECT_HANDLE,
+    base: Box<dyn Verify>,
+    msgop: Option<Box<dyn Verify>>,
+    active: bool,
+}
+
+impl MsgVerify {
+    pub fn new(
+        mech: CK_MECHANISM_TYPE,
+        key: CK_OBJECT_HANDLE,
+        op: Box<dyn Verify>,
+    ) -> MsgVerify {
+        MsgVerify {
+            mech: mech,
+            key: key,
+            base: op,
+            msgop: None,
+            active: false,
+        }
+    }
+
+    pub fn mechanism(&self) -> CK_MECHANISM_TYPE {
+        self.mech
+    }
+
+    pub fn key(&self) -> CK_OBJECT_HANDLE {
+        self.key
+    }
+
+    pub fn active(&self) -> bool {
+        self.active
+    }
+
+    pub fn begin(&mut self, op: Option<Box<dyn Verify>>) -> KResult<()> {
+        if self.active {
+            return err_rv!(CKR_OPERATION_ACTIVE);
+        }
+        if op.is_none() && (self.base.in_use() || self.base.finalized()) {
+            self.base.reset()?;
+        }
+        self.msgop = op;
+        self.active = true;
+        Ok(())
+    }
+
+    pub fn op(&mut self) -> KResult<&mut Box<dyn Verify>> {
+        if !self.active {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        match &mut self.msgop {
+            Some(op) => Ok(op),
+            None => Ok(&mut self.base),
+        }
+    }
+
+    pub fn end(&mut self) {
+        self.msgop = None;
+        self.active = false;
+    }
+}
+
 #[derive(Debug)]
 pub enum Operation {
     Empty,
     Search(Box<dyn SearchOperation>),
     Encryption(Box<dyn Encryption>),
     Decryption(Box<dyn Decryption>),
+    MsgEncryption(Box<dyn MsgEncryption>),
+    MsgDecryption(Box<dyn MsgDecryption>),
     Digest(Box<dyn Digest>),
     Sign(Box<dyn Sign>),
     Verify(Box<dyn Verify>),
+    MsgSign(MsgSign),
+    MsgVerify(MsgVerify),
 }
 
 impl Operation {
@@ -267,9 +609,13 @@ impl Operation {
             Operation::Search(op) => op.finalized(),
             Operation::Encryption(op) => op.finalized(),
             Operation::Decryption(op) => op.finalized(),
+            Operation::MsgEncryption(op) => op.finalized(),
+            Operation::MsgDecryption(op) => op.finalized(),
             Operation::Digest(op) => op.finalized(),
             Operation::Sign(op) => op.finalized(),
             Operation::Verify(op) => op.finalized(),
+            Operation::MsgSign(_) => false,
+            Operation::MsgVerify(_) => false,
         }
     }
 }
@@ -0,0 +1,597 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use super::attribute;
+use super::der;
+use super::error;
+use super::hash;
+use super::interface;
+use super::object;
+use super::{attr_element, bytes_attr_not_empty, err_rv};
+
+use attribute::{from_bool, from_bytes, from_ulong};
+use error::{KError, KResult};
+use interface::*;
+use object::{
+    CommonKeyTemplate, OAFlags, Object, ObjectAttr, ObjectTemplate,
+    ObjectTemplates, ObjectType, PrivKeyTemplate, PubKeyTemplate,
+};
+
+use super::mechanism;
+use mechanism::*;
+
+use once_cell::sync::Lazy;
+use std::fmt::Debug;
+
+/* FIPS 204 5.2: the context string is at most 255 bytes */
+const MAX_ML_DSA_CONTEXT_LEN: usize = 255;
+
+#[derive(Debug)]
+struct MlDsaParams {
+    set: CK_ML_DSA_PARAMETER_SET_TYPE,
+    ossl_name: &'static [u8],
+    pk_len: usize,
+    sk_len: usize,
+    sig_len: usize,
+}
+
+/* FIPS 204, 4: public key, private key and signature sizes */
+static ML_DSA_PARAMS: [MlDsaParams; 3] = [
+    MlDsaParams {
+        set: CKP_ML_DSA_44,
+        ossl_name: b"ML-DSA-44\0",
+        pk_len: 1312,
+        sk_len: 2560,
+        sig_len: 2420,
+    },
+    MlDsaParams {
+        set: CKP_ML_DSA_65,
+        ossl_name: b"ML-DSA-65\0",
+        pk_len: 1952,
+        sk_len: 4032,
+        sig_len: 3309,
+    },
+    MlDsaParams {
+        set: CKP_ML_DSA_87,
+        ossl_name: b"ML-DSA-87\0",
+        pk_len: 2592,
+        sk_len: 4896,
+        sig_len: 4627,
+    },
+];
+
+fn params_from_obj(obj: &Object) -> KResult<&'static MlDsaParams> {
+    let set = obj.get_attr_as_ulong(CKA_PARAMETER_SET)?;
+    match ML_DSA_PARAMS.iter().find(|p| p.set == set) {
+        Some(p) => Ok(p),
+        None => err_rv!(CKR_ATTRIBUTE_VALUE_INVALID),
+    }
+}
+
+/* Size of the encoded private key, used to validate PKCS#8 imports */
+pub fn private_key_len(set: CK_ML_DSA_PARAMETER_SET_TYPE) -> KResult<usize> {
+    match ML_DSA_PARAMS.iter().find(|p| p.set == set) {
+        Some(p) => Ok(p.sk_len),
+        None => err_rv!(CKR_ATTRIBUTE_VALUE_INVALID),
+    }
+}
+
+#[derive(Debug)]
+pub struct MlDsaPubTemplate {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl MlDsaPubTemplate {
+    pub fn new() -> MlDsaPubTemplate {
+        let mut data: MlDsaPubTemplate = MlDsaPubTemplate {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes.append(&mut data.init_common_key_attrs());
+        data.attributes
+            .append(&mut data.init_common_public_key_attrs());
+        data.attributes.push(attr_element!(CKA_PARAMETER_SET; OAFlags::AlwaysRequired | OAFlags::Unchangeable; from_ulong; val 0));
+        data.attributes.push(attr_element!(CKA_VALUE; OAFlags::RequiredOnCreate | OAFlags::UnsettableOnGenerate | OAFlags::Unchangeable; from_bytes; val Vec::new()));
+        data
+    }
+}
+
+impl ObjectTemplate for MlDsaPubTemplate {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> KResult<Object> {
+        let obj = self.default_object_create(template, false)?;
+
+        let params = params_from_obj(&obj)?;
+        if obj.get_attr_as_bytes(CKA_VALUE)?.len() != params.pk_len {
+            return err_rv!(CKR_ATTRIBUTE_VALUE_INVALID);
+        }
+
+        Ok(obj)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl CommonKeyTemplate for MlDsaPubTemplate {
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl PubKeyTemplate for MlDsaPubTemplate {
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+#[derive(Debug)]
+pub struct MlDsaPrivTemplate {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl MlDsaPrivTemplate {
+    pub fn new() -> MlDsaPrivTemplate {
+        let mut data: MlDsaPrivTemplate = MlDsaPrivTemplate {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes.append(&mut data.init_common_key_attrs());
+        data.attributes
+            .append(&mut data.init_common_private_key_attrs());
+        data.attributes.push(attr_element!(CKA_PARAMETER_SET; OAFlags::RequiredOnCreate | OAFlags::Unchangeable; from_ulong; val 0));
+        data.attributes.push(attr_element!(CKA_VALUE; OAFlags::Sensitive | OAFlags::RequiredOnCreate | OAFlags::UnsettableOnGenerate | OAFlags::Unchangeable; from_bytes; val Vec::new()));
+
+        /* default to private */
+        let private = attr_element!(CKA_PRIVATE; OAFlags::Defval | OAFlags::ChangeOnCopy; from_bool; val true);
+        match data
+            .attributes
+            .iter()
+            .position(|x| x.get_type() == CKA_PRIVATE)
+        {
+            Some(idx) => data.attributes[idx] = private,
+            None => data.attributes.push(private),
+        }
+
+        data
+    }
+}
+
+impl ObjectTemplate for MlDsaPrivTemplate {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> KResult<Object> {
+        let obj = self.default_object_create(template, false)?;
+
+        let params = params_from_obj(&obj)?;
+        bytes_attr_not_empty!(obj; CKA_VALUE);
+        if obj.get_attr_as_bytes(CKA_VALUE)?.len() != params.sk_len {
+            return err_rv!(CKR_ATTRIBUTE_VALUE_INVALID);
+        }
+
+        Ok(obj)
+    }
+
+    fn unwrap(
+        &self,
+        template: &[CK_ATTRIBUTE],
+        data: &[u8],
+    ) -> KResult<Object> {
+        self.default_private_key_unwrap(template, data)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl CommonKeyTemplate for MlDsaPrivTemplate {
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl PrivKeyTemplate for MlDsaPrivTemplate {
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+static PUBLIC_KEY_TEMPLATE: Lazy<Box<dyn ObjectTemplate>> =
+    Lazy::new(|| Box::new(MlDsaPubTemplate::new()));
+
+static PRIVATE_KEY_TEMPLATE: Lazy<Box<dyn ObjectTemplate>> =
+    Lazy::new(|| Box::new(MlDsaPrivTemplate::new()));
+
+fn check_key_object(key: &Object, public: bool, op: CK_ULONG) -> KResult<()> {
+    match key.get_attr_as_ulong(CKA_CLASS)? {
+        CKO_PUBLIC_KEY => {
+            if !public {
+                return err_rv!(CKR_KEY_TYPE_INCONSISTENT);
+            }
+        }
+        CKO_PRIVATE_KEY => {
+            if public {
+                return err_rv!(CKR_KEY_TYPE_INCONSISTENT);
+            }
+        }
+        _ => return err_rv!(CKR_KEY_TYPE_INCONSISTENT),
+    }
+    match key.get_attr_as_ulong(CKA_KEY_TYPE)? {
+        CKK_ML_DSA => (),
+        _ => return err_rv!(CKR_KEY_TYPE_INCONSISTENT),
+    }
+    match key.get_attr_as_bool(op) {
+        Ok(avail) => {
+            if !avail {
+                return err_rv!(CKR_KEY_FUNCTION_NOT_PERMITTED);
+            }
+        }
+        Err(_) => return err_rv!(CKR_KEY_FUNCTION_NOT_PERMITTED),
+    }
+    Ok(())
+}
+
+/* DER encoded OIDs of the hash functions allowed for HashML-DSA,
+ * FIPS 204 5.4.1 */
+fn hash_oid(hash: CK_MECHANISM_TYPE) -> KResult<Vec<u8>> {
+    let arc: u8 = match hash {
+        CKM_SHA256 => 0x01,
+        CKM_SHA384 => 0x02,
+        CKM_SHA512 => 0x03,
+        CKM_SHA224 => 0x04,
+        CKM_SHA3_224 => 0x07,
+        CKM_SHA3_256 => 0x08,
+        CKM_SHA3_384 => 0x09,
+        CKM_SHA3_512 => 0x0a,
+        _ => return err_rv!(CKR_MECHANISM_PARAM_INVALID),
+    };
+    Ok(der::der_wrap(
+        der::DER_OID_TAG,
+        &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, arc],
+    ))
+}
+
+fn prehash_mech(mech: CK_MECHANISM_TYPE) -> Option<CK_MECHANISM_TYPE> {
+    match mech {
+        CKM_HASH_ML_DSA_SHA224 => Some(CKM_SHA224),
+        CKM_HASH_ML_DSA_SHA256 => Some(CKM_SHA256),
+        CKM_HASH_ML_DSA_SHA384 => Some(CKM_SHA384),
+        CKM_HASH_ML_DSA_SHA512 => Some(CKM_SHA512),
+        CKM_HASH_ML_DSA_SHA3_224 => Some(CKM_SHA3_224),
+        CKM_HASH_ML_DSA_SHA3_256 => Some(CKM_SHA3_256),
+        CKM_HASH_ML_DSA_SHA3_384 => Some(CKM_SHA3_384),
+        CKM_HASH_ML_DSA_SHA3_512 => Some(CKM_SHA3_512),
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+struct MlDsaSigParams {
+    deterministic: bool,
+    context: Vec<u8>,
+    /* the pre-hash function for HashML-DSA */
+    hash: Option<CK_MECHANISM_TYPE>,
+    /* whether the data is hashed by the token or by the caller */
+    hash_data: bool,
+}
+
+/* Also used for SLH-DSA, which has the same context string limit,
+ * FIPS 205 10.2 */
+pub fn context_data(
+    hedge: CK_HEDGE_TYPE,
+    context: CK_BYTE_PTR,
+    context_len: CK_ULONG,
+) -> KResult<(bool, Vec<u8>)> {
+    let deterministic = match hedge {
+        CKH_HEDGE_PREFERRED | CKH_HEDGE_REQUIRED => false,
+        CKH_DETERMINISTIC_REQUIRED => true,
+        _ => return err_rv!(CKR_MECHANISM_PARAM_INVALID),
+    };
+    let ctxlen = context_len as usize;
+    if ctxlen > MAX_ML_DSA_CONTEXT_LEN {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    if ctxlen == 0 {
+        return Ok((deterministic, Vec::new()));
+    }
+    if context.is_null() {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    Ok((deterministic, unsafe {
+        std::slice::from_raw_parts(context, ctxlen).to_vec()
+    }))
+}
+
+fn parse_params(mech: &CK_MECHANISM) -> KResult<MlDsaSigParams> {
+    if mech.mechanism == CKM_HASH_ML_DSA {
+        if mech.pParameter.is_null()
+            || mech.ulParameterLen as usize
+                != ::std::mem::size_of::<CK_HASH_SIGN_ADDITIONAL_CONTEXT>()
+        {
+            return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+        }
+        let params = unsafe {
+            &*(mech.pParameter as *const CK_HASH_SIGN_ADDITIONAL_CONTEXT)
+        };
+        let (deterministic, context) = context_data(
+            params.hedgeVariant,
+            params.pContext,
+            params.ulContextLen,
+        )?;
+        hash_oid(params.hash)?;
+        return Ok(MlDsaSigParams {
+            deterministic: deterministic,
+            context: context,
+            hash: Some(params.hash),
+            hash_data: false,
+        });
+    }
+
+    let hash = match mech.mechanism {
+        CKM_ML_DSA => None,
+        m => match prehash_mech(m) {
+            Some(h) => Some(h),
+            None => return err_rv!(CKR_MECHANISM_INVALID),
+        },
+    };
+    if mech.pParameter.is_null() {
+        if mech.ulParameterLen != 0 {
+            return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+        }
+        return Ok(MlDsaSigParams {
+            deterministic: false,
+            context: Vec::new(),
+            hash: hash,
+            hash_data: hash.is_some(),
+        });
+    }
+    if mech.ulParameterLen as usize
+        != ::std::mem::size_of::<CK_SIGN_ADDITIONAL_CONTEXT>()
+    {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    let params =
+        unsafe { &*(mech.pParameter as *const CK_SIGN_ADDITIONAL_CONTEXT) };
+    let (deterministic, context) = context_data(
+        params.hedgeVariant,
+        params.pContext,
+        params.ulContextLen,
+    )?;
+    Ok(MlDsaSigParams {
+        deterministic: deterministic,
+        context: context,
+        hash: hash,
+        hash_data: hash.is_some(),
+    })
+}
+
+/* FIPS 204 Algorithm 4: the message representative for HashML-DSA,
+ * signed with the internal interface */
+fn prehash_message(params: &MlDsaSigParams, digest: &[u8]) -> KResult<Vec<u8>> {
+    let hash = match params.hash {
+        Some(h) => h,
+        None => return err_rv!(CKR_GENERAL_ERROR),
+    };
+    let mut msg = vec![1u8, params.context.len() as u8];
+    msg.extend_from_slice(&params.context);
+    msg.extend_from_slice(&hash_oid(hash)?);
+    msg.extend_from_slice(digest);
+    Ok(msg)
+}
+
+#[derive(Debug)]
+struct MlDsaMechanism {
+    info: CK_MECHANISM_INFO,
+}
+
+impl Mechanism for MlDsaMechanism {
+    fn info(&self) -> &CK_MECHANISM_INFO {
+        &self.info
+    }
+
+    fn sign_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> KResult<Box<dyn Sign>> {
+        if self.info.flags & CKF_SIGN != CKF_SIGN {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        match check_key_object(key, false, CKA_SIGN) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        Ok(Box::new(MlDsaOperation::sign_new(mech, key)?))
+    }
+
+    fn verify_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> KResult<Box<dyn Verify>> {
+        if self.info.flags & CKF_VERIFY != CKF_VERIFY {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        match check_key_object(key, true, CKA_VERIFY) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        Ok(Box::new(MlDsaOperation::verify_new(mech, key)?))
+    }
+
+    fn generate_keypair(
+        &self,
+        mech: &CK_MECHANISM,
+        pubkey_template: &[CK_ATTRIBUTE],
+        prikey_template: &[CK_ATTRIBUTE],
+    ) -> KResult<(Object, Object)> {
+        if mech.mechanism != CKM_ML_DSA_KEY_PAIR_GEN {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        let mut pubkey =
+            PUBLIC_KEY_TEMPLATE.default_object_create(pubkey_template, true)?;
+        if !pubkey.check_or_set_attr(from_ulong(CKA_CLASS, CKO_PUBLIC_KEY))? {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        if !pubkey.check_or_set_attr(from_ulong(CKA_KEY_TYPE, CKK_ML_DSA))? {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        let params = match params_from_obj(&pubkey) {
+            Ok(p) => p,
+            Err(_) => return err_rv!(CKR_TEMPLATE_INCONSISTENT),
+        };
+
+        let mut privkey = PRIVATE_KEY_TEMPLATE
+            .default_object_create(prikey_template, true)?;
+        if !privkey.check_or_set_attr(from_ulong(CKA_CLASS, CKO_PRIVATE_KEY))? {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        if !privkey.check_or_set_attr(from_ulong(CKA_KEY_TYPE, CKK_ML_DSA))? {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        if !privkey
+            .check_or_set_attr(from_ulong(CKA_PARAMETER_SET, params.set))?
+        {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+
+        MlDsaOperation::generate_keypair(params, &mut pubkey, &mut privkey)?;
+
+        Ok((pubkey, privkey))
+    }
+}
+
+pub fn register(mechs: &mut Mechanisms, ot: &mut ObjectTemplates) {
+    mechs.add_mechanism(
+        CKM_ML_DSA_KEY_PAIR_GEN,
+        Box::new(MlDsaMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_GENERATE_KEY_PAIR,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_ML_DSA,
+        Box::new(MlDsaMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_SIGN | CKF_VERIFY,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_HASH_ML_DSA,
+        Box::new(MlDsaMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_SIGN | CKF_VERIFY,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_HASH_ML_DSA_SHA224,
+        Box::new(MlDsaMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_SIGN | CKF_VERIFY,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_HASH_ML_DSA_SHA256,
+        Box::new(MlDsaMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_SIGN | CKF_VERIFY,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_HASH_ML_DSA_SHA384,
+        Box::new(MlDsaMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_SIGN | CKF_VERIFY,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_HASH_ML_DSA_SHA512,
+        Box::new(MlDsaMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_SIGN | CKF_VERIFY,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_HASH_ML_DSA_SHA3_224,
+        Box::new(MlDsaMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_SIGN | CKF_VERIFY,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_HASH_ML_DSA_SHA3_256,
+        Box::new(MlDsaMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_SIGN | CKF_VERIFY,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_HASH_ML_DSA_SHA3_384,
+        Box::new(MlDsaMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_SIGN | CKF_VERIFY,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_HASH_ML_DSA_SHA3_512,
+        Box::new(MlDsaMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_SIGN | CKF_VERIFY,
+            },
+        }),
+    );
+
+    ot.add_template(ObjectType::MlDsaPubKey, &PUBLIC_KEY_TEMPLATE);
+    ot.add_template(ObjectType::MlDsaPrivKey, &PRIVATE_KEY_TEMPLATE);
+}
+
+#[cfg(feature = "fips")]
+include!("ossl/mldsa.rs");
+
+#[cfg(not(feature = "fips"))]
+include!("ossl/mldsa.rs");
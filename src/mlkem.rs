@@ -0,0 +1,398 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use super::attribute;
+use super::error;
+use super::interface;
+use super::object;
+use super::{attr_element, bytes_attr_not_empty, err_rv};
+
+use attribute::{from_bool, from_bytes, from_ulong};
+use error::{KError, KResult};
+use interface::*;
+use object::{
+    CommonKeyTemplate, OAFlags, Object, ObjectAttr, ObjectTemplate,
+    ObjectTemplates, ObjectType, PrivKeyTemplate, PubKeyTemplate,
+};
+
+use super::mechanism;
+use mechanism::*;
+
+use once_cell::sync::Lazy;
+use std::fmt::Debug;
+
+pub const ML_KEM_SHARED_SECRET_LEN: usize = 32;
+
+#[derive(Debug)]
+struct MlKemParams {
+    set: CK_ML_KEM_PARAMETER_SET_TYPE,
+    ossl_name: &'static [u8],
+    ek_len: usize,
+    dk_len: usize,
+    ct_len: usize,
+}
+
+/* FIPS 203, 8: encapsulation key, decapsulation key and ciphertext sizes */
+static ML_KEM_PARAMS: [MlKemParams; 3] = [
+    MlKemParams {
+        set: CKP_ML_KEM_512,
+        ossl_name: b"ML-KEM-512\0",
+        ek_len: 800,
+        dk_len: 1632,
+        ct_len: 768,
+    },
+    MlKemParams {
+        set: CKP_ML_KEM_768,
+        ossl_name: b"ML-KEM-768\0",
+        ek_len: 1184,
+        dk_len: 2400,
+        ct_len: 1088,
+    },
+    MlKemParams {
+        set: CKP_ML_KEM_1024,
+        ossl_name: b"ML-KEM-1024\0",
+        ek_len: 1568,
+        dk_len: 3168,
+        ct_len: 1568,
+    },
+];
+
+fn params_from_obj(obj: &Object) -> KResult<&'static MlKemParams> {
+    let set = obj.get_attr_as_ulong(CKA_PARAMETER_SET)?;
+    match ML_KEM_PARAMS.iter().find(|p| p.set == set) {
+        Some(p) => Ok(p),
+        None => err_rv!(CKR_ATTRIBUTE_VALUE_INVALID),
+    }
+}
+
+#[derive(Debug)]
+pub struct MlKemPubTemplate {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl MlKemPubTemplate {
+    pub fn new() -> MlKemPubTemplate {
+        let mut data: MlKemPubTemplate = MlKemPubTemplate {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes.append(&mut data.init_common_key_attrs());
+        data.attributes
+            .append(&mut data.init_common_public_key_attrs());
+        data.attributes.push(attr_element!(CKA_ENCAPSULATE; OAFlags::empty(); from_bool; val false));
+        data.attributes.push(attr_element!(CKA_PARAMETER_SET; OAFlags::AlwaysRequired | OAFlags::Unchangeable; from_ulong; val 0));
+        data.attributes.push(attr_element!(CKA_VALUE; OAFlags::RequiredOnCreate | OAFlags::UnsettableOnGenerate | OAFlags::Unchangeable; from_bytes; val Vec::new()));
+        data
+    }
+}
+
+impl ObjectTemplate for MlKemPubTemplate {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> KResult<Object> {
+        let obj = self.default_object_create(template, false)?;
+
+        let params = params_from_obj(&obj)?;
+        if obj.get_attr_as_bytes(CKA_VALUE)?.len() != params.ek_len {
+            return err_rv!(CKR_ATTRIBUTE_VALUE_INVALID);
+        }
+
+        Ok(obj)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl CommonKeyTemplate for MlKemPubTemplate {
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl PubKeyTemplate for MlKemPubTemplate {
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+#[derive(Debug)]
+pub struct MlKemPrivTemplate {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl MlKemPrivTemplate {
+    pub fn new() -> MlKemPrivTemplate {
+        let mut data: MlKemPrivTemplate = MlKemPrivTemplate {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes.append(&mut data.init_common_key_attrs());
+        data.attributes
+            .append(&mut data.init_common_private_key_attrs());
+        data.attributes.push(attr_element!(CKA_DECAPSULATE; OAFlags::empty(); from_bool; val false));
+        data.attributes.push(attr_element!(CKA_PARAMETER_SET; OAFlags::RequiredOnCreate | OAFlags::Unchangeable; from_ulong; val 0));
+        data.attributes.push(attr_element!(CKA_VALUE; OAFlags::Sensitive | OAFlags::RequiredOnCreate | OAFlags::UnsettableOnGenerate | OAFlags::Unchangeable; from_bytes; val Vec::new()));
+
+        /* default to private */
+        let private = attr_element!(CKA_PRIVATE; OAFlags::Defval | OAFlags::ChangeOnCopy; from_bool; val true);
+        match data
+            .attributes
+            .iter()
+            .position(|x| x.get_type() == CKA_PRIVATE)
+        {
+            Some(idx) => data.attributes[idx] = private,
+            None => data.attributes.push(private),
+        }
+
+        data
+    }
+}
+
+impl ObjectTemplate for MlKemPrivTemplate {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> KResult<Object> {
+        let obj = self.default_object_create(template, false)?;
+
+        let params = params_from_obj(&obj)?;
+        bytes_attr_not_empty!(obj; CKA_VALUE);
+        if obj.get_attr_as_bytes(CKA_VALUE)?.len() != params.dk_len {
+            return err_rv!(CKR_ATTRIBUTE_VALUE_INVALID);
+        }
+
+        Ok(obj)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl CommonKeyTemplate for MlKemPrivTemplate {
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl PrivKeyTemplate for MlKemPrivTemplate {
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+static PUBLIC_KEY_TEMPLATE: Lazy<Box<dyn ObjectTemplate>> =
+    Lazy::new(|| Box::new(MlKemPubTemplate::new()));
+
+static PRIVATE_KEY_TEMPLATE: Lazy<Box<dyn ObjectTemplate>> =
+    Lazy::new(|| Box::new(MlKemPrivTemplate::new()));
+
+fn check_key_object(
+    key: &Object,
+    class: CK_OBJECT_CLASS,
+    op: CK_ULONG,
+) -> KResult<()> {
+    if key.get_attr_as_ulong(CKA_CLASS)? != class {
+        return err_rv!(CKR_KEY_TYPE_INCONSISTENT);
+    }
+    match key.get_attr_as_ulong(CKA_KEY_TYPE)? {
+        CKK_ML_KEM => (),
+        _ => return err_rv!(CKR_KEY_TYPE_INCONSISTENT),
+    }
+    match key.get_attr_as_bool(op) {
+        Ok(avail) => {
+            if !avail {
+                return err_rv!(CKR_KEY_FUNCTION_NOT_PERMITTED);
+            }
+        }
+        Err(_) => return err_rv!(CKR_KEY_FUNCTION_NOT_PERMITTED),
+    }
+    Ok(())
+}
+
+/* The shared secret becomes a generic secret unless the template asks
+ * for a different key type, it can be truncated via CKA_VALUE_LEN */
+fn shared_key(
+    key: &Object,
+    template: &[CK_ATTRIBUTE],
+    objtemplates: &ObjectTemplates,
+    mut secret: Vec<u8>,
+) -> KResult<Object> {
+    let ktype: CK_KEY_TYPE = CKK_GENERIC_SECRET;
+    let mut tmpl = template.to_vec();
+    if !template.iter().any(|a| a.type_ == CKA_KEY_TYPE) {
+        tmpl.push(CK_ATTRIBUTE {
+            type_: CKA_KEY_TYPE,
+            pValue: &ktype as *const CK_KEY_TYPE as CK_VOID_PTR,
+            ulValueLen: ::std::mem::size_of::<CK_KEY_TYPE>() as CK_ULONG,
+        });
+    }
+    let mut obj = match objtemplates.derive_key_from_template(key, &tmpl) {
+        Ok(o) => o,
+        Err(e) => {
+            secret.zeroize();
+            return Err(e);
+        }
+    };
+    let keylen = match obj.get_attr_as_ulong(CKA_VALUE_LEN) {
+        Ok(n) => n as usize,
+        Err(e) => match e {
+            KError::NotFound(_) => secret.len(),
+            _ => {
+                secret.zeroize();
+                return Err(e);
+            }
+        },
+    };
+    if keylen == 0 || keylen > secret.len() {
+        secret.zeroize();
+        return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+    }
+    if obj.get_attr_as_ulong(CKA_KEY_TYPE)? == CKK_AES {
+        match keylen {
+            16 | 24 | 32 => (),
+            _ => {
+                secret.zeroize();
+                return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+            }
+        }
+    }
+    obj.del_attr(CKA_VALUE_LEN);
+    secret[keylen..].zeroize();
+    secret.truncate(keylen);
+    obj.set_attr(from_bytes(CKA_VALUE, secret))?;
+    Ok(obj)
+}
+
+#[derive(Debug)]
+struct MlKemMechanism {
+    info: CK_MECHANISM_INFO,
+}
+
+impl Mechanism for MlKemMechanism {
+    fn info(&self) -> &CK_MECHANISM_INFO {
+        &self.info
+    }
+
+    fn generate_keypair(
+        &self,
+        mech: &CK_MECHANISM,
+        pubkey_template: &[CK_ATTRIBUTE],
+        prikey_template: &[CK_ATTRIBUTE],
+    ) -> KResult<(Object, Object)> {
+        if mech.mechanism != CKM_ML_KEM_KEY_PAIR_GEN {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        let mut pubkey =
+            PUBLIC_KEY_TEMPLATE.default_object_create(pubkey_template, true)?;
+        if !pubkey.check_or_set_attr(from_ulong(CKA_CLASS, CKO_PUBLIC_KEY))? {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        if !pubkey.check_or_set_attr(from_ulong(CKA_KEY_TYPE, CKK_ML_KEM))? {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        let params = match params_from_obj(&pubkey) {
+            Ok(p) => p,
+            Err(_) => return err_rv!(CKR_TEMPLATE_INCONSISTENT),
+        };
+
+        let mut privkey = PRIVATE_KEY_TEMPLATE
+            .default_object_create(prikey_template, true)?;
+        if !privkey.check_or_set_attr(from_ulong(CKA_CLASS, CKO_PRIVATE_KEY))? {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        if !privkey.check_or_set_attr(from_ulong(CKA_KEY_TYPE, CKK_ML_KEM))? {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        if !privkey
+            .check_or_set_attr(from_ulong(CKA_PARAMETER_SET, params.set))?
+        {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+
+        generate_keypair(params, &mut pubkey, &mut privkey)?;
+
+        Ok((pubkey, privkey))
+    }
+
+    fn encapsulate_ciphertext_len(&self, key: &Object) -> KResult<usize> {
+        Ok(params_from_obj(key)?.ct_len)
+    }
+
+    fn encapsulate(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+        template: &[CK_ATTRIBUTE],
+        objtemplates: &ObjectTemplates,
+        ciphertext: &mut [u8],
+    ) -> KResult<(Object, usize)> {
+        if mech.mechanism != CKM_ML_KEM {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        check_key_object(key, CKO_PUBLIC_KEY, CKA_ENCAPSULATE)?;
+        let params = params_from_obj(key)?;
+        if ciphertext.len() < params.ct_len {
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
+        }
+
+        let secret = encapsulate(params, key, ciphertext)?;
+        let obj = shared_key(key, template, objtemplates, secret)?;
+        Ok((obj, params.ct_len))
+    }
+
+    fn decapsulate(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+        template: &[CK_ATTRIBUTE],
+        objtemplates: &ObjectTemplates,
+        ciphertext: &[u8],
+    ) -> KResult<Object> {
+        if mech.mechanism != CKM_ML_KEM {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        check_key_object(key, CKO_PRIVATE_KEY, CKA_DECAPSULATE)?;
+        let params = params_from_obj(key)?;
+        if ciphertext.len() != params.ct_len {
+            return err_rv!(CKR_WRAPPED_KEY_LEN_RANGE);
+        }
+
+        let secret = decapsulate(params, key, ciphertext)?;
+        shared_key(key, template, objtemplates, secret)
+    }
+}
+
+pub fn register(mechs: &mut Mechanisms, ot: &mut ObjectTemplates) {
+    mechs.add_mechanism(
+        CKM_ML_KEM_KEY_PAIR_GEN,
+        Box::new(MlKemMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_GENERATE_KEY_PAIR,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_ML_KEM,
+        Box::new(MlKemMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_ENCAPSULATE | CKF_DECAPSULATE,
+            },
+        }),
+    );
+
+    ot.add_template(ObjectType::MlKemPubKey, &PUBLIC_KEY_TEMPLATE);
+    ot.add_template(ObjectType::MlKemPrivKey, &PRIVATE_KEY_TEMPLATE);
+}
+
+#[cfg(feature = "fips")]
+include!("ossl/mlkem.rs");
+
+#[cfg(not(feature = "fips"))]
+include!("ossl/mlkem.rs");
@@ -0,0 +1,442 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use super::attribute;
+use super::ecc;
+use super::error;
+use super::interface;
+use super::object;
+use super::{attr_element, bytes_attr_not_empty, err_rv};
+
+use attribute::{from_bool, from_bytes};
+use ecc::EccCurve;
+use error::{KError, KResult};
+use interface::*;
+use object::{
+    CommonKeyTemplate, OAFlags, Object, ObjectAttr, ObjectTemplate,
+    ObjectTemplates, ObjectType, PrivKeyTemplate, PubKeyTemplate,
+};
+
+use super::mechanism;
+use mechanism::*;
+
+use once_cell::sync::Lazy;
+use std::fmt::Debug;
+
+pub const MIN_EC_MONTGOMERY_SIZE_BITS: usize = 256;
+pub const MAX_EC_MONTGOMERY_SIZE_BITS: usize = 448;
+
+/* Points of small order, in their canonical and non canonical encodings,
+ * the highest bit of the X25519 u-coordinate is ignored when comparing */
+static X25519_LOW_ORDER_POINTS: [[u8; 32]; 7] = [
+    [
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ],
+    [
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ],
+    [
+        0xe0, 0xeb, 0x7a, 0x7c, 0x3b, 0x41, 0xb8, 0xae, 0x16, 0x56, 0xe3, 0xfa,
+        0xf1, 0x9f, 0xc4, 0x6a, 0xda, 0x09, 0x8d, 0xeb, 0x9c, 0x32, 0xb1, 0xfd,
+        0x86, 0x62, 0x05, 0x16, 0x5f, 0x49, 0xb8, 0x00,
+    ],
+    [
+        0x5f, 0x9c, 0x95, 0xbc, 0xa3, 0x50, 0x8c, 0x24, 0xb1, 0xd0, 0xb1, 0x55,
+        0x9c, 0x83, 0xef, 0x5b, 0x04, 0x44, 0x5c, 0xc4, 0x58, 0x1c, 0x8e, 0x86,
+        0xd8, 0x22, 0x4e, 0xdd, 0xd0, 0x9f, 0x11, 0x57,
+    ],
+    [
+        0xec, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f,
+    ],
+    [
+        0xed, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f,
+    ],
+    [
+        0xee, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f,
+    ],
+];
+
+/* For X448 these are 0, 1, p - 1, p and p + 1 */
+fn is_x448_low_order(point: &[u8]) -> bool {
+    let (lo, hi) = point.split_at(28);
+    let all = |s: &[u8], v: u8| s.iter().all(|b| *b == v);
+    /* 0 and 1 */
+    if all(hi, 0x00) && (lo[0] == 0x00 || lo[0] == 0x01) && all(&lo[1..], 0) {
+        return true;
+    }
+    /* p - 1 and p, where p is 2^448 - 2^224 - 1 */
+    if hi[0] == 0xfe
+        && all(&hi[1..], 0xff)
+        && (lo[0] == 0xfe || lo[0] == 0xff)
+        && all(&lo[1..], 0xff)
+    {
+        return true;
+    }
+    /* p + 1 */
+    if all(hi, 0xff) && all(lo, 0x00) {
+        return true;
+    }
+    false
+}
+
+fn check_public_point(curve: EccCurve, point: &[u8]) -> KResult<()> {
+    if point.len() != curve.key_len() {
+        return err_rv!(CKR_ATTRIBUTE_VALUE_INVALID);
+    }
+    let low_order = match curve {
+        EccCurve::X25519 => X25519_LOW_ORDER_POINTS
+            .iter()
+            .any(|p| p[..31] == point[..31] && p[31] == (point[31] & 0x7f)),
+        EccCurve::X448 => is_x448_low_order(point),
+        _ => return err_rv!(CKR_ATTRIBUTE_VALUE_INVALID),
+    };
+    if low_order {
+        return err_rv!(CKR_ATTRIBUTE_VALUE_INVALID);
+    }
+    Ok(())
+}
+
+fn curve_from_obj(obj: &Object) -> KResult<EccCurve> {
+    let curve =
+        EccCurve::from_ec_params(obj.get_attr_as_bytes(CKA_EC_PARAMS)?)?;
+    if curve.key_type() != CKK_EC_MONTGOMERY {
+        return err_rv!(CKR_ATTRIBUTE_VALUE_INVALID);
+    }
+    Ok(curve)
+}
+
+#[derive(Debug)]
+pub struct ECMontgomeryPubTemplate {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl ECMontgomeryPubTemplate {
+    pub fn new() -> ECMontgomeryPubTemplate {
+        let mut data: ECMontgomeryPubTemplate = ECMontgomeryPubTemplate {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes.append(&mut data.init_common_key_attrs());
+        data.attributes
+            .append(&mut data.init_common_public_key_attrs());
+        data.attributes.push(attr_element!(CKA_EC_PARAMS; OAFlags::AlwaysRequired | OAFlags::Unchangeable; from_bytes; val Vec::new()));
+        data.attributes.push(attr_element!(CKA_EC_POINT; OAFlags::RequiredOnCreate | OAFlags::UnsettableOnGenerate | OAFlags::Unchangeable; from_bytes; val Vec::new()));
+        data
+    }
+}
+
+impl ObjectTemplate for ECMontgomeryPubTemplate {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> KResult<Object> {
+        let mut obj = self.default_object_create(template, false)?;
+
+        let curve = curve_from_obj(&obj)?;
+        let point =
+            ecc::ec_point_to_raw(curve, obj.get_attr_as_bytes(CKA_EC_POINT)?)?;
+        check_public_point(curve, &point)?;
+        /* always store the point in the DER encoding */
+        obj.set_attr(from_bytes(CKA_EC_POINT, ecc::raw_to_ec_point(&point)))?;
+
+        Ok(obj)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl CommonKeyTemplate for ECMontgomeryPubTemplate {
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl PubKeyTemplate for ECMontgomeryPubTemplate {
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+#[derive(Debug)]
+pub struct ECMontgomeryPrivTemplate {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl ECMontgomeryPrivTemplate {
+    pub fn new() -> ECMontgomeryPrivTemplate {
+        let mut data: ECMontgomeryPrivTemplate = ECMontgomeryPrivTemplate {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes.append(&mut data.init_common_key_attrs());
+        data.attributes
+            .append(&mut data.init_common_private_key_attrs());
+        data.attributes.push(attr_element!(CKA_EC_PARAMS; OAFlags::RequiredOnCreate | OAFlags::Unchangeable; from_bytes; val Vec::new()));
+        data.attributes.push(attr_element!(CKA_VALUE; OAFlags::Sensitive | OAFlags::RequiredOnCreate | OAFlags::UnsettableOnGenerate | OAFlags::Unchangeable; from_bytes; val Vec::new()));
+
+        /* default to private */
+        let private = attr_element!(CKA_PRIVATE; OAFlags::Defval | OAFlags::ChangeOnCopy; from_bool; val true);
+        match data
+            .attributes
+            .iter()
+            .position(|x| x.get_type() == CKA_PRIVATE)
+        {
+            Some(idx) => data.attributes[idx] = private,
+            None => data.attributes.push(private),
+        }
+
+        data
+    }
+}
+
+impl ObjectTemplate for ECMontgomeryPrivTemplate {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> KResult<Object> {
+        let obj = self.default_object_create(template, false)?;
+
+        let curve = curve_from_obj(&obj)?;
+        bytes_attr_not_empty!(obj; CKA_VALUE);
+        if obj.get_attr_as_bytes(CKA_VALUE)?.len() != curve.key_len() {
+            return err_rv!(CKR_ATTRIBUTE_VALUE_INVALID);
+        }
+
+        Ok(obj)
+    }
+
+    fn unwrap(
+        &self,
+        template: &[CK_ATTRIBUTE],
+        data: &[u8],
+    ) -> KResult<Object> {
+        self.default_private_key_unwrap(template, data)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl CommonKeyTemplate for ECMontgomeryPrivTemplate {
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl PrivKeyTemplate for ECMontgomeryPrivTemplate {
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+static PUBLIC_KEY_TEMPLATE: Lazy<Box<dyn ObjectTemplate>> =
+    Lazy::new(|| Box::new(ECMontgomeryPubTemplate::new()));
+
+static PRIVATE_KEY_TEMPLATE: Lazy<Box<dyn ObjectTemplate>> =
+    Lazy::new(|| Box::new(ECMontgomeryPrivTemplate::new()));
+
+fn check_key_object(key: &Object, op: CK_ULONG) -> KResult<()> {
+    match key.get_attr_as_ulong(CKA_CLASS)? {
+        CKO_PRIVATE_KEY => (),
+        _ => return err_rv!(CKR_KEY_TYPE_INCONSISTENT),
+    }
+    match key.get_attr_as_ulong(CKA_KEY_TYPE)? {
+        CKK_EC_MONTGOMERY => (),
+        _ => return err_rv!(CKR_KEY_TYPE_INCONSISTENT),
+    }
+    match key.get_attr_as_bool(op) {
+        Ok(avail) => {
+            if !avail {
+                return err_rv!(CKR_KEY_FUNCTION_NOT_PERMITTED);
+            }
+        }
+        Err(_) => return err_rv!(CKR_KEY_FUNCTION_NOT_PERMITTED),
+    }
+    Ok(())
+}
+
+/* Returns the raw peer public key */
+fn parse_ecdh_params(mech: &CK_MECHANISM, curve: EccCurve) -> KResult<Vec<u8>> {
+    if mech.pParameter.is_null()
+        || mech.ulParameterLen as usize
+            != ::std::mem::size_of::<CK_ECDH1_DERIVE_PARAMS>()
+    {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    let params =
+        unsafe { &*(mech.pParameter as *const CK_ECDH1_DERIVE_PARAMS) };
+    /* only the raw shared secret is supported for now */
+    if params.kdf != CKD_NULL || params.ulSharedDataLen != 0 {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    if params.pPublicData.is_null() || params.ulPublicDataLen == 0 {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    let public = unsafe {
+        std::slice::from_raw_parts(
+            params.pPublicData,
+            params.ulPublicDataLen as usize,
+        )
+    };
+    let point = match ecc::ec_point_to_raw(curve, public) {
+        Ok(p) => p,
+        Err(_) => return err_rv!(CKR_MECHANISM_PARAM_INVALID),
+    };
+    match check_public_point(curve, &point) {
+        Ok(()) => Ok(point),
+        Err(_) => err_rv!(CKR_MECHANISM_PARAM_INVALID),
+    }
+}
+
+#[derive(Debug)]
+struct ECMontgomeryMechanism {
+    info: CK_MECHANISM_INFO,
+}
+
+impl Mechanism for ECMontgomeryMechanism {
+    fn info(&self) -> &CK_MECHANISM_INFO {
+        &self.info
+    }
+
+    fn generate_keypair(
+        &self,
+        mech: &CK_MECHANISM,
+        pubkey_template: &[CK_ATTRIBUTE],
+        prikey_template: &[CK_ATTRIBUTE],
+    ) -> KResult<(Object, Object)> {
+        if mech.mechanism != CKM_EC_MONTGOMERY_KEY_PAIR_GEN {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        let mut pubkey =
+            PUBLIC_KEY_TEMPLATE.default_object_create(pubkey_template, true)?;
+        if !pubkey.check_or_set_attr(attribute::from_ulong(
+            CKA_CLASS,
+            CKO_PUBLIC_KEY,
+        ))? {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        if !pubkey.check_or_set_attr(attribute::from_ulong(
+            CKA_KEY_TYPE,
+            CKK_EC_MONTGOMERY,
+        ))? {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        let ec_params = pubkey.get_attr_as_bytes(CKA_EC_PARAMS)?.clone();
+        let curve = match curve_from_obj(&pubkey) {
+            Ok(c) => c,
+            Err(_) => return err_rv!(CKR_TEMPLATE_INCONSISTENT),
+        };
+
+        let mut privkey = PRIVATE_KEY_TEMPLATE
+            .default_object_create(prikey_template, true)?;
+        if !privkey.check_or_set_attr(attribute::from_ulong(
+            CKA_CLASS,
+            CKO_PRIVATE_KEY,
+        ))? {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        if !privkey.check_or_set_attr(attribute::from_ulong(
+            CKA_KEY_TYPE,
+            CKK_EC_MONTGOMERY,
+        ))? {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        if !privkey.check_or_set_attr(from_bytes(CKA_EC_PARAMS, ec_params))? {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+
+        generate_keypair(curve, &mut pubkey, &mut privkey)?;
+
+        Ok((pubkey, privkey))
+    }
+
+    fn derive_key(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+        _: &[&Object],
+        template: &[CK_ATTRIBUTE],
+        objtemplates: &ObjectTemplates,
+    ) -> KResult<Object> {
+        if mech.mechanism != CKM_ECDH1_DERIVE {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        check_key_object(key, CKA_DERIVE)?;
+        let curve = curve_from_obj(key)?;
+        let peer = parse_ecdh_params(mech, curve)?;
+
+        let mut obj = objtemplates.derive_key_from_template(key, template)?;
+
+        let mut secret = derive_secret(curve, key, &peer)?;
+        let keylen = match obj.get_attr_as_ulong(CKA_VALUE_LEN) {
+            Ok(n) => n as usize,
+            Err(e) => match e {
+                KError::NotFound(_) => secret.len(),
+                _ => return Err(e),
+            },
+        };
+        if keylen == 0 || keylen > secret.len() {
+            secret.zeroize();
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        /* the X448 shared secret is not a valid AES key length */
+        if obj.get_attr_as_ulong(CKA_KEY_TYPE)? == CKK_AES {
+            match keylen {
+                16 | 24 | 32 => (),
+                _ => {
+                    secret.zeroize();
+                    return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+                }
+            }
+        }
+        obj.del_attr(CKA_VALUE_LEN);
+        obj.set_attr(from_bytes(CKA_VALUE, secret[..keylen].to_vec()))?;
+        secret.zeroize();
+
+        Ok(obj)
+    }
+}
+
+pub fn register(mechs: &mut Mechanisms, ot: &mut ObjectTemplates) {
+    mechs.add_mechanism(
+        CKM_EC_MONTGOMERY_KEY_PAIR_GEN,
+        Box::new(ECMontgomeryMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: MIN_EC_MONTGOMERY_SIZE_BITS as CK_ULONG,
+                ulMaxKeySize: MAX_EC_MONTGOMERY_SIZE_BITS as CK_ULONG,
+                flags: CKF_GENERATE_KEY_PAIR,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_ECDH1_DERIVE,
+        Box::new(ECMontgomeryMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: MIN_EC_MONTGOMERY_SIZE_BITS as CK_ULONG,
+                ulMaxKeySize: MAX_EC_MONTGOMERY_SIZE_BITS as CK_ULONG,
+                flags: CKF_DERIVE,
+            },
+        }),
+    );
+
+    ot.add_template(ObjectType::ECMontgomeryPubKey, &PUBLIC_KEY_TEMPLATE);
+    ot.add_template(ObjectType::ECMontgomeryPrivKey, &PRIVATE_KEY_TEMPLATE);
+}
+
+#[cfg(feature = "fips")]
+include!("ossl/montgomery.rs");
+
+#[cfg(not(feature = "fips"))]
+include!("ossl/montgomery.rs");
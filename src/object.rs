@@ -8,10 +8,11 @@ use super::attribute;
 use super::error;
 use super::interface;
 use super::mechanism;
+use super::pkcs8;
 use super::{err_not_found, err_rv};
 use attribute::{
-    from_bool, from_bytes, from_date_bytes, from_ignore, from_string,
-    from_ulong, AttrType, Attribute,
+    from_attr_array, from_bool, from_bytes, from_date_bytes, from_ignore,
+    from_string, from_ulong, AttrType, Attribute,
 };
 use error::{KError, KResult};
 use interface::*;
@@ -164,6 +165,33 @@ impl Object {
         }
         true
     }
+
+    /* Checks that this wrapping key is allowed to wrap key according to
+     * CKA_WRAP_WITH_TRUSTED and CKA_WRAP_TEMPLATE */
+    pub fn check_wrap_policy(&self, key: &Object) -> KResult<()> {
+        if key.get_attr_as_bool(CKA_WRAP_WITH_TRUSTED).unwrap_or(false)
+            && !self.get_attr_as_bool(CKA_TRUSTED).unwrap_or(false)
+        {
+            return err_rv!(CKR_KEY_NOT_WRAPPABLE);
+        }
+        match self.get_attr(CKA_WRAP_TEMPLATE) {
+            Some(a) => {
+                let wrap_template = a.to_attr_array()?;
+                for attr in wrap_template.iter() {
+                    match key.get_attr(attr.get_type()) {
+                        Some(k) => {
+                            if k.get_value() != attr.get_value() {
+                                return err_rv!(CKR_KEY_NOT_WRAPPABLE);
+                            }
+                        }
+                        None => return err_rv!(CKR_KEY_NOT_WRAPPABLE),
+                    }
+                }
+            }
+            None => (),
+        }
+        Ok(())
+    }
 }
 
 bitflags! {
@@ -248,6 +276,14 @@ pub trait ObjectTemplate: Debug + Send + Sync {
         self.default_copy(obj, template)
     }
 
+    fn unwrap(
+        &self,
+        _template: &[CK_ATTRIBUTE],
+        _data: &[u8],
+    ) -> KResult<Object> {
+        return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+    }
+
     fn get_attributes(&self) -> &Vec<ObjectAttr>;
 
     fn init_common_object_attrs(&self) -> Vec<ObjectAttr> {
@@ -322,6 +358,143 @@ pub trait ObjectTemplate: Debug + Send + Sync {
         Ok(obj)
     }
 
+    fn default_object_derive(
+        &self,
+        template: &[CK_ATTRIBUTE],
+        origin: &Object,
+    ) -> KResult<Object> {
+        let attributes = self.get_attributes();
+        let mut obj = Object::new();
+
+        for ck_attr in template {
+            match attributes.iter().find(|a| a.get_type() == ck_attr.type_) {
+                Some(attr) => {
+                    if attr.is(OAFlags::UnsettableOnGenerate) {
+                        return err_rv!(CKR_ATTRIBUTE_TYPE_INVALID);
+                    }
+                    /* duplicate? */
+                    match obj.get_attr(ck_attr.type_) {
+                        Some(_) => return err_rv!(CKR_TEMPLATE_INCONSISTENT),
+                        None => (),
+                    }
+                    if !attr.is(OAFlags::Ignored) {
+                        obj.attributes.push(ck_attr.to_attribute()?);
+                    }
+                }
+                None => {
+                    return err_rv!(CKR_ATTRIBUTE_VALUE_INVALID);
+                }
+            }
+        }
+        for attr in attributes {
+            match obj.get_attr(attr.get_type()) {
+                Some(_) => (),
+                None => {
+                    if attr.has_default() {
+                        obj.attributes.push(attr.attribute.clone());
+                    }
+                }
+            }
+        }
+
+        /* pkcs11-spec-v3.1 4.10: a derived key can only be always
+         * sensitive and never extractable if the base key was too */
+        let always_sensitive = obj.is_sensitive()
+            && origin
+                .get_attr_as_bool(CKA_ALWAYS_SENSITIVE)
+                .unwrap_or(false);
+        obj.set_attr(from_bool(CKA_ALWAYS_SENSITIVE, always_sensitive))?;
+        let never_extractable = !obj.is_extractable()
+            && origin
+                .get_attr_as_bool(CKA_NEVER_EXTRACTABLE)
+                .unwrap_or(false);
+        obj.set_attr(from_bool(CKA_NEVER_EXTRACTABLE, never_extractable))?;
+
+        obj.generate_unique();
+        Ok(obj)
+    }
+
+    fn default_object_unwrap(
+        &self,
+        template: &[CK_ATTRIBUTE],
+    ) -> KResult<Object> {
+        let attributes = self.get_attributes();
+        let mut obj = Object::new();
+
+        for ck_attr in template {
+            match attributes.iter().find(|a| a.get_type() == ck_attr.type_) {
+                Some(attr) => {
+                    if attr.is(OAFlags::UnsettableOnUnwrap) {
+                        return err_rv!(CKR_ATTRIBUTE_TYPE_INVALID);
+                    }
+                    /* duplicate? */
+                    match obj.get_attr(ck_attr.type_) {
+                        Some(_) => return err_rv!(CKR_TEMPLATE_INCONSISTENT),
+                        None => (),
+                    }
+                    if !attr.is(OAFlags::Ignored) {
+                        obj.attributes.push(ck_attr.to_attribute()?);
+                    }
+                }
+                None => {
+                    return err_rv!(CKR_ATTRIBUTE_VALUE_INVALID);
+                }
+            }
+        }
+        for attr in attributes {
+            match obj.get_attr(attr.get_type()) {
+                Some(_) => (),
+                None => {
+                    if attr.has_default() {
+                        obj.attributes.push(attr.attribute.clone());
+                    }
+                }
+            }
+        }
+
+        /* pkcs11-spec-v3.1 4.10: the key material has been outside of the
+         * token, so an unwrapped key is never always sensitive nor never
+         * extractable */
+        obj.set_attr(from_bool(CKA_ALWAYS_SENSITIVE, false))?;
+        obj.set_attr(from_bool(CKA_NEVER_EXTRACTABLE, false))?;
+
+        obj.generate_unique();
+        Ok(obj)
+    }
+
+    fn default_secret_key_unwrap(
+        &self,
+        template: &[CK_ATTRIBUTE],
+        data: &[u8],
+    ) -> KResult<Object> {
+        let mut obj = self.default_object_unwrap(template)?;
+        match obj.get_attr_as_ulong(CKA_VALUE_LEN) {
+            Ok(len) => {
+                if len as usize != data.len() {
+                    return err_rv!(CKR_KEY_SIZE_RANGE);
+                }
+                obj.del_attr(CKA_VALUE_LEN);
+            }
+            Err(_) => (),
+        }
+        obj.set_attr(from_bytes(CKA_VALUE, data.to_vec()))?;
+        Ok(obj)
+    }
+
+    fn default_private_key_unwrap(
+        &self,
+        template: &[CK_ATTRIBUTE],
+        data: &[u8],
+    ) -> KResult<Object> {
+        let mut obj = self.default_object_unwrap(template)?;
+        for attr in pkcs8::decode_private_key(data)? {
+            if !obj.check_or_set_attr(attr)? {
+                return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+            }
+        }
+        Ok(obj)
+    }
+
     fn default_copy(
         &self,
         origin: &Object,
@@ -449,15 +622,6 @@ pub trait CertTemplate {
     }
 
     fn basic_cert_object_create_checks(&self, obj: &mut Object) -> CK_RV {
-        match obj.get_attr_as_bool(CKA_TRUSTED) {
-            Ok(t) => {
-                if t == true {
-                    /* until we implement checking for SO auth */
-                    return CKR_ATTRIBUTE_READ_ONLY;
-                }
-            }
-            Err(_) => (),
-        }
         match obj.get_attr_as_ulong(CKA_CERTIFICATE_CATEGORY) {
             Ok(c) => match c {
                 CK_CERTIFICATE_CATEGORY_UNSPECIFIED => (),
@@ -598,8 +762,8 @@ pub trait PubKeyTemplate {
             attr_element!(CKA_VERIFY; OAFlags::empty(); from_bool; val false),
             attr_element!(CKA_VERIFY_RECOVER; OAFlags::empty(); from_bool; val false),
             attr_element!(CKA_WRAP; OAFlags::empty(); from_bool; val false),
-            attr_element!(CKA_TRUSTED; OAFlags::NeverSettable; from_bool; val false),
-            attr_element!(CKA_WRAP_TEMPLATE; OAFlags::empty(); from_bytes; val Vec::new()),
+            attr_element!(CKA_TRUSTED; OAFlags::empty(); from_bool; val false),
+            attr_element!(CKA_WRAP_TEMPLATE; OAFlags::empty(); from_attr_array; val Vec::new()),
             attr_element!(CKA_PUBLIC_KEY_INFO; OAFlags::empty(); from_bytes; val Vec::new()),
         ]
     }
@@ -621,10 +785,10 @@ pub trait PrivKeyTemplate {
             attr_element!(CKA_ALWAYS_SENSITIVE; OAFlags::NeverSettable; from_bool; val false),
             attr_element!(CKA_NEVER_EXTRACTABLE; OAFlags::NeverSettable; from_bool; val false),
             attr_element!(CKA_WRAP_WITH_TRUSTED; OAFlags::Defval | OAFlags::ChangeToTrue; from_bool; val false),
-            attr_element!(CKA_UNWRAP_TEMPLATE; OAFlags::empty(); from_bytes; val Vec::new()),
+            attr_element!(CKA_UNWRAP_TEMPLATE; OAFlags::empty(); from_attr_array; val Vec::new()),
             attr_element!(CKA_ALWAYS_AUTHENTICATE; OAFlags::Defval; from_bool; val false),
             attr_element!(CKA_PUBLIC_KEY_INFO; OAFlags::empty(); from_bytes; val Vec::new()),
-            attr_element!(CKA_DERIVE_TEMPLATE; OAFlags::empty(); from_bytes; val Vec::new()),
+            attr_element!(CKA_DERIVE_TEMPLATE; OAFlags::empty(); from_attr_array; val Vec::new()),
         ]
     }
 
@@ -647,10 +811,10 @@ pub trait SecretKeyTemplate {
             attr_element!(CKA_NEVER_EXTRACTABLE; OAFlags::NeverSettable; from_bool; val false),
             attr_element!(CKA_CHECK_VALUE; OAFlags::Ignored; from_ignore; val None),
             attr_element!(CKA_WRAP_WITH_TRUSTED; OAFlags::Defval | OAFlags::ChangeToTrue; from_bool; val false),
-            attr_element!(CKA_TRUSTED; OAFlags::NeverSettable; from_bool; val false),
-            attr_element!(CKA_WRAP_TEMPLATE; OAFlags::empty(); from_bytes; val Vec::new()),
-            attr_element!(CKA_UNWRAP_TEMPLATE; OAFlags::empty(); from_bytes; val Vec::new()),
-            attr_element!(CKA_DERIVE_TEMPLATE; OAFlags::empty(); from_bytes; val Vec::new()),
+            attr_element!(CKA_TRUSTED; OAFlags::empty(); from_bool; val false),
+            attr_element!(CKA_WRAP_TEMPLATE; OAFlags::empty(); from_attr_array; val Vec::new()),
+            attr_element!(CKA_UNWRAP_TEMPLATE; OAFlags::empty(); from_attr_array; val Vec::new()),
+            attr_element!(CKA_DERIVE_TEMPLATE; OAFlags::empty(); from_attr_array; val Vec::new()),
         ]
     }
 
@@ -701,6 +865,17 @@ impl ObjectTemplate for GenericSecretKeyTemplate {
         Ok(obj)
     }
 
+    fn unwrap(
+        &self,
+        template: &[CK_ATTRIBUTE],
+        data: &[u8],
+    ) -> KResult<Object> {
+        if data.len() == 0 {
+            return err_rv!(CKR_WRAPPED_KEY_INVALID);
+        }
+        self.default_secret_key_unwrap(template, data)
+    }
+
     fn get_attributes(&self) -> &Vec<ObjectAttr> {
         &self.attributes
     }
@@ -749,6 +924,7 @@ impl Mechanism for GenericSecretKeyMechanism {
         &self,
         _mech: &CK_MECHANISM,
         template: &[CK_ATTRIBUTE],
+        _: &ObjectTemplates,
     ) -> KResult<Object> {
         let mut key =
             GENERIC_SECRET_TEMPLATE.default_object_create(template, true)?;
@@ -787,8 +963,81 @@ pub enum ObjectType {
     X509CertObj,
     RSAPubKey,
     RSAPrivKey,
+    EDDSAPubKey,
+    EDDSAPrivKey,
+    ECMontgomeryPubKey,
+    ECMontgomeryPrivKey,
+    MlKemPubKey,
+    MlKemPrivKey,
+    MlDsaPubKey,
+    MlDsaPrivKey,
+    SlhDsaPubKey,
+    SlhDsaPrivKey,
     GenericSecretKey,
     AesKey,
+    AesXtsKey,
+    ChaCha20Key,
+    Poly1305Key,
+}
+
+fn copy_attr_value(attr: &Attribute, ck_attr: &mut CK_ATTRIBUTE) -> CK_RV {
+    if attr.get_attrtype() == AttrType::AttrArrayType {
+        let attrs = match attr.to_attr_array() {
+            Ok(a) => a,
+            Err(_) => {
+                ck_attr.ulValueLen = CK_UNAVAILABLE_INFORMATION;
+                return CKR_GENERAL_ERROR;
+            }
+        };
+        let array_len =
+            (attrs.len() * std::mem::size_of::<CK_ATTRIBUTE>()) as CK_ULONG;
+        if ck_attr.pValue.is_null() {
+            ck_attr.ulValueLen = array_len;
+            return CKR_OK;
+        }
+        if ck_attr.ulValueLen < array_len {
+            ck_attr.ulValueLen = CK_UNAVAILABLE_INFORMATION;
+            return CKR_BUFFER_TOO_SMALL;
+        }
+        ck_attr.ulValueLen = array_len;
+        let array: &mut [CK_ATTRIBUTE] = unsafe {
+            std::slice::from_raw_parts_mut(
+                ck_attr.pValue as *mut _,
+                attrs.len(),
+            )
+        };
+        /* elements are filled in recursively, the caller is expected to
+         * provide buffers for each of them */
+        let mut result = CKR_OK;
+        for (a, ck_a) in attrs.iter().zip(array.iter_mut()) {
+            ck_a.type_ = a.get_type();
+            let rv = copy_attr_value(a, ck_a);
+            if rv != CKR_OK {
+                result = rv;
+            }
+        }
+        return result;
+    }
+
+    let attr_val = attr.get_value();
+    let attr_len = attr_val.len() as CK_ULONG;
+    if ck_attr.pValue.is_null() {
+        ck_attr.ulValueLen = attr_len;
+    } else {
+        if ck_attr.ulValueLen < attr_len {
+            ck_attr.ulValueLen = CK_UNAVAILABLE_INFORMATION;
+            return CKR_BUFFER_TOO_SMALL;
+        }
+        ck_attr.ulValueLen = attr_len;
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                attr_val.as_ptr(),
+                ck_attr.pValue as *mut _,
+                attr_val.len(),
+            );
+        }
+    }
+    CKR_OK
 }
 
 #[derive(Debug)]
@@ -859,6 +1108,21 @@ impl ObjectTemplates {
                     CKK_RSA => self
                         .get_template(ObjectType::RSAPubKey)?
                         .create(template),
+                    CKK_EC_EDWARDS => self
+                        .get_template(ObjectType::EDDSAPubKey)?
+                        .create(template),
+                    CKK_EC_MONTGOMERY => self
+                        .get_template(ObjectType::ECMontgomeryPubKey)?
+                        .create(template),
+                    CKK_ML_KEM => self
+                        .get_template(ObjectType::MlKemPubKey)?
+                        .create(template),
+                    CKK_ML_DSA => self
+                        .get_template(ObjectType::MlDsaPubKey)?
+                        .create(template),
+                    CKK_SLH_DSA => self
+                        .get_template(ObjectType::SlhDsaPubKey)?
+                        .create(template),
                     _ => err_rv!(CKR_ATTRIBUTE_VALUE_INVALID),
                 }
             }
@@ -872,6 +1136,21 @@ impl ObjectTemplates {
                     CKK_RSA => self
                         .get_template(ObjectType::RSAPrivKey)?
                         .create(template),
+                    CKK_EC_EDWARDS => self
+                        .get_template(ObjectType::EDDSAPrivKey)?
+                        .create(template),
+                    CKK_EC_MONTGOMERY => self
+                        .get_template(ObjectType::ECMontgomeryPrivKey)?
+                        .create(template),
+                    CKK_ML_KEM => self
+                        .get_template(ObjectType::MlKemPrivKey)?
+                        .create(template),
+                    CKK_ML_DSA => self
+                        .get_template(ObjectType::MlDsaPrivKey)?
+                        .create(template),
+                    CKK_SLH_DSA => self
+                        .get_template(ObjectType::SlhDsaPrivKey)?
+                        .create(template),
                     _ => err_rv!(CKR_ATTRIBUTE_VALUE_INVALID),
                 }
             }
@@ -883,12 +1162,21 @@ impl ObjectTemplates {
                     };
                 match ktype {
                     CKK_GENERIC_SECRET | CKK_SHA_1_HMAC | CKK_SHA256_HMAC
-                    | CKK_SHA384_HMAC | CKK_SHA512_HMAC => self
+                    | CKK_SHA384_HMAC | CKK_SHA512_HMAC | CKK_HKDF => self
                         .get_template(ObjectType::GenericSecretKey)?
                         .create(template),
                     CKK_AES => {
                         self.get_template(ObjectType::AesKey)?.create(template)
                     }
+                    CKK_AES_XTS => self
+                        .get_template(ObjectType::AesXtsKey)?
+                        .create(template),
+                    CKK_CHACHA20 => self
+                        .get_template(ObjectType::ChaCha20Key)?
+                        .create(template),
+                    CKK_POLY1305 => self
+                        .get_template(ObjectType::Poly1305Key)?
+                        .create(template),
                     _ => err_rv!(CKR_ATTRIBUTE_VALUE_INVALID),
                 }
             }
@@ -923,6 +1211,21 @@ impl ObjectTemplates {
                     Err(_) => err_rv!(CKR_DEVICE_ERROR),
                     Ok(ktype) => match ktype {
                         CKK_RSA => self.get_template(ObjectType::RSAPubKey),
+                        CKK_EC_EDWARDS => {
+                            self.get_template(ObjectType::EDDSAPubKey)
+                        }
+                        CKK_EC_MONTGOMERY => {
+                            self.get_template(ObjectType::ECMontgomeryPubKey)
+                        }
+                        CKK_ML_KEM => {
+                            self.get_template(ObjectType::MlKemPubKey)
+                        }
+                        CKK_ML_DSA => {
+                            self.get_template(ObjectType::MlDsaPubKey)
+                        }
+                        CKK_SLH_DSA => {
+                            self.get_template(ObjectType::SlhDsaPubKey)
+                        }
                         _ => err_rv!(CKR_DEVICE_ERROR),
                     },
                 },
@@ -930,6 +1233,21 @@ impl ObjectTemplates {
                     Err(_) => err_rv!(CKR_DEVICE_ERROR),
                     Ok(ktype) => match ktype {
                         CKK_RSA => self.get_template(ObjectType::RSAPrivKey),
+                        CKK_EC_EDWARDS => {
+                            self.get_template(ObjectType::EDDSAPrivKey)
+                        }
+                        CKK_EC_MONTGOMERY => {
+                            self.get_template(ObjectType::ECMontgomeryPrivKey)
+                        }
+                        CKK_ML_KEM => {
+                            self.get_template(ObjectType::MlKemPrivKey)
+                        }
+                        CKK_ML_DSA => {
+                            self.get_template(ObjectType::MlDsaPrivKey)
+                        }
+                        CKK_SLH_DSA => {
+                            self.get_template(ObjectType::SlhDsaPrivKey)
+                        }
                         _ => err_rv!(CKR_DEVICE_ERROR),
                     },
                 },
@@ -938,10 +1256,17 @@ impl ObjectTemplates {
                     Ok(ktype) => match ktype {
                         CKK_GENERIC_SECRET | CKK_SHA_1_HMAC
                         | CKK_SHA256_HMAC | CKK_SHA384_HMAC
-                        | CKK_SHA512_HMAC => {
+                        | CKK_SHA512_HMAC | CKK_HKDF => {
                             self.get_template(ObjectType::GenericSecretKey)
                         }
                         CKK_AES => self.get_template(ObjectType::AesKey),
+                        CKK_AES_XTS => self.get_template(ObjectType::AesXtsKey),
+                        CKK_CHACHA20 => {
+                            self.get_template(ObjectType::ChaCha20Key)
+                        }
+                        CKK_POLY1305 => {
+                            self.get_template(ObjectType::Poly1305Key)
+                        }
                         _ => err_rv!(CKR_DEVICE_ERROR),
                     },
                 },
@@ -1010,24 +1335,9 @@ impl ObjectTemplates {
                     continue;
                 }
                 Some(attr) => {
-                    let attr_val = attr.get_value();
-                    let attr_len = attr_val.len() as CK_ULONG;
-                    if ck_attr.pValue.is_null() {
-                        ck_attr.ulValueLen = attr_len;
-                    } else {
-                        if ck_attr.ulValueLen < attr_len {
-                            ck_attr.ulValueLen = CK_UNAVAILABLE_INFORMATION;
-                            result = CKR_BUFFER_TOO_SMALL;
-                        } else {
-                            ck_attr.ulValueLen = attr_len;
-                            unsafe {
-                                std::ptr::copy_nonoverlapping(
-                                    attr_val.as_ptr(),
-                                    ck_attr.pValue as *mut _,
-                                    attr_val.len(),
-                                );
-                            }
-                        }
+                    let rv = copy_attr_value(attr, ck_attr);
+                    if rv != CKR_OK {
+                        result = rv;
                     }
                 }
             }
@@ -1093,6 +1403,117 @@ impl ObjectTemplates {
         Ok(())
     }
 
+    pub fn derive_key_from_template(
+        &self,
+        origin: &Object,
+        template: &[CK_ATTRIBUTE],
+    ) -> KResult<Object> {
+        let class = match template.iter().find(|a| a.type_ == CKA_CLASS) {
+            Some(c) => c.to_ulong()?,
+            None => CKO_SECRET_KEY,
+        };
+        if class != CKO_SECRET_KEY {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        let ktype = match template.iter().find(|a| a.type_ == CKA_KEY_TYPE) {
+            Some(k) => k.to_ulong()?,
+            None => return err_rv!(CKR_TEMPLATE_INCOMPLETE),
+        };
+        let mut key = match ktype {
+            CKK_GENERIC_SECRET | CKK_SHA_1_HMAC | CKK_SHA256_HMAC
+            | CKK_SHA384_HMAC | CKK_SHA512_HMAC | CKK_HKDF => self
+                .get_template(ObjectType::GenericSecretKey)?
+                .default_object_derive(template, origin)?,
+            CKK_AES => self
+                .get_template(ObjectType::AesKey)?
+                .default_object_derive(template, origin)?,
+            _ => return err_rv!(CKR_TEMPLATE_INCONSISTENT),
+        };
+        /* catch invalid AES key lengths before any KDF runs, mechanisms
+         * that default the length still need to check the result */
+        if ktype == CKK_AES {
+            match key.get_attr_as_ulong(CKA_VALUE_LEN) {
+                Ok(16) | Ok(24) | Ok(32) => (),
+                Ok(_) => return err_rv!(CKR_TEMPLATE_INCONSISTENT),
+                Err(e) => match e {
+                    KError::NotFound(_) => (),
+                    _ => return Err(e),
+                },
+            }
+        }
+        key.set_attr(from_ulong(CKA_CLASS, CKO_SECRET_KEY))?;
+        Ok(key)
+    }
+
+    pub fn generate_key_from_template(
+        &self,
+        template: &[CK_ATTRIBUTE],
+    ) -> KResult<Object> {
+        let class = match template.iter().find(|a| a.type_ == CKA_CLASS) {
+            Some(c) => c.to_ulong()?,
+            None => CKO_SECRET_KEY,
+        };
+        if class != CKO_SECRET_KEY {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        let ktype = match template.iter().find(|a| a.type_ == CKA_KEY_TYPE) {
+            Some(k) => k.to_ulong()?,
+            None => return err_rv!(CKR_TEMPLATE_INCOMPLETE),
+        };
+        let mut key = match ktype {
+            CKK_GENERIC_SECRET | CKK_SHA_1_HMAC | CKK_SHA256_HMAC
+            | CKK_SHA384_HMAC | CKK_SHA512_HMAC | CKK_HKDF => self
+                .get_template(ObjectType::GenericSecretKey)?
+                .default_object_create(template, true)?,
+            CKK_AES => self
+                .get_template(ObjectType::AesKey)?
+                .default_object_create(template, true)?,
+            _ => return err_rv!(CKR_TEMPLATE_INCONSISTENT),
+        };
+        key.set_attr(from_ulong(CKA_CLASS, CKO_SECRET_KEY))?;
+        Ok(key)
+    }
+
+    pub fn unwrap_key_from_template(
+        &self,
+        template: &[CK_ATTRIBUTE],
+        data: &[u8],
+    ) -> KResult<Object> {
+        let class = match template.iter().find(|a| a.type_ == CKA_CLASS) {
+            Some(c) => c.to_ulong()?,
+            None => return err_rv!(CKR_TEMPLATE_INCOMPLETE),
+        };
+        let ktype = match template.iter().find(|a| a.type_ == CKA_KEY_TYPE) {
+            Some(k) => k.to_ulong()?,
+            None => match class {
+                CKO_PRIVATE_KEY => pkcs8::private_key_type(data)?,
+                _ => return err_rv!(CKR_TEMPLATE_INCOMPLETE),
+            },
+        };
+        let otype = match class {
+            CKO_SECRET_KEY => match ktype {
+                CKK_GENERIC_SECRET | CKK_SHA_1_HMAC | CKK_SHA256_HMAC
+                | CKK_SHA384_HMAC | CKK_SHA512_HMAC | CKK_HKDF => {
+                    ObjectType::GenericSecretKey
+                }
+                CKK_AES => ObjectType::AesKey,
+                CKK_AES_XTS => ObjectType::AesXtsKey,
+                CKK_CHACHA20 => ObjectType::ChaCha20Key,
+                CKK_POLY1305 => ObjectType::Poly1305Key,
+                _ => return err_rv!(CKR_TEMPLATE_INCONSISTENT),
+            },
+            CKO_PRIVATE_KEY => match ktype {
+                CKK_RSA => ObjectType::RSAPrivKey,
+                CKK_EC_EDWARDS => ObjectType::EDDSAPrivKey,
+                CKK_EC_MONTGOMERY => ObjectType::ECMontgomeryPrivKey,
+                CKK_ML_DSA => ObjectType::MlDsaPrivKey,
+                _ => return err_rv!(CKR_TEMPLATE_INCONSISTENT),
+            },
+            _ => return err_rv!(CKR_TEMPLATE_INCONSISTENT),
+        };
+        self.get_template(otype)?.unwrap(template, data)
+    }
+
     pub fn copy(
         &self,
         obj: &Object,
@@ -19,6 +19,25 @@ const AES_256_CBC_NAME: &[u8; 12] = b"AES-256-CBC\0";
 const AES_128_ECB_NAME: &[u8; 12] = b"AES-128-ECB\0";
 const AES_192_ECB_NAME: &[u8; 12] = b"AES-192-ECB\0";
 const AES_256_ECB_NAME: &[u8; 12] = b"AES-256-ECB\0";
+const AES_128_GCM_NAME: &[u8; 12] = b"AES-128-GCM\0";
+const AES_192_GCM_NAME: &[u8; 12] = b"AES-192-GCM\0";
+const AES_256_GCM_NAME: &[u8; 12] = b"AES-256-GCM\0";
+const AES_128_CCM_NAME: &[u8; 12] = b"AES-128-CCM\0";
+const AES_192_CCM_NAME: &[u8; 12] = b"AES-192-CCM\0";
+const AES_256_CCM_NAME: &[u8; 12] = b"AES-256-CCM\0";
+const AES_128_WRAP_NAME: &[u8; 13] = b"AES-128-WRAP\0";
+const AES_192_WRAP_NAME: &[u8; 13] = b"AES-192-WRAP\0";
+const AES_256_WRAP_NAME: &[u8; 13] = b"AES-256-WRAP\0";
+const AES_128_WRAP_PAD_NAME: &[u8; 17] = b"AES-128-WRAP-PAD\0";
+const AES_192_WRAP_PAD_NAME: &[u8; 17] = b"AES-192-WRAP-PAD\0";
+const AES_256_WRAP_PAD_NAME: &[u8; 17] = b"AES-256-WRAP-PAD\0";
+const AES_128_XTS_NAME: &[u8; 12] = b"AES-128-XTS\0";
+const AES_256_XTS_NAME: &[u8; 12] = b"AES-256-XTS\0";
+const AES_128_CBC_CTS_NAME: &[u8; 16] = b"AES-128-CBC-CTS\0";
+const AES_192_CBC_CTS_NAME: &[u8; 16] = b"AES-192-CBC-CTS\0";
+const AES_256_CBC_CTS_NAME: &[u8; 16] = b"AES-256-CBC-CTS\0";
+const CMAC_NAME: &[u8; 5] = b"CMAC\0";
+const GMAC_NAME: &[u8; 5] = b"GMAC\0";
 
 cfg_if::cfg_if! {
     if #[cfg(not(feature = "fips"))] {
@@ -49,6 +68,23 @@ cfg_if::cfg_if! {
             aes128ecb: EvpCipher,
             aes192ecb: EvpCipher,
             aes256ecb: EvpCipher,
+            aes128gcm: EvpCipher,
+            aes192gcm: EvpCipher,
+            aes256gcm: EvpCipher,
+            aes128ccm: EvpCipher,
+            aes192ccm: EvpCipher,
+            aes256ccm: EvpCipher,
+            aes128wrap: EvpCipher,
+            aes192wrap: EvpCipher,
+            aes256wrap: EvpCipher,
+            aes128wrappad: EvpCipher,
+            aes192wrappad: EvpCipher,
+            aes256wrappad: EvpCipher,
+            aes128xts: EvpCipher,
+            aes256xts: EvpCipher,
+            aes128cts: EvpCipher,
+            aes192cts: EvpCipher,
+            aes256cts: EvpCipher,
         }
     } else {
         struct AesCiphers {
@@ -61,6 +97,23 @@ cfg_if::cfg_if! {
             aes128ecb: EvpCipher,
             aes192ecb: EvpCipher,
             aes256ecb: EvpCipher,
+            aes128gcm: EvpCipher,
+            aes192gcm: EvpCipher,
+            aes256gcm: EvpCipher,
+            aes128ccm: EvpCipher,
+            aes192ccm: EvpCipher,
+            aes256ccm: EvpCipher,
+            aes128wrap: EvpCipher,
+            aes192wrap: EvpCipher,
+            aes256wrap: EvpCipher,
+            aes128wrappad: EvpCipher,
+            aes192wrappad: EvpCipher,
+            aes256wrappad: EvpCipher,
+            aes128xts: EvpCipher,
+            aes256xts: EvpCipher,
+            aes128cts: EvpCipher,
+            aes192cts: EvpCipher,
+            aes256cts: EvpCipher,
             aes128cfb8: EvpCipher,
             aes192cfb8: EvpCipher,
             aes256cfb8: EvpCipher,
@@ -106,6 +159,23 @@ cfg_if::cfg_if! {
             aes128ecb: init_cipher(AES_128_ECB_NAME),
             aes192ecb: init_cipher(AES_192_ECB_NAME),
             aes256ecb: init_cipher(AES_256_ECB_NAME),
+            aes128gcm: init_cipher(AES_128_GCM_NAME),
+            aes192gcm: init_cipher(AES_192_GCM_NAME),
+            aes256gcm: init_cipher(AES_256_GCM_NAME),
+            aes128ccm: init_cipher(AES_128_CCM_NAME),
+            aes192ccm: init_cipher(AES_192_CCM_NAME),
+            aes256ccm: init_cipher(AES_256_CCM_NAME),
+            aes128wrap: init_cipher(AES_128_WRAP_NAME),
+            aes192wrap: init_cipher(AES_192_WRAP_NAME),
+            aes256wrap: init_cipher(AES_256_WRAP_NAME),
+            aes128wrappad: init_cipher(AES_128_WRAP_PAD_NAME),
+            aes192wrappad: init_cipher(AES_192_WRAP_PAD_NAME),
+            aes256wrappad: init_cipher(AES_256_WRAP_PAD_NAME),
+            aes128xts: init_cipher(AES_128_XTS_NAME),
+            aes256xts: init_cipher(AES_256_XTS_NAME),
+            aes128cts: init_cipher(AES_128_CBC_CTS_NAME),
+            aes192cts: init_cipher(AES_192_CBC_CTS_NAME),
+            aes256cts: init_cipher(AES_256_CBC_CTS_NAME),
         });
     } else {
         static AES_CIPHERS: Lazy<AesCiphers> = Lazy::new(|| AesCiphers {
@@ -118,6 +188,23 @@ cfg_if::cfg_if! {
             aes128ecb: init_cipher(AES_128_ECB_NAME),
             aes192ecb: init_cipher(AES_192_ECB_NAME),
             aes256ecb: init_cipher(AES_256_ECB_NAME),
+            aes128gcm: init_cipher(AES_128_GCM_NAME),
+            aes192gcm: init_cipher(AES_192_GCM_NAME),
+            aes256gcm: init_cipher(AES_256_GCM_NAME),
+            aes128ccm: init_cipher(AES_128_CCM_NAME),
+            aes192ccm: init_cipher(AES_192_CCM_NAME),
+            aes256ccm: init_cipher(AES_256_CCM_NAME),
+            aes128wrap: init_cipher(AES_128_WRAP_NAME),
+            aes192wrap: init_cipher(AES_192_WRAP_NAME),
+            aes256wrap: init_cipher(AES_256_WRAP_NAME),
+            aes128wrappad: init_cipher(AES_128_WRAP_PAD_NAME),
+            aes192wrappad: init_cipher(AES_192_WRAP_PAD_NAME),
+            aes256wrappad: init_cipher(AES_256_WRAP_PAD_NAME),
+            aes128xts: init_cipher(AES_128_XTS_NAME),
+            aes256xts: init_cipher(AES_256_XTS_NAME),
+            aes128cts: init_cipher(AES_128_CBC_CTS_NAME),
+            aes192cts: init_cipher(AES_192_CBC_CTS_NAME),
+            aes256cts: init_cipher(AES_256_CBC_CTS_NAME),
             aes128cfb8: init_cipher(AES_128_CFB8_NAME),
             aes192cfb8: init_cipher(AES_192_CFB8_NAME),
             aes256cfb8: init_cipher(AES_256_CFB8_NAME),
@@ -151,11 +238,217 @@ fn object_to_raw_key(key: &Object) -> KResult<AesKey> {
     Ok(AesKey { raw: val.clone() })
 }
 
+fn init_aead_ctx(
+    ctx: &mut EvpCipherCtx,
+    evpcipher: &EvpCipher,
+    key: &[u8],
+    iv: &[u8],
+    aad: &[u8],
+    enc: bool,
+) -> KResult<()> {
+    let encflag = if enc { 1 } else { 0 };
+    if unsafe {
+        EVP_CipherInit_ex(
+            ctx.as_mut_ptr(),
+            evpcipher.as_ptr(),
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+            encflag,
+        )
+    } != 1
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    /* the IV length must be set before the IV itself */
+    let mut ivlen = iv.len();
+    let params = [
+        unsafe {
+            OSSL_PARAM_construct_size_t(
+                OSSL_CIPHER_PARAM_IVLEN.as_ptr() as *const i8,
+                &mut ivlen,
+            )
+        },
+        unsafe { OSSL_PARAM_construct_end() },
+    ];
+    if unsafe { EVP_CIPHER_CTX_set_params(ctx.as_mut_ptr(), params.as_ptr()) }
+        != 1
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    if unsafe {
+        EVP_CipherInit_ex(
+            ctx.as_mut_ptr(),
+            std::ptr::null(),
+            std::ptr::null_mut(),
+            key.as_ptr(),
+            iv.as_ptr(),
+            encflag,
+        )
+    } != 1
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    if aad.len() > 0 {
+        let mut outl: std::os::raw::c_int = 0;
+        if unsafe {
+            EVP_CipherUpdate(
+                ctx.as_mut_ptr(),
+                std::ptr::null_mut(),
+                &mut outl,
+                aad.as_ptr(),
+                aad.len() as std::os::raw::c_int,
+            )
+        } != 1
+        {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+    }
+    Ok(())
+}
+
+fn get_aead_tag(ctx: &mut EvpCipherCtx, tag: &mut [u8]) -> KResult<()> {
+    let mut params = [
+        unsafe {
+            OSSL_PARAM_construct_octet_string(
+                OSSL_CIPHER_PARAM_AEAD_TAG.as_ptr() as *const i8,
+                tag.as_mut_ptr() as *mut std::os::raw::c_void,
+                tag.len(),
+            )
+        },
+        unsafe { OSSL_PARAM_construct_end() },
+    ];
+    if unsafe {
+        EVP_CIPHER_CTX_get_params(ctx.as_mut_ptr(), params.as_mut_ptr())
+    } != 1
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    Ok(())
+}
+
+fn set_aead_tag(ctx: &mut EvpCipherCtx, tag: &mut [u8]) -> KResult<()> {
+    let params = [
+        unsafe {
+            OSSL_PARAM_construct_octet_string(
+                OSSL_CIPHER_PARAM_AEAD_TAG.as_ptr() as *const i8,
+                tag.as_mut_ptr() as *mut std::os::raw::c_void,
+                tag.len(),
+            )
+        },
+        unsafe { OSSL_PARAM_construct_end() },
+    ];
+    if unsafe { EVP_CIPHER_CTX_set_params(ctx.as_mut_ptr(), params.as_ptr()) }
+        != 1
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    Ok(())
+}
+
+fn init_ccm_ctx(
+    ctx: &mut EvpCipherCtx,
+    evpcipher: &EvpCipher,
+    key: &[u8],
+    params: &AesParams,
+    tag: Option<&mut [u8]>,
+    enc: bool,
+) -> KResult<()> {
+    let encflag = if enc { 1 } else { 0 };
+    if unsafe {
+        EVP_CipherInit_ex(
+            ctx.as_mut_ptr(),
+            evpcipher.as_ptr(),
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+            encflag,
+        )
+    } != 1
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    /* the IV and tag lengths must be set before the key and IV, when
+     * decrypting the expected tag is set at the same time */
+    let mut ivlen = params.iv.len();
+    let (tagptr, taglen) = match tag {
+        Some(t) => (t.as_mut_ptr() as *mut std::os::raw::c_void, t.len()),
+        None => (std::ptr::null_mut(), params.taglen),
+    };
+    let ctx_params = [
+        unsafe {
+            OSSL_PARAM_construct_size_t(
+                OSSL_CIPHER_PARAM_IVLEN.as_ptr() as *const i8,
+                &mut ivlen,
+            )
+        },
+        unsafe {
+            OSSL_PARAM_construct_octet_string(
+                OSSL_CIPHER_PARAM_AEAD_TAG.as_ptr() as *const i8,
+                tagptr,
+                taglen,
+            )
+        },
+        unsafe { OSSL_PARAM_construct_end() },
+    ];
+    if unsafe {
+        EVP_CIPHER_CTX_set_params(ctx.as_mut_ptr(), ctx_params.as_ptr())
+    } != 1
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    if unsafe {
+        EVP_CipherInit_ex(
+            ctx.as_mut_ptr(),
+            std::ptr::null(),
+            std::ptr::null_mut(),
+            key.as_ptr(),
+            params.iv.as_ptr(),
+            encflag,
+        )
+    } != 1
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    /* the total data length must be set before any AAD */
+    let mut outl: std::os::raw::c_int = 0;
+    if unsafe {
+        EVP_CipherUpdate(
+            ctx.as_mut_ptr(),
+            std::ptr::null_mut(),
+            &mut outl,
+            std::ptr::null(),
+            params.datalen as std::os::raw::c_int,
+        )
+    } != 1
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    if params.aad.len() > 0 {
+        if unsafe {
+            EVP_CipherUpdate(
+                ctx.as_mut_ptr(),
+                std::ptr::null_mut(),
+                &mut outl,
+                params.aad.as_ptr(),
+                params.aad.len() as std::os::raw::c_int,
+            )
+        } != 1
+        {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+    }
+    Ok(())
+}
+
 #[derive(Debug)]
 struct AesParams {
     pad: bool,
     iv: Vec<u8>,
     maxblocks: u128,
+    aad: Vec<u8>,
+    taglen: usize,
+    datalen: usize,
 }
 
 #[derive(Debug)]
@@ -169,11 +462,13 @@ struct AesOperation {
     blocksize: usize,
     finalbuf: Vec<u8>,
     blockctr: u128,
+    databuf: Vec<u8>,
 }
 
 impl Drop for AesOperation {
     fn drop(&mut self) {
-        self.finalbuf.zeroize()
+        self.finalbuf.zeroize();
+        self.databuf.zeroize();
     }
 }
 
@@ -181,7 +476,8 @@ impl AesOperation {
     fn init_params(mech: &CK_MECHANISM) -> KResult<AesParams> {
         let mut maxblocks = 0u128;
         let pad = match mech.mechanism {
-            CKM_AES_CTR | CKM_AES_CBC | CKM_AES_ECB => false,
+            CKM_AES_CTR | CKM_AES_CBC | CKM_AES_ECB | CKM_AES_GCM
+            | CKM_AES_CCM | CKM_AES_KEY_WRAP | CKM_AES_KEY_WRAP_KWP => false,
             CKM_AES_CBC_PAD => true,
             #[cfg(not(feature = "fips"))]
             CKM_AES_CFB8 | CKM_AES_CFB1 | CKM_AES_CFB128 | CKM_AES_OFB => false,
@@ -236,6 +532,9 @@ impl AesOperation {
                         pad: pad,
                         iv: iv,
                         maxblocks: maxblocks,
+                        aad: Vec::new(),
+                        taglen: 0,
+                        datalen: 0,
                     })
                 }
             }
@@ -253,13 +552,151 @@ impl AesOperation {
                             .to_vec()
                         },
                         maxblocks: maxblocks,
+                        aad: Vec::new(),
+                        taglen: 0,
+                        datalen: 0,
                     })
                 }
             }
+            CKM_AES_GCM => {
+                if mech.pParameter.is_null() {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+                if mech.ulParameterLen as usize
+                    != ::std::mem::size_of::<CK_GCM_PARAMS>()
+                {
+                    return err_rv!(CKR_ARGUMENTS_BAD);
+                }
+                let gcm_params =
+                    unsafe { &*(mech.pParameter as *const CK_GCM_PARAMS) };
+                if gcm_params.pIv.is_null() || gcm_params.ulIvLen == 0 {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+                if gcm_params.pAAD.is_null() && gcm_params.ulAADLen != 0 {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+                /* Tag sizes allowed by NIST SP 800-38D */
+                match gcm_params.ulTagBits {
+                    32 | 64 | 96 | 104 | 112 | 120 | 128 => (),
+                    _ => return err_rv!(CKR_MECHANISM_PARAM_INVALID),
+                }
+                Ok(AesParams {
+                    pad: pad,
+                    iv: unsafe {
+                        std::slice::from_raw_parts(
+                            gcm_params.pIv,
+                            gcm_params.ulIvLen as usize,
+                        )
+                        .to_vec()
+                    },
+                    maxblocks: maxblocks,
+                    aad: if gcm_params.ulAADLen == 0 {
+                        Vec::new()
+                    } else {
+                        unsafe {
+                            std::slice::from_raw_parts(
+                                gcm_params.pAAD,
+                                gcm_params.ulAADLen as usize,
+                            )
+                            .to_vec()
+                        }
+                    },
+                    taglen: (gcm_params.ulTagBits / 8) as usize,
+                    datalen: 0,
+                })
+            }
+            CKM_AES_CCM => {
+                if mech.pParameter.is_null() {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+                if mech.ulParameterLen as usize
+                    != ::std::mem::size_of::<CK_CCM_PARAMS>()
+                {
+                    return err_rv!(CKR_ARGUMENTS_BAD);
+                }
+                let ccm_params =
+                    unsafe { &*(mech.pParameter as *const CK_CCM_PARAMS) };
+                /* Nonce and MAC sizes allowed by NIST SP 800-38C */
+                let noncelen = ccm_params.ulNonceLen as usize;
+                if ccm_params.pNonce.is_null() || noncelen < 7 || noncelen > 13
+                {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+                match ccm_params.ulMACLen {
+                    4 | 6 | 8 | 10 | 12 | 14 | 16 => (),
+                    _ => return err_rv!(CKR_MECHANISM_PARAM_INVALID),
+                }
+                if ccm_params.pAAD.is_null() && ccm_params.ulAADLen != 0 {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+                /* the data length must fit in the remaining 15 - noncelen
+                 * octets of the first block */
+                let lenbits = (15 - noncelen) * 8;
+                let datalen = ccm_params.ulDataLen as usize;
+                if (lenbits < 64 && (datalen as u64) >= (1u64 << lenbits))
+                    || datalen > std::os::raw::c_int::MAX as usize
+                {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+                Ok(AesParams {
+                    pad: pad,
+                    iv: unsafe {
+                        std::slice::from_raw_parts(ccm_params.pNonce, noncelen)
+                            .to_vec()
+                    },
+                    maxblocks: maxblocks,
+                    aad: if ccm_params.ulAADLen == 0 {
+                        Vec::new()
+                    } else {
+                        unsafe {
+                            std::slice::from_raw_parts(
+                                ccm_params.pAAD,
+                                ccm_params.ulAADLen as usize,
+                            )
+                            .to_vec()
+                        }
+                    },
+                    taglen: ccm_params.ulMACLen as usize,
+                    datalen: datalen,
+                })
+            }
+            CKM_AES_KEY_WRAP | CKM_AES_KEY_WRAP_KWP => {
+                /* the IV is optional, the RFC default is used if omitted */
+                let ivlen = match mech.mechanism {
+                    CKM_AES_KEY_WRAP => 8,
+                    _ => 4,
+                };
+                let iv = match mech.ulParameterLen as usize {
+                    0 => Vec::new(),
+                    len => {
+                        if len != ivlen || mech.pParameter.is_null() {
+                            return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                        }
+                        unsafe {
+                            std::slice::from_raw_parts(
+                                mech.pParameter as *mut u8,
+                                len,
+                            )
+                            .to_vec()
+                        }
+                    }
+                };
+                Ok(AesParams {
+                    pad: pad,
+                    iv: iv,
+                    maxblocks: maxblocks,
+                    aad: Vec::new(),
+                    taglen: 0,
+                    datalen: 0,
+                })
+            }
             CKM_AES_ECB => Ok(AesParams {
                 pad: pad,
                 iv: Vec::with_capacity(0),
                 maxblocks: maxblocks,
+                aad: Vec::new(),
+                taglen: 0,
+                datalen: 0,
             }),
             #[cfg(not(feature = "fips"))]
             CKM_AES_CFB8 | CKM_AES_CFB1 | CKM_AES_CFB128 | CKM_AES_OFB => {
@@ -276,6 +713,9 @@ impl AesOperation {
                             .to_vec()
                         },
                         maxblocks: maxblocks,
+                        aad: Vec::new(),
+                        taglen: 0,
+                        datalen: 0,
                     })
                 }
             }
@@ -312,6 +752,30 @@ impl AesOperation {
                 32 => &AES_CIPHERS.aes256ecb,
                 _ => return err_rv!(CKR_MECHANISM_INVALID),
             },
+            CKM_AES_GCM => match keylen {
+                16 => &AES_CIPHERS.aes128gcm,
+                24 => &AES_CIPHERS.aes192gcm,
+                32 => &AES_CIPHERS.aes256gcm,
+                _ => return err_rv!(CKR_MECHANISM_INVALID),
+            },
+            CKM_AES_CCM => match keylen {
+                16 => &AES_CIPHERS.aes128ccm,
+                24 => &AES_CIPHERS.aes192ccm,
+                32 => &AES_CIPHERS.aes256ccm,
+                _ => return err_rv!(CKR_MECHANISM_INVALID),
+            },
+            CKM_AES_KEY_WRAP => match keylen {
+                16 => &AES_CIPHERS.aes128wrap,
+                24 => &AES_CIPHERS.aes192wrap,
+                32 => &AES_CIPHERS.aes256wrap,
+                _ => return err_rv!(CKR_MECHANISM_INVALID),
+            },
+            CKM_AES_KEY_WRAP_KWP => match keylen {
+                16 => &AES_CIPHERS.aes128wrappad,
+                24 => &AES_CIPHERS.aes192wrappad,
+                32 => &AES_CIPHERS.aes256wrappad,
+                _ => return err_rv!(CKR_MECHANISM_INVALID),
+            },
             #[cfg(not(feature = "fips"))]
             CKM_AES_CFB8 => match keylen {
                 16 => &AES_CIPHERS.aes128cfb8,
@@ -355,6 +819,7 @@ impl AesOperation {
             blocksize: 0,
             finalbuf: Vec::new(),
             blockctr: 0,
+            databuf: Vec::new(),
         })
     }
 
@@ -369,537 +834,2438 @@ impl AesOperation {
             blocksize: 0,
             finalbuf: Vec::new(),
             blockctr: 0,
+            databuf: Vec::new(),
         })
     }
 
-    fn wrap(
-        _mech: &CK_MECHANISM,
-        _wrapping_key: &Object,
-        _key: &Object,
-        _data: CK_BYTE_PTR,
-        _data_len: CK_ULONG_PTR,
-    ) -> KResult<()> {
-        return err_rv!(CKR_DEVICE_ERROR);
-    }
-
-    fn unwrap(
-        _mech: &CK_MECHANISM,
-        _wrapping_key: &Object,
-        _data: &[u8],
-    ) -> KResult<Vec<u8>> {
-        return err_rv!(CKR_DEVICE_ERROR);
-    }
-}
-
-impl MechOperation for AesOperation {
-    fn mechanism(&self) -> CK_MECHANISM_TYPE {
-        self.mech
-    }
-    fn in_use(&self) -> bool {
-        self.in_use
-    }
-    fn finalized(&self) -> bool {
-        self.finalized
+    fn init_aead(&mut self, evpcipher: &EvpCipher, enc: bool) -> KResult<()> {
+        init_aead_ctx(
+            self.ctx.as_mut().unwrap(),
+            evpcipher,
+            &self.key.raw,
+            &self.params.iv,
+            &self.params.aad,
+            enc,
+        )
     }
-}
 
-impl Encryption for AesOperation {
-    fn encrypt(
+    fn aead_encrypt_final(
         &mut self,
-        plain: &[u8],
         cipher: CK_BYTE_PTR,
         cipher_len: CK_ULONG_PTR,
     ) -> KResult<()> {
-        if self.in_use {
-            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        if cipher.is_null() {
+            unsafe {
+                *cipher_len = self.params.taglen as CK_ULONG;
+            }
+            return Ok(());
         }
-        if self.finalized {
-            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        if (unsafe { *cipher_len } as usize) < self.params.taglen {
+            /* This is the only non-fatal error */
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
         }
-        let clen: CK_ULONG = unsafe { *cipher_len };
-        let mut outb: *mut u8 = cipher;
-        let mut outl: CK_ULONG = unsafe { *cipher_len };
-        self.encrypt_update(plain, outb, &mut outl)?;
-        if outl > clen {
-            self.finalized = true;
+        self.finalized = true;
+
+        let ctx = self.ctx.as_mut().unwrap().as_mut_ptr();
+        let mut outl: std::os::raw::c_int = 0;
+        if unsafe { EVP_EncryptFinal_ex(ctx, cipher, &mut outl) } != 1 {
             return err_rv!(CKR_DEVICE_ERROR);
         }
-        if !self.params.pad {
-            self.finalized = true;
-            unsafe { *cipher_len = outl };
-            return Ok(());
+        /* GCM never holds back data, the final call only computes the tag */
+        if outl != 0 {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        get_aead_tag(self.ctx.as_mut().unwrap(), unsafe {
+            std::slice::from_raw_parts_mut(cipher, self.params.taglen)
+        })?;
+        unsafe {
+            *cipher_len = self.params.taglen as CK_ULONG;
         }
-        let mut foutl = clen - outl;
-        outb = unsafe { cipher.add(outl as usize) };
-        self.encrypt_final(outb, &mut foutl)?;
-        unsafe { *cipher_len = foutl + outl };
         Ok(())
     }
 
-    fn encrypt_update(
+    fn aead_decrypt_update(
         &mut self,
-        plain: &[u8],
-        cipher: CK_BYTE_PTR,
-        cipher_len: CK_ULONG_PTR,
+        cipher: &[u8],
+        plain: CK_BYTE_PTR,
+        plain_len: CK_ULONG_PTR,
     ) -> KResult<()> {
-        if cipher_len.is_null() {
-            return err_rv!(CKR_ARGUMENTS_BAD);
+        /* No plaintext can be released before the tag is checked, so all
+         * data is buffered and decrypted at once in the final call */
+        if !plain.is_null() {
+            self.databuf.extend_from_slice(cipher);
         }
-        if self.finalized {
-            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        unsafe {
+            *plain_len = 0;
         }
-        if !self.in_use {
-            self.in_use = true;
-
-            let evpcipher =
-                match Self::init_cipher(self.mech, self.key.raw.len()) {
-                    Ok(c) => c,
-                    Err(e) => {
-                        self.finalized = true;
-                        return Err(e);
-                    }
-                };
+        Ok(())
+    }
 
-            if unsafe {
-                EVP_EncryptInit_ex(
-                    self.ctx.as_mut().unwrap().as_mut_ptr(),
-                    evpcipher.as_ptr(),
-                    std::ptr::null_mut(),
-                    self.key.raw.as_ptr(),
-                    if self.params.iv.len() != 0 {
-                        self.params.iv.as_ptr()
-                    } else {
-                        std::ptr::null()
-                    },
-                )
-            } != 1
-            {
-                self.finalized = true;
-                return err_rv!(CKR_DEVICE_ERROR);
+    fn aead_decrypt_final(
+        &mut self,
+        plain: CK_BYTE_PTR,
+        plain_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if self.databuf.len() < self.params.taglen {
+            self.finalized = true;
+            return err_rv!(CKR_ENCRYPTED_DATA_LEN_RANGE);
+        }
+        let datalen = self.databuf.len() - self.params.taglen;
+        if plain.is_null() {
+            unsafe {
+                *plain_len = datalen as CK_ULONG;
             }
+            return Ok(());
+        }
+        if (unsafe { *plain_len } as usize) < datalen {
+            /* This is the only non-fatal error */
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
+        }
+        self.finalized = true;
+
+        let (data, tag) = self.databuf.split_at_mut(datalen);
+        set_aead_tag(self.ctx.as_mut().unwrap(), tag)?;
+        let ctx = self.ctx.as_mut().unwrap().as_mut_ptr();
+
+        /* decrypt in a scratch buffer so that nothing is returned to the
+         * caller unless the tag matches */
+        let mut outbuf: Vec<u8> = vec![0; datalen + AES_BLOCK_SIZE];
+        let mut outl: std::os::raw::c_int = 0;
+        if datalen > 0 {
             if unsafe {
-                EVP_CIPHER_CTX_set_padding(
-                    self.ctx.as_mut().unwrap().as_mut_ptr(),
-                    if self.params.pad { 1 } else { 0 },
+                EVP_DecryptUpdate(
+                    ctx,
+                    outbuf.as_mut_ptr(),
+                    &mut outl,
+                    data.as_ptr(),
+                    datalen as std::os::raw::c_int,
                 )
             } != 1
             {
-                self.finalized = true;
+                outbuf.zeroize();
                 return err_rv!(CKR_DEVICE_ERROR);
             }
-            self.blocksize =
-                unsafe { EVP_CIPHER_get_block_size(evpcipher.as_ptr()) }
-                    as usize;
         }
-
-        let cipher_ulen = unsafe { *cipher_len } as usize;
-        let outblocks = plain.len() / self.blocksize;
-        let outlen = outblocks * self.blocksize;
-        if cipher.is_null() {
-            unsafe {
-                *cipher_len = outlen as CK_ULONG;
-            }
-            return Ok(());
-        } else {
-            if !self.params.pad && plain.len() != outlen {
-                self.finalized = true;
-                return err_rv!(CKR_DATA_LEN_RANGE);
-            }
-            if cipher_ulen < outlen {
-                /* This is the only, non-fatal error */
-                return err_rv!(CKR_BUFFER_TOO_SMALL);
-            }
+        let mut foutl: std::os::raw::c_int = 0;
+        if unsafe {
+            EVP_DecryptFinal_ex(
+                ctx,
+                outbuf.as_mut_ptr().add(outl as usize),
+                &mut foutl,
+            )
+        } != 1
+        {
+            outbuf.zeroize();
+            return err_rv!(CKR_ENCRYPTED_DATA_INVALID);
         }
-
-        if self.params.maxblocks > 0 {
-            let reqblocks =
-                ((plain.len() + AES_BLOCK_SIZE - 1) / AES_BLOCK_SIZE) as u128;
-            if self.blockctr + reqblocks > self.params.maxblocks {
-                self.finalized = true;
-                return err_rv!(CKR_DATA_LEN_RANGE);
-            }
-            self.blockctr += reqblocks;
-        }
-
-        let mut outl: std::os::raw::c_int = 0;
-        if unsafe {
-            EVP_EncryptUpdate(
-                self.ctx.as_mut().unwrap().as_mut_ptr(),
-                cipher,
-                &mut outl,
-                plain.as_ptr(),
-                plain.len() as std::os::raw::c_int,
-            )
-        } != 1
-        {
-            self.finalized = true;
+        let total = (outl + foutl) as usize;
+        if total != datalen {
+            outbuf.zeroize();
             return err_rv!(CKR_DEVICE_ERROR);
         }
         unsafe {
-            *cipher_len = outl as CK_ULONG;
+            std::ptr::copy_nonoverlapping(outbuf.as_ptr(), plain, total);
+            *plain_len = total as CK_ULONG;
         }
+        outbuf.zeroize();
         Ok(())
     }
 
-    fn encrypt_final(
+    fn ccm_encrypt_update(
         &mut self,
+        plain: &[u8],
         cipher: CK_BYTE_PTR,
         cipher_len: CK_ULONG_PTR,
     ) -> KResult<()> {
-        if self.finalized {
-            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        if self.databuf.len() + plain.len() > self.params.datalen {
+            self.finalized = true;
+            return err_rv!(CKR_DATA_LEN_RANGE);
         }
-        if !self.in_use {
-            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        /* CCM can't process data until its total length is known, so all
+         * data is buffered and encrypted at once in the final call */
+        if !cipher.is_null() {
+            self.databuf.extend_from_slice(plain);
         }
-        if cipher.is_null() {
-            if !self.params.pad {
-                unsafe {
-                    *cipher_len = 0;
-                }
-                return Ok(());
-            }
-            /* OpenSSL does not let us know if there is remaining data to output, so we always have
-             * to report the max if queried, which is a full blocksize */
-            let mut clen: CK_ULONG = self.blocksize as CK_ULONG;
-            if self.finalbuf.len() > 0 {
-                clen = self.finalbuf.len() as CK_ULONG;
-            }
-
-            unsafe {
-                *cipher_len = clen;
-            }
-            return Ok(());
+        unsafe {
+            *cipher_len = 0;
         }
+        Ok(())
+    }
 
-        if !self.params.pad {
+    fn ccm_encrypt_final(
+        &mut self,
+        cipher: CK_BYTE_PTR,
+        cipher_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if self.databuf.len() != self.params.datalen {
             self.finalized = true;
-            return Ok(());
-        }
-
-        if self.params.maxblocks > 0 {
-            if self.blockctr >= self.params.maxblocks {
-                return err_rv!(CKR_DATA_LEN_RANGE);
-            }
+            return err_rv!(CKR_DATA_LEN_RANGE);
         }
-
-        let mut cipher_buf: *mut u8 = cipher;
-        let cipher_ulen = unsafe { *cipher_len } as usize;
-        /* check if this is a second call where we saved the final buffer */
-        if self.finalbuf.len() > 0 {
-            if cipher_ulen < self.finalbuf.len() {
-                return err_rv!(CKR_BUFFER_TOO_SMALL);
-            }
-            self.finalized = true;
+        let datalen = self.params.datalen;
+        let outlen = datalen + self.params.taglen;
+        if cipher.is_null() {
             unsafe {
-                std::ptr::copy_nonoverlapping(
-                    self.finalbuf.as_ptr(),
-                    cipher,
-                    self.finalbuf.len(),
-                );
-                *cipher_len = self.finalbuf.len() as CK_ULONG;
+                *cipher_len = outlen as CK_ULONG;
             }
             return Ok(());
         }
-
-        if cipher_ulen < self.blocksize {
-            /* if we get less then we need to be prepared to hold the final block size from
-             * openssl, and return it later */
-            self.finalbuf.reserve_exact(self.blocksize);
-            cipher_buf = self.finalbuf.as_mut_ptr();
-        } else {
-            self.finalized = true;
+        if (unsafe { *cipher_len } as usize) < outlen {
+            /* This is the only non-fatal error */
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
         }
+        self.finalized = true;
 
+        let evpcipher = Self::init_cipher(self.mech, self.key.raw.len())?;
+        init_ccm_ctx(
+            self.ctx.as_mut().unwrap(),
+            evpcipher,
+            &self.key.raw,
+            &self.params,
+            None,
+            true,
+        )?;
         let mut outl: std::os::raw::c_int = 0;
         if unsafe {
-            EVP_EncryptFinal_ex(
+            EVP_EncryptUpdate(
                 self.ctx.as_mut().unwrap().as_mut_ptr(),
-                cipher_buf,
+                cipher,
                 &mut outl,
+                self.databuf.as_ptr(),
+                datalen as std::os::raw::c_int,
             )
         } != 1
         {
-            self.finalized = true;
             return err_rv!(CKR_DEVICE_ERROR);
         }
-        if outl == 0 {
-            self.finalized = true;
-        } else if cipher_buf != cipher {
-            if outl as usize > self.blocksize {
-                self.finalized = true;
-                return err_rv!(CKR_DEVICE_ERROR);
-            }
-            unsafe {
-                self.finalbuf.set_len(outl as usize);
-            }
-            if cipher_ulen >= outl as usize {
-                unsafe {
-                    std::ptr::copy_nonoverlapping(
-                        cipher_buf,
-                        cipher,
-                        outl as usize,
-                    );
-                }
-                self.finalized = true;
-            } else {
-                /* This is the only non-fatal error */
-                return err_rv!(CKR_BUFFER_TOO_SMALL);
-            }
+        if outl as usize != datalen {
+            return err_rv!(CKR_DEVICE_ERROR);
         }
+        self.databuf.zeroize();
+        get_aead_tag(self.ctx.as_mut().unwrap(), unsafe {
+            std::slice::from_raw_parts_mut(
+                cipher.add(datalen),
+                self.params.taglen,
+            )
+        })?;
         unsafe {
-            *cipher_len = outl as CK_ULONG;
+            *cipher_len = outlen as CK_ULONG;
         }
         Ok(())
     }
-}
 
-impl Decryption for AesOperation {
-    fn decrypt(
+    fn ccm_decrypt_update(
         &mut self,
         cipher: &[u8],
         plain: CK_BYTE_PTR,
         plain_len: CK_ULONG_PTR,
     ) -> KResult<()> {
-        if self.in_use {
-            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
-        }
-        if self.finalized {
-            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
-        }
-        let plen: CK_ULONG = unsafe { *plain_len };
-        let mut outb: *mut u8 = plain;
-        let mut outl: CK_ULONG = unsafe { *plain_len };
-        self.decrypt_update(cipher, outb, &mut outl)?;
-        if outl > plen {
-            self.finalized = true;
-            return err_rv!(CKR_DEVICE_ERROR);
-        }
-        if !self.params.pad {
+        if self.databuf.len() + cipher.len()
+            > self.params.datalen + self.params.taglen
+        {
             self.finalized = true;
-            unsafe { *plain_len = outl };
-            return Ok(());
+            return err_rv!(CKR_ENCRYPTED_DATA_LEN_RANGE);
         }
-        let mut foutl = plen - outl;
-        outb = unsafe { plain.add(outl as usize) };
-        self.decrypt_final(outb, &mut foutl)?;
-        unsafe { *plain_len = foutl + outl };
-        Ok(())
+        self.aead_decrypt_update(cipher, plain, plain_len)
     }
 
-    fn decrypt_update(
+    fn ccm_decrypt_final(
         &mut self,
-        cipher: &[u8],
         plain: CK_BYTE_PTR,
         plain_len: CK_ULONG_PTR,
     ) -> KResult<()> {
-        if plain_len.is_null() {
-            return err_rv!(CKR_ARGUMENTS_BAD);
-        }
-        if self.finalized {
-            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
-        }
-        if !self.in_use {
-            self.in_use = true;
-
-            let evpcipher =
-                match Self::init_cipher(self.mech, self.key.raw.len()) {
-                    Ok(c) => c,
-                    Err(e) => {
-                        self.finalized = true;
-                        return Err(e);
-                    }
-                };
-
-            if unsafe {
-                EVP_DecryptInit_ex(
-                    self.ctx.as_mut().unwrap().as_mut_ptr(),
-                    evpcipher.as_ptr(),
-                    std::ptr::null_mut(),
-                    self.key.raw.as_ptr(),
-                    if self.params.iv.len() != 0 {
-                        self.params.iv.as_ptr()
-                    } else {
-                        std::ptr::null()
-                    },
-                )
-            } != 1
-            {
-                self.finalized = true;
-                return err_rv!(CKR_DEVICE_ERROR);
-            }
-            if unsafe {
-                EVP_CIPHER_CTX_set_padding(
-                    self.ctx.as_mut().unwrap().as_mut_ptr(),
-                    if self.params.pad { 1 } else { 0 },
-                )
-            } != 1
-            {
-                self.finalized = true;
-                return err_rv!(CKR_DEVICE_ERROR);
-            }
-            self.blocksize =
-                unsafe { EVP_CIPHER_get_block_size(evpcipher.as_ptr()) }
-                    as usize;
+        let datalen = self.params.datalen;
+        if self.databuf.len() != datalen + self.params.taglen {
+            self.finalized = true;
+            return err_rv!(CKR_ENCRYPTED_DATA_LEN_RANGE);
         }
-
-        let plain_ulen = unsafe { *plain_len } as usize;
-        let outblocks = cipher.len() / self.blocksize;
-        let outlen = outblocks * self.blocksize;
         if plain.is_null() {
             unsafe {
-                *plain_len = outlen as CK_ULONG;
+                *plain_len = datalen as CK_ULONG;
             }
             return Ok(());
-        } else {
-            if !self.params.pad && cipher.len() != outlen {
-                self.finalized = true;
-                return err_rv!(CKR_DATA_LEN_RANGE);
-            }
-            if plain_ulen < outlen {
-                /* This is the only, non-fatal error */
-                return err_rv!(CKR_BUFFER_TOO_SMALL);
-            }
         }
-
-        if self.params.maxblocks > 0 {
-            let reqblocks =
-                ((cipher.len() + AES_BLOCK_SIZE - 1) / AES_BLOCK_SIZE) as u128;
-            if self.blockctr + reqblocks > self.params.maxblocks {
-                self.finalized = true;
-                return err_rv!(CKR_DATA_LEN_RANGE);
-            }
-            self.blockctr += reqblocks;
+        if (unsafe { *plain_len } as usize) < datalen {
+            /* This is the only non-fatal error */
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
         }
+        self.finalized = true;
+
+        let evpcipher = Self::init_cipher(self.mech, self.key.raw.len())?;
+        let (data, tag) = self.databuf.split_at_mut(datalen);
+        init_ccm_ctx(
+            self.ctx.as_mut().unwrap(),
+            evpcipher,
+            &self.key.raw,
+            &self.params,
+            Some(tag),
+            false,
+        )?;
 
+        /* decrypt in a scratch buffer so that nothing is returned to the
+         * caller unless the tag matches */
+        let mut outbuf: Vec<u8> = vec![0; datalen];
         let mut outl: std::os::raw::c_int = 0;
         if unsafe {
             EVP_DecryptUpdate(
                 self.ctx.as_mut().unwrap().as_mut_ptr(),
-                plain,
+                outbuf.as_mut_ptr(),
                 &mut outl,
-                cipher.as_ptr(),
-                cipher.len() as std::os::raw::c_int,
+                data.as_ptr(),
+                datalen as std::os::raw::c_int,
             )
         } != 1
         {
-            self.finalized = true;
+            outbuf.zeroize();
+            return err_rv!(CKR_ENCRYPTED_DATA_INVALID);
+        }
+        if outl as usize != datalen {
+            outbuf.zeroize();
             return err_rv!(CKR_DEVICE_ERROR);
         }
         unsafe {
-            *plain_len = outl as CK_ULONG;
+            std::ptr::copy_nonoverlapping(outbuf.as_ptr(), plain, datalen);
+            *plain_len = datalen as CK_ULONG;
         }
+        outbuf.zeroize();
         Ok(())
     }
 
-    fn decrypt_final(
+    fn kw_encrypt(
         &mut self,
-        plain: CK_BYTE_PTR,
-        plain_len: CK_ULONG_PTR,
+        plain: &[u8],
+        cipher: CK_BYTE_PTR,
+        cipher_len: CK_ULONG_PTR,
     ) -> KResult<()> {
-        if self.finalized {
-            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        /* RFC 3394 requires at least two 64 bit blocks of input, RFC 5649
+         * pads any non empty input */
+        let valid = match self.mech {
+            CKM_AES_KEY_WRAP => plain.len() >= 16 && plain.len() % 8 == 0,
+            _ => plain.len() > 0,
+        };
+        if !valid {
+            self.finalized = true;
+            return err_rv!(CKR_DATA_LEN_RANGE);
         }
-        if !self.in_use {
-            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        let outlen = self.encryption_len(plain.len(), true)?;
+        if (unsafe { *cipher_len } as usize) < outlen {
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
         }
-        if plain.is_null() {
-            if !self.params.pad {
-                unsafe {
-                    *plain_len = 0;
-                }
-                return Ok(());
-            }
-            /* OpenSSL does not let us know if there is remaining data to output, so we always have
-             * to report the max if queried, which is a full blocksize */
-            let mut plen: CK_ULONG = self.blocksize as CK_ULONG;
-            if self.finalbuf.len() > 0 {
-                plen = self.finalbuf.len() as CK_ULONG;
-            }
+        self.in_use = true;
+        self.finalized = true;
 
-            unsafe {
-                *plain_len = plen;
-            }
-            return Ok(());
-        }
-
-        if !self.params.pad {
-            self.finalized = true;
-            return Ok(());
+        let evpcipher = Self::init_cipher(self.mech, self.key.raw.len())?;
+        if unsafe {
+            EVP_EncryptInit_ex(
+                self.ctx.as_mut().unwrap().as_mut_ptr(),
+                evpcipher.as_ptr(),
+                std::ptr::null_mut(),
+                self.key.raw.as_ptr(),
+                if self.params.iv.len() != 0 {
+                    self.params.iv.as_ptr()
+                } else {
+                    std::ptr::null()
+                },
+            )
+        } != 1
+        {
+            return err_rv!(CKR_DEVICE_ERROR);
         }
-
-        if self.params.maxblocks > 0 {
-            if self.blockctr >= self.params.maxblocks {
-                return err_rv!(CKR_DATA_LEN_RANGE);
-            }
+        let mut outl: std::os::raw::c_int = 0;
+        if unsafe {
+            EVP_EncryptUpdate(
+                self.ctx.as_mut().unwrap().as_mut_ptr(),
+                cipher,
+                &mut outl,
+                plain.as_ptr(),
+                plain.len() as std::os::raw::c_int,
+            )
+        } != 1
+        {
+            return err_rv!(CKR_DEVICE_ERROR);
         }
-        let mut plain_buf: *mut u8 = plain;
-        let plain_ulen = unsafe { *plain_len } as usize;
-        /* check if this is a second call where we saved the final buffer */
-        if self.finalbuf.len() > 0 {
-            if plain_ulen < self.finalbuf.len() {
-                return err_rv!(CKR_BUFFER_TOO_SMALL);
-            }
-            self.finalized = true;
-            unsafe {
-                std::ptr::copy_nonoverlapping(
-                    self.finalbuf.as_ptr(),
-                    plain,
-                    self.finalbuf.len(),
-                );
-                *plain_len = self.finalbuf.len() as CK_ULONG;
-            }
-            return Ok(());
+        if outl as usize != outlen {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        unsafe {
+            *cipher_len = outl as CK_ULONG;
         }
+        Ok(())
+    }
 
-        if plain_ulen < self.blocksize {
-            /* if we get less then we need to be prepared to hold the final block size from
-             * openssl, and return it later */
-            self.finalbuf.reserve_exact(self.blocksize);
-            plain_buf = self.finalbuf.as_mut_ptr();
-        } else {
+    fn kw_decrypt(
+        &mut self,
+        cipher: &[u8],
+        plain: CK_BYTE_PTR,
+        plain_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        let minlen = match self.mech {
+            CKM_AES_KEY_WRAP => 24,
+            _ => 16,
+        };
+        if cipher.len() < minlen || cipher.len() % 8 != 0 {
             self.finalized = true;
+            return err_rv!(CKR_ENCRYPTED_DATA_LEN_RANGE);
+        }
+        let outlen = self.decryption_len(cipher.len(), true)?;
+        if (unsafe { *plain_len } as usize) < outlen {
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
         }
+        self.in_use = true;
+        self.finalized = true;
 
+        let evpcipher = Self::init_cipher(self.mech, self.key.raw.len())?;
+        if unsafe {
+            EVP_DecryptInit_ex(
+                self.ctx.as_mut().unwrap().as_mut_ptr(),
+                evpcipher.as_ptr(),
+                std::ptr::null_mut(),
+                self.key.raw.as_ptr(),
+                if self.params.iv.len() != 0 {
+                    self.params.iv.as_ptr()
+                } else {
+                    std::ptr::null()
+                },
+            )
+        } != 1
+        {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        /* the integrity check is performed as part of the update, so a
+         * failure here means the wrapped data was not authentic */
         let mut outl: std::os::raw::c_int = 0;
         if unsafe {
-            EVP_DecryptFinal_ex(
+            EVP_DecryptUpdate(
                 self.ctx.as_mut().unwrap().as_mut_ptr(),
-                plain_buf,
+                plain,
                 &mut outl,
+                cipher.as_ptr(),
+                cipher.len() as std::os::raw::c_int,
             )
         } != 1
         {
+            return err_rv!(CKR_ENCRYPTED_DATA_INVALID);
+        }
+        if outl as usize > outlen {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        unsafe {
+            *plain_len = outl as CK_ULONG;
+        }
+        Ok(())
+    }
+
+    fn wrap(
+        mech: &CK_MECHANISM,
+        wrapping_key: &Object,
+        key: &Object,
+        data: CK_BYTE_PTR,
+        data_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        let keydata = AesKey {
+            raw: match key.get_attr_as_ulong(CKA_CLASS)? {
+                CKO_SECRET_KEY => key.get_attr_as_bytes(CKA_VALUE)?.clone(),
+                CKO_PRIVATE_KEY => pkcs8::encode_private_key(key)?,
+                _ => return err_rv!(CKR_KEY_NOT_WRAPPABLE),
+            },
+        };
+        let mut op = Self::encrypt_new(mech, wrapping_key)?;
+        let outlen = op.encryption_len(keydata.raw.len(), true)?;
+        if data.is_null() {
+            unsafe {
+                *data_len = outlen as CK_ULONG;
+            }
+            return Ok(());
+        }
+        if (unsafe { *data_len } as usize) < outlen {
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
+        }
+        match op.encrypt(&keydata.raw, data, data_len) {
+            Err(KError::RvError(e)) if e.rv == CKR_DATA_LEN_RANGE => {
+                err_rv!(CKR_KEY_SIZE_RANGE)
+            }
+            r => r,
+        }
+    }
+
+    fn unwrap(
+        mech: &CK_MECHANISM,
+        wrapping_key: &Object,
+        data: &[u8],
+    ) -> KResult<Vec<u8>> {
+        let mut op = Self::decrypt_new(mech, wrapping_key)?;
+        let mut result = vec![0u8; op.decryption_len(data.len(), true)?];
+        let mut len = result.len() as CK_ULONG;
+        let ret = op.decrypt(data, result.as_mut_ptr(), &mut len);
+        if ret.is_err() {
+            result.zeroize();
+        }
+        match ret {
+            Ok(()) => {
+                result.truncate(len as usize);
+                Ok(result)
+            }
+            Err(KError::RvError(e)) => match e.rv {
+                CKR_ENCRYPTED_DATA_INVALID => err_rv!(CKR_WRAPPED_KEY_INVALID),
+                CKR_ENCRYPTED_DATA_LEN_RANGE | CKR_DATA_LEN_RANGE => {
+                    err_rv!(CKR_WRAPPED_KEY_LEN_RANGE)
+                }
+                _ => Err(KError::RvError(e)),
+            },
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl MechOperation for AesOperation {
+    fn mechanism(&self) -> CK_MECHANISM_TYPE {
+        self.mech
+    }
+    fn in_use(&self) -> bool {
+        self.in_use
+    }
+    fn finalized(&self) -> bool {
+        self.finalized
+    }
+}
+
+impl Encryption for AesOperation {
+    fn encrypt(
+        &mut self,
+        plain: &[u8],
+        cipher: CK_BYTE_PTR,
+        cipher_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        let clen: CK_ULONG = unsafe { *cipher_len };
+        match self.mech {
+            CKM_AES_KEY_WRAP | CKM_AES_KEY_WRAP_KWP => {
+                return self.kw_encrypt(plain, cipher, cipher_len);
+            }
+            CKM_AES_GCM | CKM_AES_CCM => {
+                /* make sure the tag fits before any data is processed */
+                if (clen as usize) < plain.len() + self.params.taglen {
+                    return err_rv!(CKR_BUFFER_TOO_SMALL);
+                }
+            }
+            _ => (),
+        }
+        let mut outb: *mut u8 = cipher;
+        let mut outl: CK_ULONG = unsafe { *cipher_len };
+        self.encrypt_update(plain, outb, &mut outl)?;
+        if outl > clen {
             self.finalized = true;
             return err_rv!(CKR_DEVICE_ERROR);
         }
-        if outl == 0 {
+        if !self.params.pad
+            && self.mech != CKM_AES_GCM
+            && self.mech != CKM_AES_CCM
+        {
             self.finalized = true;
-        } else if plain_buf != plain {
-            if outl as usize > self.blocksize {
+            unsafe { *cipher_len = outl };
+            return Ok(());
+        }
+        let mut foutl = clen - outl;
+        outb = unsafe { cipher.add(outl as usize) };
+        self.encrypt_final(outb, &mut foutl)?;
+        unsafe { *cipher_len = foutl + outl };
+        Ok(())
+    }
+
+    fn encrypt_update(
+        &mut self,
+        plain: &[u8],
+        cipher: CK_BYTE_PTR,
+        cipher_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if cipher_len.is_null() {
+            return err_rv!(CKR_ARGUMENTS_BAD);
+        }
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if self.mech == CKM_AES_CCM {
+            self.in_use = true;
+            return self.ccm_encrypt_update(plain, cipher, cipher_len);
+        }
+        if !self.in_use {
+            self.in_use = true;
+
+            let evpcipher =
+                match Self::init_cipher(self.mech, self.key.raw.len()) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        self.finalized = true;
+                        return Err(e);
+                    }
+                };
+
+            if self.mech == CKM_AES_GCM {
+                match self.init_aead(evpcipher, true) {
+                    Ok(()) => (),
+                    Err(e) => {
+                        self.finalized = true;
+                        return Err(e);
+                    }
+                }
+            } else if unsafe {
+                EVP_EncryptInit_ex(
+                    self.ctx.as_mut().unwrap().as_mut_ptr(),
+                    evpcipher.as_ptr(),
+                    std::ptr::null_mut(),
+                    self.key.raw.as_ptr(),
+                    if self.params.iv.len() != 0 {
+                        self.params.iv.as_ptr()
+                    } else {
+                        std::ptr::null()
+                    },
+                )
+            } != 1
+            {
+                self.finalized = true;
+                return err_rv!(CKR_DEVICE_ERROR);
+            }
+            if unsafe {
+                EVP_CIPHER_CTX_set_padding(
+                    self.ctx.as_mut().unwrap().as_mut_ptr(),
+                    if self.params.pad { 1 } else { 0 },
+                )
+            } != 1
+            {
                 self.finalized = true;
                 return err_rv!(CKR_DEVICE_ERROR);
             }
+            self.blocksize =
+                unsafe { EVP_CIPHER_get_block_size(evpcipher.as_ptr()) }
+                    as usize;
+        }
+
+        let cipher_ulen = unsafe { *cipher_len } as usize;
+        let outblocks = plain.len() / self.blocksize;
+        let outlen = outblocks * self.blocksize;
+        if cipher.is_null() {
             unsafe {
-                self.finalbuf.set_len(outl as usize);
+                *cipher_len = outlen as CK_ULONG;
             }
-            if plain_ulen >= outl as usize {
-                unsafe {
-                    std::ptr::copy_nonoverlapping(
-                        plain_buf,
-                        plain,
-                        outl as usize,
-                    );
-                }
+            return Ok(());
+        } else {
+            if !self.params.pad && plain.len() != outlen {
                 self.finalized = true;
-            } else {
-                /* This is the only non-fatal error */
+                return err_rv!(CKR_DATA_LEN_RANGE);
+            }
+            if cipher_ulen < outlen {
+                /* This is the only, non-fatal error */
                 return err_rv!(CKR_BUFFER_TOO_SMALL);
             }
         }
-        unsafe {
-            *plain_len = outl as CK_ULONG;
+
+        if self.params.maxblocks > 0 {
+            let reqblocks =
+                ((plain.len() + AES_BLOCK_SIZE - 1) / AES_BLOCK_SIZE) as u128;
+            if self.blockctr + reqblocks > self.params.maxblocks {
+                self.finalized = true;
+                return err_rv!(CKR_DATA_LEN_RANGE);
+            }
+            self.blockctr += reqblocks;
+        }
+
+        let mut outl: std::os::raw::c_int = 0;
+        if unsafe {
+            EVP_EncryptUpdate(
+                self.ctx.as_mut().unwrap().as_mut_ptr(),
+                cipher,
+                &mut outl,
+                plain.as_ptr(),
+                plain.len() as std::os::raw::c_int,
+            )
+        } != 1
+        {
+            self.finalized = true;
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        unsafe {
+            *cipher_len = outl as CK_ULONG;
+        }
+        Ok(())
+    }
+
+    fn encrypt_final(
+        &mut self,
+        cipher: CK_BYTE_PTR,
+        cipher_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if !self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        match self.mech {
+            CKM_AES_GCM => return self.aead_encrypt_final(cipher, cipher_len),
+            CKM_AES_CCM => return self.ccm_encrypt_final(cipher, cipher_len),
+            _ => (),
+        }
+        if cipher.is_null() {
+            if !self.params.pad {
+                unsafe {
+                    *cipher_len = 0;
+                }
+                return Ok(());
+            }
+            /* OpenSSL does not let us know if there is remaining data to output, so we always have
+             * to report the max if queried, which is a full blocksize */
+            let mut clen: CK_ULONG = self.blocksize as CK_ULONG;
+            if self.finalbuf.len() > 0 {
+                clen = self.finalbuf.len() as CK_ULONG;
+            }
+
+            unsafe {
+                *cipher_len = clen;
+            }
+            return Ok(());
+        }
+
+        if !self.params.pad {
+            self.finalized = true;
+            return Ok(());
+        }
+
+        if self.params.maxblocks > 0 {
+            if self.blockctr >= self.params.maxblocks {
+                return err_rv!(CKR_DATA_LEN_RANGE);
+            }
+        }
+
+        let mut cipher_buf: *mut u8 = cipher;
+        let cipher_ulen = unsafe { *cipher_len } as usize;
+        /* check if this is a second call where we saved the final buffer */
+        if self.finalbuf.len() > 0 {
+            if cipher_ulen < self.finalbuf.len() {
+                return err_rv!(CKR_BUFFER_TOO_SMALL);
+            }
+            self.finalized = true;
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    self.finalbuf.as_ptr(),
+                    cipher,
+                    self.finalbuf.len(),
+                );
+                *cipher_len = self.finalbuf.len() as CK_ULONG;
+            }
+            return Ok(());
+        }
+
+        if cipher_ulen < self.blocksize {
+            /* if we get less then we need to be prepared to hold the final block size from
+             * openssl, and return it later */
+            self.finalbuf.reserve_exact(self.blocksize);
+            cipher_buf = self.finalbuf.as_mut_ptr();
+        } else {
+            self.finalized = true;
+        }
+
+        let mut outl: std::os::raw::c_int = 0;
+        if unsafe {
+            EVP_EncryptFinal_ex(
+                self.ctx.as_mut().unwrap().as_mut_ptr(),
+                cipher_buf,
+                &mut outl,
+            )
+        } != 1
+        {
+            self.finalized = true;
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        if outl == 0 {
+            self.finalized = true;
+        } else if cipher_buf != cipher {
+            if outl as usize > self.blocksize {
+                self.finalized = true;
+                return err_rv!(CKR_DEVICE_ERROR);
+            }
+            unsafe {
+                self.finalbuf.set_len(outl as usize);
+            }
+            if cipher_ulen >= outl as usize {
+                unsafe {
+                    std::ptr::copy_nonoverlapping(
+                        cipher_buf,
+                        cipher,
+                        outl as usize,
+                    );
+                }
+                self.finalized = true;
+            } else {
+                /* This is the only non-fatal error */
+                return err_rv!(CKR_BUFFER_TOO_SMALL);
+            }
+        }
+        unsafe {
+            *cipher_len = outl as CK_ULONG;
+        }
+        Ok(())
+    }
+
+    fn encryption_len(&self, data_len: usize, fin: bool) -> KResult<usize> {
+        Ok(match self.mech {
+            CKM_AES_GCM => {
+                if fin {
+                    data_len + self.params.taglen
+                } else {
+                    data_len
+                }
+            }
+            CKM_AES_CCM => {
+                if fin {
+                    self.databuf.len() + data_len + self.params.taglen
+                } else {
+                    0
+                }
+            }
+            CKM_AES_KEY_WRAP => data_len + 8,
+            CKM_AES_KEY_WRAP_KWP => ((data_len + 7) / 8) * 8 + 8,
+            CKM_AES_CBC_PAD => {
+                if fin {
+                    (data_len / AES_BLOCK_SIZE + 1) * AES_BLOCK_SIZE
+                } else {
+                    ((data_len + AES_BLOCK_SIZE - 1) / AES_BLOCK_SIZE)
+                        * AES_BLOCK_SIZE
+                }
+            }
+            _ => data_len,
+        })
+    }
+}
+
+impl Decryption for AesOperation {
+    fn decrypt(
+        &mut self,
+        cipher: &[u8],
+        plain: CK_BYTE_PTR,
+        plain_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        let plen: CK_ULONG = unsafe { *plain_len };
+        match self.mech {
+            CKM_AES_KEY_WRAP | CKM_AES_KEY_WRAP_KWP => {
+                return self.kw_decrypt(cipher, plain, plain_len);
+            }
+            CKM_AES_GCM | CKM_AES_CCM => {
+                if cipher.len() < self.params.taglen {
+                    self.finalized = true;
+                    return err_rv!(CKR_ENCRYPTED_DATA_LEN_RANGE);
+                }
+                if (plen as usize) < cipher.len() - self.params.taglen {
+                    return err_rv!(CKR_BUFFER_TOO_SMALL);
+                }
+            }
+            _ => (),
+        }
+        let mut outb: *mut u8 = plain;
+        let mut outl: CK_ULONG = unsafe { *plain_len };
+        self.decrypt_update(cipher, outb, &mut outl)?;
+        if outl > plen {
+            self.finalized = true;
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        if !self.params.pad
+            && self.mech != CKM_AES_GCM
+            && self.mech != CKM_AES_CCM
+        {
+            self.finalized = true;
+            unsafe { *plain_len = outl };
+            return Ok(());
+        }
+        let mut foutl = plen - outl;
+        outb = unsafe { plain.add(outl as usize) };
+        self.decrypt_final(outb, &mut foutl)?;
+        unsafe { *plain_len = foutl + outl };
+        Ok(())
+    }
+
+    fn decrypt_update(
+        &mut self,
+        cipher: &[u8],
+        plain: CK_BYTE_PTR,
+        plain_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if plain_len.is_null() {
+            return err_rv!(CKR_ARGUMENTS_BAD);
+        }
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if self.mech == CKM_AES_CCM {
+            self.in_use = true;
+            return self.ccm_decrypt_update(cipher, plain, plain_len);
+        }
+        if !self.in_use {
+            self.in_use = true;
+
+            let evpcipher =
+                match Self::init_cipher(self.mech, self.key.raw.len()) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        self.finalized = true;
+                        return Err(e);
+                    }
+                };
+
+            if self.mech == CKM_AES_GCM {
+                match self.init_aead(evpcipher, false) {
+                    Ok(()) => (),
+                    Err(e) => {
+                        self.finalized = true;
+                        return Err(e);
+                    }
+                }
+            } else if unsafe {
+                EVP_DecryptInit_ex(
+                    self.ctx.as_mut().unwrap().as_mut_ptr(),
+                    evpcipher.as_ptr(),
+                    std::ptr::null_mut(),
+                    self.key.raw.as_ptr(),
+                    if self.params.iv.len() != 0 {
+                        self.params.iv.as_ptr()
+                    } else {
+                        std::ptr::null()
+                    },
+                )
+            } != 1
+            {
+                self.finalized = true;
+                return err_rv!(CKR_DEVICE_ERROR);
+            }
+            if unsafe {
+                EVP_CIPHER_CTX_set_padding(
+                    self.ctx.as_mut().unwrap().as_mut_ptr(),
+                    if self.params.pad { 1 } else { 0 },
+                )
+            } != 1
+            {
+                self.finalized = true;
+                return err_rv!(CKR_DEVICE_ERROR);
+            }
+            self.blocksize =
+                unsafe { EVP_CIPHER_get_block_size(evpcipher.as_ptr()) }
+                    as usize;
+        }
+
+        if self.mech == CKM_AES_GCM {
+            return self.aead_decrypt_update(cipher, plain, plain_len);
+        }
+
+        let plain_ulen = unsafe { *plain_len } as usize;
+        let outblocks = cipher.len() / self.blocksize;
+        let outlen = outblocks * self.blocksize;
+        if plain.is_null() {
+            unsafe {
+                *plain_len = outlen as CK_ULONG;
+            }
+            return Ok(());
+        } else {
+            if !self.params.pad && cipher.len() != outlen {
+                self.finalized = true;
+                return err_rv!(CKR_DATA_LEN_RANGE);
+            }
+            if plain_ulen < outlen {
+                /* This is the only, non-fatal error */
+                return err_rv!(CKR_BUFFER_TOO_SMALL);
+            }
+        }
+
+        if self.params.maxblocks > 0 {
+            let reqblocks =
+                ((cipher.len() + AES_BLOCK_SIZE - 1) / AES_BLOCK_SIZE) as u128;
+            if self.blockctr + reqblocks > self.params.maxblocks {
+                self.finalized = true;
+                return err_rv!(CKR_DATA_LEN_RANGE);
+            }
+            self.blockctr += reqblocks;
+        }
+
+        let mut outl: std::os::raw::c_int = 0;
+        if unsafe {
+            EVP_DecryptUpdate(
+                self.ctx.as_mut().unwrap().as_mut_ptr(),
+                plain,
+                &mut outl,
+                cipher.as_ptr(),
+                cipher.len() as std::os::raw::c_int,
+            )
+        } != 1
+        {
+            self.finalized = true;
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        unsafe {
+            *plain_len = outl as CK_ULONG;
+        }
+        Ok(())
+    }
+
+    fn decrypt_final(
+        &mut self,
+        plain: CK_BYTE_PTR,
+        plain_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if !self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        match self.mech {
+            CKM_AES_GCM => return self.aead_decrypt_final(plain, plain_len),
+            CKM_AES_CCM => return self.ccm_decrypt_final(plain, plain_len),
+            _ => (),
+        }
+        if plain.is_null() {
+            if !self.params.pad {
+                unsafe {
+                    *plain_len = 0;
+                }
+                return Ok(());
+            }
+            /* OpenSSL does not let us know if there is remaining data to output, so we always have
+             * to report the max if queried, which is a full blocksize */
+            let mut plen: CK_ULONG = self.blocksize as CK_ULONG;
+            if self.finalbuf.len() > 0 {
+                plen = self.finalbuf.len() as CK_ULONG;
+            }
+
+            unsafe {
+                *plain_len = plen;
+            }
+            return Ok(());
+        }
+
+        if !self.params.pad {
+            self.finalized = true;
+            return Ok(());
+        }
+
+        if self.params.maxblocks > 0 {
+            if self.blockctr >= self.params.maxblocks {
+                return err_rv!(CKR_DATA_LEN_RANGE);
+            }
+        }
+        let mut plain_buf: *mut u8 = plain;
+        let plain_ulen = unsafe { *plain_len } as usize;
+        /* check if this is a second call where we saved the final buffer */
+        if self.finalbuf.len() > 0 {
+            if plain_ulen < self.finalbuf.len() {
+                return err_rv!(CKR_BUFFER_TOO_SMALL);
+            }
+            self.finalized = true;
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    self.finalbuf.as_ptr(),
+                    plain,
+                    self.finalbuf.len(),
+                );
+                *plain_len = self.finalbuf.len() as CK_ULONG;
+            }
+            return Ok(());
+        }
+
+        if plain_ulen < self.blocksize {
+            /* if we get less then we need to be prepared to hold the final block size from
+             * openssl, and return it later */
+            self.finalbuf.reserve_exact(self.blocksize);
+            plain_buf = self.finalbuf.as_mut_ptr();
+        } else {
+            self.finalized = true;
+        }
+
+        let mut outl: std::os::raw::c_int = 0;
+        if unsafe {
+            EVP_DecryptFinal_ex(
+                self.ctx.as_mut().unwrap().as_mut_ptr(),
+                plain_buf,
+                &mut outl,
+            )
+        } != 1
+        {
+            self.finalized = true;
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        if outl == 0 {
+            self.finalized = true;
+        } else if plain_buf != plain {
+            if outl as usize > self.blocksize {
+                self.finalized = true;
+                return err_rv!(CKR_DEVICE_ERROR);
+            }
+            unsafe {
+                self.finalbuf.set_len(outl as usize);
+            }
+            if plain_ulen >= outl as usize {
+                unsafe {
+                    std::ptr::copy_nonoverlapping(
+                        plain_buf,
+                        plain,
+                        outl as usize,
+                    );
+                }
+                self.finalized = true;
+            } else {
+                /* This is the only non-fatal error */
+                return err_rv!(CKR_BUFFER_TOO_SMALL);
+            }
+        }
+        unsafe {
+            *plain_len = outl as CK_ULONG;
+        }
+        Ok(())
+    }
+
+    fn decryption_len(&self, data_len: usize, fin: bool) -> KResult<usize> {
+        Ok(match self.mech {
+            CKM_AES_GCM | CKM_AES_CCM => {
+                if fin {
+                    (self.databuf.len() + data_len)
+                        .saturating_sub(self.params.taglen)
+                } else {
+                    0
+                }
+            }
+            CKM_AES_KEY_WRAP | CKM_AES_KEY_WRAP_KWP => {
+                data_len.saturating_sub(8)
+            }
+            CKM_AES_CBC_PAD => {
+                if fin && data_len == 0 {
+                    AES_BLOCK_SIZE
+                } else {
+                    ((data_len + AES_BLOCK_SIZE - 1) / AES_BLOCK_SIZE)
+                        * AES_BLOCK_SIZE
+                }
+            }
+            _ => data_len,
+        })
+    }
+}
+
+/* NIST SP 800-38D, 8.3: no more than 2^32 invocations with random IVs */
+const MAX_RANDOM_IV_MSGS: u64 = 1 << 32;
+
+fn gcm_msg_params<'a>(
+    param: CK_VOID_PTR,
+    paramlen: usize,
+) -> KResult<&'a mut CK_GCM_MESSAGE_PARAMS> {
+    if paramlen != ::std::mem::size_of::<CK_GCM_MESSAGE_PARAMS>() {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    let params = unsafe { &mut *(param as *mut CK_GCM_MESSAGE_PARAMS) };
+    if params.pIv.is_null() || params.ulIvLen == 0 || params.pTag.is_null() {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    match params.ulTagBits {
+        32 | 64 | 96 | 104 | 112 | 120 | 128 => (),
+        _ => return err_rv!(CKR_MECHANISM_PARAM_INVALID),
+    }
+    Ok(params)
+}
+
+#[derive(Debug)]
+struct AesMsgOperation {
+    mech: CK_MECHANISM_TYPE,
+    key: AesKey,
+    ctx: EvpCipherCtx,
+    enc: bool,
+    finalized: bool,
+    in_use: bool,
+    ivgen: CK_GENERATOR_FUNCTION,
+    ivfixed: usize,
+    ivlen: usize,
+    ivcounter: u64,
+}
+
+impl AesMsgOperation {
+    fn new(mech: &CK_MECHANISM, key: &Object, enc: bool) -> KResult<Self> {
+        match mech.mechanism {
+            CKM_AES_GCM => (),
+            _ => return err_rv!(CKR_MECHANISM_INVALID),
+        }
+        Ok(AesMsgOperation {
+            mech: mech.mechanism,
+            key: object_to_raw_key(key)?,
+            ctx: EvpCipherCtx::from_ptr(unsafe { EVP_CIPHER_CTX_new() })?,
+            enc: enc,
+            finalized: false,
+            in_use: false,
+            ivgen: CKG_NO_GENERATE,
+            ivfixed: 0,
+            ivlen: 0,
+            ivcounter: 0,
+        })
+    }
+
+    fn encrypt_new(mech: &CK_MECHANISM, key: &Object) -> KResult<Self> {
+        Self::new(mech, key, true)
+    }
+
+    fn decrypt_new(mech: &CK_MECHANISM, key: &Object) -> KResult<Self> {
+        Self::new(mech, key, false)
+    }
+
+    /* Fills in the IV according to the requested generator and returns
+     * a copy of it, the generated IV is also returned to the caller */
+    fn prep_iv(
+        &mut self,
+        params: &mut CK_GCM_MESSAGE_PARAMS,
+    ) -> KResult<Vec<u8>> {
+        let ivlen = params.ulIvLen as usize;
+        let iv = unsafe { std::slice::from_raw_parts_mut(params.pIv, ivlen) };
+        if params.ivGenerator == CKG_NO_GENERATE {
+            if self.enc {
+                /* FIPS requires the IV to be generated in the module */
+                #[cfg(feature = "fips")]
+                return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+            }
+            return Ok(iv.to_vec());
+        }
+        if !self.enc {
+            return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+        }
+
+        /* only byte aligned fixed fields are supported */
+        let fixedbits = params.ulIvFixedBits as usize;
+        if fixedbits % 8 != 0 || fixedbits >= ivlen * 8 {
+            return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+        }
+        let fixed = fixedbits / 8;
+        if self.ivcounter == 0 {
+            self.ivgen = params.ivGenerator;
+            self.ivfixed = fixed;
+            self.ivlen = ivlen;
+        } else if self.ivgen != params.ivGenerator
+            || self.ivfixed != fixed
+            || self.ivlen != ivlen
+        {
+            /* switching generator would void the uniqueness guarantees */
+            return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+        }
+
+        let varlen = ivlen - fixed;
+        match params.ivGenerator {
+            CKG_GENERATE_COUNTER | CKG_GENERATE_COUNTER_XOR => {
+                if varlen < 8 && self.ivcounter >> (varlen * 8) != 0 {
+                    return err_rv!(CKR_KEY_EXHAUSTED);
+                }
+                let ctr = self.ivcounter.to_be_bytes();
+                let ctrlen = std::cmp::min(varlen, ctr.len());
+                let ctrpos = ivlen - ctrlen;
+                if params.ivGenerator == CKG_GENERATE_COUNTER {
+                    iv[fixed..ctrpos].fill(0);
+                    iv[ctrpos..].copy_from_slice(&ctr[(8 - ctrlen)..]);
+                } else {
+                    for i in 0..ctrlen {
+                        iv[ctrpos + i] ^= ctr[8 - ctrlen + i];
+                    }
+                }
+            }
+            CKG_GENERATE | CKG_GENERATE_RANDOM => {
+                if self.ivcounter >= MAX_RANDOM_IV_MSGS {
+                    return err_rv!(CKR_KEY_EXHAUSTED);
+                }
+                super::CSPRNG.with(|rng| {
+                    rng.borrow_mut().generate_random(&mut iv[fixed..])
+                })?;
+            }
+            _ => return err_rv!(CKR_MECHANISM_PARAM_INVALID),
+        }
+        if self.ivcounter == u64::MAX {
+            return err_rv!(CKR_KEY_EXHAUSTED);
+        }
+        self.ivcounter += 1;
+        Ok(iv.to_vec())
+    }
+
+    fn begin(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: usize,
+        aad: &[u8],
+    ) -> KResult<()> {
+        if self.in_use {
+            return err_rv!(CKR_OPERATION_ACTIVE);
+        }
+        let params = gcm_msg_params(param, paramlen)?;
+        let iv = self.prep_iv(params)?;
+        let evpcipher =
+            AesOperation::init_cipher(self.mech, self.key.raw.len())?;
+        init_aead_ctx(
+            &mut self.ctx,
+            evpcipher,
+            &self.key.raw,
+            &iv,
+            aad,
+            self.enc,
+        )?;
+        if !self.enc {
+            set_aead_tag(&mut self.ctx, unsafe {
+                std::slice::from_raw_parts_mut(
+                    params.pTag,
+                    (params.ulTagBits / 8) as usize,
+                )
+            })?;
+        }
+        self.in_use = true;
+        Ok(())
+    }
+
+    fn update(
+        &mut self,
+        data: &[u8],
+        out: CK_BYTE_PTR,
+        out_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if !self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if (unsafe { *out_len } as usize) < data.len() {
+            /* This is the only non-fatal error */
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
+        }
+        let mut outl: std::os::raw::c_int = 0;
+        if data.len() > 0 {
+            if unsafe {
+                EVP_CipherUpdate(
+                    self.ctx.as_mut_ptr(),
+                    out,
+                    &mut outl,
+                    data.as_ptr(),
+                    data.len() as std::os::raw::c_int,
+                )
+            } != 1
+            {
+                self.in_use = false;
+                return err_rv!(CKR_DEVICE_ERROR);
+            }
+        }
+        unsafe {
+            *out_len = outl as CK_ULONG;
+        }
+        Ok(())
+    }
+
+    fn end(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: usize,
+        data: &[u8],
+        out: CK_BYTE_PTR,
+        out_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if !self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        let params = gcm_msg_params(param, paramlen)?;
+        if (unsafe { *out_len } as usize) < data.len() {
+            /* This is the only non-fatal error */
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
+        }
+        self.in_use = false;
+
+        /* the last part is processed in a scratch buffer so that no
+         * plaintext is released if the tag does not match */
+        let mut outbuf: Vec<u8> = vec![0; data.len() + AES_BLOCK_SIZE];
+        let mut outl: std::os::raw::c_int = 0;
+        if data.len() > 0 {
+            if unsafe {
+                EVP_CipherUpdate(
+                    self.ctx.as_mut_ptr(),
+                    outbuf.as_mut_ptr(),
+                    &mut outl,
+                    data.as_ptr(),
+                    data.len() as std::os::raw::c_int,
+                )
+            } != 1
+            {
+                outbuf.zeroize();
+                return err_rv!(CKR_DEVICE_ERROR);
+            }
+        }
+        let mut foutl: std::os::raw::c_int = 0;
+        if unsafe {
+            EVP_CipherFinal_ex(
+                self.ctx.as_mut_ptr(),
+                outbuf.as_mut_ptr().add(outl as usize),
+                &mut foutl,
+            )
+        } != 1
+        {
+            outbuf.zeroize();
+            if self.enc {
+                return err_rv!(CKR_DEVICE_ERROR);
+            }
+            return err_rv!(CKR_AEAD_DECRYPT_FAILED);
+        }
+        let total = (outl + foutl) as usize;
+        if total != data.len() {
+            outbuf.zeroize();
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        if self.enc {
+            get_aead_tag(&mut self.ctx, unsafe {
+                std::slice::from_raw_parts_mut(
+                    params.pTag,
+                    (params.ulTagBits / 8) as usize,
+                )
+            })?;
+        }
+        unsafe {
+            std::ptr::copy_nonoverlapping(outbuf.as_ptr(), out, total);
+            *out_len = total as CK_ULONG;
+        }
+        outbuf.zeroize();
+        Ok(())
+    }
+}
+
+impl MechOperation for AesMsgOperation {
+    fn mechanism(&self) -> CK_MECHANISM_TYPE {
+        self.mech
+    }
+    fn in_use(&self) -> bool {
+        self.in_use
+    }
+    fn finalized(&self) -> bool {
+        self.finalized
+    }
+}
+
+impl MsgEncryption for AesMsgOperation {
+    fn msg_encrypt(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: usize,
+        aad: &[u8],
+        plain: &[u8],
+        cipher: CK_BYTE_PTR,
+        cipher_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        /* check early so that no IV is consumed */
+        if (unsafe { *cipher_len } as usize) < plain.len() {
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
+        }
+        self.begin(param, paramlen, aad)?;
+        self.end(param, paramlen, plain, cipher, cipher_len)
+    }
+
+    fn msg_encrypt_begin(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: usize,
+        aad: &[u8],
+    ) -> KResult<()> {
+        self.begin(param, paramlen, aad)
+    }
+
+    fn msg_encrypt_next(
+        &mut self,
+        _param: CK_VOID_PTR,
+        _paramlen: usize,
+        plain: &[u8],
+        cipher: CK_BYTE_PTR,
+        cipher_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        self.update(plain, cipher, cipher_len)
+    }
+
+    fn msg_encrypt_final(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: usize,
+        plain: &[u8],
+        cipher: CK_BYTE_PTR,
+        cipher_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        self.end(param, paramlen, plain, cipher, cipher_len)
+    }
+
+    fn msg_encryption_len(&self, data_len: usize, _: bool) -> KResult<usize> {
+        /* the tag is returned in the message parameters */
+        Ok(data_len)
+    }
+}
+
+impl MsgDecryption for AesMsgOperation {
+    fn msg_decrypt(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: usize,
+        aad: &[u8],
+        cipher: &[u8],
+        plain: CK_BYTE_PTR,
+        plain_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if (unsafe { *plain_len } as usize) < cipher.len() {
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
+        }
+        self.begin(param, paramlen, aad)?;
+        self.end(param, paramlen, cipher, plain, plain_len)
+    }
+
+    fn msg_decrypt_begin(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: usize,
+        aad: &[u8],
+    ) -> KResult<()> {
+        self.begin(param, paramlen, aad)
+    }
+
+    fn msg_decrypt_next(
+        &mut self,
+        _param: CK_VOID_PTR,
+        _paramlen: usize,
+        cipher: &[u8],
+        plain: CK_BYTE_PTR,
+        plain_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        self.update(cipher, plain, plain_len)
+    }
+
+    fn msg_decrypt_final(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: usize,
+        cipher: &[u8],
+        plain: CK_BYTE_PTR,
+        plain_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        self.end(param, paramlen, cipher, plain, plain_len)
+    }
+
+    fn msg_decryption_len(&self, data_len: usize, _: bool) -> KResult<usize> {
+        Ok(data_len)
+    }
+}
+
+#[derive(Debug)]
+struct AesMacOperation {
+    mech: CK_MECHANISM_TYPE,
+    key: AesKey,
+    cipher: &'static [u8],
+    iv: Vec<u8>,
+    maclen: usize,
+    ctx: EvpMacCtx,
+    finalized: bool,
+    in_use: bool,
+}
+
+impl AesMacOperation {
+    fn init(mech: &CK_MECHANISM, key: &Object) -> KResult<AesMacOperation> {
+        let mut iv = Vec::<u8>::new();
+        let (macname, maclen) = match mech.mechanism {
+            CKM_AES_CMAC => {
+                if mech.ulParameterLen != 0 {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+                (CMAC_NAME, AES_BLOCK_SIZE)
+            }
+            CKM_AES_CMAC_GENERAL => {
+                if mech.pParameter.is_null()
+                    || mech.ulParameterLen as usize
+                        != ::std::mem::size_of::<CK_MAC_GENERAL_PARAMS>()
+                {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+                let len = unsafe { *(mech.pParameter as *const CK_ULONG) };
+                if len < 1 || len as usize > AES_BLOCK_SIZE {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+                (CMAC_NAME, len as usize)
+            }
+            CKM_AES_GMAC => {
+                if mech.pParameter.is_null() || mech.ulParameterLen == 0 {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+                iv.extend_from_slice(unsafe {
+                    std::slice::from_raw_parts(
+                        mech.pParameter as *const u8,
+                        mech.ulParameterLen as usize,
+                    )
+                });
+                (GMAC_NAME, AES_BLOCK_SIZE)
+            }
+            _ => return err_rv!(CKR_MECHANISM_INVALID),
+        };
+        let key = object_to_raw_key(key)?;
+        /* CMAC is defined over the CBC mode of the cipher, GMAC over the
+         * GCM mode */
+        let cipher: &'static [u8] = match (mech.mechanism, key.raw.len()) {
+            (CKM_AES_GMAC, 16) => AES_128_GCM_NAME,
+            (CKM_AES_GMAC, 24) => AES_192_GCM_NAME,
+            (CKM_AES_GMAC, 32) => AES_256_GCM_NAME,
+            (_, 16) => AES_128_CBC_NAME,
+            (_, 24) => AES_192_CBC_NAME,
+            (_, 32) => AES_256_CBC_NAME,
+            _ => return err_rv!(CKR_KEY_SIZE_RANGE),
+        };
+        let mut mac = EvpMac::from_ptr(unsafe {
+            EVP_MAC_fetch(
+                get_libctx(),
+                macname.as_ptr() as *const i8,
+                std::ptr::null(),
+            )
+        })?;
+        let mut op = AesMacOperation {
+            mech: mech.mechanism,
+            key: key,
+            cipher: cipher,
+            iv: iv,
+            maclen: maclen,
+            ctx: EvpMacCtx::from_ptr(unsafe {
+                EVP_MAC_CTX_new(mac.as_mut_ptr())
+            })?,
+            finalized: false,
+            in_use: false,
+        };
+        op.begin()?;
+        Ok(op)
+    }
+
+    fn begin(&mut self) -> KResult<()> {
+        let mut params = [
+            unsafe {
+                OSSL_PARAM_construct_utf8_string(
+                    OSSL_MAC_PARAM_CIPHER.as_ptr() as *const i8,
+                    self.cipher.as_ptr() as *mut i8,
+                    0,
+                )
+            },
+            unsafe { OSSL_PARAM_construct_end() },
+            unsafe { OSSL_PARAM_construct_end() },
+        ];
+        if !self.iv.is_empty() {
+            params[1] = unsafe {
+                OSSL_PARAM_construct_octet_string(
+                    OSSL_MAC_PARAM_IV.as_ptr() as *const i8,
+                    self.iv.as_mut_ptr() as *mut std::os::raw::c_void,
+                    self.iv.len(),
+                )
+            };
+        }
+        if unsafe {
+            EVP_MAC_init(
+                self.ctx.as_mut_ptr(),
+                self.key.raw.as_ptr(),
+                self.key.raw.len(),
+                params.as_ptr(),
+            )
+        } != 1
+        {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        Ok(())
+    }
+
+    fn update(&mut self, data: &[u8]) -> KResult<()> {
+        if unsafe {
+            EVP_MAC_update(self.ctx.as_mut_ptr(), data.as_ptr(), data.len())
+        } != 1
+        {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        Ok(())
+    }
+
+    fn finalize(&mut self, output: &mut [u8]) -> KResult<()> {
+        let mut mac = [0u8; AES_BLOCK_SIZE];
+        let mut outlen = 0usize;
+        if unsafe {
+            EVP_MAC_final(
+                self.ctx.as_mut_ptr(),
+                mac.as_mut_ptr(),
+                &mut outlen,
+                mac.len(),
+            )
+        } != 1
+        {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        if outlen != AES_BLOCK_SIZE {
+            return err_rv!(CKR_GENERAL_ERROR);
+        }
+        output.copy_from_slice(&mac[..output.len()]);
+        Ok(())
+    }
+}
+
+impl MechOperation for AesMacOperation {
+    fn mechanism(&self) -> CK_MECHANISM_TYPE {
+        self.mech
+    }
+    fn in_use(&self) -> bool {
+        self.in_use
+    }
+    fn finalized(&self) -> bool {
+        self.finalized
+    }
+    fn reset(&mut self) -> KResult<()> {
+        self.begin()?;
+        self.finalized = false;
+        self.in_use = false;
+        Ok(())
+    }
+}
+
+impl Sign for AesMacOperation {
+    fn sign(&mut self, data: &[u8], signature: &mut [u8]) -> KResult<()> {
+        if self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        match self.sign_update(data) {
+            Err(e) => {
+                self.finalized = true;
+                return Err(e);
+            }
+            Ok(()) => (),
+        }
+        self.sign_final(signature)
+    }
+
+    fn sign_update(&mut self, data: &[u8]) -> KResult<()> {
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        self.in_use = true;
+        self.update(data)
+    }
+
+    fn sign_final(&mut self, signature: &mut [u8]) -> KResult<()> {
+        if !self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        self.finalized = true;
+        if signature.len() != self.maclen {
+            return err_rv!(CKR_GENERAL_ERROR);
+        }
+        self.finalize(signature)
+    }
+
+    fn signature_len(&self) -> KResult<usize> {
+        Ok(self.maclen)
+    }
+}
+
+impl Verify for AesMacOperation {
+    fn verify(&mut self, data: &[u8], signature: &[u8]) -> KResult<()> {
+        if self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        match self.verify_update(data) {
+            Err(e) => {
+                self.finalized = true;
+                return Err(e);
+            }
+            Ok(()) => (),
+        }
+        self.verify_final(signature)
+    }
+
+    fn verify_update(&mut self, data: &[u8]) -> KResult<()> {
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        self.in_use = true;
+        self.update(data)
+    }
+
+    fn verify_final(&mut self, signature: &[u8]) -> KResult<()> {
+        if !self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        self.finalized = true;
+        if signature.len() != self.maclen {
+            return err_rv!(CKR_SIGNATURE_LEN_RANGE);
+        }
+        let mut verify: Vec<u8> = vec![0; self.maclen];
+        self.finalize(verify.as_mut_slice())?;
+        if !constant_time_eq(&verify, signature) {
+            return err_rv!(CKR_SIGNATURE_INVALID);
+        }
+        Ok(())
+    }
+
+    fn signature_len(&self) -> KResult<usize> {
+        Ok(self.maclen)
+    }
+}
+
+/* Each single part operation, or each part of a multi part operation,
+ * is a separate XTS data unit (a sector). The tweak given as parameter
+ * is the data unit sequence number of the first part, encoded in little
+ * endian order as in IEEE 1619, and it is incremented for each
+ * following part */
+#[derive(Debug)]
+struct AesXtsOperation {
+    mech: CK_MECHANISM_TYPE,
+    tweak: [u8; AES_BLOCK_SIZE],
+    units: u64,
+    finalized: bool,
+    in_use: bool,
+    ctx: EvpCipherCtx,
+}
+
+impl AesXtsOperation {
+    fn new(
+        mech: &CK_MECHANISM,
+        key: &Object,
+        enc: bool,
+    ) -> KResult<AesXtsOperation> {
+        if mech.mechanism != CKM_AES_XTS {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        if mech.pParameter.is_null()
+            || mech.ulParameterLen as usize != AES_BLOCK_SIZE
+        {
+            return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+        }
+        let mut tweak = [0u8; AES_BLOCK_SIZE];
+        tweak.copy_from_slice(unsafe {
+            std::slice::from_raw_parts(
+                mech.pParameter as *const u8,
+                AES_BLOCK_SIZE,
+            )
+        });
+        let key = AesKey {
+            raw: key.get_attr_as_bytes(CKA_VALUE)?.clone(),
+        };
+        check_xts_key(&key.raw)?;
+        let evpcipher = match key.raw.len() {
+            32 => &AES_CIPHERS.aes128xts,
+            64 => &AES_CIPHERS.aes256xts,
+            _ => return err_rv!(CKR_KEY_SIZE_RANGE),
+        };
+        let mut ctx = EvpCipherCtx::from_ptr(unsafe { EVP_CIPHER_CTX_new() })?;
+        if unsafe {
+            EVP_CipherInit_ex(
+                ctx.as_mut_ptr(),
+                evpcipher.as_ptr(),
+                std::ptr::null_mut(),
+                key.raw.as_ptr(),
+                tweak.as_ptr(),
+                if enc { 1 } else { 0 },
+            )
+        } != 1
+        {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        Ok(AesXtsOperation {
+            mech: mech.mechanism,
+            tweak: tweak,
+            units: 0,
+            finalized: false,
+            in_use: false,
+            ctx: ctx,
+        })
+    }
+
+    fn encrypt_new(mech: &CK_MECHANISM, key: &Object) -> KResult<Self> {
+        Self::new(mech, key, true)
+    }
+
+    fn decrypt_new(mech: &CK_MECHANISM, key: &Object) -> KResult<Self> {
+        Self::new(mech, key, false)
+    }
+
+    fn data_unit(&mut self, input: &[u8], output: *mut u8) -> KResult<()> {
+        /* ciphertext stealing needs at least one full block */
+        if input.len() < AES_BLOCK_SIZE
+            || input.len() > std::os::raw::c_int::MAX as usize
+        {
+            self.finalized = true;
+            return err_rv!(CKR_DATA_LEN_RANGE);
+        }
+        if self.units > 0 {
+            for b in self.tweak.iter_mut() {
+                *b = b.wrapping_add(1);
+                if *b != 0 {
+                    break;
+                }
+            }
+            /* only reset the IV, the key and direction are kept */
+            if unsafe {
+                EVP_CipherInit_ex(
+                    self.ctx.as_mut_ptr(),
+                    std::ptr::null(),
+                    std::ptr::null_mut(),
+                    std::ptr::null(),
+                    self.tweak.as_ptr(),
+                    -1,
+                )
+            } != 1
+            {
+                self.finalized = true;
+                return err_rv!(CKR_DEVICE_ERROR);
+            }
+        }
+        self.units += 1;
+        let mut outl: std::os::raw::c_int = 0;
+        if unsafe {
+            EVP_CipherUpdate(
+                self.ctx.as_mut_ptr(),
+                output,
+                &mut outl,
+                input.as_ptr(),
+                input.len() as std::os::raw::c_int,
+            )
+        } != 1
+        {
+            self.finalized = true;
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        if outl as usize != input.len() {
+            self.finalized = true;
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        Ok(())
+    }
+
+    fn single_part(
+        &mut self,
+        input: &[u8],
+        output: CK_BYTE_PTR,
+        output_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if (unsafe { *output_len } as usize) < input.len() {
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
+        }
+        self.finalized = true;
+        self.data_unit(input, output)?;
+        unsafe {
+            *output_len = input.len() as CK_ULONG;
+        }
+        Ok(())
+    }
+
+    fn update(
+        &mut self,
+        input: &[u8],
+        output: CK_BYTE_PTR,
+        output_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if output_len.is_null() {
+            return err_rv!(CKR_ARGUMENTS_BAD);
+        }
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if output.is_null() {
+            unsafe {
+                *output_len = input.len() as CK_ULONG;
+            }
+            return Ok(());
+        }
+        if (unsafe { *output_len } as usize) < input.len() {
+            /* This is the only non-fatal error */
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
+        }
+        self.in_use = true;
+        self.data_unit(input, output)?;
+        unsafe {
+            *output_len = input.len() as CK_ULONG;
+        }
+        Ok(())
+    }
+
+    fn finalize(
+        &mut self,
+        output: CK_BYTE_PTR,
+        output_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if !self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        /* all data units have been fully processed already */
+        if !output.is_null() {
+            self.finalized = true;
+        }
+        unsafe {
+            *output_len = 0;
+        }
+        Ok(())
+    }
+}
+
+impl MechOperation for AesXtsOperation {
+    fn mechanism(&self) -> CK_MECHANISM_TYPE {
+        self.mech
+    }
+    fn in_use(&self) -> bool {
+        self.in_use
+    }
+    fn finalized(&self) -> bool {
+        self.finalized
+    }
+}
+
+impl Encryption for AesXtsOperation {
+    fn encrypt(
+        &mut self,
+        plain: &[u8],
+        cipher: CK_BYTE_PTR,
+        cipher_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        self.single_part(plain, cipher, cipher_len)
+    }
+
+    fn encrypt_update(
+        &mut self,
+        plain: &[u8],
+        cipher: CK_BYTE_PTR,
+        cipher_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        self.update(plain, cipher, cipher_len)
+    }
+
+    fn encrypt_final(
+        &mut self,
+        cipher: CK_BYTE_PTR,
+        cipher_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        self.finalize(cipher, cipher_len)
+    }
+
+    fn encryption_len(&self, data_len: usize, _fin: bool) -> KResult<usize> {
+        Ok(data_len)
+    }
+}
+
+impl Decryption for AesXtsOperation {
+    fn decrypt(
+        &mut self,
+        cipher: &[u8],
+        plain: CK_BYTE_PTR,
+        plain_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        self.single_part(cipher, plain, plain_len)
+    }
+
+    fn decrypt_update(
+        &mut self,
+        cipher: &[u8],
+        plain: CK_BYTE_PTR,
+        plain_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        self.update(cipher, plain, plain_len)
+    }
+
+    fn decrypt_final(
+        &mut self,
+        plain: CK_BYTE_PTR,
+        plain_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        self.finalize(plain, plain_len)
+    }
+
+    fn decryption_len(&self, data_len: usize, _fin: bool) -> KResult<usize> {
+        Ok(data_len)
+    }
+}
+
+/* OpenSSL CTS ciphers only accept the whole message in a single update
+ * call, so multi part operations process all leading full blocks with
+ * plain CBC and keep back the last two (possibly partial) blocks, which
+ * are handed to the CTS cipher on final with the last CBC chaining block
+ * as IV */
+#[derive(Debug)]
+struct AesCtsOperation {
+    mech: CK_MECHANISM_TYPE,
+    key: AesKey,
+    mode: &'static [u8],
+    lastblock: [u8; AES_BLOCK_SIZE],
+    enc: bool,
+    finalized: bool,
+    in_use: bool,
+    ctx: EvpCipherCtx,
+    databuf: Vec<u8>,
+}
+
+impl Drop for AesCtsOperation {
+    fn drop(&mut self) {
+        self.databuf.zeroize();
+    }
+}
+
+impl AesCtsOperation {
+    fn new(
+        mech: &CK_MECHANISM,
+        key: &Object,
+        enc: bool,
+    ) -> KResult<AesCtsOperation> {
+        let mode: &'static [u8] = match mech.mechanism {
+            CKM_AES_CTS => OSSL_CIPHER_CTS_MODE_CS3,
+            KRYMECH_AES_CTS_CS1 => OSSL_CIPHER_CTS_MODE_CS1,
+            KRYMECH_AES_CTS_CS2 => OSSL_CIPHER_CTS_MODE_CS2,
+            _ => return err_rv!(CKR_MECHANISM_INVALID),
+        };
+        if mech.pParameter.is_null()
+            || mech.ulParameterLen as usize != AES_BLOCK_SIZE
+        {
+            return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+        }
+        let mut iv = [0u8; AES_BLOCK_SIZE];
+        iv.copy_from_slice(unsafe {
+            std::slice::from_raw_parts(
+                mech.pParameter as *const u8,
+                AES_BLOCK_SIZE,
+            )
+        });
+        let key = object_to_raw_key(key)?;
+        let evpcipher = match key.raw.len() {
+            16 => &AES_CIPHERS.aes128cbc,
+            24 => &AES_CIPHERS.aes192cbc,
+            32 => &AES_CIPHERS.aes256cbc,
+            _ => return err_rv!(CKR_KEY_SIZE_RANGE),
+        };
+        let mut ctx = EvpCipherCtx::from_ptr(unsafe { EVP_CIPHER_CTX_new() })?;
+        if unsafe {
+            EVP_CipherInit_ex(
+                ctx.as_mut_ptr(),
+                evpcipher.as_ptr(),
+                std::ptr::null_mut(),
+                key.raw.as_ptr(),
+                iv.as_ptr(),
+                if enc { 1 } else { 0 },
+            )
+        } != 1
+        {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        if unsafe { EVP_CIPHER_CTX_set_padding(ctx.as_mut_ptr(), 0) } != 1 {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        Ok(AesCtsOperation {
+            mech: mech.mechanism,
+            key: key,
+            mode: mode,
+            lastblock: iv,
+            enc: enc,
+            finalized: false,
+            in_use: false,
+            ctx: ctx,
+            databuf: Vec::new(),
+        })
+    }
+
+    fn encrypt_new(mech: &CK_MECHANISM, key: &Object) -> KResult<Self> {
+        Self::new(mech, key, true)
+    }
+
+    fn decrypt_new(mech: &CK_MECHANISM, key: &Object) -> KResult<Self> {
+        Self::new(mech, key, false)
+    }
+
+    /* The amount of data that must be held back for the final call, this
+     * is always more than one block, unless the whole message is shorter,
+     * and never more than two blocks */
+    fn tail_len(len: usize) -> usize {
+        if len <= 2 * AES_BLOCK_SIZE {
+            len
+        } else {
+            len - ((len - AES_BLOCK_SIZE - 1) / AES_BLOCK_SIZE) * AES_BLOCK_SIZE
+        }
+    }
+
+    fn data_len_range<T>(&self) -> KResult<T> {
+        if self.enc {
+            err_rv!(CKR_DATA_LEN_RANGE)
+        } else {
+            err_rv!(CKR_ENCRYPTED_DATA_LEN_RANGE)
+        }
+    }
+
+    fn update(
+        &mut self,
+        input: &[u8],
+        output: CK_BYTE_PTR,
+        output_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if output_len.is_null() {
+            return err_rv!(CKR_ARGUMENTS_BAD);
+        }
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        let total = self.databuf.len() + input.len();
+        let outlen = total - Self::tail_len(total);
+        if outlen > std::os::raw::c_int::MAX as usize {
+            self.finalized = true;
+            return self.data_len_range();
+        }
+        if output.is_null() {
+            unsafe {
+                *output_len = outlen as CK_ULONG;
+            }
+            return Ok(());
+        }
+        if (unsafe { *output_len } as usize) < outlen {
+            /* This is the only non-fatal error */
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
+        }
+        self.in_use = true;
+        self.databuf.extend_from_slice(input);
+        if outlen > 0 {
+            let mut outl: std::os::raw::c_int = 0;
+            if unsafe {
+                EVP_CipherUpdate(
+                    self.ctx.as_mut_ptr(),
+                    output,
+                    &mut outl,
+                    self.databuf.as_ptr(),
+                    outlen as std::os::raw::c_int,
+                )
+            } != 1
+            {
+                self.finalized = true;
+                return err_rv!(CKR_DEVICE_ERROR);
+            }
+            if outl as usize != outlen {
+                self.finalized = true;
+                return err_rv!(CKR_DEVICE_ERROR);
+            }
+            /* the CBC chaining value is always the last ciphertext block */
+            let last = outlen - AES_BLOCK_SIZE;
+            if self.enc {
+                self.lastblock.copy_from_slice(unsafe {
+                    std::slice::from_raw_parts(output.add(last), AES_BLOCK_SIZE)
+                });
+            } else {
+                self.lastblock.copy_from_slice(&self.databuf[last..outlen]);
+            }
+            let mut tail = self.databuf[outlen..].to_vec();
+            std::mem::swap(&mut self.databuf, &mut tail);
+            tail.zeroize();
+        }
+        unsafe {
+            *output_len = outlen as CK_ULONG;
+        }
+        Ok(())
+    }
+
+    fn finalize(
+        &mut self,
+        output: CK_BYTE_PTR,
+        output_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if output.is_null() {
+            unsafe {
+                *output_len = self.databuf.len() as CK_ULONG;
+            }
+            return Ok(());
+        }
+        if self.databuf.len() < AES_BLOCK_SIZE {
+            self.finalized = true;
+            return self.data_len_range();
+        }
+        if (unsafe { *output_len } as usize) < self.databuf.len() {
+            /* This is the only non-fatal error */
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
+        }
+        self.finalized = true;
+        let evpcipher = match self.key.raw.len() {
+            16 => &AES_CIPHERS.aes128cts,
+            24 => &AES_CIPHERS.aes192cts,
+            32 => &AES_CIPHERS.aes256cts,
+            _ => return err_rv!(CKR_KEY_SIZE_RANGE),
+        };
+        let mut ctx = EvpCipherCtx::from_ptr(unsafe { EVP_CIPHER_CTX_new() })?;
+        if unsafe {
+            EVP_CipherInit_ex(
+                ctx.as_mut_ptr(),
+                evpcipher.as_ptr(),
+                std::ptr::null_mut(),
+                self.key.raw.as_ptr(),
+                self.lastblock.as_ptr(),
+                if self.enc { 1 } else { 0 },
+            )
+        } != 1
+        {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        let params = [
+            unsafe {
+                OSSL_PARAM_construct_utf8_string(
+                    OSSL_CIPHER_PARAM_CTS_MODE.as_ptr() as *const i8,
+                    self.mode.as_ptr() as *mut i8,
+                    self.mode.len(),
+                )
+            },
+            unsafe { OSSL_PARAM_construct_end() },
+        ];
+        if unsafe {
+            EVP_CIPHER_CTX_set_params(ctx.as_mut_ptr(), params.as_ptr())
+        } != 1
+        {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        let mut outl: std::os::raw::c_int = 0;
+        if unsafe {
+            EVP_CipherUpdate(
+                ctx.as_mut_ptr(),
+                output,
+                &mut outl,
+                self.databuf.as_ptr(),
+                self.databuf.len() as std::os::raw::c_int,
+            )
+        } != 1
+        {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        if outl as usize != self.databuf.len() {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        unsafe {
+            *output_len = outl as CK_ULONG;
+        }
+        Ok(())
+    }
+
+    fn single_part(
+        &mut self,
+        input: &[u8],
+        output: CK_BYTE_PTR,
+        output_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if input.len() > std::os::raw::c_int::MAX as usize {
+            self.finalized = true;
+            return self.data_len_range();
+        }
+        if (unsafe { *output_len } as usize) < input.len() {
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
+        }
+        /* a single part is processed with the CTS cipher directly */
+        self.databuf.extend_from_slice(input);
+        self.finalize(output, output_len)
+    }
+}
+
+impl MechOperation for AesCtsOperation {
+    fn mechanism(&self) -> CK_MECHANISM_TYPE {
+        self.mech
+    }
+    fn in_use(&self) -> bool {
+        self.in_use
+    }
+    fn finalized(&self) -> bool {
+        self.finalized
+    }
+}
+
+impl Encryption for AesCtsOperation {
+    fn encrypt(
+        &mut self,
+        plain: &[u8],
+        cipher: CK_BYTE_PTR,
+        cipher_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        self.single_part(plain, cipher, cipher_len)
+    }
+
+    fn encrypt_update(
+        &mut self,
+        plain: &[u8],
+        cipher: CK_BYTE_PTR,
+        cipher_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        self.update(plain, cipher, cipher_len)
+    }
+
+    fn encrypt_final(
+        &mut self,
+        cipher: CK_BYTE_PTR,
+        cipher_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if !self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        self.finalize(cipher, cipher_len)
+    }
+
+    fn encryption_len(&self, data_len: usize, fin: bool) -> KResult<usize> {
+        let total = self.databuf.len() + data_len;
+        if fin {
+            Ok(total)
+        } else {
+            Ok(total - Self::tail_len(total))
+        }
+    }
+}
+
+impl Decryption for AesCtsOperation {
+    fn decrypt(
+        &mut self,
+        cipher: &[u8],
+        plain: CK_BYTE_PTR,
+        plain_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        self.single_part(cipher, plain, plain_len)
+    }
+
+    fn decrypt_update(
+        &mut self,
+        cipher: &[u8],
+        plain: CK_BYTE_PTR,
+        plain_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        self.update(cipher, plain, plain_len)
+    }
+
+    fn decrypt_final(
+        &mut self,
+        plain: CK_BYTE_PTR,
+        plain_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if !self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        self.finalize(plain, plain_len)
+    }
+
+    fn decryption_len(&self, data_len: usize, fin: bool) -> KResult<usize> {
+        let total = self.databuf.len() + data_len;
+        if fin {
+            Ok(total)
+        } else {
+            Ok(total - Self::tail_len(total))
         }
-        Ok(())
     }
 }
@@ -0,0 +1,1139 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use {super::ossl, ossl::*};
+
+use zeroize::Zeroize;
+
+const CHACHA20_NAME: &[u8; 9] = b"ChaCha20\0";
+const CHACHA20_POLY1305_NAME: &[u8; 18] = b"ChaCha20-Poly1305\0";
+const POLY1305_NAME: &[u8; 9] = b"POLY1305\0";
+const CHACHA20_BLOCK_SIZE: usize = 64;
+const CHACHA20_POLY1305_NONCE_SIZE: usize = 12;
+const POLY1305_TAG_SIZE: usize = 16;
+
+struct ChaCha20Ciphers {
+    chacha20: EvpCipher,
+    chacha20poly1305: EvpCipher,
+}
+
+/* see the comment on AesCiphers */
+unsafe impl Send for ChaCha20Ciphers {}
+unsafe impl Sync for ChaCha20Ciphers {}
+
+fn fetch_cipher(name: &[u8]) -> EvpCipher {
+    EvpCipher::from_ptr(unsafe {
+        EVP_CIPHER_fetch(
+            get_libctx(),
+            name.as_ptr() as *const i8,
+            std::ptr::null(),
+        )
+    })
+    .unwrap()
+}
+
+static CHACHA20_CIPHERS: Lazy<ChaCha20Ciphers> =
+    Lazy::new(|| ChaCha20Ciphers {
+        chacha20: fetch_cipher(CHACHA20_NAME),
+        chacha20poly1305: fetch_cipher(CHACHA20_POLY1305_NAME),
+    });
+
+#[derive(Debug)]
+struct ChaCha20Key {
+    raw: Vec<u8>,
+}
+
+impl Drop for ChaCha20Key {
+    fn drop(&mut self) {
+        self.raw.zeroize()
+    }
+}
+
+fn object_to_raw_key(key: &Object) -> KResult<ChaCha20Key> {
+    let val = key.get_attr_as_bytes(CKA_VALUE)?;
+    check_key_len(val.len())?;
+    Ok(ChaCha20Key { raw: val.clone() })
+}
+
+fn aead_tag(ctx: &mut EvpCipherCtx, tag: &mut [u8], set: bool) -> KResult<()> {
+    let mut params = [
+        unsafe {
+            OSSL_PARAM_construct_octet_string(
+                OSSL_CIPHER_PARAM_AEAD_TAG.as_ptr() as *const i8,
+                tag.as_mut_ptr() as *mut std::os::raw::c_void,
+                tag.len(),
+            )
+        },
+        unsafe { OSSL_PARAM_construct_end() },
+    ];
+    let ret = if set {
+        unsafe { EVP_CIPHER_CTX_set_params(ctx.as_mut_ptr(), params.as_ptr()) }
+    } else {
+        unsafe {
+            EVP_CIPHER_CTX_get_params(ctx.as_mut_ptr(), params.as_mut_ptr())
+        }
+    };
+    if ret != 1 {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+struct ChaCha20Params {
+    iv: Vec<u8>,
+    aad: Vec<u8>,
+    maxbytes: u128,
+}
+
+impl ChaCha20Params {
+    fn new(mech: &CK_MECHANISM) -> KResult<ChaCha20Params> {
+        match mech.mechanism {
+            CKM_CHACHA20 => {
+                if mech.pParameter.is_null()
+                    || mech.ulParameterLen as usize
+                        != ::std::mem::size_of::<CK_CHACHA20_PARAMS>()
+                {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+                let params =
+                    unsafe { &*(mech.pParameter as *const CK_CHACHA20_PARAMS) };
+                /* RFC 8439 uses a 32 bit counter and a 96 bit nonce, the
+                 * original construction a 64 bit counter and nonce */
+                match (params.blockCounterBits, params.ulNonceBits) {
+                    (32, 96) | (64, 64) => (),
+                    _ => return err_rv!(CKR_MECHANISM_PARAM_INVALID),
+                }
+                if params.pNonce.is_null() {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+                /* OpenSSL takes the counter and the nonce as a single 128
+                 * bit IV laid out as in the ChaCha20 state, the counter is
+                 * therefore expected in little endian order, a missing
+                 * counter means the keystream starts at block 0 */
+                let ctrlen = (params.blockCounterBits / 8) as usize;
+                let mut iv = vec![0u8; ctrlen];
+                if !params.pBlockCounter.is_null() {
+                    iv.copy_from_slice(unsafe {
+                        std::slice::from_raw_parts(params.pBlockCounter, ctrlen)
+                    });
+                }
+                /* OpenSSL would carry a 32 bit counter into the nonce, so
+                 * stop before the counter wraps */
+                let maxbytes = if ctrlen == 4 {
+                    let ctr = u32::from_le_bytes([iv[0], iv[1], iv[2], iv[3]]);
+                    ((1u128 << 32) - ctr as u128) * CHACHA20_BLOCK_SIZE as u128
+                } else {
+                    0
+                };
+                iv.extend_from_slice(unsafe {
+                    std::slice::from_raw_parts(
+                        params.pNonce,
+                        (params.ulNonceBits / 8) as usize,
+                    )
+                });
+                Ok(ChaCha20Params {
+                    iv: iv,
+                    aad: Vec::new(),
+                    maxbytes: maxbytes,
+                })
+            }
+            CKM_CHACHA20_POLY1305 => {
+                if mech.pParameter.is_null()
+                    || mech.ulParameterLen as usize
+                        != ::std::mem::size_of::<
+                            CK_SALSA20_CHACHA20_POLY1305_PARAMS,
+                        >()
+                {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+                let params = unsafe {
+                    &*(mech.pParameter
+                        as *const CK_SALSA20_CHACHA20_POLY1305_PARAMS)
+                };
+                /* only the RFC 8439 construction is supported */
+                if params.pNonce.is_null()
+                    || params.ulNonceLen as usize
+                        != CHACHA20_POLY1305_NONCE_SIZE
+                {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+                if params.pAAD.is_null() && params.ulAADLen != 0 {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+                Ok(ChaCha20Params {
+                    iv: unsafe {
+                        std::slice::from_raw_parts(
+                            params.pNonce,
+                            CHACHA20_POLY1305_NONCE_SIZE,
+                        )
+                        .to_vec()
+                    },
+                    aad: if params.ulAADLen == 0 {
+                        Vec::new()
+                    } else {
+                        unsafe {
+                            std::slice::from_raw_parts(
+                                params.pAAD,
+                                params.ulAADLen as usize,
+                            )
+                            .to_vec()
+                        }
+                    },
+                    /* block 0 is used for the Poly1305 key */
+                    maxbytes: ((1u128 << 32) - 1) * CHACHA20_BLOCK_SIZE as u128,
+                })
+            }
+            _ => err_rv!(CKR_MECHANISM_INVALID),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ChaCha20Operation {
+    mech: CK_MECHANISM_TYPE,
+    key: ChaCha20Key,
+    params: ChaCha20Params,
+    finalized: bool,
+    in_use: bool,
+    ctx: EvpCipherCtx,
+    bytectr: u128,
+    databuf: Vec<u8>,
+}
+
+impl Drop for ChaCha20Operation {
+    fn drop(&mut self) {
+        self.databuf.zeroize();
+    }
+}
+
+impl ChaCha20Operation {
+    fn new(
+        mech: &CK_MECHANISM,
+        key: &Object,
+        enc: bool,
+    ) -> KResult<ChaCha20Operation> {
+        let mut op = ChaCha20Operation {
+            mech: mech.mechanism,
+            key: object_to_raw_key(key)?,
+            params: ChaCha20Params::new(mech)?,
+            finalized: false,
+            in_use: false,
+            ctx: EvpCipherCtx::from_ptr(unsafe { EVP_CIPHER_CTX_new() })?,
+            bytectr: 0,
+            databuf: Vec::new(),
+        };
+        op.init(enc)?;
+        Ok(op)
+    }
+
+    fn encrypt_new(
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> KResult<ChaCha20Operation> {
+        Self::new(mech, key, true)
+    }
+
+    fn decrypt_new(
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> KResult<ChaCha20Operation> {
+        Self::new(mech, key, false)
+    }
+
+    fn init(&mut self, enc: bool) -> KResult<()> {
+        let evpcipher = match self.mech {
+            CKM_CHACHA20 => &CHACHA20_CIPHERS.chacha20,
+            CKM_CHACHA20_POLY1305 => &CHACHA20_CIPHERS.chacha20poly1305,
+            _ => return err_rv!(CKR_MECHANISM_INVALID),
+        };
+        if unsafe {
+            EVP_CipherInit_ex(
+                self.ctx.as_mut_ptr(),
+                evpcipher.as_ptr(),
+                std::ptr::null_mut(),
+                self.key.raw.as_ptr(),
+                self.params.iv.as_ptr(),
+                if enc { 1 } else { 0 },
+            )
+        } != 1
+        {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        if !self.params.aad.is_empty() {
+            let mut outl: std::os::raw::c_int = 0;
+            if unsafe {
+                EVP_CipherUpdate(
+                    self.ctx.as_mut_ptr(),
+                    std::ptr::null_mut(),
+                    &mut outl,
+                    self.params.aad.as_ptr(),
+                    self.params.aad.len() as std::os::raw::c_int,
+                )
+            } != 1
+            {
+                return err_rv!(CKR_DEVICE_ERROR);
+            }
+        }
+        Ok(())
+    }
+
+    fn taglen(&self) -> usize {
+        match self.mech {
+            CKM_CHACHA20_POLY1305 => POLY1305_TAG_SIZE,
+            _ => 0,
+        }
+    }
+
+    /* ChaCha20 is a stream cipher, the output always matches the input */
+    fn update(&mut self, input: &[u8], output: *mut u8) -> KResult<usize> {
+        if input.is_empty() {
+            return Ok(0);
+        }
+        if self.params.maxbytes > 0 {
+            let total = self.bytectr + input.len() as u128;
+            if total > self.params.maxbytes {
+                self.finalized = true;
+                return err_rv!(CKR_DATA_LEN_RANGE);
+            }
+            self.bytectr = total;
+        }
+        let mut outl: std::os::raw::c_int = 0;
+        if unsafe {
+            EVP_CipherUpdate(
+                self.ctx.as_mut_ptr(),
+                output,
+                &mut outl,
+                input.as_ptr(),
+                input.len() as std::os::raw::c_int,
+            )
+        } != 1
+        {
+            self.finalized = true;
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        if outl as usize != input.len() {
+            self.finalized = true;
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        Ok(input.len())
+    }
+
+    fn aead_decrypt_final(
+        &mut self,
+        plain: CK_BYTE_PTR,
+        plain_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if self.databuf.len() < POLY1305_TAG_SIZE {
+            self.finalized = true;
+            return err_rv!(CKR_ENCRYPTED_DATA_LEN_RANGE);
+        }
+        let datalen = self.databuf.len() - POLY1305_TAG_SIZE;
+        if plain.is_null() {
+            unsafe {
+                *plain_len = datalen as CK_ULONG;
+            }
+            return Ok(());
+        }
+        if (unsafe { *plain_len } as usize) < datalen {
+            /* This is the only non-fatal error */
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
+        }
+        self.finalized = true;
+
+        let mut data = std::mem::take(&mut self.databuf);
+        let mut tag = data.split_off(datalen);
+        if let Err(e) = aead_tag(&mut self.ctx, &mut tag, true) {
+            data.zeroize();
+            return Err(e);
+        }
+
+        /* decrypt in a scratch buffer so that nothing is returned to the
+         * caller unless the tag matches */
+        let mut outbuf: Vec<u8> = vec![0; datalen];
+        let ret = self.update(&data, outbuf.as_mut_ptr());
+        data.zeroize();
+        if let Err(e) = ret {
+            outbuf.zeroize();
+            return Err(e);
+        }
+        let mut outl: std::os::raw::c_int = 0;
+        if unsafe {
+            EVP_DecryptFinal_ex(
+                self.ctx.as_mut_ptr(),
+                outbuf.as_mut_ptr().add(datalen),
+                &mut outl,
+            )
+        } != 1
+        {
+            outbuf.zeroize();
+            return err_rv!(CKR_ENCRYPTED_DATA_INVALID);
+        }
+        unsafe {
+            std::ptr::copy_nonoverlapping(outbuf.as_ptr(), plain, datalen);
+            *plain_len = datalen as CK_ULONG;
+        }
+        outbuf.zeroize();
+        Ok(())
+    }
+}
+
+impl MechOperation for ChaCha20Operation {
+    fn mechanism(&self) -> CK_MECHANISM_TYPE {
+        self.mech
+    }
+    fn in_use(&self) -> bool {
+        self.in_use
+    }
+    fn finalized(&self) -> bool {
+        self.finalized
+    }
+}
+
+impl Encryption for ChaCha20Operation {
+    fn encrypt(
+        &mut self,
+        plain: &[u8],
+        cipher: CK_BYTE_PTR,
+        cipher_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        let clen: CK_ULONG = unsafe { *cipher_len };
+        /* make sure the tag fits before any data is processed */
+        if (clen as usize) < plain.len() + self.taglen() {
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
+        }
+        let mut outl: CK_ULONG = clen;
+        self.encrypt_update(plain, cipher, &mut outl)?;
+        let mut foutl = clen - outl;
+        self.encrypt_final(unsafe { cipher.add(outl as usize) }, &mut foutl)?;
+        unsafe { *cipher_len = foutl + outl };
+        Ok(())
+    }
+
+    fn encrypt_update(
+        &mut self,
+        plain: &[u8],
+        cipher: CK_BYTE_PTR,
+        cipher_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if cipher_len.is_null() {
+            return err_rv!(CKR_ARGUMENTS_BAD);
+        }
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if cipher.is_null() {
+            unsafe {
+                *cipher_len = plain.len() as CK_ULONG;
+            }
+            return Ok(());
+        }
+        if (unsafe { *cipher_len } as usize) < plain.len() {
+            /* This is the only non-fatal error */
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
+        }
+        self.in_use = true;
+        let outl = self.update(plain, cipher)?;
+        unsafe {
+            *cipher_len = outl as CK_ULONG;
+        }
+        Ok(())
+    }
+
+    fn encrypt_final(
+        &mut self,
+        cipher: CK_BYTE_PTR,
+        cipher_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if !self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        let taglen = self.taglen();
+        if cipher.is_null() {
+            unsafe {
+                *cipher_len = taglen as CK_ULONG;
+            }
+            return Ok(());
+        }
+        if (unsafe { *cipher_len } as usize) < taglen {
+            /* This is the only non-fatal error */
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
+        }
+        self.finalized = true;
+
+        let mut outl: std::os::raw::c_int = 0;
+        if unsafe {
+            EVP_EncryptFinal_ex(self.ctx.as_mut_ptr(), cipher, &mut outl)
+        } != 1
+        {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        if outl != 0 {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        if taglen > 0 {
+            aead_tag(
+                &mut self.ctx,
+                unsafe { std::slice::from_raw_parts_mut(cipher, taglen) },
+                false,
+            )?;
+        }
+        unsafe {
+            *cipher_len = taglen as CK_ULONG;
+        }
+        Ok(())
+    }
+
+    fn encryption_len(&self, data_len: usize, fin: bool) -> KResult<usize> {
+        if fin {
+            Ok(data_len + self.taglen())
+        } else {
+            Ok(data_len)
+        }
+    }
+}
+
+impl Decryption for ChaCha20Operation {
+    fn decrypt(
+        &mut self,
+        cipher: &[u8],
+        plain: CK_BYTE_PTR,
+        plain_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        let taglen = self.taglen();
+        if cipher.len() < taglen {
+            self.finalized = true;
+            return err_rv!(CKR_ENCRYPTED_DATA_LEN_RANGE);
+        }
+        let plen: CK_ULONG = unsafe { *plain_len };
+        if (plen as usize) < cipher.len() - taglen {
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
+        }
+        let mut outl: CK_ULONG = plen;
+        self.decrypt_update(cipher, plain, &mut outl)?;
+        let mut foutl = plen - outl;
+        self.decrypt_final(unsafe { plain.add(outl as usize) }, &mut foutl)?;
+        unsafe { *plain_len = foutl + outl };
+        Ok(())
+    }
+
+    fn decrypt_update(
+        &mut self,
+        cipher: &[u8],
+        plain: CK_BYTE_PTR,
+        plain_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if plain_len.is_null() {
+            return err_rv!(CKR_ARGUMENTS_BAD);
+        }
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if self.mech == CKM_CHACHA20_POLY1305 {
+            /* No plaintext can be released before the tag is checked, so
+             * all data is buffered and decrypted in the final call */
+            if !plain.is_null() {
+                self.in_use = true;
+                self.databuf.extend_from_slice(cipher);
+            }
+            unsafe {
+                *plain_len = 0;
+            }
+            return Ok(());
+        }
+        if plain.is_null() {
+            unsafe {
+                *plain_len = cipher.len() as CK_ULONG;
+            }
+            return Ok(());
+        }
+        if (unsafe { *plain_len } as usize) < cipher.len() {
+            /* This is the only non-fatal error */
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
+        }
+        self.in_use = true;
+        let outl = self.update(cipher, plain)?;
+        unsafe {
+            *plain_len = outl as CK_ULONG;
+        }
+        Ok(())
+    }
+
+    fn decrypt_final(
+        &mut self,
+        plain: CK_BYTE_PTR,
+        plain_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if !self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if self.mech == CKM_CHACHA20_POLY1305 {
+            return self.aead_decrypt_final(plain, plain_len);
+        }
+        if !plain.is_null() {
+            self.finalized = true;
+        }
+        unsafe {
+            *plain_len = 0;
+        }
+        Ok(())
+    }
+
+    fn decryption_len(&self, data_len: usize, fin: bool) -> KResult<usize> {
+        Ok(match self.mech {
+            CKM_CHACHA20_POLY1305 => {
+                if fin {
+                    (self.databuf.len() + data_len)
+                        .saturating_sub(POLY1305_TAG_SIZE)
+                } else {
+                    0
+                }
+            }
+            _ => data_len,
+        })
+    }
+}
+
+fn chacha20_poly1305_msg_params<'a>(
+    param: CK_VOID_PTR,
+    paramlen: usize,
+) -> KResult<&'a mut CK_SALSA20_CHACHA20_POLY1305_MSG_PARAMS> {
+    if param.is_null()
+        || paramlen
+            != ::std::mem::size_of::<CK_SALSA20_CHACHA20_POLY1305_MSG_PARAMS>()
+    {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    let params = unsafe {
+        &mut *(param as *mut CK_SALSA20_CHACHA20_POLY1305_MSG_PARAMS)
+    };
+    /* only the RFC 8439 construction is supported */
+    if params.pNonce.is_null()
+        || params.ulNonceLen as usize != CHACHA20_POLY1305_NONCE_SIZE
+        || params.pTag.is_null()
+    {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    Ok(params)
+}
+
+#[derive(Debug)]
+struct ChaCha20MsgOperation {
+    mech: CK_MECHANISM_TYPE,
+    key: ChaCha20Key,
+    ctx: EvpCipherCtx,
+    enc: bool,
+    finalized: bool,
+    in_use: bool,
+    bytectr: u128,
+}
+
+impl ChaCha20MsgOperation {
+    fn new(mech: &CK_MECHANISM, key: &Object, enc: bool) -> KResult<Self> {
+        match mech.mechanism {
+            CKM_CHACHA20_POLY1305 => (),
+            _ => return err_rv!(CKR_MECHANISM_INVALID),
+        }
+        Ok(ChaCha20MsgOperation {
+            mech: mech.mechanism,
+            key: object_to_raw_key(key)?,
+            ctx: EvpCipherCtx::from_ptr(unsafe { EVP_CIPHER_CTX_new() })?,
+            enc: enc,
+            finalized: false,
+            in_use: false,
+            bytectr: 0,
+        })
+    }
+
+    fn encrypt_new(mech: &CK_MECHANISM, key: &Object) -> KResult<Self> {
+        Self::new(mech, key, true)
+    }
+
+    fn decrypt_new(mech: &CK_MECHANISM, key: &Object) -> KResult<Self> {
+        Self::new(mech, key, false)
+    }
+
+    fn begin(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: usize,
+        aad: &[u8],
+    ) -> KResult<()> {
+        if self.in_use {
+            return err_rv!(CKR_OPERATION_ACTIVE);
+        }
+        let params = chacha20_poly1305_msg_params(param, paramlen)?;
+        if unsafe {
+            EVP_CipherInit_ex(
+                self.ctx.as_mut_ptr(),
+                CHACHA20_CIPHERS.chacha20poly1305.as_ptr(),
+                std::ptr::null_mut(),
+                self.key.raw.as_ptr(),
+                params.pNonce,
+                if self.enc { 1 } else { 0 },
+            )
+        } != 1
+        {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        if !aad.is_empty() {
+            let mut outl: std::os::raw::c_int = 0;
+            if unsafe {
+                EVP_CipherUpdate(
+                    self.ctx.as_mut_ptr(),
+                    std::ptr::null_mut(),
+                    &mut outl,
+                    aad.as_ptr(),
+                    aad.len() as std::os::raw::c_int,
+                )
+            } != 1
+            {
+                return err_rv!(CKR_DEVICE_ERROR);
+            }
+        }
+        if !self.enc {
+            aead_tag(
+                &mut self.ctx,
+                unsafe {
+                    std::slice::from_raw_parts_mut(
+                        params.pTag,
+                        POLY1305_TAG_SIZE,
+                    )
+                },
+                true,
+            )?;
+        }
+        self.bytectr = 0;
+        self.in_use = true;
+        Ok(())
+    }
+
+    fn cipher_update(&mut self, data: &[u8], out: *mut u8) -> KResult<usize> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+        /* block 0 is used for the Poly1305 key */
+        let total = self.bytectr + data.len() as u128;
+        if total > ((1u128 << 32) - 1) * CHACHA20_BLOCK_SIZE as u128 {
+            self.in_use = false;
+            return err_rv!(CKR_DATA_LEN_RANGE);
+        }
+        self.bytectr = total;
+        let mut outl: std::os::raw::c_int = 0;
+        if unsafe {
+            EVP_CipherUpdate(
+                self.ctx.as_mut_ptr(),
+                out,
+                &mut outl,
+                data.as_ptr(),
+                data.len() as std::os::raw::c_int,
+            )
+        } != 1
+        {
+            self.in_use = false;
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        if outl as usize != data.len() {
+            self.in_use = false;
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        Ok(data.len())
+    }
+
+    fn update(
+        &mut self,
+        data: &[u8],
+        out: CK_BYTE_PTR,
+        out_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if !self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if (unsafe { *out_len } as usize) < data.len() {
+            /* This is the only non-fatal error */
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
+        }
+        let outl = self.cipher_update(data, out)?;
+        unsafe {
+            *out_len = outl as CK_ULONG;
+        }
+        Ok(())
+    }
+
+    fn end(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: usize,
+        data: &[u8],
+        out: CK_BYTE_PTR,
+        out_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if !self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        let params = chacha20_poly1305_msg_params(param, paramlen)?;
+        if (unsafe { *out_len } as usize) < data.len() {
+            /* This is the only non-fatal error */
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
+        }
+
+        /* the last part is processed in a scratch buffer so that no
+         * plaintext is released if the tag does not match */
+        let mut outbuf: Vec<u8> = vec![0; data.len()];
+        if let Err(e) = self.cipher_update(data, outbuf.as_mut_ptr()) {
+            outbuf.zeroize();
+            return Err(e);
+        }
+        self.in_use = false;
+        let mut outl: std::os::raw::c_int = 0;
+        if unsafe {
+            EVP_CipherFinal_ex(
+                self.ctx.as_mut_ptr(),
+                outbuf.as_mut_ptr().add(data.len()),
+                &mut outl,
+            )
+        } != 1
+        {
+            outbuf.zeroize();
+            if self.enc {
+                return err_rv!(CKR_DEVICE_ERROR);
+            }
+            return err_rv!(CKR_AEAD_DECRYPT_FAILED);
+        }
+        if outl != 0 {
+            outbuf.zeroize();
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        if self.enc {
+            aead_tag(
+                &mut self.ctx,
+                unsafe {
+                    std::slice::from_raw_parts_mut(
+                        params.pTag,
+                        POLY1305_TAG_SIZE,
+                    )
+                },
+                false,
+            )?;
+        }
+        unsafe {
+            std::ptr::copy_nonoverlapping(outbuf.as_ptr(), out, data.len());
+            *out_len = data.len() as CK_ULONG;
+        }
+        outbuf.zeroize();
+        Ok(())
+    }
+}
+
+impl MechOperation for ChaCha20MsgOperation {
+    fn mechanism(&self) -> CK_MECHANISM_TYPE {
+        self.mech
+    }
+    fn in_use(&self) -> bool {
+        self.in_use
+    }
+    fn finalized(&self) -> bool {
+        self.finalized
+    }
+}
+
+impl MsgEncryption for ChaCha20MsgOperation {
+    fn msg_encrypt(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: usize,
+        aad: &[u8],
+        plain: &[u8],
+        cipher: CK_BYTE_PTR,
+        cipher_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if (unsafe { *cipher_len } as usize) < plain.len() {
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
+        }
+        self.begin(param, paramlen, aad)?;
+        self.end(param, paramlen, plain, cipher, cipher_len)
+    }
+
+    fn msg_encrypt_begin(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: usize,
+        aad: &[u8],
+    ) -> KResult<()> {
+        self.begin(param, paramlen, aad)
+    }
+
+    fn msg_encrypt_next(
+        &mut self,
+        _param: CK_VOID_PTR,
+        _paramlen: usize,
+        plain: &[u8],
+        cipher: CK_BYTE_PTR,
+        cipher_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        self.update(plain, cipher, cipher_len)
+    }
+
+    fn msg_encrypt_final(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: usize,
+        plain: &[u8],
+        cipher: CK_BYTE_PTR,
+        cipher_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        self.end(param, paramlen, plain, cipher, cipher_len)
+    }
+
+    fn msg_encryption_len(&self, data_len: usize, _: bool) -> KResult<usize> {
+        /* the tag is returned in the message parameters */
+        Ok(data_len)
+    }
+}
+
+impl MsgDecryption for ChaCha20MsgOperation {
+    fn msg_decrypt(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: usize,
+        aad: &[u8],
+        cipher: &[u8],
+        plain: CK_BYTE_PTR,
+        plain_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if (unsafe { *plain_len } as usize) < cipher.len() {
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
+        }
+        self.begin(param, paramlen, aad)?;
+        self.end(param, paramlen, cipher, plain, plain_len)
+    }
+
+    fn msg_decrypt_begin(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: usize,
+        aad: &[u8],
+    ) -> KResult<()> {
+        self.begin(param, paramlen, aad)
+    }
+
+    fn msg_decrypt_next(
+        &mut self,
+        _param: CK_VOID_PTR,
+        _paramlen: usize,
+        cipher: &[u8],
+        plain: CK_BYTE_PTR,
+        plain_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        self.update(cipher, plain, plain_len)
+    }
+
+    fn msg_decrypt_final(
+        &mut self,
+        param: CK_VOID_PTR,
+        paramlen: usize,
+        cipher: &[u8],
+        plain: CK_BYTE_PTR,
+        plain_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        self.end(param, paramlen, cipher, plain, plain_len)
+    }
+
+    fn msg_decryption_len(&self, data_len: usize, _: bool) -> KResult<usize> {
+        Ok(data_len)
+    }
+}
+
+#[derive(Debug)]
+struct Poly1305Operation {
+    mech: CK_MECHANISM_TYPE,
+    ctx: EvpMacCtx,
+    finalized: bool,
+    in_use: bool,
+}
+
+impl Poly1305Operation {
+    fn init(mech: &CK_MECHANISM, key: &Object) -> KResult<Poly1305Operation> {
+        if mech.mechanism != CKM_POLY1305 {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        if mech.ulParameterLen != 0 {
+            return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+        }
+        let key = object_to_raw_key(key)?;
+        let mut mac = EvpMac::from_ptr(unsafe {
+            EVP_MAC_fetch(
+                get_libctx(),
+                POLY1305_NAME.as_ptr() as *const i8,
+                std::ptr::null(),
+            )
+        })?;
+        let mut ctx =
+            EvpMacCtx::from_ptr(unsafe { EVP_MAC_CTX_new(mac.as_mut_ptr()) })?;
+        if unsafe {
+            EVP_MAC_init(
+                ctx.as_mut_ptr(),
+                key.raw.as_ptr(),
+                key.raw.len(),
+                std::ptr::null(),
+            )
+        } != 1
+        {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        Ok(Poly1305Operation {
+            mech: mech.mechanism,
+            ctx: ctx,
+            finalized: false,
+            in_use: false,
+        })
+    }
+
+    fn update(&mut self, data: &[u8]) -> KResult<()> {
+        if unsafe {
+            EVP_MAC_update(self.ctx.as_mut_ptr(), data.as_ptr(), data.len())
+        } != 1
+        {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        Ok(())
+    }
+
+    fn finalize(&mut self, output: &mut [u8]) -> KResult<()> {
+        let mut outlen = 0usize;
+        if unsafe {
+            EVP_MAC_final(
+                self.ctx.as_mut_ptr(),
+                output.as_mut_ptr(),
+                &mut outlen,
+                output.len(),
+            )
+        } != 1
+        {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        if outlen != POLY1305_TAG_SIZE {
+            return err_rv!(CKR_GENERAL_ERROR);
+        }
+        Ok(())
+    }
+}
+
+impl MechOperation for Poly1305Operation {
+    fn mechanism(&self) -> CK_MECHANISM_TYPE {
+        self.mech
+    }
+    fn in_use(&self) -> bool {
+        self.in_use
+    }
+    fn finalized(&self) -> bool {
+        self.finalized
+    }
+}
+
+impl Sign for Poly1305Operation {
+    fn sign(&mut self, data: &[u8], signature: &mut [u8]) -> KResult<()> {
+        if self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        match self.sign_update(data) {
+            Err(e) => {
+                self.finalized = true;
+                return Err(e);
+            }
+            Ok(()) => (),
+        }
+        self.sign_final(signature)
+    }
+
+    fn sign_update(&mut self, data: &[u8]) -> KResult<()> {
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        self.in_use = true;
+        self.update(data)
+    }
+
+    fn sign_final(&mut self, signature: &mut [u8]) -> KResult<()> {
+        if !self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        self.finalized = true;
+        if signature.len() != POLY1305_TAG_SIZE {
+            return err_rv!(CKR_GENERAL_ERROR);
+        }
+        self.finalize(signature)
+    }
+
+    fn signature_len(&self) -> KResult<usize> {
+        Ok(POLY1305_TAG_SIZE)
+    }
+}
+
+impl Verify for Poly1305Operation {
+    fn verify(&mut self, data: &[u8], signature: &[u8]) -> KResult<()> {
+        if self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        match self.verify_update(data) {
+            Err(e) => {
+                self.finalized = true;
+                return Err(e);
+            }
+            Ok(()) => (),
+        }
+        self.verify_final(signature)
+    }
+
+    fn verify_update(&mut self, data: &[u8]) -> KResult<()> {
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        self.in_use = true;
+        self.update(data)
+    }
+
+    fn verify_final(&mut self, signature: &[u8]) -> KResult<()> {
+        if !self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        self.finalized = true;
+        if signature.len() != POLY1305_TAG_SIZE {
+            return err_rv!(CKR_SIGNATURE_LEN_RANGE);
+        }
+        let mut verify: Vec<u8> = vec![0; POLY1305_TAG_SIZE];
+        self.finalize(verify.as_mut_slice())?;
+        if !constant_time_eq(&verify, signature) {
+            return err_rv!(CKR_SIGNATURE_INVALID);
+        }
+        Ok(())
+    }
+
+    fn signature_len(&self) -> KResult<usize> {
+        Ok(POLY1305_TAG_SIZE)
+    }
+}
@@ -52,8 +52,90 @@ ptr_wrapper!(BigNum; BIGNUM; BN_free);
 ptr_wrapper!(OsslParam; OSSL_PARAM; OSSL_PARAM_free);
 ptr_wrapper!(EvpCipherCtx; EVP_CIPHER_CTX; EVP_CIPHER_CTX_free);
 ptr_wrapper!(EvpCipher; EVP_CIPHER; EVP_CIPHER_free);
+ptr_wrapper!(EvpMac; EVP_MAC; EVP_MAC_free);
+ptr_wrapper!(EvpMacCtx; EVP_MAC_CTX; EVP_MAC_CTX_free);
 
 pub fn bn_num_bytes(a: *const BIGNUM) -> usize {
     let x = unsafe { (BN_num_bits(a) + 7) / 8 };
     x as usize
 }
+
+/* Imports a key from a single raw octet string component, the algorithm
+ * name must be NUL terminated */
+pub fn make_pkey(
+    algname: &[u8],
+    name: &[u8],
+    selection: u32,
+    key: &mut Vec<u8>,
+) -> KResult<EvpPkey> {
+    let mut params = [
+        unsafe {
+            OSSL_PARAM_construct_octet_string(
+                name.as_ptr() as *const i8,
+                key.as_mut_ptr() as *mut std::os::raw::c_void,
+                key.len(),
+            )
+        },
+        unsafe { OSSL_PARAM_construct_end() },
+    ];
+
+    let mut ctx = EvpPkeyCtx::from_ptr(unsafe {
+        EVP_PKEY_CTX_new_from_name(
+            get_libctx(),
+            algname.as_ptr() as *const i8,
+            std::ptr::null(),
+        )
+    })?;
+    if unsafe { EVP_PKEY_fromdata_init(ctx.as_mut_ptr()) } != 1 {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    let mut pkey: *mut EVP_PKEY = std::ptr::null_mut();
+    if unsafe {
+        EVP_PKEY_fromdata(
+            ctx.as_mut_ptr(),
+            &mut pkey,
+            selection as i32,
+            params.as_mut_ptr(),
+        )
+    } != 1
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    EvpPkey::from_ptr(pkey)
+}
+
+/* Compares MACs and tags without leaking the position of the first
+ * difference */
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    unsafe {
+        CRYPTO_memcmp(
+            a.as_ptr() as *const std::os::raw::c_void,
+            b.as_ptr() as *const std::os::raw::c_void,
+            a.len(),
+        ) == 0
+    }
+}
+
+pub fn param_octet_to_vec(
+    params: *mut OSSL_PARAM,
+    name: &[u8],
+) -> KResult<Vec<u8>> {
+    let p = unsafe { OSSL_PARAM_locate(params, name.as_ptr() as *const i8) };
+    if p.is_null() {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    let mut buf: *const std::os::raw::c_void = std::ptr::null();
+    let mut buf_len = 0usize;
+    if unsafe { OSSL_PARAM_get_octet_string_ptr(p, &mut buf, &mut buf_len) }
+        != 1
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    Ok(
+        unsafe { std::slice::from_raw_parts(buf as *const u8, buf_len) }
+            .to_vec(),
+    )
+}
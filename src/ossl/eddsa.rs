@@ -0,0 +1,382 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+#[cfg(feature = "fips")]
+use {super::fips, fips::*};
+
+#[cfg(not(feature = "fips"))]
+use {super::ossl, ossl::*};
+
+use zeroize::Zeroize;
+
+fn new_pkey_ctx(curve: EccCurve) -> KResult<EvpPkeyCtx> {
+    Ok(EvpPkeyCtx::from_ptr(unsafe {
+        EVP_PKEY_CTX_new_from_name(
+            get_libctx(),
+            curve.ossl_name().as_ptr() as *const i8,
+            std::ptr::null(),
+        )
+    })?)
+}
+
+fn object_to_curve(key: &Object) -> KResult<EccCurve> {
+    let curve =
+        EccCurve::from_ec_params(key.get_attr_as_bytes(CKA_EC_PARAMS)?)?;
+    if curve.key_type() != CKK_EC_EDWARDS {
+        return err_rv!(CKR_KEY_TYPE_INCONSISTENT);
+    }
+    Ok(curve)
+}
+
+fn object_to_eddsa_public_key(key: &Object) -> KResult<EvpPkey> {
+    let curve = object_to_curve(key)?;
+    let mut point =
+        ecc::ec_point_to_raw(curve, key.get_attr_as_bytes(CKA_EC_POINT)?)?;
+    make_pkey(
+        curve.ossl_name(),
+        OSSL_PKEY_PARAM_PUB_KEY,
+        EVP_PKEY_PUBLIC_KEY,
+        &mut point,
+    )
+}
+
+fn object_to_eddsa_private_key(key: &Object) -> KResult<EvpPkey> {
+    let curve = object_to_curve(key)?;
+    let mut value = key.get_attr_as_bytes(CKA_VALUE)?.clone();
+    /* OpenSSL computes the public key from the private key */
+    let pkey = make_pkey(
+        curve.ossl_name(),
+        OSSL_PKEY_PARAM_PRIV_KEY,
+        EVP_PKEY_PRIVATE_KEY,
+        &mut value,
+    );
+    value.zeroize();
+    pkey
+}
+
+#[derive(Debug)]
+struct EddsaOperation {
+    mech: CK_MECHANISM_TYPE,
+    curve: EccCurve,
+    output_len: usize,
+    public_key: EvpPkey,
+    private_key: EvpPkey,
+    params: EddsaParams,
+    data: Vec<u8>,
+    finalized: bool,
+    in_use: bool,
+    #[cfg(feature = "fips")]
+    sigctx: ProviderSignatureCtx,
+    #[cfg(not(feature = "fips"))]
+    sigctx: EvpMdCtx,
+}
+
+impl EddsaOperation {
+    fn sign_new(mech: &CK_MECHANISM, key: &Object) -> KResult<EddsaOperation> {
+        let curve = object_to_curve(key)?;
+        Ok(EddsaOperation {
+            mech: mech.mechanism,
+            curve: curve,
+            output_len: 2 * curve.key_len(),
+            public_key: EvpPkey::empty(),
+            private_key: object_to_eddsa_private_key(key)?,
+            params: parse_params(mech, curve)?,
+            data: Vec::new(),
+            finalized: false,
+            in_use: false,
+            sigctx: Self::new_sigctx(curve)?,
+        })
+    }
+
+    fn verify_new(
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> KResult<EddsaOperation> {
+        let curve = object_to_curve(key)?;
+        Ok(EddsaOperation {
+            mech: mech.mechanism,
+            curve: curve,
+            output_len: 2 * curve.key_len(),
+            public_key: object_to_eddsa_public_key(key)?,
+            private_key: EvpPkey::empty(),
+            params: parse_params(mech, curve)?,
+            data: Vec::new(),
+            finalized: false,
+            in_use: false,
+            sigctx: Self::new_sigctx(curve)?,
+        })
+    }
+
+    fn generate_keypair(
+        curve: EccCurve,
+        pubkey: &mut Object,
+        privkey: &mut Object,
+    ) -> KResult<()> {
+        let mut ctx = new_pkey_ctx(curve)?;
+        if unsafe { EVP_PKEY_keygen_init(ctx.as_mut_ptr()) } != 1 {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        let mut pkey: *mut EVP_PKEY = std::ptr::null_mut();
+        if unsafe { EVP_PKEY_generate(ctx.as_mut_ptr(), &mut pkey) } != 1 {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        let evp_pkey = EvpPkey::from_ptr(pkey)?;
+        let mut params: *mut OSSL_PARAM = std::ptr::null_mut();
+        if unsafe {
+            EVP_PKEY_todata(
+                evp_pkey.as_ptr(),
+                EVP_PKEY_KEYPAIR as std::os::raw::c_int,
+                &mut params,
+            )
+        } != 1
+        {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        let mut ossl_params = OsslParam::from_ptr(params)?;
+
+        /* Public Key */
+        let point = param_octet_to_vec(
+            ossl_params.as_mut_ptr(),
+            OSSL_PKEY_PARAM_PUB_KEY,
+        )?;
+        pubkey.set_attr(attribute::from_bytes(
+            CKA_EC_POINT,
+            ecc::raw_to_ec_point(&point),
+        ))?;
+
+        /* Private Key */
+        let value = param_octet_to_vec(
+            ossl_params.as_mut_ptr(),
+            OSSL_PKEY_PARAM_PRIV_KEY,
+        )?;
+        privkey.set_attr(attribute::from_bytes(CKA_VALUE, value))?;
+        Ok(())
+    }
+
+    fn sig_params(&self) -> KResult<[OSSL_PARAM; 3]> {
+        let instance = instance_name(self.curve, &self.params)?;
+        Ok([
+            unsafe {
+                OSSL_PARAM_construct_utf8_string(
+                    OSSL_SIGNATURE_PARAM_INSTANCE.as_ptr() as *const i8,
+                    instance.as_ptr() as *mut i8,
+                    instance.len() - 1,
+                )
+            },
+            unsafe {
+                OSSL_PARAM_construct_octet_string(
+                    OSSL_SIGNATURE_PARAM_CONTEXT_STRING.as_ptr() as *const i8,
+                    self.params.context_data.as_ptr()
+                        as *mut std::os::raw::c_void,
+                    self.params.context_data.len(),
+                )
+            },
+            unsafe { OSSL_PARAM_construct_end() },
+        ])
+    }
+
+    #[cfg(feature = "fips")]
+    fn new_sigctx(curve: EccCurve) -> KResult<ProviderSignatureCtx> {
+        ProviderSignatureCtx::new(
+            curve.ossl_name().as_ptr() as *const std::os::raw::c_char
+        )
+    }
+
+    #[cfg(feature = "fips")]
+    fn sign_data(&mut self, signature: &mut [u8]) -> KResult<()> {
+        let params = self.sig_params()?;
+        self.sigctx.digest_sign_init(
+            std::ptr::null(),
+            &self.private_key,
+            params.as_ptr(),
+        )?;
+        self.sigctx.digest_sign(signature, &self.data)
+    }
+
+    #[cfg(feature = "fips")]
+    fn verify_data(&mut self, signature: &[u8]) -> KResult<()> {
+        let params = self.sig_params()?;
+        self.sigctx.digest_verify_init(
+            std::ptr::null(),
+            &self.public_key,
+            params.as_ptr(),
+        )?;
+        match self.sigctx.digest_verify(signature, &self.data) {
+            Ok(()) => Ok(()),
+            Err(_) => err_rv!(CKR_SIGNATURE_INVALID),
+        }
+    }
+
+    #[cfg(not(feature = "fips"))]
+    fn new_sigctx(_: EccCurve) -> KResult<EvpMdCtx> {
+        EvpMdCtx::from_ptr(unsafe { EVP_MD_CTX_new() })
+    }
+
+    #[cfg(not(feature = "fips"))]
+    fn sign_data(&mut self, signature: &mut [u8]) -> KResult<()> {
+        let params = self.sig_params()?;
+        if unsafe {
+            EVP_DigestSignInit_ex(
+                self.sigctx.as_mut_ptr(),
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                get_libctx(),
+                std::ptr::null(),
+                self.private_key.as_mut_ptr(),
+                params.as_ptr(),
+            )
+        } != 1
+        {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        let mut siglen = signature.len();
+        if unsafe {
+            EVP_DigestSign(
+                self.sigctx.as_mut_ptr(),
+                signature.as_mut_ptr(),
+                &mut siglen,
+                self.data.as_ptr(),
+                self.data.len(),
+            )
+        } != 1
+        {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        if siglen != self.output_len {
+            return err_rv!(CKR_GENERAL_ERROR);
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "fips"))]
+    fn verify_data(&mut self, signature: &[u8]) -> KResult<()> {
+        let params = self.sig_params()?;
+        if unsafe {
+            EVP_DigestVerifyInit_ex(
+                self.sigctx.as_mut_ptr(),
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                get_libctx(),
+                std::ptr::null(),
+                self.public_key.as_mut_ptr(),
+                params.as_ptr(),
+            )
+        } != 1
+        {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        if unsafe {
+            EVP_DigestVerify(
+                self.sigctx.as_mut_ptr(),
+                signature.as_ptr(),
+                signature.len(),
+                self.data.as_ptr(),
+                self.data.len(),
+            )
+        } != 1
+        {
+            return err_rv!(CKR_SIGNATURE_INVALID);
+        }
+        Ok(())
+    }
+}
+
+impl MechOperation for EddsaOperation {
+    fn mechanism(&self) -> CK_MECHANISM_TYPE {
+        self.mech
+    }
+    fn in_use(&self) -> bool {
+        self.in_use
+    }
+    fn finalized(&self) -> bool {
+        self.finalized
+    }
+    fn reset(&mut self) -> KResult<()> {
+        self.data.zeroize();
+        self.finalized = false;
+        self.in_use = false;
+        Ok(())
+    }
+}
+
+/* EdDSA is not a streaming algorithm, so multi-part operations
+ * accumulate the data and sign it all at once at the end */
+impl Sign for EddsaOperation {
+    fn sign(&mut self, data: &[u8], signature: &mut [u8]) -> KResult<()> {
+        if self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        self.sign_update(data)?;
+        self.sign_final(signature)
+    }
+
+    fn sign_update(&mut self, data: &[u8]) -> KResult<()> {
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        self.in_use = true;
+        self.data.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn sign_final(&mut self, signature: &mut [u8]) -> KResult<()> {
+        if !self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        self.finalized = true;
+        if signature.len() != self.output_len {
+            return err_rv!(CKR_GENERAL_ERROR);
+        }
+        self.sign_data(signature)
+    }
+
+    fn signature_len(&self) -> KResult<usize> {
+        Ok(self.output_len)
+    }
+}
+
+impl Verify for EddsaOperation {
+    fn verify(&mut self, data: &[u8], signature: &[u8]) -> KResult<()> {
+        if self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        self.verify_update(data)?;
+        self.verify_final(signature)
+    }
+
+    fn verify_update(&mut self, data: &[u8]) -> KResult<()> {
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        self.in_use = true;
+        self.data.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn verify_final(&mut self, signature: &[u8]) -> KResult<()> {
+        if !self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        self.finalized = true;
+        if signature.len() != self.output_len {
+            return err_rv!(CKR_SIGNATURE_LEN_RANGE);
+        }
+        self.verify_data(signature)
+    }
+
+    fn signature_len(&self) -> KResult<usize> {
+        Ok(self.output_len)
+    }
+}
@@ -0,0 +1,222 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+#[cfg(feature = "fips")]
+use {super::fips, fips::*};
+
+#[cfg(not(feature = "fips"))]
+use {super::ossl, ossl::*};
+
+use zeroize::Zeroize;
+
+fn new_pkey_ctx(params: &MlKemParams) -> KResult<EvpPkeyCtx> {
+    Ok(EvpPkeyCtx::from_ptr(unsafe {
+        EVP_PKEY_CTX_new_from_name(
+            get_libctx(),
+            params.ossl_name.as_ptr() as *const i8,
+            std::ptr::null(),
+        )
+    })?)
+}
+
+fn make_pkey(
+    params: &MlKemParams,
+    name: &[u8],
+    selection: u32,
+    key: &mut Vec<u8>,
+) -> KResult<EvpPkey> {
+    let mut ossl_params = [
+        unsafe {
+            OSSL_PARAM_construct_octet_string(
+                name.as_ptr() as *const i8,
+                key.as_mut_ptr() as *mut std::os::raw::c_void,
+                key.len(),
+            )
+        },
+        unsafe { OSSL_PARAM_construct_end() },
+    ];
+
+    let mut ctx = new_pkey_ctx(params)?;
+    if unsafe { EVP_PKEY_fromdata_init(ctx.as_mut_ptr()) } != 1 {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    let mut pkey: *mut EVP_PKEY = std::ptr::null_mut();
+    if unsafe {
+        EVP_PKEY_fromdata(
+            ctx.as_mut_ptr(),
+            &mut pkey,
+            selection as i32,
+            ossl_params.as_mut_ptr(),
+        )
+    } != 1
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    EvpPkey::from_ptr(pkey)
+}
+
+fn param_octet_to_vec(
+    params: *mut OSSL_PARAM,
+    name: &[u8],
+) -> KResult<Vec<u8>> {
+    let p = unsafe { OSSL_PARAM_locate(params, name.as_ptr() as *const i8) };
+    if p.is_null() {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    let mut buf: *const std::os::raw::c_void = std::ptr::null();
+    let mut buf_len = 0usize;
+    if unsafe { OSSL_PARAM_get_octet_string_ptr(p, &mut buf, &mut buf_len) }
+        != 1
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    Ok(
+        unsafe { std::slice::from_raw_parts(buf as *const u8, buf_len) }
+            .to_vec(),
+    )
+}
+
+fn generate_keypair(
+    params: &MlKemParams,
+    pubkey: &mut Object,
+    privkey: &mut Object,
+) -> KResult<()> {
+    let mut ctx = new_pkey_ctx(params)?;
+    if unsafe { EVP_PKEY_keygen_init(ctx.as_mut_ptr()) } != 1 {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    let mut pkey: *mut EVP_PKEY = std::ptr::null_mut();
+    if unsafe { EVP_PKEY_generate(ctx.as_mut_ptr(), &mut pkey) } != 1 {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    let evp_pkey = EvpPkey::from_ptr(pkey)?;
+    let mut data: *mut OSSL_PARAM = std::ptr::null_mut();
+    if unsafe {
+        EVP_PKEY_todata(
+            evp_pkey.as_ptr(),
+            EVP_PKEY_KEYPAIR as std::os::raw::c_int,
+            &mut data,
+        )
+    } != 1
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    let mut ossl_params = OsslParam::from_ptr(data)?;
+
+    /* Encapsulation Key */
+    let value =
+        param_octet_to_vec(ossl_params.as_mut_ptr(), OSSL_PKEY_PARAM_PUB_KEY)?;
+    if value.len() != params.ek_len {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    pubkey.set_attr(attribute::from_bytes(CKA_VALUE, value))?;
+
+    /* Decapsulation Key */
+    let mut value =
+        param_octet_to_vec(ossl_params.as_mut_ptr(), OSSL_PKEY_PARAM_PRIV_KEY)?;
+    if value.len() != params.dk_len {
+        value.zeroize();
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    privkey.set_attr(attribute::from_bytes(CKA_VALUE, value))?;
+    Ok(())
+}
+
+fn encapsulate(
+    params: &MlKemParams,
+    key: &Object,
+    ciphertext: &mut [u8],
+) -> KResult<Vec<u8>> {
+    let mut value = key.get_attr_as_bytes(CKA_VALUE)?.clone();
+    let mut pkey = make_pkey(
+        params,
+        OSSL_PKEY_PARAM_PUB_KEY,
+        EVP_PKEY_PUBLIC_KEY,
+        &mut value,
+    )?;
+
+    let mut ctx = EvpPkeyCtx::from_ptr(unsafe {
+        EVP_PKEY_CTX_new_from_pkey(
+            get_libctx(),
+            pkey.as_mut_ptr(),
+            std::ptr::null(),
+        )
+    })?;
+    if unsafe { EVP_PKEY_encapsulate_init(ctx.as_mut_ptr(), std::ptr::null()) }
+        != 1
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    let mut ct_len = ciphertext.len();
+    let mut secret = vec![0u8; ML_KEM_SHARED_SECRET_LEN];
+    let mut secret_len = secret.len();
+    if unsafe {
+        EVP_PKEY_encapsulate(
+            ctx.as_mut_ptr(),
+            ciphertext.as_mut_ptr(),
+            &mut ct_len,
+            secret.as_mut_ptr(),
+            &mut secret_len,
+        )
+    } != 1
+    {
+        secret.zeroize();
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    if ct_len != params.ct_len || secret_len != ML_KEM_SHARED_SECRET_LEN {
+        secret.zeroize();
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    Ok(secret)
+}
+
+fn decapsulate(
+    params: &MlKemParams,
+    key: &Object,
+    ciphertext: &[u8],
+) -> KResult<Vec<u8>> {
+    let mut value = key.get_attr_as_bytes(CKA_VALUE)?.clone();
+    let pkey = make_pkey(
+        params,
+        OSSL_PKEY_PARAM_PRIV_KEY,
+        EVP_PKEY_PRIVATE_KEY,
+        &mut value,
+    );
+    value.zeroize();
+    let mut pkey = pkey?;
+
+    let mut ctx = EvpPkeyCtx::from_ptr(unsafe {
+        EVP_PKEY_CTX_new_from_pkey(
+            get_libctx(),
+            pkey.as_mut_ptr(),
+            std::ptr::null(),
+        )
+    })?;
+    if unsafe { EVP_PKEY_decapsulate_init(ctx.as_mut_ptr(), std::ptr::null()) }
+        != 1
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    /* invalid ciphertexts are implicitly rejected by returning a
+     * pseudorandom secret, FIPS 203 6.3 */
+    let mut secret = vec![0u8; ML_KEM_SHARED_SECRET_LEN];
+    let mut secret_len = secret.len();
+    if unsafe {
+        EVP_PKEY_decapsulate(
+            ctx.as_mut_ptr(),
+            secret.as_mut_ptr(),
+            &mut secret_len,
+            ciphertext.as_ptr(),
+            ciphertext.len(),
+        )
+    } != 1
+    {
+        secret.zeroize();
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    if secret_len != ML_KEM_SHARED_SECRET_LEN {
+        secret.zeroize();
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    Ok(secret)
+}
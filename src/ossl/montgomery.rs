@@ -0,0 +1,119 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+#[cfg(feature = "fips")]
+use {super::fips, fips::*};
+
+#[cfg(not(feature = "fips"))]
+use {super::ossl, ossl::*};
+
+use zeroize::Zeroize;
+
+fn new_pkey_ctx(curve: EccCurve) -> KResult<EvpPkeyCtx> {
+    Ok(EvpPkeyCtx::from_ptr(unsafe {
+        EVP_PKEY_CTX_new_from_name(
+            get_libctx(),
+            curve.ossl_name().as_ptr() as *const i8,
+            std::ptr::null(),
+        )
+    })?)
+}
+
+fn generate_keypair(
+    curve: EccCurve,
+    pubkey: &mut Object,
+    privkey: &mut Object,
+) -> KResult<()> {
+    let mut ctx = new_pkey_ctx(curve)?;
+    if unsafe { EVP_PKEY_keygen_init(ctx.as_mut_ptr()) } != 1 {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    let mut pkey: *mut EVP_PKEY = std::ptr::null_mut();
+    if unsafe { EVP_PKEY_generate(ctx.as_mut_ptr(), &mut pkey) } != 1 {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    let evp_pkey = EvpPkey::from_ptr(pkey)?;
+    let mut params: *mut OSSL_PARAM = std::ptr::null_mut();
+    if unsafe {
+        EVP_PKEY_todata(
+            evp_pkey.as_ptr(),
+            EVP_PKEY_KEYPAIR as std::os::raw::c_int,
+            &mut params,
+        )
+    } != 1
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    let mut ossl_params = OsslParam::from_ptr(params)?;
+
+    /* Public Key */
+    let point =
+        param_octet_to_vec(ossl_params.as_mut_ptr(), OSSL_PKEY_PARAM_PUB_KEY)?;
+    pubkey.set_attr(attribute::from_bytes(
+        CKA_EC_POINT,
+        ecc::raw_to_ec_point(&point),
+    ))?;
+
+    /* Private Key */
+    let value =
+        param_octet_to_vec(ossl_params.as_mut_ptr(), OSSL_PKEY_PARAM_PRIV_KEY)?;
+    privkey.set_attr(attribute::from_bytes(CKA_VALUE, value))?;
+    Ok(())
+}
+
+fn derive_secret(
+    curve: EccCurve,
+    key: &Object,
+    peer: &Vec<u8>,
+) -> KResult<Vec<u8>> {
+    let mut value = key.get_attr_as_bytes(CKA_VALUE)?.clone();
+    let pkey = make_pkey(
+        curve.ossl_name(),
+        OSSL_PKEY_PARAM_PRIV_KEY,
+        EVP_PKEY_PRIVATE_KEY,
+        &mut value,
+    );
+    value.zeroize();
+    let mut pkey = pkey?;
+    let mut point = peer.clone();
+    let mut peer_pkey = make_pkey(
+        curve.ossl_name(),
+        OSSL_PKEY_PARAM_PUB_KEY,
+        EVP_PKEY_PUBLIC_KEY,
+        &mut point,
+    )?;
+
+    let mut ctx = EvpPkeyCtx::from_ptr(unsafe {
+        EVP_PKEY_CTX_new_from_pkey(
+            get_libctx(),
+            pkey.as_mut_ptr(),
+            std::ptr::null(),
+        )
+    })?;
+    if unsafe { EVP_PKEY_derive_init(ctx.as_mut_ptr()) } != 1 {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    if unsafe {
+        EVP_PKEY_derive_set_peer(ctx.as_mut_ptr(), peer_pkey.as_mut_ptr())
+    } != 1
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    let mut secret_len = 0usize;
+    if unsafe {
+        EVP_PKEY_derive(ctx.as_mut_ptr(), std::ptr::null_mut(), &mut secret_len)
+    } != 1
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    let mut secret = vec![0u8; secret_len];
+    if unsafe {
+        EVP_PKEY_derive(ctx.as_mut_ptr(), secret.as_mut_ptr(), &mut secret_len)
+    } != 1
+    {
+        secret.zeroize();
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    secret.resize(secret_len, 0);
+    Ok(secret)
+}
@@ -478,6 +478,12 @@ impl MechOperation for RsaPKCSOperation {
     fn finalized(&self) -> bool {
         self.finalized
     }
+    fn reset(&mut self) -> KResult<()> {
+        /* the signature context is re-initialized on first use */
+        self.finalized = false;
+        self.in_use = false;
+        Ok(())
+    }
 }
 
 impl Encryption for RsaPKCSOperation {
@@ -585,7 +591,7 @@ impl Encryption for RsaPKCSOperation {
         return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
     }
 
-    fn encryption_len(&self) -> KResult<usize> {
+    fn encryption_len(&self, _: usize, _: bool) -> KResult<usize> {
         match self.mech {
             CKM_RSA_PKCS => Ok(self.output_len),
             _ => err_rv!(CKR_GENERAL_ERROR),
@@ -684,7 +690,7 @@ impl Decryption for RsaPKCSOperation {
         return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
     }
 
-    fn decryption_len(&self) -> KResult<usize> {
+    fn decryption_len(&self, _: usize, _: bool) -> KResult<usize> {
         match self.mech {
             CKM_RSA_PKCS => Ok(self.output_len),
             _ => err_rv!(CKR_GENERAL_ERROR),
@@ -0,0 +1,376 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+#[cfg(feature = "fips")]
+use {super::fips, fips::*};
+
+#[cfg(not(feature = "fips"))]
+use {super::ossl, ossl::*};
+
+use zeroize::Zeroize;
+
+fn new_pkey_ctx(params: &SlhDsaParams) -> KResult<EvpPkeyCtx> {
+    Ok(EvpPkeyCtx::from_ptr(unsafe {
+        EVP_PKEY_CTX_new_from_name(
+            get_libctx(),
+            params.ossl_name.as_ptr() as *const i8,
+            std::ptr::null(),
+        )
+    })?)
+}
+
+fn make_pkey(
+    params: &SlhDsaParams,
+    name: &[u8],
+    selection: u32,
+    key: &mut Vec<u8>,
+) -> KResult<EvpPkey> {
+    let mut ossl_params = [
+        unsafe {
+            OSSL_PARAM_construct_octet_string(
+                name.as_ptr() as *const i8,
+                key.as_mut_ptr() as *mut std::os::raw::c_void,
+                key.len(),
+            )
+        },
+        unsafe { OSSL_PARAM_construct_end() },
+    ];
+
+    let mut ctx = new_pkey_ctx(params)?;
+    if unsafe { EVP_PKEY_fromdata_init(ctx.as_mut_ptr()) } != 1 {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    let mut pkey: *mut EVP_PKEY = std::ptr::null_mut();
+    if unsafe {
+        EVP_PKEY_fromdata(
+            ctx.as_mut_ptr(),
+            &mut pkey,
+            selection as i32,
+            ossl_params.as_mut_ptr(),
+        )
+    } != 1
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    EvpPkey::from_ptr(pkey)
+}
+
+fn object_to_slhdsa_public_key(
+    params: &SlhDsaParams,
+    key: &Object,
+) -> KResult<EvpPkey> {
+    let mut value = key.get_attr_as_bytes(CKA_VALUE)?.clone();
+    make_pkey(
+        params,
+        OSSL_PKEY_PARAM_PUB_KEY,
+        EVP_PKEY_PUBLIC_KEY,
+        &mut value,
+    )
+}
+
+fn object_to_slhdsa_private_key(
+    params: &SlhDsaParams,
+    key: &Object,
+) -> KResult<EvpPkey> {
+    let mut value = key.get_attr_as_bytes(CKA_VALUE)?.clone();
+    let pkey = make_pkey(
+        params,
+        OSSL_PKEY_PARAM_PRIV_KEY,
+        EVP_PKEY_PRIVATE_KEY,
+        &mut value,
+    );
+    value.zeroize();
+    pkey
+}
+
+fn param_octet_to_vec(
+    params: *mut OSSL_PARAM,
+    name: &[u8],
+) -> KResult<Vec<u8>> {
+    let p = unsafe { OSSL_PARAM_locate(params, name.as_ptr() as *const i8) };
+    if p.is_null() {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    let mut buf: *const std::os::raw::c_void = std::ptr::null();
+    let mut buf_len = 0usize;
+    if unsafe { OSSL_PARAM_get_octet_string_ptr(p, &mut buf, &mut buf_len) }
+        != 1
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    Ok(
+        unsafe { std::slice::from_raw_parts(buf as *const u8, buf_len) }
+            .to_vec(),
+    )
+}
+
+#[derive(Debug)]
+struct SlhDsaOperation {
+    mech: CK_MECHANISM_TYPE,
+    output_len: usize,
+    public_key: EvpPkey,
+    private_key: EvpPkey,
+    params: SlhDsaSigParams,
+    finalized: bool,
+    in_use: bool,
+    sigctx: EvpMdCtx,
+}
+
+impl SlhDsaOperation {
+    fn sign_new(mech: &CK_MECHANISM, key: &Object) -> KResult<SlhDsaOperation> {
+        let params = params_from_obj(key)?;
+        Ok(SlhDsaOperation {
+            mech: mech.mechanism,
+            output_len: params.sig_len,
+            public_key: EvpPkey::empty(),
+            private_key: object_to_slhdsa_private_key(params, key)?,
+            params: parse_params(mech)?,
+            finalized: false,
+            in_use: false,
+            sigctx: EvpMdCtx::from_ptr(unsafe { EVP_MD_CTX_new() })?,
+        })
+    }
+
+    fn verify_new(
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> KResult<SlhDsaOperation> {
+        let params = params_from_obj(key)?;
+        Ok(SlhDsaOperation {
+            mech: mech.mechanism,
+            output_len: params.sig_len,
+            public_key: object_to_slhdsa_public_key(params, key)?,
+            private_key: EvpPkey::empty(),
+            params: parse_params(mech)?,
+            finalized: false,
+            in_use: false,
+            sigctx: EvpMdCtx::from_ptr(unsafe { EVP_MD_CTX_new() })?,
+        })
+    }
+
+    fn generate_keypair(
+        params: &SlhDsaParams,
+        seed: Option<Vec<u8>>,
+        pubkey: &mut Object,
+        privkey: &mut Object,
+    ) -> KResult<()> {
+        let mut ctx = new_pkey_ctx(params)?;
+        if unsafe { EVP_PKEY_keygen_init(ctx.as_mut_ptr()) } != 1 {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        /* FIPS 205 Algorithm 18, slh_keygen_internal() */
+        if let Some(mut seed) = seed {
+            let ossl_params = [
+                unsafe {
+                    OSSL_PARAM_construct_octet_string(
+                        OSSL_PKEY_PARAM_SLH_DSA_SEED.as_ptr() as *const i8,
+                        seed.as_mut_ptr() as *mut std::os::raw::c_void,
+                        seed.len(),
+                    )
+                },
+                unsafe { OSSL_PARAM_construct_end() },
+            ];
+            let ret = unsafe {
+                EVP_PKEY_CTX_set_params(ctx.as_mut_ptr(), ossl_params.as_ptr())
+            };
+            seed.zeroize();
+            if ret != 1 {
+                return err_rv!(CKR_DEVICE_ERROR);
+            }
+        }
+        let mut pkey: *mut EVP_PKEY = std::ptr::null_mut();
+        if unsafe { EVP_PKEY_generate(ctx.as_mut_ptr(), &mut pkey) } != 1 {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        let evp_pkey = EvpPkey::from_ptr(pkey)?;
+        let mut data: *mut OSSL_PARAM = std::ptr::null_mut();
+        if unsafe {
+            EVP_PKEY_todata(
+                evp_pkey.as_ptr(),
+                EVP_PKEY_KEYPAIR as std::os::raw::c_int,
+                &mut data,
+            )
+        } != 1
+        {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        let mut ossl_params = OsslParam::from_ptr(data)?;
+
+        /* Public Key */
+        let value = param_octet_to_vec(
+            ossl_params.as_mut_ptr(),
+            OSSL_PKEY_PARAM_PUB_KEY,
+        )?;
+        if value.len() != 2 * params.n {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        pubkey.set_attr(attribute::from_bytes(CKA_VALUE, value))?;
+
+        /* Private Key */
+        let mut value = param_octet_to_vec(
+            ossl_params.as_mut_ptr(),
+            OSSL_PKEY_PARAM_PRIV_KEY,
+        )?;
+        if value.len() != 4 * params.n {
+            value.zeroize();
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        privkey.set_attr(attribute::from_bytes(CKA_VALUE, value))?;
+        Ok(())
+    }
+
+    fn sigctx_init(&mut self, sign: bool) -> KResult<()> {
+        let mut deterministic: std::os::raw::c_int =
+            if self.params.deterministic { 1 } else { 0 };
+        let params = [
+            unsafe {
+                OSSL_PARAM_construct_octet_string(
+                    OSSL_SIGNATURE_PARAM_CONTEXT_STRING.as_ptr() as *const i8,
+                    self.params.context.as_ptr() as *mut std::os::raw::c_void,
+                    self.params.context.len(),
+                )
+            },
+            unsafe {
+                OSSL_PARAM_construct_int(
+                    OSSL_SIGNATURE_PARAM_DETERMINISTIC.as_ptr() as *const i8,
+                    &mut deterministic,
+                )
+            },
+            unsafe { OSSL_PARAM_construct_end() },
+        ];
+        let ret = if sign {
+            unsafe {
+                EVP_DigestSignInit_ex(
+                    self.sigctx.as_mut_ptr(),
+                    std::ptr::null_mut(),
+                    std::ptr::null(),
+                    get_libctx(),
+                    std::ptr::null(),
+                    self.private_key.as_mut_ptr(),
+                    params.as_ptr(),
+                )
+            }
+        } else {
+            unsafe {
+                EVP_DigestVerifyInit_ex(
+                    self.sigctx.as_mut_ptr(),
+                    std::ptr::null_mut(),
+                    std::ptr::null(),
+                    get_libctx(),
+                    std::ptr::null(),
+                    self.public_key.as_mut_ptr(),
+                    params.as_ptr(),
+                )
+            }
+        };
+        if ret != 1 {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        Ok(())
+    }
+}
+
+impl MechOperation for SlhDsaOperation {
+    fn mechanism(&self) -> CK_MECHANISM_TYPE {
+        self.mech
+    }
+    fn in_use(&self) -> bool {
+        self.in_use
+    }
+    fn finalized(&self) -> bool {
+        self.finalized
+    }
+    fn reset(&mut self) -> KResult<()> {
+        self.finalized = false;
+        self.in_use = false;
+        Ok(())
+    }
+}
+
+/* SLH-DSA is only offered as a single-part operation, signing and
+ * verifying require the whole message */
+impl Sign for SlhDsaOperation {
+    fn sign(&mut self, data: &[u8], signature: &mut [u8]) -> KResult<()> {
+        if self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        self.finalized = true;
+        if signature.len() != self.output_len {
+            return err_rv!(CKR_GENERAL_ERROR);
+        }
+        self.sigctx_init(true)?;
+        let mut siglen = signature.len();
+        if unsafe {
+            EVP_DigestSign(
+                self.sigctx.as_mut_ptr(),
+                signature.as_mut_ptr(),
+                &mut siglen,
+                data.as_ptr(),
+                data.len(),
+            )
+        } != 1
+        {
+            return err_rv!(CKR_DEVICE_ERROR);
+        }
+        if siglen != self.output_len {
+            return err_rv!(CKR_GENERAL_ERROR);
+        }
+        Ok(())
+    }
+
+    fn sign_update(&mut self, _data: &[u8]) -> KResult<()> {
+        err_rv!(CKR_OPERATION_NOT_INITIALIZED)
+    }
+
+    fn sign_final(&mut self, _signature: &mut [u8]) -> KResult<()> {
+        err_rv!(CKR_OPERATION_NOT_INITIALIZED)
+    }
+
+    fn signature_len(&self) -> KResult<usize> {
+        Ok(self.output_len)
+    }
+}
+
+impl Verify for SlhDsaOperation {
+    fn verify(&mut self, data: &[u8], signature: &[u8]) -> KResult<()> {
+        if self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        self.finalized = true;
+        if signature.len() != self.output_len {
+            return err_rv!(CKR_SIGNATURE_LEN_RANGE);
+        }
+        self.sigctx_init(false)?;
+        if unsafe {
+            EVP_DigestVerify(
+                self.sigctx.as_mut_ptr(),
+                signature.as_ptr(),
+                signature.len(),
+                data.as_ptr(),
+                data.len(),
+            )
+        } != 1
+        {
+            return err_rv!(CKR_SIGNATURE_INVALID);
+        }
+        Ok(())
+    }
+
+    fn verify_update(&mut self, _data: &[u8]) -> KResult<()> {
+        err_rv!(CKR_OPERATION_NOT_INITIALIZED)
+    }
+
+    fn verify_final(&mut self, _signature: &[u8]) -> KResult<()> {
+        err_rv!(CKR_OPERATION_NOT_INITIALIZED)
+    }
+
+    fn signature_len(&self) -> KResult<usize> {
+        Ok(self.output_len)
+    }
+}
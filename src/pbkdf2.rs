@@ -0,0 +1,200 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use super::attribute;
+use super::err_rv;
+use super::error;
+use super::hash;
+use super::hmac;
+use super::interface;
+use super::mechanism;
+use super::object;
+
+use attribute::from_bytes;
+use error::{KError, KResult};
+use interface::*;
+use mechanism::*;
+use object::{Object, ObjectTemplates};
+
+use std::fmt::Debug;
+use zeroize::Zeroize;
+
+/* Minimums enforced in FIPS mode, salt and iterations are from
+ * SP 800-132, keys must provide at least 112 bits of security */
+const FIPS_MIN_PASSWORD_LEN: usize = 8;
+const FIPS_MIN_SALT_LEN: usize = 16;
+const FIPS_MIN_ITERATIONS: CK_ULONG = 1000;
+const FIPS_MIN_KEY_LEN: usize = 14;
+
+fn prf_to_hash_mech(
+    prf: CK_PKCS5_PBKD2_PSEUDO_RANDOM_FUNCTION_TYPE,
+) -> KResult<CK_MECHANISM_TYPE> {
+    match prf {
+        CKP_PKCS5_PBKD2_HMAC_SHA1 => Ok(CKM_SHA_1),
+        CKP_PKCS5_PBKD2_HMAC_SHA224 => Ok(CKM_SHA224),
+        CKP_PKCS5_PBKD2_HMAC_SHA256 => Ok(CKM_SHA256),
+        CKP_PKCS5_PBKD2_HMAC_SHA384 => Ok(CKM_SHA384),
+        CKP_PKCS5_PBKD2_HMAC_SHA512 => Ok(CKM_SHA512),
+        _ => err_rv!(CKR_MECHANISM_PARAM_INVALID),
+    }
+}
+
+fn pbkdf2_params(mech: &CK_MECHANISM) -> KResult<&CK_PKCS5_PBKD2_PARAMS2> {
+    if mech.pParameter.is_null()
+        || mech.ulParameterLen as usize
+            != ::std::mem::size_of::<CK_PKCS5_PBKD2_PARAMS2>()
+    {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    let params =
+        unsafe { &*(mech.pParameter as *const CK_PKCS5_PBKD2_PARAMS2) };
+    if params.saltSource != CKZ_SALT_SPECIFIED {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    if params.pSaltSourceData.is_null() && params.ulSaltSourceDataLen != 0 {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    if params.pPassword.is_null() && params.ulPasswordLen != 0 {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    /* only the GOST PRF takes additional data */
+    if !params.pPrfData.is_null() || params.ulPrfDataLen != 0 {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    if params.iterations == 0 {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    prf_to_hash_mech(params.prf)?;
+    Ok(params)
+}
+
+fn check_fips_limits(
+    params: &CK_PKCS5_PBKD2_PARAMS2,
+    keylen: usize,
+) -> KResult<()> {
+    if !cfg!(feature = "fips") {
+        return Ok(());
+    }
+    if (params.ulPasswordLen as usize) < FIPS_MIN_PASSWORD_LEN
+        || (params.ulSaltSourceDataLen as usize) < FIPS_MIN_SALT_LEN
+        || params.iterations < FIPS_MIN_ITERATIONS
+    {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    if keylen < FIPS_MIN_KEY_LEN {
+        return err_rv!(CKR_KEY_SIZE_RANGE);
+    }
+    Ok(())
+}
+
+/* RFC 8018, 5.2 */
+fn pbkdf2(
+    hash: CK_MECHANISM_TYPE,
+    password: &[u8],
+    salt: &[u8],
+    iterations: CK_ULONG,
+    len: usize,
+) -> KResult<Vec<u8>> {
+    let mut dk: Vec<u8> = Vec::new();
+    let mut block = 0u32;
+    while dk.len() < len {
+        block += 1;
+        let mut u = hmac::hmac(hash, password, &[salt, &block.to_be_bytes()])?;
+        let mut t = u.clone();
+        for _ in 1..iterations {
+            let next = hmac::hmac(hash, password, &[u.as_slice()])?;
+            u.zeroize();
+            u = next;
+            for (x, y) in t.iter_mut().zip(u.iter()) {
+                *x ^= *y;
+            }
+        }
+        u.zeroize();
+        dk.extend_from_slice(t.as_slice());
+        t.zeroize();
+    }
+    dk[len..].zeroize();
+    dk.truncate(len);
+    Ok(dk)
+}
+
+#[derive(Debug)]
+struct PBKDF2Mechanism {
+    info: CK_MECHANISM_INFO,
+}
+
+impl Mechanism for PBKDF2Mechanism {
+    fn info(&self) -> &CK_MECHANISM_INFO {
+        &self.info
+    }
+
+    fn generate_key(
+        &self,
+        mech: &CK_MECHANISM,
+        template: &[CK_ATTRIBUTE],
+        objtemplates: &ObjectTemplates,
+    ) -> KResult<Object> {
+        if self.info.flags & CKF_GENERATE != CKF_GENERATE {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        let params = pbkdf2_params(mech)?;
+        let hash = prf_to_hash_mech(params.prf)?;
+
+        let mut key = objtemplates.generate_key_from_template(template)?;
+        let keylen = key.get_attr_as_ulong(CKA_VALUE_LEN)? as usize;
+        if keylen == 0 {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        if key.get_attr_as_ulong(CKA_KEY_TYPE)? == CKK_AES {
+            match keylen {
+                16 | 24 | 32 => (),
+                _ => return err_rv!(CKR_KEY_SIZE_RANGE),
+            }
+        }
+        /* RFC 8018 limits the output to (2^32 - 1) blocks */
+        let hashlen = hash::HashOperation::new(hash)?.hashlen();
+        if keylen / hashlen >= u32::MAX as usize {
+            return err_rv!(CKR_KEY_SIZE_RANGE);
+        }
+        check_fips_limits(params, keylen)?;
+        key.del_attr(CKA_VALUE_LEN);
+
+        let password: &[u8] = if params.pPassword.is_null() {
+            &[]
+        } else {
+            unsafe {
+                std::slice::from_raw_parts(
+                    params.pPassword,
+                    params.ulPasswordLen as usize,
+                )
+            }
+        };
+        let salt: &[u8] = if params.pSaltSourceData.is_null() {
+            &[]
+        } else {
+            unsafe {
+                std::slice::from_raw_parts(
+                    params.pSaltSourceData as *const u8,
+                    params.ulSaltSourceDataLen as usize,
+                )
+            }
+        };
+        let value = pbkdf2(hash, password, salt, params.iterations, keylen)?;
+        key.set_attr(from_bytes(CKA_VALUE, value))?;
+
+        Ok(key)
+    }
+}
+
+pub fn register(mechs: &mut Mechanisms, _: &mut ObjectTemplates) {
+    mechs.add_mechanism(
+        CKM_PKCS5_PBKD2,
+        Box::new(PBKDF2Mechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_GENERATE,
+            },
+        }),
+    );
+}
@@ -22,4 +22,133 @@ pub const KRYERR_OFFSET: CK_ULONG = 485259;
 pub const KRYERR_TOKEN_NOT_INITIALIZED: CK_ULONG =
     CKR_VENDOR_DEFINED + KRYERR_OFFSET + 1;
 
+pub const KRYMECH_OFFSET: CK_ULONG = 485259;
+pub const KRYMECH_AES_CTS_CS1: CK_ULONG =
+    CKM_VENDOR_DEFINED + KRYMECH_OFFSET + 1;
+pub const KRYMECH_AES_CTS_CS2: CK_ULONG =
+    CKM_VENDOR_DEFINED + KRYMECH_OFFSET + 2;
+
+/* M-of-N secret sharing of secret keys, the shares are wrapped to
+ * custodian RSA keys */
+pub const KRYMECH_SHAMIR_SPLIT: CK_ULONG =
+    CKM_VENDOR_DEFINED + KRYMECH_OFFSET + 4;
+pub const KRYMECH_SHAMIR_COMBINE: CK_ULONG =
+    CKM_VENDOR_DEFINED + KRYMECH_OFFSET + 5;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct KRY_SHAMIR_SPLIT_PARAMS {
+    pub ulThreshold: CK_ULONG,
+    pub ulCustodianKeys: CK_ULONG,
+    pub phCustodianKeys: CK_OBJECT_HANDLE_PTR,
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct KRY_SHAMIR_COMBINE_PARAMS {
+    pub ulCustodianKeys: CK_ULONG,
+    pub phCustodianKeys: CK_OBJECT_HANDLE_PTR,
+}
+
+/* SSH key derivation, not yet in the standard headers */
+pub const CKM_SSHKDF_DERIVE: CK_ULONG = CKM_VENDOR_DEFINED + KRYMECH_OFFSET + 3;
+pub const CKF_SSHKDF_INITIAL_IV_CLIENT_TO_SERVER: CK_BYTE = 0x41;
+pub const CKF_SSHKDF_INITIAL_IV_SERVER_TO_CLIENT: CK_BYTE = 0x42;
+pub const CKF_SSHKDF_ENCRYPTION_KEY_CLIENT_TO_SERVER: CK_BYTE = 0x43;
+pub const CKF_SSHKDF_ENCRYPTION_KEY_SERVER_TO_CLIENT: CK_BYTE = 0x44;
+pub const CKF_SSHKDF_INTEGRITY_KEY_CLIENT_TO_SERVER: CK_BYTE = 0x45;
+pub const CKF_SSHKDF_INTEGRITY_KEY_SERVER_TO_CLIENT: CK_BYTE = 0x46;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct CK_SSHKDF_PARAMS {
+    pub prfHashMechanism: CK_MECHANISM_TYPE,
+    pub derivedKeyType: CK_BYTE,
+    pub pExchangeHash: CK_BYTE_PTR,
+    pub ulExchangeHashLen: CK_ULONG,
+    pub pSessionId: CK_BYTE_PTR,
+    pub ulSessionIdLen: CK_ULONG,
+}
+
+/* PKCS#11 3.2 definitions, not yet in the standard headers */
+pub const CKA_PARAMETER_SET: CK_ATTRIBUTE_TYPE = 0x0000061d;
+pub const CKA_ENCAPSULATE: CK_ATTRIBUTE_TYPE = 0x00000633;
+pub const CKA_DECAPSULATE: CK_ATTRIBUTE_TYPE = 0x00000634;
+pub const CKA_SEED: CK_ATTRIBUTE_TYPE = 0x00000637;
+pub const CKF_ENCAPSULATE: CK_FLAGS = 0x10000000;
+pub const CKF_DECAPSULATE: CK_FLAGS = 0x20000000;
+
+pub const CKK_ML_KEM: CK_KEY_TYPE = 0x00000049;
+pub const CKM_ML_KEM_KEY_PAIR_GEN: CK_MECHANISM_TYPE = 0x0000000f;
+pub const CKM_ML_KEM: CK_MECHANISM_TYPE = 0x00000017;
+pub type CK_ML_KEM_PARAMETER_SET_TYPE = CK_ULONG;
+pub const CKP_ML_KEM_512: CK_ML_KEM_PARAMETER_SET_TYPE = 0x00000001;
+pub const CKP_ML_KEM_768: CK_ML_KEM_PARAMETER_SET_TYPE = 0x00000002;
+pub const CKP_ML_KEM_1024: CK_ML_KEM_PARAMETER_SET_TYPE = 0x00000003;
+
+pub const CKK_ML_DSA: CK_KEY_TYPE = 0x0000004a;
+pub const CKM_ML_DSA_KEY_PAIR_GEN: CK_MECHANISM_TYPE = 0x0000001c;
+pub const CKM_ML_DSA: CK_MECHANISM_TYPE = 0x0000001d;
+pub const CKM_HASH_ML_DSA: CK_MECHANISM_TYPE = 0x0000001f;
+pub const CKM_HASH_ML_DSA_SHA224: CK_MECHANISM_TYPE = 0x00000023;
+pub const CKM_HASH_ML_DSA_SHA256: CK_MECHANISM_TYPE = 0x00000024;
+pub const CKM_HASH_ML_DSA_SHA384: CK_MECHANISM_TYPE = 0x00000025;
+pub const CKM_HASH_ML_DSA_SHA512: CK_MECHANISM_TYPE = 0x00000026;
+pub const CKM_HASH_ML_DSA_SHA3_224: CK_MECHANISM_TYPE = 0x00000027;
+pub const CKM_HASH_ML_DSA_SHA3_256: CK_MECHANISM_TYPE = 0x00000028;
+pub const CKM_HASH_ML_DSA_SHA3_384: CK_MECHANISM_TYPE = 0x00000029;
+pub const CKM_HASH_ML_DSA_SHA3_512: CK_MECHANISM_TYPE = 0x0000002a;
+pub type CK_ML_DSA_PARAMETER_SET_TYPE = CK_ULONG;
+pub const CKP_ML_DSA_44: CK_ML_DSA_PARAMETER_SET_TYPE = 0x00000001;
+pub const CKP_ML_DSA_65: CK_ML_DSA_PARAMETER_SET_TYPE = 0x00000002;
+pub const CKP_ML_DSA_87: CK_ML_DSA_PARAMETER_SET_TYPE = 0x00000003;
+
+pub const CKK_SLH_DSA: CK_KEY_TYPE = 0x0000004b;
+pub const CKM_SLH_DSA_KEY_PAIR_GEN: CK_MECHANISM_TYPE = 0x0000002d;
+pub const CKM_SLH_DSA: CK_MECHANISM_TYPE = 0x0000002e;
+pub type CK_SLH_DSA_PARAMETER_SET_TYPE = CK_ULONG;
+pub const CKP_SLH_DSA_SHA2_128S: CK_SLH_DSA_PARAMETER_SET_TYPE = 0x00000001;
+pub const CKP_SLH_DSA_SHAKE_128S: CK_SLH_DSA_PARAMETER_SET_TYPE = 0x00000002;
+pub const CKP_SLH_DSA_SHA2_128F: CK_SLH_DSA_PARAMETER_SET_TYPE = 0x00000003;
+pub const CKP_SLH_DSA_SHAKE_128F: CK_SLH_DSA_PARAMETER_SET_TYPE = 0x00000004;
+pub const CKP_SLH_DSA_SHA2_192S: CK_SLH_DSA_PARAMETER_SET_TYPE = 0x00000005;
+pub const CKP_SLH_DSA_SHAKE_192S: CK_SLH_DSA_PARAMETER_SET_TYPE = 0x00000006;
+pub const CKP_SLH_DSA_SHA2_192F: CK_SLH_DSA_PARAMETER_SET_TYPE = 0x00000007;
+pub const CKP_SLH_DSA_SHAKE_192F: CK_SLH_DSA_PARAMETER_SET_TYPE = 0x00000008;
+pub const CKP_SLH_DSA_SHA2_256S: CK_SLH_DSA_PARAMETER_SET_TYPE = 0x00000009;
+pub const CKP_SLH_DSA_SHAKE_256S: CK_SLH_DSA_PARAMETER_SET_TYPE = 0x0000000a;
+pub const CKP_SLH_DSA_SHA2_256F: CK_SLH_DSA_PARAMETER_SET_TYPE = 0x0000000b;
+pub const CKP_SLH_DSA_SHAKE_256F: CK_SLH_DSA_PARAMETER_SET_TYPE = 0x0000000c;
+
+pub type CK_HEDGE_TYPE = CK_ULONG;
+pub const CKH_HEDGE_PREFERRED: CK_HEDGE_TYPE = 0x00000000;
+pub const CKH_HEDGE_REQUIRED: CK_HEDGE_TYPE = 0x00000001;
+pub const CKH_DETERMINISTIC_REQUIRED: CK_HEDGE_TYPE = 0x00000002;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct CK_SIGN_ADDITIONAL_CONTEXT {
+    pub hedgeVariant: CK_HEDGE_TYPE,
+    pub pContext: CK_BYTE_PTR,
+    pub ulContextLen: CK_ULONG,
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct CK_HASH_SIGN_ADDITIONAL_CONTEXT {
+    pub hedgeVariant: CK_HEDGE_TYPE,
+    pub pContext: CK_BYTE_PTR,
+    pub ulContextLen: CK_ULONG,
+    pub hash: CK_MECHANISM_TYPE,
+}
+
+/* NSS vendor defined mechanisms */
+pub const NSSCK_VENDOR_NSS: CK_ULONG = 0x4E534350;
+pub const CKM_NSS: CK_ULONG = CKM_VENDOR_DEFINED | NSSCK_VENDOR_NSS;
+pub const CKM_NSS_TLS_EXTENDED_MASTER_KEY_DERIVE: CK_ULONG = CKM_NSS + 25;
+pub const CKM_NSS_TLS_EXTENDED_MASTER_KEY_DERIVE_DH: CK_ULONG = CKM_NSS + 26;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct CK_NSS_TLS_EXTENDED_MASTER_KEY_DERIVE_PARAMS {
+    pub prfHashMechanism: CK_MECHANISM_TYPE,
+    pub pSessionHash: CK_BYTE_PTR,
+    pub ulSessionHashLen: CK_ULONG,
+    pub pVersion: CK_VERSION_PTR,
+}
+
 pub const KRY_UNSPEC: CK_ULONG = CK_UNAVAILABLE_INFORMATION;
@@ -0,0 +1,283 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use super::attribute;
+use super::der;
+use super::ecc;
+use super::err_rv;
+use super::error;
+use super::interface;
+use super::mldsa;
+use super::object;
+
+use attribute::{from_bytes, from_ulong, Attribute};
+use der::*;
+use ecc::EccCurve;
+use error::{KError, KResult};
+use interface::*;
+use object::Object;
+
+use zeroize::Zeroize;
+
+/* rsaEncryption: 1.2.840.113549.1.1.1 */
+const RSA_ENCRYPTION_OID: [u8; 9] =
+    [0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x01, 0x01];
+
+/* id-ml-dsa-44/65/87: 2.16.840.1.101.3.4.3.17-19 */
+const ML_DSA_OIDS: [(CK_ML_DSA_PARAMETER_SET_TYPE, [u8; 9]); 3] = [
+    (
+        CKP_ML_DSA_44,
+        [0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x03, 0x11],
+    ),
+    (
+        CKP_ML_DSA_65,
+        [0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x03, 0x12],
+    ),
+    (
+        CKP_ML_DSA_87,
+        [0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x03, 0x13],
+    ),
+];
+
+/* Order of the INTEGERs in the PKCS#1 RSAPrivateKey structure, after
+ * the version */
+const RSA_KEY_ATTRS: [CK_ATTRIBUTE_TYPE; 8] = [
+    CKA_MODULUS,
+    CKA_PUBLIC_EXPONENT,
+    CKA_PRIVATE_EXPONENT,
+    CKA_PRIME_1,
+    CKA_PRIME_2,
+    CKA_EXPONENT_1,
+    CKA_EXPONENT_2,
+    CKA_COEFFICIENT,
+];
+
+/* PrivateKeyInfo ::= SEQUENCE {
+ *     version             INTEGER,
+ *     privateKeyAlgorithm AlgorithmIdentifier,
+ *     privateKey          OCTET STRING,
+ *     attributes          [0] IMPLICIT Attributes OPTIONAL }
+ */
+fn private_key_info(algorithm: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut body = der_wrap(DER_INTEGER_TAG, &[0]);
+    body.extend_from_slice(algorithm);
+    let mut octet = der_wrap(DER_OCTET_STRING_TAG, key);
+    body.extend_from_slice(&octet);
+    octet.zeroize();
+    let pki = der_wrap(DER_SEQUENCE_TAG, &body);
+    body.zeroize();
+    pki
+}
+
+fn rsa_private_key(key: &Object) -> KResult<Vec<u8>> {
+    /* PKCS#8 requires the full CRT form of the key */
+    for a in RSA_KEY_ATTRS {
+        match key.get_attr_as_bytes(a) {
+            Ok(v) if !v.is_empty() => (),
+            _ => return err_rv!(CKR_KEY_NOT_WRAPPABLE),
+        }
+    }
+    let mut body = der_wrap(DER_INTEGER_TAG, &[0]);
+    for a in RSA_KEY_ATTRS {
+        let mut int = der_wrap_uint(key.get_attr_as_bytes(a)?);
+        body.extend_from_slice(&int);
+        int.zeroize();
+    }
+    let mut rsa_key = der_wrap(DER_SEQUENCE_TAG, &body);
+    body.zeroize();
+
+    let mut algorithm = der_wrap(DER_OID_TAG, &RSA_ENCRYPTION_OID);
+    algorithm.extend_from_slice(&der_wrap(DER_NULL_TAG, &[]));
+    let pki =
+        private_key_info(&der_wrap(DER_SEQUENCE_TAG, &algorithm), &rsa_key);
+    rsa_key.zeroize();
+    Ok(pki)
+}
+
+/* RFC 8410: the private key is the raw key wrapped in an OCTET STRING,
+ * and the AlgorithmIdentifier has no parameters */
+fn ecc_private_key(key: &Object) -> KResult<Vec<u8>> {
+    let curve =
+        EccCurve::from_ec_params(key.get_attr_as_bytes(CKA_EC_PARAMS)?)?;
+    let mut ecc_key =
+        der_wrap(DER_OCTET_STRING_TAG, key.get_attr_as_bytes(CKA_VALUE)?);
+    let pki = private_key_info(
+        &der_wrap(DER_SEQUENCE_TAG, &curve.to_ec_params()),
+        &ecc_key,
+    );
+    ecc_key.zeroize();
+    Ok(pki)
+}
+
+/* FIPS 204 keys are exported in the expandedKey form of the
+ * ML-DSA-PrivateKey CHOICE, as the seed is not retained */
+fn mldsa_private_key(key: &Object) -> KResult<Vec<u8>> {
+    let set = key.get_attr_as_ulong(CKA_PARAMETER_SET)?;
+    let oid = match ML_DSA_OIDS.iter().find(|o| o.0 == set) {
+        Some(o) => o.1,
+        None => return err_rv!(CKR_KEY_NOT_WRAPPABLE),
+    };
+    let mut mldsa_key =
+        der_wrap(DER_OCTET_STRING_TAG, key.get_attr_as_bytes(CKA_VALUE)?);
+    let pki = private_key_info(
+        &der_wrap(DER_SEQUENCE_TAG, &der_wrap(DER_OID_TAG, &oid)),
+        &mldsa_key,
+    );
+    mldsa_key.zeroize();
+    Ok(pki)
+}
+
+/* Encodes a private key object as a DER PrivateKeyInfo */
+pub fn encode_private_key(key: &Object) -> KResult<Vec<u8>> {
+    match key.get_attr_as_ulong(CKA_KEY_TYPE)? {
+        CKK_RSA => rsa_private_key(key),
+        CKK_EC_EDWARDS | CKK_EC_MONTGOMERY => ecc_private_key(key),
+        CKK_ML_DSA => mldsa_private_key(key),
+        _ => err_rv!(CKR_KEY_NOT_WRAPPABLE),
+    }
+}
+
+/* Returns the AlgorithmIdentifier and the privateKey contents */
+fn parse_private_key_info(data: &[u8]) -> KResult<(&[u8], &[u8])> {
+    let body = match der_unwrap(DER_SEQUENCE_TAG, data) {
+        Some(b) => b,
+        None => return err_rv!(CKR_WRAPPED_KEY_INVALID),
+    };
+    /* v1 (0) is PrivateKeyInfo, v2 (1) is the RFC 5958
+     * OneAsymmetricKey that may carry the public key after the
+     * attributes, which is ignored here */
+    let rest = match der_next_uint(body) {
+        Some((v, r)) if v == [0] || v == [1] => r,
+        _ => return err_rv!(CKR_WRAPPED_KEY_INVALID),
+    };
+    let (algorithm, rest) = match der_next(DER_SEQUENCE_TAG, rest) {
+        Some(a) => a,
+        None => return err_rv!(CKR_WRAPPED_KEY_INVALID),
+    };
+    match der_next(DER_OCTET_STRING_TAG, rest) {
+        Some((key, _)) => Ok((algorithm, key)),
+        None => err_rv!(CKR_WRAPPED_KEY_INVALID),
+    }
+}
+
+enum KeyAlgorithm {
+    Rsa,
+    Ecc(EccCurve),
+    MlDsa(CK_ML_DSA_PARAMETER_SET_TYPE),
+}
+
+fn parse_algorithm(algorithm: &[u8]) -> KResult<KeyAlgorithm> {
+    let (oid, params) = match der_next(DER_OID_TAG, algorithm) {
+        Some(o) => o,
+        None => return err_rv!(CKR_WRAPPED_KEY_INVALID),
+    };
+    if oid == RSA_ENCRYPTION_OID {
+        if !params.is_empty() {
+            match der_unwrap(DER_NULL_TAG, params) {
+                Some(n) if n.is_empty() => (),
+                _ => return err_rv!(CKR_WRAPPED_KEY_INVALID),
+            }
+        }
+        return Ok(KeyAlgorithm::Rsa);
+    }
+    if !params.is_empty() {
+        return err_rv!(CKR_WRAPPED_KEY_INVALID);
+    }
+    if let Some(o) = ML_DSA_OIDS.iter().find(|o| o.1 == oid) {
+        return Ok(KeyAlgorithm::MlDsa(o.0));
+    }
+    match EccCurve::from_ec_params(&der_wrap(DER_OID_TAG, oid)) {
+        Ok(curve) => Ok(KeyAlgorithm::Ecc(curve)),
+        Err(_) => err_rv!(CKR_WRAPPED_KEY_INVALID),
+    }
+}
+
+/* Returns the key type indicated by the AlgorithmIdentifier of a DER
+ * PrivateKeyInfo */
+pub fn private_key_type(data: &[u8]) -> KResult<CK_KEY_TYPE> {
+    let (algorithm, _) = parse_private_key_info(data)?;
+    match parse_algorithm(algorithm)? {
+        KeyAlgorithm::Rsa => Ok(CKK_RSA),
+        KeyAlgorithm::Ecc(curve) => Ok(curve.key_type()),
+        KeyAlgorithm::MlDsa(_) => Ok(CKK_ML_DSA),
+    }
+}
+
+fn rsa_key_attrs(key: &[u8]) -> KResult<Vec<Attribute>> {
+    let body = match der_unwrap(DER_SEQUENCE_TAG, key) {
+        Some(b) => b,
+        None => return err_rv!(CKR_WRAPPED_KEY_INVALID),
+    };
+    /* only two-prime keys (version 0) are supported */
+    let mut rest = match der_next_uint(body) {
+        Some((v, r)) if v == [0] => r,
+        _ => return err_rv!(CKR_WRAPPED_KEY_INVALID),
+    };
+    let mut attrs = vec![from_ulong(CKA_KEY_TYPE, CKK_RSA)];
+    for a in RSA_KEY_ATTRS {
+        match der_next_uint(rest) {
+            Some((int, r)) => {
+                attrs.push(from_bytes(a, int.to_vec()));
+                rest = r;
+            }
+            None => return err_rv!(CKR_WRAPPED_KEY_INVALID),
+        }
+    }
+    if !rest.is_empty() {
+        return err_rv!(CKR_WRAPPED_KEY_INVALID);
+    }
+    Ok(attrs)
+}
+
+fn ecc_key_attrs(curve: EccCurve, key: &[u8]) -> KResult<Vec<Attribute>> {
+    match der_unwrap(DER_OCTET_STRING_TAG, key) {
+        Some(k) if k.len() == curve.key_len() => Ok(vec![
+            from_ulong(CKA_KEY_TYPE, curve.key_type()),
+            from_bytes(CKA_EC_PARAMS, curve.to_ec_params()),
+            from_bytes(CKA_VALUE, k.to_vec()),
+        ]),
+        _ => err_rv!(CKR_WRAPPED_KEY_INVALID),
+    }
+}
+
+/* Accepts the expandedKey and both forms of the ML-DSA-PrivateKey
+ * CHOICE, a key carried only as a seed can not be imported */
+fn mldsa_key_attrs(
+    set: CK_ML_DSA_PARAMETER_SET_TYPE,
+    key: &[u8],
+) -> KResult<Vec<Attribute>> {
+    let expanded = match der_split(key) {
+        Some((DER_OCTET_STRING_TAG, k, rest)) if rest.is_empty() => k,
+        Some((DER_SEQUENCE_TAG, both, rest)) if rest.is_empty() => {
+            match der_next(DER_OCTET_STRING_TAG, both) {
+                Some((seed, r)) if seed.len() == 32 => {
+                    match der_unwrap(DER_OCTET_STRING_TAG, r) {
+                        Some(k) => k,
+                        None => return err_rv!(CKR_WRAPPED_KEY_INVALID),
+                    }
+                }
+                _ => return err_rv!(CKR_WRAPPED_KEY_INVALID),
+            }
+        }
+        _ => return err_rv!(CKR_WRAPPED_KEY_INVALID),
+    };
+    if expanded.len() != mldsa::private_key_len(set)? {
+        return err_rv!(CKR_WRAPPED_KEY_INVALID);
+    }
+    Ok(vec![
+        from_ulong(CKA_KEY_TYPE, CKK_ML_DSA),
+        from_ulong(CKA_PARAMETER_SET, set),
+        from_bytes(CKA_VALUE, expanded.to_vec()),
+    ])
+}
+
+/* Decodes a DER PrivateKeyInfo into the key type and key material
+ * attributes of a private key object */
+pub fn decode_private_key(data: &[u8]) -> KResult<Vec<Attribute>> {
+    let (algorithm, key) = parse_private_key_info(data)?;
+    match parse_algorithm(algorithm)? {
+        KeyAlgorithm::Rsa => rsa_key_attrs(key),
+        KeyAlgorithm::Ecc(curve) => ecc_key_attrs(curve, key),
+        KeyAlgorithm::MlDsa(set) => mldsa_key_attrs(set, key),
+    }
+}
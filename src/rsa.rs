@@ -1,6 +1,7 @@
 // Copyright 2023 Simo Sorce
 // See LICENSE.txt file for terms
 
+use super::aes;
 use super::attribute;
 use super::error;
 use super::interface;
@@ -134,6 +135,18 @@ impl ObjectTemplate for RSAPrivTemplate {
         Ok(obj)
     }
 
+    fn unwrap(
+        &self,
+        template: &[CK_ATTRIBUTE],
+        data: &[u8],
+    ) -> KResult<Object> {
+        let mut obj = self.default_private_key_unwrap(template, data)?;
+
+        rsa_import(&mut obj)?;
+
+        Ok(obj)
+    }
+
     fn get_attributes(&self) -> &Vec<ObjectAttr> {
         &self.attributes
     }
@@ -318,6 +331,55 @@ impl Mechanism for RsaPKCSMechanism {
 
         Ok((pubkey, privkey))
     }
+
+    fn wrap_key(
+        &self,
+        mech: &CK_MECHANISM,
+        wrapping_key: &Object,
+        key: &Object,
+        _: &[&Object],
+        data: CK_BYTE_PTR,
+        data_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if self.info.flags & CKF_WRAP != CKF_WRAP {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        match check_key_object(wrapping_key, true, CKA_WRAP) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        RsaPKCSOperation::wrap(
+            mech,
+            wrapping_key,
+            key,
+            data,
+            data_len,
+            &self.info,
+        )
+    }
+
+    fn unwrap_key(
+        &self,
+        mech: &CK_MECHANISM,
+        wrapping_key: &Object,
+        _: &[&Object],
+        data: &[u8],
+        template: &[CK_ATTRIBUTE],
+        objtemplates: &ObjectTemplates,
+    ) -> KResult<Object> {
+        if self.info.flags & CKF_UNWRAP != CKF_UNWRAP {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        match check_key_object(wrapping_key, false, CKA_UNWRAP) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        let mut keydata =
+            RsaPKCSOperation::unwrap(mech, wrapping_key, data, &self.info)?;
+        let key = objtemplates.unwrap_key_from_template(template, &keydata);
+        keydata.zeroize();
+        key
+    }
 }
 
 pub fn register(mechs: &mut Mechanisms, ot: &mut ObjectTemplates) {
@@ -327,7 +389,12 @@ pub fn register(mechs: &mut Mechanisms, ot: &mut ObjectTemplates) {
             info: CK_MECHANISM_INFO {
                 ulMinKeySize: MIN_RSA_SIZE_BITS as CK_ULONG,
                 ulMaxKeySize: MAX_RSA_SIZE_BITS as CK_ULONG,
-                flags: CKF_ENCRYPT | CKF_DECRYPT | CKF_SIGN | CKF_VERIFY,
+                flags: CKF_ENCRYPT
+                    | CKF_DECRYPT
+                    | CKF_SIGN
+                    | CKF_VERIFY
+                    | CKF_MESSAGE_SIGN
+                    | CKF_MESSAGE_VERIFY,
             },
         }),
     );
@@ -337,7 +404,10 @@ pub fn register(mechs: &mut Mechanisms, ot: &mut ObjectTemplates) {
             info: CK_MECHANISM_INFO {
                 ulMinKeySize: MIN_RSA_SIZE_BITS as CK_ULONG,
                 ulMaxKeySize: MAX_RSA_SIZE_BITS as CK_ULONG,
-                flags: CKF_SIGN | CKF_VERIFY,
+                flags: CKF_SIGN
+                    | CKF_VERIFY
+                    | CKF_MESSAGE_SIGN
+                    | CKF_MESSAGE_VERIFY,
             },
         }),
     );
@@ -348,7 +418,10 @@ pub fn register(mechs: &mut Mechanisms, ot: &mut ObjectTemplates) {
             info: CK_MECHANISM_INFO {
                 ulMinKeySize: MIN_RSA_SIZE_BITS as CK_ULONG,
                 ulMaxKeySize: MAX_RSA_SIZE_BITS as CK_ULONG,
-                flags: CKF_SIGN | CKF_VERIFY,
+                flags: CKF_SIGN
+                    | CKF_VERIFY
+                    | CKF_MESSAGE_SIGN
+                    | CKF_MESSAGE_VERIFY,
             },
         }),
     );
@@ -359,7 +432,10 @@ pub fn register(mechs: &mut Mechanisms, ot: &mut ObjectTemplates) {
             info: CK_MECHANISM_INFO {
                 ulMinKeySize: MIN_RSA_SIZE_BITS as CK_ULONG,
                 ulMaxKeySize: MAX_RSA_SIZE_BITS as CK_ULONG,
-                flags: CKF_SIGN | CKF_VERIFY,
+                flags: CKF_SIGN
+                    | CKF_VERIFY
+                    | CKF_MESSAGE_SIGN
+                    | CKF_MESSAGE_VERIFY,
             },
         }),
     );
@@ -370,7 +446,21 @@ pub fn register(mechs: &mut Mechanisms, ot: &mut ObjectTemplates) {
             info: CK_MECHANISM_INFO {
                 ulMinKeySize: MIN_RSA_SIZE_BITS as CK_ULONG,
                 ulMaxKeySize: MAX_RSA_SIZE_BITS as CK_ULONG,
-                flags: CKF_SIGN | CKF_VERIFY,
+                flags: CKF_SIGN
+                    | CKF_VERIFY
+                    | CKF_MESSAGE_SIGN
+                    | CKF_MESSAGE_VERIFY,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_RSA_AES_KEY_WRAP,
+        Box::new(RsaPKCSMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: MIN_RSA_SIZE_BITS as CK_ULONG,
+                ulMaxKeySize: MAX_RSA_SIZE_BITS as CK_ULONG,
+                flags: CKF_WRAP | CKF_UNWRAP,
             },
         }),
     );
@@ -390,8 +480,78 @@ pub fn register(mechs: &mut Mechanisms, ot: &mut ObjectTemplates) {
     ot.add_template(ObjectType::RSAPrivKey, &PRIVATE_KEY_TEMPLATE);
 }
 
+fn secret_wrap_info() -> CK_MECHANISM_INFO {
+    CK_MECHANISM_INFO {
+        ulMinKeySize: MIN_RSA_SIZE_BITS as CK_ULONG,
+        ulMaxKeySize: MAX_RSA_SIZE_BITS as CK_ULONG,
+        flags: CKF_WRAP | CKF_UNWRAP,
+    }
+}
+
+/* Wraps raw secret data to an RSA public key with RSA AES key wrap,
+ * using a 256 bit AES key and SHA-256 OAEP, for mechanisms that need to
+ * protect secrets that are not key objects */
+pub fn wrap_secret(wrapping_key: &Object, secret: &[u8]) -> KResult<Vec<u8>> {
+    match check_key_object(wrapping_key, true, CKA_WRAP) {
+        Ok(_) => (),
+        Err(e) => return Err(e),
+    }
+    let mut key = Object::new();
+    key.set_attr(from_ulong(CKA_CLASS, CKO_SECRET_KEY))?;
+    key.set_attr(from_bytes(CKA_VALUE, secret.to_vec()))?;
+    let info = secret_wrap_info();
+    with_secret_wrap_mech(|mech| {
+        let mut len: CK_ULONG = 0;
+        RsaPKCSOperation::wrap(
+            mech,
+            wrapping_key,
+            &key,
+            std::ptr::null_mut(),
+            &mut len,
+            &info,
+        )?;
+        let mut wrapped = vec![0u8; len as usize];
+        RsaPKCSOperation::wrap(
+            mech,
+            wrapping_key,
+            &key,
+            wrapped.as_mut_ptr(),
+            &mut len,
+            &info,
+        )?;
+        wrapped.truncate(len as usize);
+        Ok(wrapped)
+    })
+}
+
+/* Length of the output of wrap_secret() for a secret of the given
+ * length, without performing the wrap */
+pub fn wrap_secret_len(
+    wrapping_key: &Object,
+    secret_len: usize,
+) -> KResult<usize> {
+    match check_key_object(wrapping_key, true, CKA_WRAP) {
+        Ok(_) => (),
+        Err(e) => return Err(e),
+    }
+    let modulus = wrapping_key.get_attr_as_bytes(CKA_MODULUS)?;
+    Ok(modulus.len() + aes::kwp_len(secret_len))
+}
+
+pub fn unwrap_secret(wrapping_key: &Object, data: &[u8]) -> KResult<Vec<u8>> {
+    match check_key_object(wrapping_key, false, CKA_UNWRAP) {
+        Ok(_) => (),
+        Err(e) => return Err(e),
+    }
+    with_secret_wrap_mech(|mech| {
+        RsaPKCSOperation::unwrap(mech, wrapping_key, data, &secret_wrap_info())
+    })
+}
+
 #[cfg(feature = "fips")]
 include!("fips/rsa.rs");
 
 #[cfg(not(feature = "fips"))]
 include!("ossl/rsa.rs");
+
+include!("rsa_aes_wrap.rs");
@@ -0,0 +1,363 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+/* CKM_RSA_AES_KEY_WRAP and the RSA-OAEP helpers it needs, these only use
+ * interfaces that are common to the OpenSSL and FIPS backends */
+
+fn oaep_digest_name(mech: CK_MECHANISM_TYPE) -> KResult<&'static [u8]> {
+    let name: &'static [u8] = match mech {
+        CKM_SHA_1 => OSSL_DIGEST_NAME_SHA1,
+        CKM_SHA224 => OSSL_DIGEST_NAME_SHA2_224,
+        CKM_SHA256 => OSSL_DIGEST_NAME_SHA2_256,
+        CKM_SHA384 => OSSL_DIGEST_NAME_SHA2_384,
+        CKM_SHA512 => OSSL_DIGEST_NAME_SHA2_512,
+        CKM_SHA3_224 => OSSL_DIGEST_NAME_SHA3_224,
+        CKM_SHA3_256 => OSSL_DIGEST_NAME_SHA3_256,
+        CKM_SHA3_384 => OSSL_DIGEST_NAME_SHA3_384,
+        CKM_SHA3_512 => OSSL_DIGEST_NAME_SHA3_512,
+        _ => return err_rv!(CKR_MECHANISM_PARAM_INVALID),
+    };
+    Ok(name)
+}
+
+fn mgf1_digest_name(mgf: CK_RSA_PKCS_MGF_TYPE) -> KResult<&'static [u8]> {
+    let name: &'static [u8] = match mgf {
+        CKG_MGF1_SHA1 => OSSL_DIGEST_NAME_SHA1,
+        CKG_MGF1_SHA224 => OSSL_DIGEST_NAME_SHA2_224,
+        CKG_MGF1_SHA256 => OSSL_DIGEST_NAME_SHA2_256,
+        CKG_MGF1_SHA384 => OSSL_DIGEST_NAME_SHA2_384,
+        CKG_MGF1_SHA512 => OSSL_DIGEST_NAME_SHA2_512,
+        CKG_MGF1_SHA3_224 => OSSL_DIGEST_NAME_SHA3_224,
+        CKG_MGF1_SHA3_256 => OSSL_DIGEST_NAME_SHA3_256,
+        CKG_MGF1_SHA3_384 => OSSL_DIGEST_NAME_SHA3_384,
+        CKG_MGF1_SHA3_512 => OSSL_DIGEST_NAME_SHA3_512,
+        _ => return err_rv!(CKR_MECHANISM_PARAM_INVALID),
+    };
+    Ok(name)
+}
+
+#[derive(Debug)]
+struct RsaOaepParams {
+    digest: &'static [u8],
+    mgf1: &'static [u8],
+    label: Vec<u8>,
+}
+
+impl RsaOaepParams {
+    fn new(params: *const CK_RSA_PKCS_OAEP_PARAMS) -> KResult<RsaOaepParams> {
+        if params.is_null() {
+            return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+        }
+        let oaep = unsafe { &*params };
+        let label = match oaep.source {
+            CKZ_DATA_SPECIFIED => {
+                if oaep.pSourceData.is_null() || oaep.ulSourceDataLen == 0 {
+                    Vec::new()
+                } else {
+                    unsafe {
+                        slice::from_raw_parts(
+                            oaep.pSourceData as *const u8,
+                            oaep.ulSourceDataLen as usize,
+                        )
+                    }
+                    .to_vec()
+                }
+            }
+            /* some applications leave the source unset when there is no
+             * label */
+            0 => {
+                if oaep.ulSourceDataLen != 0 {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+                Vec::new()
+            }
+            _ => return err_rv!(CKR_MECHANISM_PARAM_INVALID),
+        };
+        Ok(RsaOaepParams {
+            digest: oaep_digest_name(oaep.hashAlg)?,
+            mgf1: mgf1_digest_name(oaep.mgf)?,
+            label: label,
+        })
+    }
+
+    fn ossl_params(&mut self) -> [OSSL_PARAM; 5] {
+        let mut params = [
+            unsafe {
+                OSSL_PARAM_construct_utf8_string(
+                    OSSL_PKEY_PARAM_PAD_MODE.as_ptr() as *const i8,
+                    OSSL_PKEY_RSA_PAD_MODE_OAEP.as_ptr() as *mut i8,
+                    OSSL_PKEY_RSA_PAD_MODE_OAEP.len(),
+                )
+            },
+            unsafe {
+                OSSL_PARAM_construct_utf8_string(
+                    OSSL_ASYM_CIPHER_PARAM_OAEP_DIGEST.as_ptr() as *const i8,
+                    self.digest.as_ptr() as *mut i8,
+                    self.digest.len(),
+                )
+            },
+            unsafe {
+                OSSL_PARAM_construct_utf8_string(
+                    OSSL_ASYM_CIPHER_PARAM_MGF1_DIGEST.as_ptr() as *const i8,
+                    self.mgf1.as_ptr() as *mut i8,
+                    self.mgf1.len(),
+                )
+            },
+            unsafe { OSSL_PARAM_construct_end() },
+            unsafe { OSSL_PARAM_construct_end() },
+        ];
+        if !self.label.is_empty() {
+            params[3] = unsafe {
+                OSSL_PARAM_construct_octet_string(
+                    OSSL_ASYM_CIPHER_PARAM_OAEP_LABEL.as_ptr() as *const i8,
+                    self.label.as_mut_ptr() as *mut std::os::raw::c_void,
+                    self.label.len(),
+                )
+            };
+        }
+        params
+    }
+}
+
+/* Returns the AES key length and the OAEP parameters */
+fn rsa_aes_key_wrap_params(
+    mech: &CK_MECHANISM,
+) -> KResult<(usize, RsaOaepParams)> {
+    if mech.mechanism != CKM_RSA_AES_KEY_WRAP {
+        return err_rv!(CKR_MECHANISM_INVALID);
+    }
+    if mech.pParameter.is_null()
+        || mech.ulParameterLen as usize
+            != ::std::mem::size_of::<CK_RSA_AES_KEY_WRAP_PARAMS>()
+    {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    let params =
+        unsafe { &*(mech.pParameter as *const CK_RSA_AES_KEY_WRAP_PARAMS) };
+    let aes_key_len = match params.ulAESKeyBits {
+        128 | 192 | 256 => (params.ulAESKeyBits / 8) as usize,
+        _ => return err_rv!(CKR_MECHANISM_PARAM_INVALID),
+    };
+    Ok((aes_key_len, RsaOaepParams::new(params.pOAEPParams)?))
+}
+
+fn rsa_oaep_encrypt(
+    pkey: &mut EvpPkey,
+    oaep: &mut RsaOaepParams,
+    plain: &[u8],
+    cipher: &mut [u8],
+) -> KResult<usize> {
+    let mut ctx = EvpPkeyCtx::from_ptr(unsafe {
+        EVP_PKEY_CTX_new_from_pkey(
+            get_libctx(),
+            pkey.as_mut_ptr(),
+            std::ptr::null_mut(),
+        )
+    })?;
+    if unsafe { EVP_PKEY_encrypt_init(ctx.as_mut_ptr()) } != 1 {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    let params = oaep.ossl_params();
+    if unsafe { EVP_PKEY_CTX_set_params(ctx.as_mut_ptr(), params.as_ptr()) }
+        != 1
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    let mut outlen = cipher.len();
+    if unsafe {
+        EVP_PKEY_encrypt(
+            ctx.as_mut_ptr(),
+            cipher.as_mut_ptr(),
+            &mut outlen,
+            plain.as_ptr(),
+            plain.len(),
+        )
+    } != 1
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    Ok(outlen)
+}
+
+fn rsa_oaep_decrypt(
+    pkey: &mut EvpPkey,
+    oaep: &mut RsaOaepParams,
+    cipher: &[u8],
+) -> KResult<Vec<u8>> {
+    let mut ctx = EvpPkeyCtx::from_ptr(unsafe {
+        EVP_PKEY_CTX_new_from_pkey(
+            get_libctx(),
+            pkey.as_mut_ptr(),
+            std::ptr::null_mut(),
+        )
+    })?;
+    if unsafe { EVP_PKEY_decrypt_init(ctx.as_mut_ptr()) } != 1 {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    let params = oaep.ossl_params();
+    if unsafe { EVP_PKEY_CTX_set_params(ctx.as_mut_ptr(), params.as_ptr()) }
+        != 1
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    let mut outlen = 0usize;
+    if unsafe {
+        EVP_PKEY_decrypt(
+            ctx.as_mut_ptr(),
+            std::ptr::null_mut(),
+            &mut outlen,
+            cipher.as_ptr(),
+            cipher.len(),
+        )
+    } != 1
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    let mut plain = vec![0u8; outlen];
+    if unsafe {
+        EVP_PKEY_decrypt(
+            ctx.as_mut_ptr(),
+            plain.as_mut_ptr(),
+            &mut outlen,
+            cipher.as_ptr(),
+            cipher.len(),
+        )
+    } != 1
+    {
+        plain.zeroize();
+        return err_rv!(CKR_ENCRYPTED_DATA_INVALID);
+    }
+    plain.truncate(outlen);
+    Ok(plain)
+}
+
+fn rsa_aes_wrap(
+    aes_key: &[u8],
+    oaep: &mut RsaOaepParams,
+    wrapping_key: &Object,
+    key: &Object,
+    out: &mut [u8],
+) -> KResult<()> {
+    let modulus_len = wrapping_key.get_attr_as_bytes(CKA_MODULUS)?.len();
+    let wrapped = aes::kwp_wrap(aes_key, key)?;
+    if modulus_len + wrapped.len() != out.len() {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    let mut pkey = object_to_rsa_public_key(wrapping_key)?;
+    if rsa_oaep_encrypt(&mut pkey, oaep, aes_key, &mut out[..modulus_len])?
+        != modulus_len
+    {
+        return err_rv!(CKR_DEVICE_ERROR);
+    }
+    out[modulus_len..].copy_from_slice(&wrapped);
+    Ok(())
+}
+
+/* Calls f with the CKM_RSA_AES_KEY_WRAP mechanism used to protect raw
+ * secrets: a 256 bit AES key and SHA-256 OAEP without a label */
+fn with_secret_wrap_mech<T>(
+    f: impl FnOnce(&CK_MECHANISM) -> KResult<T>,
+) -> KResult<T> {
+    let mut oaep = CK_RSA_PKCS_OAEP_PARAMS {
+        hashAlg: CKM_SHA256,
+        mgf: CKG_MGF1_SHA256,
+        source: CKZ_DATA_SPECIFIED,
+        pSourceData: std::ptr::null_mut(),
+        ulSourceDataLen: 0,
+    };
+    let mut params = CK_RSA_AES_KEY_WRAP_PARAMS {
+        ulAESKeyBits: 256,
+        pOAEPParams: &mut oaep,
+    };
+    let mech = CK_MECHANISM {
+        mechanism: CKM_RSA_AES_KEY_WRAP,
+        pParameter: &mut params as *mut CK_RSA_AES_KEY_WRAP_PARAMS as *mut _,
+        ulParameterLen: ::std::mem::size_of::<CK_RSA_AES_KEY_WRAP_PARAMS>()
+            as CK_ULONG,
+    };
+    f(&mech)
+}
+
+impl RsaPKCSOperation {
+    fn wrap(
+        mech: &CK_MECHANISM,
+        wrapping_key: &Object,
+        key: &Object,
+        data: CK_BYTE_PTR,
+        data_len: CK_ULONG_PTR,
+        info: &CK_MECHANISM_INFO,
+    ) -> KResult<()> {
+        let (aes_key_len, mut oaep) = rsa_aes_key_wrap_params(mech)?;
+        let modulus = wrapping_key.get_attr_as_bytes(CKA_MODULUS)?;
+        let modulus_bits: u64 = modulus.len() as u64 * 8;
+        if modulus_bits < info.ulMinKeySize
+            || (info.ulMaxKeySize != 0 && modulus_bits > info.ulMaxKeySize)
+        {
+            return err_rv!(CKR_KEY_SIZE_RANGE);
+        }
+        let outlen = modulus.len() + aes::kwp_wrap_len(key)?;
+        if data.is_null() {
+            unsafe {
+                *data_len = outlen as CK_ULONG;
+            }
+            return Ok(());
+        }
+        if (unsafe { *data_len } as usize) < outlen {
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
+        }
+
+        let out = unsafe { slice::from_raw_parts_mut(data, outlen) };
+
+        /* ephemeral AES key used to wrap the target key with AES-KWP */
+        let mut aes_key = vec![0u8; aes_key_len];
+        let ret = match super::CSPRNG.with(|rng| {
+            rng.borrow_mut().generate_random(aes_key.as_mut_slice())
+        }) {
+            Ok(()) => rsa_aes_wrap(&aes_key, &mut oaep, wrapping_key, key, out),
+            Err(e) => Err(e),
+        };
+        aes_key.zeroize();
+        ret?;
+        unsafe {
+            *data_len = outlen as CK_ULONG;
+        }
+        Ok(())
+    }
+
+    fn unwrap(
+        mech: &CK_MECHANISM,
+        wrapping_key: &Object,
+        data: &[u8],
+        info: &CK_MECHANISM_INFO,
+    ) -> KResult<Vec<u8>> {
+        let (aes_key_len, mut oaep) = rsa_aes_key_wrap_params(mech)?;
+        let modulus = wrapping_key.get_attr_as_bytes(CKA_MODULUS)?;
+        let modulus_bits: u64 = modulus.len() as u64 * 8;
+        if modulus_bits < info.ulMinKeySize
+            || (info.ulMaxKeySize != 0 && modulus_bits > info.ulMaxKeySize)
+        {
+            return err_rv!(CKR_KEY_SIZE_RANGE);
+        }
+        let modulus_len = modulus.len();
+        if data.len() <= modulus_len {
+            return err_rv!(CKR_WRAPPED_KEY_LEN_RANGE);
+        }
+
+        let mut pkey = object_to_rsa_private_key(wrapping_key)?;
+        let mut aes_key = match rsa_oaep_decrypt(
+            &mut pkey,
+            &mut oaep,
+            &data[..modulus_len],
+        ) {
+            Ok(k) => k,
+            Err(_) => return err_rv!(CKR_WRAPPED_KEY_INVALID),
+        };
+        let ret = if aes_key.len() != aes_key_len {
+            err_rv!(CKR_WRAPPED_KEY_INVALID)
+        } else {
+            aes::kwp_unwrap(&aes_key, &data[modulus_len..])
+        };
+        aes_key.zeroize();
+        ret
+    }
+}
@@ -0,0 +1,387 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use super::attribute;
+use super::err_rv;
+use super::error;
+use super::interface;
+use super::mechanism;
+use super::object;
+use super::rsa;
+
+use attribute::from_bool;
+use error::{KError, KResult};
+use interface::*;
+use mechanism::*;
+use object::{Object, ObjectTemplates};
+
+use std::fmt::Debug;
+use zeroize::Zeroize;
+
+/* Shares are evaluated at x = 1..=255, each one is wrapped on its own
+ * and prefixed by its length in the output, the plaintext of a share is
+ * x || threshold || y */
+const MAX_SHARES: usize = 255;
+const SHARE_HEADER_LEN: usize = 2;
+const SEGMENT_LEN_SIZE: usize = 4;
+
+/* GF(2^8) with the AES polynomial, branch free */
+fn gf_mul(a: u8, b: u8) -> u8 {
+    let mut a = a;
+    let mut b = b;
+    let mut r = 0u8;
+    for _ in 0..8 {
+        r ^= a & (b & 1).wrapping_neg();
+        let carry = (a >> 7).wrapping_neg();
+        a = (a << 1) ^ (0x1b & carry);
+        b >>= 1;
+    }
+    r
+}
+
+fn gf_inv(a: u8) -> u8 {
+    /* a^254 */
+    let mut r = a;
+    for _ in 0..6 {
+        r = gf_mul(r, r);
+        r = gf_mul(r, a);
+    }
+    gf_mul(r, r)
+}
+
+fn handles_param<'a>(
+    ptr: CK_OBJECT_HANDLE_PTR,
+    len: CK_ULONG,
+) -> KResult<&'a [CK_OBJECT_HANDLE]> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+    if ptr.is_null() {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    Ok(unsafe { std::slice::from_raw_parts(ptr, len as usize) })
+}
+
+fn split_params(mech: &CK_MECHANISM) -> KResult<&KRY_SHAMIR_SPLIT_PARAMS> {
+    if mech.pParameter.is_null()
+        || mech.ulParameterLen as usize
+            != ::std::mem::size_of::<KRY_SHAMIR_SPLIT_PARAMS>()
+    {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    let params =
+        unsafe { &*(mech.pParameter as *const KRY_SHAMIR_SPLIT_PARAMS) };
+    /* the wrapping key receives the first share */
+    let shares = params.ulCustodianKeys as usize + 1;
+    let threshold = params.ulThreshold as usize;
+    if threshold < 2 || threshold > shares || shares > MAX_SHARES {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    Ok(params)
+}
+
+fn combine_params(mech: &CK_MECHANISM) -> KResult<&KRY_SHAMIR_COMBINE_PARAMS> {
+    if mech.pParameter.is_null()
+        || mech.ulParameterLen as usize
+            != ::std::mem::size_of::<KRY_SHAMIR_COMBINE_PARAMS>()
+    {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    let params =
+        unsafe { &*(mech.pParameter as *const KRY_SHAMIR_COMBINE_PARAMS) };
+    if params.ulCustodianKeys as usize >= MAX_SHARES {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    Ok(params)
+}
+
+/* Returns one share per custodian, for each byte of the secret a random
+ * polynomial of degree threshold - 1 is evaluated at x = 1..=shares */
+fn split(
+    secret: &[u8],
+    threshold: usize,
+    shares: usize,
+) -> KResult<Vec<Vec<u8>>> {
+    let mut out: Vec<Vec<u8>> = Vec::with_capacity(shares);
+    for x in 1..=shares {
+        let mut share = Vec::with_capacity(SHARE_HEADER_LEN + secret.len());
+        share.push(x as u8);
+        share.push(threshold as u8);
+        out.push(share);
+    }
+    let mut coeffs = vec![0u8; threshold];
+    for s in secret {
+        coeffs[0] = *s;
+        let ret = super::CSPRNG
+            .with(|rng| rng.borrow_mut().generate_random(&mut coeffs[1..]));
+        if let Err(e) = ret {
+            coeffs.zeroize();
+            for share in out.iter_mut() {
+                share.zeroize();
+            }
+            return Err(e);
+        }
+        for share in out.iter_mut() {
+            let x = share[0];
+            /* Horner's method */
+            let mut y = 0u8;
+            for c in coeffs.iter().rev() {
+                y = gf_mul(y, x) ^ c;
+            }
+            share.push(y);
+        }
+    }
+    coeffs.zeroize();
+    Ok(out)
+}
+
+/* Lagrange interpolation at x = 0 */
+fn combine(shares: &[Vec<u8>]) -> KResult<Vec<u8>> {
+    let first = match shares.first() {
+        Some(s) => s,
+        None => return err_rv!(CKR_WRAPPED_KEY_INVALID),
+    };
+    if first.len() <= SHARE_HEADER_LEN {
+        return err_rv!(CKR_WRAPPED_KEY_INVALID);
+    }
+    let threshold = first[1] as usize;
+    if threshold < 2 || shares.len() < threshold {
+        return err_rv!(CKR_WRAPPED_KEY_INVALID);
+    }
+    for (i, share) in shares.iter().enumerate() {
+        if share.len() != first.len()
+            || share[0] == 0
+            || share[1] != first[1]
+            || shares[..i].iter().any(|s| s[0] == share[0])
+        {
+            return err_rv!(CKR_WRAPPED_KEY_INVALID);
+        }
+    }
+
+    let mut weights: Vec<u8> = Vec::with_capacity(shares.len());
+    for share in shares {
+        let xi = share[0];
+        let mut num = 1u8;
+        let mut den = 1u8;
+        for other in shares {
+            let xj = other[0];
+            if xj != xi {
+                num = gf_mul(num, xj);
+                den = gf_mul(den, xj ^ xi);
+            }
+        }
+        weights.push(gf_mul(num, gf_inv(den)));
+    }
+    let mut secret = vec![0u8; first.len() - SHARE_HEADER_LEN];
+    for (share, w) in shares.iter().zip(weights.iter()) {
+        for (s, y) in secret.iter_mut().zip(share[SHARE_HEADER_LEN..].iter()) {
+            *s ^= gf_mul(*y, *w);
+        }
+    }
+    Ok(secret)
+}
+
+#[derive(Debug)]
+struct ShamirMechanism {
+    info: CK_MECHANISM_INFO,
+}
+
+impl Mechanism for ShamirMechanism {
+    fn info(&self) -> &CK_MECHANISM_INFO {
+        &self.info
+    }
+
+    fn wrap_key_objects(
+        &self,
+        mech: &CK_MECHANISM,
+    ) -> KResult<Vec<CK_OBJECT_HANDLE>> {
+        match mech.mechanism {
+            KRYMECH_SHAMIR_SPLIT => {
+                let params = split_params(mech)?;
+                Ok(handles_param(
+                    params.phCustodianKeys,
+                    params.ulCustodianKeys,
+                )?
+                .to_vec())
+            }
+            KRYMECH_SHAMIR_COMBINE => {
+                let params = combine_params(mech)?;
+                Ok(handles_param(
+                    params.phCustodianKeys,
+                    params.ulCustodianKeys,
+                )?
+                .to_vec())
+            }
+            _ => err_rv!(CKR_MECHANISM_INVALID),
+        }
+    }
+
+    fn wrap_key(
+        &self,
+        mech: &CK_MECHANISM,
+        wrapping_key: &Object,
+        key: &Object,
+        objects: &[&Object],
+        data: CK_BYTE_PTR,
+        data_len: CK_ULONG_PTR,
+    ) -> KResult<()> {
+        if self.info.flags & CKF_WRAP != CKF_WRAP {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        let params = split_params(mech)?;
+        if key.get_attr_as_ulong(CKA_CLASS)? != CKO_SECRET_KEY {
+            return err_rv!(CKR_KEY_NOT_WRAPPABLE);
+        }
+        /* the caller only checks the wrapping key */
+        for custodian in objects {
+            custodian.check_wrap_policy(key)?;
+        }
+        let secret = key.get_attr_as_bytes(CKA_VALUE)?;
+        if secret.is_empty() {
+            return err_rv!(CKR_KEY_NOT_WRAPPABLE);
+        }
+
+        let mut custodians: Vec<&Object> = Vec::with_capacity(objects.len());
+        custodians.push(wrapping_key);
+        custodians.extend_from_slice(objects);
+
+        /* the size only depends on the custodian keys and the share size,
+         * so no split is needed to answer a size query */
+        let share_len = SHARE_HEADER_LEN + secret.len();
+        let mut outlen = 0;
+        for custodian in custodians.iter() {
+            outlen +=
+                SEGMENT_LEN_SIZE + rsa::wrap_secret_len(custodian, share_len)?;
+        }
+        if data.is_null() {
+            unsafe {
+                *data_len = outlen as CK_ULONG;
+            }
+            return Ok(());
+        }
+        if (unsafe { *data_len } as usize) < outlen {
+            return err_rv!(CKR_BUFFER_TOO_SMALL);
+        }
+
+        let mut shares = split(
+            secret.as_slice(),
+            params.ulThreshold as usize,
+            custodians.len(),
+        )?;
+        let mut out: Vec<u8> = Vec::with_capacity(outlen);
+        let mut ret = Ok(());
+        for (custodian, share) in custodians.iter().zip(shares.iter()) {
+            match rsa::wrap_secret(custodian, share.as_slice()) {
+                Ok(w) => {
+                    out.extend_from_slice(&(w.len() as u32).to_be_bytes());
+                    out.extend_from_slice(w.as_slice());
+                }
+                Err(e) => {
+                    ret = Err(e);
+                    break;
+                }
+            }
+        }
+        for share in shares.iter_mut() {
+            share.zeroize();
+        }
+        ret?;
+        if out.len() != outlen {
+            return err_rv!(CKR_GENERAL_ERROR);
+        }
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(out.as_ptr(), data, out.len());
+            *data_len = out.len() as CK_ULONG;
+        }
+        Ok(())
+    }
+
+    fn unwrap_key(
+        &self,
+        mech: &CK_MECHANISM,
+        wrapping_key: &Object,
+        objects: &[&Object],
+        data: &[u8],
+        template: &[CK_ATTRIBUTE],
+        objtemplates: &ObjectTemplates,
+    ) -> KResult<Object> {
+        if self.info.flags & CKF_UNWRAP != CKF_UNWRAP {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        combine_params(mech)?;
+
+        let mut custodians: Vec<&Object> = Vec::with_capacity(objects.len());
+        custodians.push(wrapping_key);
+        custodians.extend_from_slice(objects);
+        let mut shares: Vec<Vec<u8>> = Vec::with_capacity(custodians.len());
+        let mut ret = Ok(());
+        let mut offset = 0;
+        for custodian in custodians {
+            if data.len() < offset + SEGMENT_LEN_SIZE {
+                ret = err_rv!(CKR_WRAPPED_KEY_LEN_RANGE);
+                break;
+            }
+            let mut len = [0u8; SEGMENT_LEN_SIZE];
+            len.copy_from_slice(&data[offset..(offset + SEGMENT_LEN_SIZE)]);
+            let len = u32::from_be_bytes(len) as usize;
+            offset += SEGMENT_LEN_SIZE;
+            if data.len() < offset + len {
+                ret = err_rv!(CKR_WRAPPED_KEY_LEN_RANGE);
+                break;
+            }
+            match rsa::unwrap_secret(custodian, &data[offset..(offset + len)]) {
+                Ok(s) => shares.push(s),
+                Err(e) => {
+                    ret = Err(e);
+                    break;
+                }
+            }
+            offset += len;
+        }
+        if ret.is_ok() && offset != data.len() {
+            ret = err_rv!(CKR_WRAPPED_KEY_LEN_RANGE);
+        }
+        let secret = match ret {
+            Ok(()) => combine(shares.as_slice()),
+            Err(e) => Err(e),
+        };
+        for share in shares.iter_mut() {
+            share.zeroize();
+        }
+        let mut secret = secret?;
+
+        let key = objtemplates.unwrap_key_from_template(template, &secret);
+        secret.zeroize();
+        let mut key = key?;
+        if key.get_attr_as_ulong(CKA_CLASS)? != CKO_SECRET_KEY {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        /* the key was never in the token as a whole */
+        key.set_attr(from_bool(CKA_LOCAL, false))?;
+        Ok(key)
+    }
+}
+
+pub fn register(mechs: &mut Mechanisms, _: &mut ObjectTemplates) {
+    mechs.add_mechanism(
+        KRYMECH_SHAMIR_SPLIT,
+        Box::new(ShamirMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_WRAP,
+            },
+        }),
+    );
+    mechs.add_mechanism(
+        KRYMECH_SHAMIR_COMBINE,
+        Box::new(ShamirMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_UNWRAP,
+            },
+        }),
+    );
+}
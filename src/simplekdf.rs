@@ -0,0 +1,316 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use super::attribute;
+use super::err_rv;
+use super::error;
+use super::interface;
+use super::mechanism;
+use super::object;
+
+use attribute::{from_bool, from_bytes};
+use error::{KError, KResult};
+use interface::*;
+use mechanism::*;
+use object::{Object, ObjectTemplates};
+
+use std::fmt::Debug;
+use zeroize::Zeroize;
+
+fn check_key_object(key: &Object, derive: bool) -> KResult<()> {
+    if key.get_attr_as_ulong(CKA_CLASS)? != CKO_SECRET_KEY {
+        return err_rv!(CKR_KEY_TYPE_INCONSISTENT);
+    }
+    if !derive {
+        return Ok(());
+    }
+    match key.get_attr_as_bool(CKA_DERIVE) {
+        Ok(avail) => {
+            if !avail {
+                return err_rv!(CKR_KEY_FUNCTION_NOT_PERMITTED);
+            }
+        }
+        Err(_) => return err_rv!(CKR_KEY_FUNCTION_NOT_PERMITTED),
+    }
+    Ok(())
+}
+
+fn string_data(mech: &CK_MECHANISM) -> KResult<&[u8]> {
+    if mech.pParameter.is_null()
+        || mech.ulParameterLen as usize
+            != ::std::mem::size_of::<CK_KEY_DERIVATION_STRING_DATA>()
+    {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    let params =
+        unsafe { &*(mech.pParameter as *const CK_KEY_DERIVATION_STRING_DATA) };
+    if params.pData.is_null() || params.ulLen == 0 {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    Ok(unsafe {
+        std::slice::from_raw_parts(params.pData, params.ulLen as usize)
+    })
+}
+
+fn ulong_param(mech: &CK_MECHANISM) -> KResult<CK_ULONG> {
+    if mech.pParameter.is_null()
+        || mech.ulParameterLen as usize != ::std::mem::size_of::<CK_ULONG>()
+    {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    Ok(unsafe { *(mech.pParameter as *const CK_ULONG) })
+}
+
+/* pkcs11-spec-v3.1 2.43: the derived key is sensitive if any of the
+ * base keys is, and can only be extractable if all of them are.
+ * Returns the new object without a value and the key length */
+fn derived_key_object(
+    keys: &[&Object],
+    template: &[CK_ATTRIBUTE],
+    objtemplates: &ObjectTemplates,
+    maxlen: usize,
+) -> KResult<(Object, usize)> {
+    let ktype: CK_KEY_TYPE = CKK_GENERIC_SECRET;
+    let truebool: CK_BBOOL = CK_TRUE;
+    let falsebool: CK_BBOOL = CK_FALSE;
+    let sensitive = keys.iter().any(|k| k.is_sensitive());
+    let extractable = keys.iter().all(|k| k.is_extractable());
+
+    let mut tmpl: Vec<CK_ATTRIBUTE> = Vec::with_capacity(template.len() + 3);
+    for attr in template {
+        match attr.type_ {
+            CKA_SENSITIVE => {
+                if !sensitive {
+                    tmpl.push(*attr);
+                } else if !attr.to_bool()? {
+                    return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+                }
+            }
+            CKA_EXTRACTABLE => {
+                if extractable {
+                    tmpl.push(*attr);
+                } else if attr.to_bool()? {
+                    return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+                }
+            }
+            _ => tmpl.push(*attr),
+        }
+    }
+    if !template.iter().any(|a| a.type_ == CKA_KEY_TYPE) {
+        tmpl.push(CK_ATTRIBUTE {
+            type_: CKA_KEY_TYPE,
+            pValue: &ktype as *const CK_KEY_TYPE as CK_VOID_PTR,
+            ulValueLen: ::std::mem::size_of::<CK_KEY_TYPE>() as CK_ULONG,
+        });
+    }
+    if sensitive {
+        tmpl.push(CK_ATTRIBUTE {
+            type_: CKA_SENSITIVE,
+            pValue: &truebool as *const CK_BBOOL as CK_VOID_PTR,
+            ulValueLen: ::std::mem::size_of::<CK_BBOOL>() as CK_ULONG,
+        });
+    }
+    if !extractable {
+        tmpl.push(CK_ATTRIBUTE {
+            type_: CKA_EXTRACTABLE,
+            pValue: &falsebool as *const CK_BBOOL as CK_VOID_PTR,
+            ulValueLen: ::std::mem::size_of::<CK_BBOOL>() as CK_ULONG,
+        });
+    }
+
+    let mut obj = objtemplates.derive_key_from_template(keys[0], &tmpl)?;
+    let keylen = match obj.get_attr_as_ulong(CKA_VALUE_LEN) {
+        Ok(n) => n as usize,
+        Err(e) => match e {
+            KError::NotFound(_) => maxlen,
+            _ => return Err(e),
+        },
+    };
+    if keylen == 0 || keylen > maxlen {
+        return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+    }
+    if obj.get_attr_as_ulong(CKA_KEY_TYPE)? == CKK_AES {
+        match keylen {
+            16 | 24 | 32 => (),
+            _ => return err_rv!(CKR_TEMPLATE_INCONSISTENT),
+        }
+    }
+    obj.del_attr(CKA_VALUE_LEN);
+
+    /* the template code only accounts for the first base key */
+    for key in &keys[1..] {
+        if !key.get_attr_as_bool(CKA_ALWAYS_SENSITIVE).unwrap_or(false) {
+            obj.set_attr(from_bool(CKA_ALWAYS_SENSITIVE, false))?;
+        }
+        if !key.get_attr_as_bool(CKA_NEVER_EXTRACTABLE).unwrap_or(false) {
+            obj.set_attr(from_bool(CKA_NEVER_EXTRACTABLE, false))?;
+        }
+    }
+    Ok((obj, keylen))
+}
+
+/* Takes ownership of the derived value and zeroizes it on error */
+fn derived_key(
+    keys: &[&Object],
+    template: &[CK_ATTRIBUTE],
+    objtemplates: &ObjectTemplates,
+    mut value: Vec<u8>,
+) -> KResult<Object> {
+    let (mut obj, keylen) =
+        match derived_key_object(keys, template, objtemplates, value.len()) {
+            Ok(r) => r,
+            Err(e) => {
+                value.zeroize();
+                return Err(e);
+            }
+        };
+    value[keylen..].zeroize();
+    value.truncate(keylen);
+    obj.set_attr(from_bytes(CKA_VALUE, value))?;
+    Ok(obj)
+}
+
+#[derive(Debug)]
+struct SimpleKDFMechanism {
+    info: CK_MECHANISM_INFO,
+}
+
+impl Mechanism for SimpleKDFMechanism {
+    fn info(&self) -> &CK_MECHANISM_INFO {
+        &self.info
+    }
+
+    fn derive_key_objects(
+        &self,
+        mech: &CK_MECHANISM,
+    ) -> KResult<Vec<CK_OBJECT_HANDLE>> {
+        match mech.mechanism {
+            CKM_CONCATENATE_BASE_AND_KEY => Ok(vec![ulong_param(mech)?]),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn derive_key(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+        objects: &[&Object],
+        template: &[CK_ATTRIBUTE],
+        objtemplates: &ObjectTemplates,
+    ) -> KResult<Object> {
+        if self.info.flags & CKF_DERIVE != CKF_DERIVE {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        match check_key_object(key, true) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        let base = key.get_attr_as_bytes(CKA_VALUE)?;
+
+        match mech.mechanism {
+            CKM_CONCATENATE_BASE_AND_KEY => {
+                let other = match objects.first() {
+                    Some(o) => *o,
+                    None => return err_rv!(CKR_GENERAL_ERROR),
+                };
+                match check_key_object(other, false) {
+                    Ok(_) => (),
+                    Err(e) => return Err(e),
+                }
+                let other_value = other.get_attr_as_bytes(CKA_VALUE)?;
+                let mut value = base.clone();
+                value.extend_from_slice(other_value.as_slice());
+                derived_key(&[key, other], template, objtemplates, value)
+            }
+            CKM_CONCATENATE_BASE_AND_DATA => {
+                let data = string_data(mech)?;
+                let mut value = base.clone();
+                value.extend_from_slice(data);
+                derived_key(&[key], template, objtemplates, value)
+            }
+            CKM_CONCATENATE_DATA_AND_BASE => {
+                let data = string_data(mech)?;
+                let mut value = data.to_vec();
+                value.extend_from_slice(base.as_slice());
+                derived_key(&[key], template, objtemplates, value)
+            }
+            CKM_XOR_BASE_AND_DATA => {
+                let data = string_data(mech)?;
+                let value: Vec<u8> =
+                    base.iter().zip(data.iter()).map(|(b, d)| b ^ d).collect();
+                derived_key(&[key], template, objtemplates, value)
+            }
+            CKM_EXTRACT_KEY_FROM_KEY => {
+                /* bits are taken MSB first starting at the given index,
+                 * wrapping around to the start of the base key */
+                let index = ulong_param(mech)? as usize;
+                let bits = base.len() * 8;
+                if index >= bits {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+                let mut value = vec![0u8; base.len()];
+                for i in 0..bits {
+                    let b = (index + i) % bits;
+                    if base[b / 8] & (0x80 >> (b % 8)) != 0 {
+                        value[i / 8] |= 0x80 >> (i % 8);
+                    }
+                }
+                derived_key(&[key], template, objtemplates, value)
+            }
+            _ => err_rv!(CKR_MECHANISM_INVALID),
+        }
+    }
+}
+
+pub fn register(mechs: &mut Mechanisms, _: &mut ObjectTemplates) {
+    mechs.add_mechanism(
+        CKM_CONCATENATE_BASE_AND_KEY,
+        Box::new(SimpleKDFMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_DERIVE,
+            },
+        }),
+    );
+    mechs.add_mechanism(
+        CKM_CONCATENATE_BASE_AND_DATA,
+        Box::new(SimpleKDFMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_DERIVE,
+            },
+        }),
+    );
+    mechs.add_mechanism(
+        CKM_CONCATENATE_DATA_AND_BASE,
+        Box::new(SimpleKDFMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_DERIVE,
+            },
+        }),
+    );
+    mechs.add_mechanism(
+        CKM_XOR_BASE_AND_DATA,
+        Box::new(SimpleKDFMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_DERIVE,
+            },
+        }),
+    );
+    mechs.add_mechanism(
+        CKM_EXTRACT_KEY_FROM_KEY,
+        Box::new(SimpleKDFMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_DERIVE,
+            },
+        }),
+    );
+}
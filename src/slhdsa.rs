@@ -0,0 +1,439 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use super::attribute;
+use super::error;
+use super::interface;
+use super::mldsa;
+use super::object;
+use super::{attr_element, bytes_attr_not_empty, err_rv};
+
+use attribute::{from_bool, from_bytes, from_ulong};
+use error::{KError, KResult};
+use interface::*;
+use object::{
+    CommonKeyTemplate, OAFlags, Object, ObjectAttr, ObjectTemplate,
+    ObjectTemplates, ObjectType, PrivKeyTemplate, PubKeyTemplate,
+};
+
+use super::mechanism;
+use mechanism::*;
+
+use once_cell::sync::Lazy;
+use std::fmt::Debug;
+
+#[derive(Debug)]
+struct SlhDsaParams {
+    set: CK_SLH_DSA_PARAMETER_SET_TYPE,
+    ossl_name: &'static [u8],
+    /* security parameter, public keys are 2n and private keys 4n bytes */
+    n: usize,
+    sig_len: usize,
+}
+
+/* FIPS 205, 11: security parameter and signature sizes */
+static SLH_DSA_PARAMS: [SlhDsaParams; 12] = [
+    SlhDsaParams {
+        set: CKP_SLH_DSA_SHA2_128S,
+        ossl_name: b"SLH-DSA-SHA2-128s\0",
+        n: 16,
+        sig_len: 7856,
+    },
+    SlhDsaParams {
+        set: CKP_SLH_DSA_SHAKE_128S,
+        ossl_name: b"SLH-DSA-SHAKE-128s\0",
+        n: 16,
+        sig_len: 7856,
+    },
+    SlhDsaParams {
+        set: CKP_SLH_DSA_SHA2_128F,
+        ossl_name: b"SLH-DSA-SHA2-128f\0",
+        n: 16,
+        sig_len: 17088,
+    },
+    SlhDsaParams {
+        set: CKP_SLH_DSA_SHAKE_128F,
+        ossl_name: b"SLH-DSA-SHAKE-128f\0",
+        n: 16,
+        sig_len: 17088,
+    },
+    SlhDsaParams {
+        set: CKP_SLH_DSA_SHA2_192S,
+        ossl_name: b"SLH-DSA-SHA2-192s\0",
+        n: 24,
+        sig_len: 16224,
+    },
+    SlhDsaParams {
+        set: CKP_SLH_DSA_SHAKE_192S,
+        ossl_name: b"SLH-DSA-SHAKE-192s\0",
+        n: 24,
+        sig_len: 16224,
+    },
+    SlhDsaParams {
+        set: CKP_SLH_DSA_SHA2_192F,
+        ossl_name: b"SLH-DSA-SHA2-192f\0",
+        n: 24,
+        sig_len: 35664,
+    },
+    SlhDsaParams {
+        set: CKP_SLH_DSA_SHAKE_192F,
+        ossl_name: b"SLH-DSA-SHAKE-192f\0",
+        n: 24,
+        sig_len: 35664,
+    },
+    SlhDsaParams {
+        set: CKP_SLH_DSA_SHA2_256S,
+        ossl_name: b"SLH-DSA-SHA2-256s\0",
+        n: 32,
+        sig_len: 29792,
+    },
+    SlhDsaParams {
+        set: CKP_SLH_DSA_SHAKE_256S,
+        ossl_name: b"SLH-DSA-SHAKE-256s\0",
+        n: 32,
+        sig_len: 29792,
+    },
+    SlhDsaParams {
+        set: CKP_SLH_DSA_SHA2_256F,
+        ossl_name: b"SLH-DSA-SHA2-256f\0",
+        n: 32,
+        sig_len: 49856,
+    },
+    SlhDsaParams {
+        set: CKP_SLH_DSA_SHAKE_256F,
+        ossl_name: b"SLH-DSA-SHAKE-256f\0",
+        n: 32,
+        sig_len: 49856,
+    },
+];
+
+fn params_from_obj(obj: &Object) -> KResult<&'static SlhDsaParams> {
+    let set = obj.get_attr_as_ulong(CKA_PARAMETER_SET)?;
+    match SLH_DSA_PARAMS.iter().find(|p| p.set == set) {
+        Some(p) => Ok(p),
+        None => err_rv!(CKR_ATTRIBUTE_VALUE_INVALID),
+    }
+}
+
+#[derive(Debug)]
+pub struct SlhDsaPubTemplate {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl SlhDsaPubTemplate {
+    pub fn new() -> SlhDsaPubTemplate {
+        let mut data: SlhDsaPubTemplate = SlhDsaPubTemplate {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes.append(&mut data.init_common_key_attrs());
+        data.attributes
+            .append(&mut data.init_common_public_key_attrs());
+        data.attributes.push(attr_element!(CKA_PARAMETER_SET; OAFlags::AlwaysRequired | OAFlags::Unchangeable; from_ulong; val 0));
+        data.attributes.push(attr_element!(CKA_VALUE; OAFlags::RequiredOnCreate | OAFlags::UnsettableOnGenerate | OAFlags::Unchangeable; from_bytes; val Vec::new()));
+        data
+    }
+}
+
+impl ObjectTemplate for SlhDsaPubTemplate {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> KResult<Object> {
+        let obj = self.default_object_create(template, false)?;
+
+        let params = params_from_obj(&obj)?;
+        if obj.get_attr_as_bytes(CKA_VALUE)?.len() != 2 * params.n {
+            return err_rv!(CKR_ATTRIBUTE_VALUE_INVALID);
+        }
+
+        Ok(obj)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl CommonKeyTemplate for SlhDsaPubTemplate {
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl PubKeyTemplate for SlhDsaPubTemplate {
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+#[derive(Debug)]
+pub struct SlhDsaPrivTemplate {
+    attributes: Vec<ObjectAttr>,
+}
+
+impl SlhDsaPrivTemplate {
+    pub fn new() -> SlhDsaPrivTemplate {
+        let mut data: SlhDsaPrivTemplate = SlhDsaPrivTemplate {
+            attributes: Vec::new(),
+        };
+        data.attributes.append(&mut data.init_common_object_attrs());
+        data.attributes
+            .append(&mut data.init_common_storage_attrs());
+        data.attributes.append(&mut data.init_common_key_attrs());
+        data.attributes
+            .append(&mut data.init_common_private_key_attrs());
+        data.attributes.push(attr_element!(CKA_PARAMETER_SET; OAFlags::RequiredOnCreate | OAFlags::Unchangeable; from_ulong; val 0));
+        data.attributes.push(attr_element!(CKA_VALUE; OAFlags::Sensitive | OAFlags::RequiredOnCreate | OAFlags::UnsettableOnGenerate | OAFlags::Unchangeable; from_bytes; val Vec::new()));
+        /* SK.seed || SK.prf || PK.seed, only used during key generation */
+        data.attributes.push(attr_element!(CKA_SEED; OAFlags::Sensitive | OAFlags::UnsettableOnCreate | OAFlags::Unchangeable; from_bytes; val Vec::new()));
+
+        /* default to private */
+        let private = attr_element!(CKA_PRIVATE; OAFlags::Defval | OAFlags::ChangeOnCopy; from_bool; val true);
+        match data
+            .attributes
+            .iter()
+            .position(|x| x.get_type() == CKA_PRIVATE)
+        {
+            Some(idx) => data.attributes[idx] = private,
+            None => data.attributes.push(private),
+        }
+
+        data
+    }
+}
+
+impl ObjectTemplate for SlhDsaPrivTemplate {
+    fn create(&self, template: &[CK_ATTRIBUTE]) -> KResult<Object> {
+        let obj = self.default_object_create(template, false)?;
+
+        let params = params_from_obj(&obj)?;
+        bytes_attr_not_empty!(obj; CKA_VALUE);
+        if obj.get_attr_as_bytes(CKA_VALUE)?.len() != 4 * params.n {
+            return err_rv!(CKR_ATTRIBUTE_VALUE_INVALID);
+        }
+
+        Ok(obj)
+    }
+
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl CommonKeyTemplate for SlhDsaPrivTemplate {
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+impl PrivKeyTemplate for SlhDsaPrivTemplate {
+    fn get_attributes(&self) -> &Vec<ObjectAttr> {
+        &self.attributes
+    }
+}
+
+static PUBLIC_KEY_TEMPLATE: Lazy<Box<dyn ObjectTemplate>> =
+    Lazy::new(|| Box::new(SlhDsaPubTemplate::new()));
+
+static PRIVATE_KEY_TEMPLATE: Lazy<Box<dyn ObjectTemplate>> =
+    Lazy::new(|| Box::new(SlhDsaPrivTemplate::new()));
+
+fn check_key_object(key: &Object, public: bool, op: CK_ULONG) -> KResult<()> {
+    match key.get_attr_as_ulong(CKA_CLASS)? {
+        CKO_PUBLIC_KEY => {
+            if !public {
+                return err_rv!(CKR_KEY_TYPE_INCONSISTENT);
+            }
+        }
+        CKO_PRIVATE_KEY => {
+            if public {
+                return err_rv!(CKR_KEY_TYPE_INCONSISTENT);
+            }
+        }
+        _ => return err_rv!(CKR_KEY_TYPE_INCONSISTENT),
+    }
+    match key.get_attr_as_ulong(CKA_KEY_TYPE)? {
+        CKK_SLH_DSA => (),
+        _ => return err_rv!(CKR_KEY_TYPE_INCONSISTENT),
+    }
+    match key.get_attr_as_bool(op) {
+        Ok(avail) => {
+            if !avail {
+                return err_rv!(CKR_KEY_FUNCTION_NOT_PERMITTED);
+            }
+        }
+        Err(_) => return err_rv!(CKR_KEY_FUNCTION_NOT_PERMITTED),
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+struct SlhDsaSigParams {
+    deterministic: bool,
+    context: Vec<u8>,
+}
+
+fn parse_params(mech: &CK_MECHANISM) -> KResult<SlhDsaSigParams> {
+    if mech.mechanism != CKM_SLH_DSA {
+        return err_rv!(CKR_MECHANISM_INVALID);
+    }
+    if mech.pParameter.is_null() {
+        if mech.ulParameterLen != 0 {
+            return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+        }
+        return Ok(SlhDsaSigParams {
+            deterministic: false,
+            context: Vec::new(),
+        });
+    }
+    if mech.ulParameterLen as usize
+        != ::std::mem::size_of::<CK_SIGN_ADDITIONAL_CONTEXT>()
+    {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    let params =
+        unsafe { &*(mech.pParameter as *const CK_SIGN_ADDITIONAL_CONTEXT) };
+    let (deterministic, context) = mldsa::context_data(
+        params.hedgeVariant,
+        params.pContext,
+        params.ulContextLen,
+    )?;
+    Ok(SlhDsaSigParams {
+        deterministic: deterministic,
+        context: context,
+    })
+}
+
+#[derive(Debug)]
+struct SlhDsaMechanism {
+    info: CK_MECHANISM_INFO,
+}
+
+impl Mechanism for SlhDsaMechanism {
+    fn info(&self) -> &CK_MECHANISM_INFO {
+        &self.info
+    }
+
+    fn sign_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> KResult<Box<dyn Sign>> {
+        if self.info.flags & CKF_SIGN != CKF_SIGN {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        match check_key_object(key, false, CKA_SIGN) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        Ok(Box::new(SlhDsaOperation::sign_new(mech, key)?))
+    }
+
+    fn verify_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> KResult<Box<dyn Verify>> {
+        if self.info.flags & CKF_VERIFY != CKF_VERIFY {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        match check_key_object(key, true, CKA_VERIFY) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        Ok(Box::new(SlhDsaOperation::verify_new(mech, key)?))
+    }
+
+    fn generate_keypair(
+        &self,
+        mech: &CK_MECHANISM,
+        pubkey_template: &[CK_ATTRIBUTE],
+        prikey_template: &[CK_ATTRIBUTE],
+    ) -> KResult<(Object, Object)> {
+        if mech.mechanism != CKM_SLH_DSA_KEY_PAIR_GEN {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        let mut pubkey =
+            PUBLIC_KEY_TEMPLATE.default_object_create(pubkey_template, true)?;
+        if !pubkey.check_or_set_attr(from_ulong(CKA_CLASS, CKO_PUBLIC_KEY))? {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        if !pubkey.check_or_set_attr(from_ulong(CKA_KEY_TYPE, CKK_SLH_DSA))? {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        let params = match params_from_obj(&pubkey) {
+            Ok(p) => p,
+            Err(_) => return err_rv!(CKR_TEMPLATE_INCONSISTENT),
+        };
+
+        let mut privkey = PRIVATE_KEY_TEMPLATE
+            .default_object_create(prikey_template, true)?;
+        if !privkey.check_or_set_attr(from_ulong(CKA_CLASS, CKO_PRIVATE_KEY))? {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        if !privkey.check_or_set_attr(from_ulong(CKA_KEY_TYPE, CKK_SLH_DSA))? {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        if !privkey
+            .check_or_set_attr(from_ulong(CKA_PARAMETER_SET, params.set))?
+        {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+
+        let seed = match privkey.get_attr_as_bytes(CKA_SEED) {
+            Ok(s) => {
+                if s.len() != 3 * params.n {
+                    return err_rv!(CKR_ATTRIBUTE_VALUE_INVALID);
+                }
+                Some(s.clone())
+            }
+            Err(e) => match e {
+                KError::NotFound(_) => None,
+                _ => return Err(e),
+            },
+        };
+        privkey.del_attr(CKA_SEED);
+
+        SlhDsaOperation::generate_keypair(
+            params,
+            seed,
+            &mut pubkey,
+            &mut privkey,
+        )?;
+
+        Ok((pubkey, privkey))
+    }
+}
+
+pub fn register(mechs: &mut Mechanisms, ot: &mut ObjectTemplates) {
+    mechs.add_mechanism(
+        CKM_SLH_DSA_KEY_PAIR_GEN,
+        Box::new(SlhDsaMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_GENERATE_KEY_PAIR,
+            },
+        }),
+    );
+
+    mechs.add_mechanism(
+        CKM_SLH_DSA,
+        Box::new(SlhDsaMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_SIGN | CKF_VERIFY,
+            },
+        }),
+    );
+
+    ot.add_template(ObjectType::SlhDsaPubKey, &PUBLIC_KEY_TEMPLATE);
+    ot.add_template(ObjectType::SlhDsaPrivKey, &PRIVATE_KEY_TEMPLATE);
+}
+
+#[cfg(feature = "fips")]
+include!("ossl/slhdsa.rs");
+
+#[cfg(not(feature = "fips"))]
+include!("ossl/slhdsa.rs");
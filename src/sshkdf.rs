@@ -0,0 +1,189 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use super::attribute;
+use super::err_rv;
+use super::error;
+use super::hash;
+use super::interface;
+use super::mechanism;
+use super::object;
+
+use attribute::from_bytes;
+use error::{KError, KResult};
+use interface::*;
+use mechanism::*;
+use object::{Object, ObjectTemplates};
+
+use std::fmt::Debug;
+use zeroize::Zeroize;
+
+fn check_key_object(key: &Object) -> KResult<()> {
+    match key.get_attr_as_ulong(CKA_CLASS)? {
+        CKO_SECRET_KEY => {
+            if key.get_attr_as_ulong(CKA_KEY_TYPE)? != CKK_GENERIC_SECRET {
+                return err_rv!(CKR_KEY_TYPE_INCONSISTENT);
+            }
+        }
+        _ => return err_rv!(CKR_KEY_TYPE_INCONSISTENT),
+    }
+    match key.get_attr_as_bool(CKA_DERIVE) {
+        Ok(avail) => {
+            if !avail {
+                return err_rv!(CKR_KEY_FUNCTION_NOT_PERMITTED);
+            }
+        }
+        Err(_) => return err_rv!(CKR_KEY_FUNCTION_NOT_PERMITTED),
+    }
+    Ok(())
+}
+
+fn sshkdf_params(mech: &CK_MECHANISM) -> KResult<&CK_SSHKDF_PARAMS> {
+    if mech.pParameter.is_null()
+        || mech.ulParameterLen as usize
+            != ::std::mem::size_of::<CK_SSHKDF_PARAMS>()
+    {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    let params = unsafe { &*(mech.pParameter as *const CK_SSHKDF_PARAMS) };
+    match params.derivedKeyType {
+        CKF_SSHKDF_INITIAL_IV_CLIENT_TO_SERVER
+        | CKF_SSHKDF_INITIAL_IV_SERVER_TO_CLIENT
+        | CKF_SSHKDF_ENCRYPTION_KEY_CLIENT_TO_SERVER
+        | CKF_SSHKDF_ENCRYPTION_KEY_SERVER_TO_CLIENT
+        | CKF_SSHKDF_INTEGRITY_KEY_CLIENT_TO_SERVER
+        | CKF_SSHKDF_INTEGRITY_KEY_SERVER_TO_CLIENT => (),
+        _ => return err_rv!(CKR_MECHANISM_PARAM_INVALID),
+    }
+    if params.pExchangeHash.is_null()
+        || params.ulExchangeHashLen == 0
+        || params.pSessionId.is_null()
+        || params.ulSessionIdLen == 0
+    {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    Ok(params)
+}
+
+fn digest(op: &mut hash::HashOperation, data: &[&[u8]]) -> KResult<Vec<u8>> {
+    for d in data {
+        op.digest_update(d)?;
+    }
+    let mut out = vec![0u8; op.hashlen()];
+    op.digest_final(out.as_mut_slice())?;
+    Ok(out)
+}
+
+/* RFC 4253, 7.2
+ * The shared secret K is used as stored in the key object, callers are
+ * expected to provide it already encoded as an mpint */
+fn sshkdf(
+    hash: CK_MECHANISM_TYPE,
+    secret: &[u8],
+    exchange_hash: &[u8],
+    key_type: CK_BYTE,
+    session_id: &[u8],
+    len: usize,
+) -> KResult<Vec<u8>> {
+    let mut op = hash::HashOperation::new(hash)?;
+    let mut out =
+        digest(&mut op, &[secret, exchange_hash, &[key_type], session_id])?;
+    while out.len() < len {
+        op.reset()?;
+        let mut next =
+            digest(&mut op, &[secret, exchange_hash, out.as_slice()])?;
+        out.extend_from_slice(next.as_slice());
+        next.zeroize();
+    }
+    out[len..].zeroize();
+    out.truncate(len);
+    Ok(out)
+}
+
+#[derive(Debug)]
+struct SSHKDFMechanism {
+    info: CK_MECHANISM_INFO,
+}
+
+impl Mechanism for SSHKDFMechanism {
+    fn info(&self) -> &CK_MECHANISM_INFO {
+        &self.info
+    }
+
+    fn derive_key(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+        _: &[&Object],
+        template: &[CK_ATTRIBUTE],
+        objtemplates: &ObjectTemplates,
+    ) -> KResult<Object> {
+        if self.info.flags & CKF_DERIVE != CKF_DERIVE {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        match check_key_object(key) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        let params = sshkdf_params(mech)?;
+        match hash::HashOperation::new(params.prfHashMechanism) {
+            Ok(_) => (),
+            Err(_) => return err_rv!(CKR_MECHANISM_PARAM_INVALID),
+        }
+
+        let mut obj = objtemplates.derive_key_from_template(key, template)?;
+        let keylen = match obj.get_attr_as_ulong(CKA_VALUE_LEN) {
+            Ok(n) => n as usize,
+            Err(e) => match e {
+                KError::NotFound(_) => return err_rv!(CKR_TEMPLATE_INCOMPLETE),
+                _ => return Err(e),
+            },
+        };
+        if keylen == 0 {
+            return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+        }
+        if obj.get_attr_as_ulong(CKA_KEY_TYPE)? == CKK_AES {
+            match keylen {
+                16 | 24 | 32 => (),
+                _ => return err_rv!(CKR_TEMPLATE_INCONSISTENT),
+            }
+        }
+        obj.del_attr(CKA_VALUE_LEN);
+
+        let exchange_hash = unsafe {
+            std::slice::from_raw_parts(
+                params.pExchangeHash,
+                params.ulExchangeHashLen as usize,
+            )
+        };
+        let session_id = unsafe {
+            std::slice::from_raw_parts(
+                params.pSessionId,
+                params.ulSessionIdLen as usize,
+            )
+        };
+        let value = sshkdf(
+            params.prfHashMechanism,
+            key.get_attr_as_bytes(CKA_VALUE)?.as_slice(),
+            exchange_hash,
+            params.derivedKeyType,
+            session_id,
+            keylen,
+        )?;
+        obj.set_attr(from_bytes(CKA_VALUE, value))?;
+        Ok(obj)
+    }
+}
+
+pub fn register(mechs: &mut Mechanisms, _: &mut ObjectTemplates) {
+    mechs.add_mechanism(
+        CKM_SSHKDF_DERIVE,
+        Box::new(SSHKDFMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_DERIVE,
+            },
+        }),
+    );
+}
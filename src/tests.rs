@@ -1505,6 +1505,396 @@ fn test_aes_operations() {
         assert_eq!(ret, CKR_DATA_LEN_RANGE);
     }
 
+    {
+        /* AES GCM */
+        let mut iv = "BA0987654321".as_bytes().to_vec();
+        let mut aad = "AUTH ME".as_bytes().to_vec();
+        let mut param = CK_GCM_PARAMS {
+            pIv: iv.as_mut_ptr(),
+            ulIvLen: iv.len() as CK_ULONG,
+            ulIvBits: (iv.len() * 8) as CK_ULONG,
+            pAAD: aad.as_mut_ptr(),
+            ulAADLen: aad.len() as CK_ULONG,
+            ulTagBits: 128,
+        };
+        let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+            mechanism: CKM_AES_GCM,
+            pParameter: &mut param as *mut CK_GCM_PARAMS as CK_VOID_PTR,
+            ulParameterLen: std::mem::size_of::<CK_GCM_PARAMS>() as CK_ULONG,
+        };
+
+        ret = fn_encrypt_init(session, &mut mechanism, handle);
+        assert_eq!(ret, CKR_OK);
+
+        /* the tag is appended to the ciphertext */
+        let data = "01234567";
+        let mut enc_len: CK_ULONG = 0;
+        ret = fn_encrypt(
+            session,
+            CString::new(data).unwrap().into_raw() as *mut u8,
+            data.len() as CK_ULONG,
+            std::ptr::null_mut(),
+            &mut enc_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(enc_len as usize, data.len() + 16);
+
+        let mut enc: [u8; 32] = [0; 32];
+        ret = fn_encrypt(
+            session,
+            CString::new(data).unwrap().into_raw() as *mut u8,
+            data.len() as CK_ULONG,
+            enc.as_mut_ptr(),
+            &mut enc_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(enc_len as usize, data.len() + 16);
+
+        ret = fn_decrypt_init(session, &mut mechanism, handle);
+        assert_eq!(ret, CKR_OK);
+
+        let dec: [u8; 32] = [0; 32];
+        let mut dec_len: CK_ULONG = 32;
+        ret = fn_decrypt(
+            session,
+            enc.as_mut_ptr(),
+            enc_len,
+            dec.as_ptr() as *mut _,
+            &mut dec_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(dec_len as usize, data.len());
+        assert_eq!(data.as_bytes(), &dec[..dec_len as usize]);
+
+        /* multi part */
+        ret = fn_encrypt_init(session, &mut mechanism, handle);
+        assert_eq!(ret, CKR_OK);
+
+        let mut menc: [u8; 32] = [0; 32];
+        let mut menc_len: CK_ULONG = 32;
+        ret = fn_encrypt_update(
+            session,
+            CString::new(&data[..3]).unwrap().into_raw() as *mut u8,
+            3,
+            menc.as_mut_ptr(),
+            &mut menc_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(menc_len, 3);
+        let mut offset = menc_len as usize;
+        menc_len = (menc.len() - offset) as CK_ULONG;
+        ret = fn_encrypt_update(
+            session,
+            CString::new(&data[3..]).unwrap().into_raw() as *mut u8,
+            (data.len() - 3) as CK_ULONG,
+            unsafe { menc.as_mut_ptr().add(offset) },
+            &mut menc_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        offset += menc_len as usize;
+        menc_len = 0;
+        ret = fn_encrypt_final(session, std::ptr::null_mut(), &mut menc_len);
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(menc_len, 16);
+        ret = fn_encrypt_final(
+            session,
+            unsafe { menc.as_mut_ptr().add(offset) },
+            &mut menc_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        offset += menc_len as usize;
+        assert_eq!(&enc[..enc_len as usize], &menc[..offset]);
+
+        ret = fn_decrypt_init(session, &mut mechanism, handle);
+        assert_eq!(ret, CKR_OK);
+
+        /* plaintext is withheld until the tag is verified */
+        let mdec: [u8; 32] = [0; 32];
+        let mut mdec_len: CK_ULONG = 32;
+        ret = fn_decrypt_update(
+            session,
+            menc.as_mut_ptr(),
+            offset as CK_ULONG,
+            mdec.as_ptr() as *mut _,
+            &mut mdec_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(mdec_len, 0);
+        mdec_len = 32;
+        ret = fn_decrypt_final(session, mdec.as_ptr() as *mut _, &mut mdec_len);
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(data.as_bytes(), &mdec[..mdec_len as usize]);
+
+        /* a corrupted tag must be detected */
+        enc[enc_len as usize - 1] ^= 0xff;
+        ret = fn_decrypt_init(session, &mut mechanism, handle);
+        assert_eq!(ret, CKR_OK);
+        dec_len = 32;
+        ret = fn_decrypt(
+            session,
+            enc.as_mut_ptr(),
+            enc_len,
+            dec.as_ptr() as *mut _,
+            &mut dec_len,
+        );
+        assert_eq!(ret, CKR_ENCRYPTED_DATA_INVALID);
+    }
+
+    {
+        /* AES CCM */
+        let data = "0123456789ABCDEF01234";
+        let mut nonce = "NONCE0123456".as_bytes().to_vec();
+        let mut aad = "AUTH ME".as_bytes().to_vec();
+        let mut param = CK_CCM_PARAMS {
+            ulDataLen: data.len() as CK_ULONG,
+            pNonce: nonce.as_mut_ptr(),
+            ulNonceLen: nonce.len() as CK_ULONG,
+            pAAD: aad.as_mut_ptr(),
+            ulAADLen: aad.len() as CK_ULONG,
+            ulMACLen: 12,
+        };
+        let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+            mechanism: CKM_AES_CCM,
+            pParameter: &mut param as *mut CK_CCM_PARAMS as CK_VOID_PTR,
+            ulParameterLen: std::mem::size_of::<CK_CCM_PARAMS>() as CK_ULONG,
+        };
+
+        ret = fn_encrypt_init(session, &mut mechanism, handle);
+        assert_eq!(ret, CKR_OK);
+
+        let mut enc_len: CK_ULONG = 0;
+        ret = fn_encrypt(
+            session,
+            CString::new(data).unwrap().into_raw() as *mut u8,
+            data.len() as CK_ULONG,
+            std::ptr::null_mut(),
+            &mut enc_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(enc_len as usize, data.len() + 12);
+
+        let mut enc: [u8; 48] = [0; 48];
+        ret = fn_encrypt(
+            session,
+            CString::new(data).unwrap().into_raw() as *mut u8,
+            data.len() as CK_ULONG,
+            enc.as_mut_ptr(),
+            &mut enc_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(enc_len as usize, data.len() + 12);
+
+        ret = fn_decrypt_init(session, &mut mechanism, handle);
+        assert_eq!(ret, CKR_OK);
+
+        let dec: [u8; 48] = [0; 48];
+        let mut dec_len: CK_ULONG = 48;
+        ret = fn_decrypt(
+            session,
+            enc.as_mut_ptr(),
+            enc_len,
+            dec.as_ptr() as *mut _,
+            &mut dec_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(dec_len as usize, data.len());
+        assert_eq!(data.as_bytes(), &dec[..dec_len as usize]);
+
+        /* multi part, output is returned once all data is available */
+        ret = fn_encrypt_init(session, &mut mechanism, handle);
+        assert_eq!(ret, CKR_OK);
+
+        let mut menc: [u8; 48] = [0; 48];
+        let mut menc_len: CK_ULONG = 48;
+        ret = fn_encrypt_update(
+            session,
+            CString::new(&data[..10]).unwrap().into_raw() as *mut u8,
+            10,
+            menc.as_mut_ptr(),
+            &mut menc_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(menc_len, 0);
+        menc_len = 48;
+        ret = fn_encrypt_update(
+            session,
+            CString::new(&data[10..]).unwrap().into_raw() as *mut u8,
+            (data.len() - 10) as CK_ULONG,
+            menc.as_mut_ptr(),
+            &mut menc_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(menc_len, 0);
+        menc_len = 0;
+        ret = fn_encrypt_final(session, std::ptr::null_mut(), &mut menc_len);
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(menc_len, enc_len);
+        ret = fn_encrypt_final(session, menc.as_mut_ptr(), &mut menc_len);
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(&enc[..enc_len as usize], &menc[..menc_len as usize]);
+
+        /* more data than declared in the parameters */
+        ret = fn_encrypt_init(session, &mut mechanism, handle);
+        assert_eq!(ret, CKR_OK);
+        menc_len = 48;
+        ret = fn_encrypt_update(
+            session,
+            CString::new(data).unwrap().into_raw() as *mut u8,
+            data.len() as CK_ULONG,
+            menc.as_mut_ptr(),
+            &mut menc_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        menc_len = 48;
+        ret = fn_encrypt_update(
+            session,
+            CString::new("X").unwrap().into_raw() as *mut u8,
+            1,
+            menc.as_mut_ptr(),
+            &mut menc_len,
+        );
+        assert_eq!(ret, CKR_DATA_LEN_RANGE);
+
+        /* less data than declared in the parameters */
+        ret = fn_encrypt_init(session, &mut mechanism, handle);
+        assert_eq!(ret, CKR_OK);
+        menc_len = 48;
+        ret = fn_encrypt(
+            session,
+            CString::new(&data[..10]).unwrap().into_raw() as *mut u8,
+            10,
+            menc.as_mut_ptr(),
+            &mut menc_len,
+        );
+        assert_eq!(ret, CKR_DATA_LEN_RANGE);
+
+        /* a corrupted ciphertext must be detected */
+        enc[0] ^= 0xff;
+        ret = fn_decrypt_init(session, &mut mechanism, handle);
+        assert_eq!(ret, CKR_OK);
+        dec_len = 48;
+        ret = fn_decrypt(
+            session,
+            enc.as_mut_ptr(),
+            enc_len,
+            dec.as_ptr() as *mut _,
+            &mut dec_len,
+        );
+        assert_eq!(ret, CKR_ENCRYPTED_DATA_INVALID);
+
+        /* the CCM parameters are mandatory */
+        mechanism.pParameter = std::ptr::null_mut();
+        ret = fn_encrypt_init(session, &mut mechanism, handle);
+        assert_eq!(ret, CKR_MECHANISM_PARAM_INVALID);
+    }
+
+    {
+        /* AES GCM message based encryption */
+        let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+            mechanism: CKM_AES_GCM,
+            pParameter: std::ptr::null_mut(),
+            ulParameterLen: 0,
+        };
+        ret = fn_message_encrypt_init(session, &mut mechanism, handle);
+        assert_eq!(ret, CKR_OK);
+
+        /* 4 fixed bytes followed by a generated counter */
+        let mut iv: [u8; 12] = [0xa5; 12];
+        let mut tag: [u8; 16] = [0; 16];
+        let mut param = CK_GCM_MESSAGE_PARAMS {
+            pIv: iv.as_mut_ptr(),
+            ulIvLen: iv.len() as CK_ULONG,
+            ulIvFixedBits: 32,
+            ivGenerator: CKG_GENERATE_COUNTER,
+            pTag: tag.as_mut_ptr(),
+            ulTagBits: 128,
+        };
+        let mut aad = "AUTH ME".as_bytes().to_vec();
+        let mut data = "01234567".as_bytes().to_vec();
+        let mut enc: [u8; 8] = [0; 8];
+        let mut enc_len: CK_ULONG = enc.len() as CK_ULONG;
+        for ctr in 0..2 {
+            ret = fn_encrypt_message(
+                session,
+                &mut param as *mut CK_GCM_MESSAGE_PARAMS as CK_VOID_PTR,
+                std::mem::size_of::<CK_GCM_MESSAGE_PARAMS>() as CK_ULONG,
+                aad.as_mut_ptr(),
+                aad.len() as CK_ULONG,
+                data.as_mut_ptr(),
+                data.len() as CK_ULONG,
+                enc.as_mut_ptr(),
+                &mut enc_len,
+            );
+            assert_eq!(ret, CKR_OK);
+            assert_eq!(enc_len as usize, data.len());
+            assert_eq!(&iv[..4], &[0xa5; 4]);
+            assert_eq!(&iv[4..], &[0, 0, 0, 0, 0, 0, 0, ctr]);
+        }
+
+        ret = fn_message_encrypt_final(session);
+        assert_eq!(ret, CKR_OK);
+
+        /* decrypt the last message in parts */
+        ret = fn_message_decrypt_init(session, &mut mechanism, handle);
+        assert_eq!(ret, CKR_OK);
+        param.ivGenerator = CKG_NO_GENERATE;
+        ret = fn_decrypt_message_begin(
+            session,
+            &mut param as *mut CK_GCM_MESSAGE_PARAMS as CK_VOID_PTR,
+            std::mem::size_of::<CK_GCM_MESSAGE_PARAMS>() as CK_ULONG,
+            aad.as_mut_ptr(),
+            aad.len() as CK_ULONG,
+        );
+        assert_eq!(ret, CKR_OK);
+        let mut dec: [u8; 8] = [0; 8];
+        let mut dec_len: CK_ULONG = dec.len() as CK_ULONG;
+        ret = fn_decrypt_message_next(
+            session,
+            &mut param as *mut CK_GCM_MESSAGE_PARAMS as CK_VOID_PTR,
+            std::mem::size_of::<CK_GCM_MESSAGE_PARAMS>() as CK_ULONG,
+            enc.as_mut_ptr(),
+            3,
+            dec.as_mut_ptr(),
+            &mut dec_len,
+            0,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(dec_len, 3);
+        let mut part_len: CK_ULONG = (dec.len() - 3) as CK_ULONG;
+        ret = fn_decrypt_message_next(
+            session,
+            &mut param as *mut CK_GCM_MESSAGE_PARAMS as CK_VOID_PTR,
+            std::mem::size_of::<CK_GCM_MESSAGE_PARAMS>() as CK_ULONG,
+            unsafe { enc.as_mut_ptr().add(3) },
+            (enc.len() - 3) as CK_ULONG,
+            unsafe { dec.as_mut_ptr().add(3) },
+            &mut part_len,
+            CKF_END_OF_MESSAGE,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(part_len as usize, enc.len() - 3);
+        assert_eq!(&dec, data.as_slice());
+
+        /* a corrupted tag must be detected */
+        tag[0] ^= 0xff;
+        dec_len = dec.len() as CK_ULONG;
+        ret = fn_decrypt_message(
+            session,
+            &mut param as *mut CK_GCM_MESSAGE_PARAMS as CK_VOID_PTR,
+            std::mem::size_of::<CK_GCM_MESSAGE_PARAMS>() as CK_ULONG,
+            aad.as_mut_ptr(),
+            aad.len() as CK_ULONG,
+            enc.as_mut_ptr(),
+            enc.len() as CK_ULONG,
+            dec.as_mut_ptr(),
+            &mut dec_len,
+        );
+        assert_eq!(ret, CKR_AEAD_DECRYPT_FAILED);
+
+        ret = fn_message_decrypt_final(session);
+        assert_eq!(ret, CKR_OK);
+    }
+
     ret = fn_close_session(session);
     assert_eq!(ret, CKR_OK);
 
@@ -2318,6 +2708,136 @@ fn test_signatures() {
         &mut mechanism,
     );
 
+    /* ### SHA256 HMAC, message based */
+    ret = fn_message_sign_init(session, &mut mechanism, key_handle);
+    assert_eq!(ret, CKR_OK);
+
+    let mut siglen: CK_ULONG = 0;
+    ret = fn_sign_message(
+        session,
+        std::ptr::null_mut(),
+        0,
+        testcase.value.as_mut_ptr(),
+        testcase.value.len() as CK_ULONG,
+        std::ptr::null_mut(),
+        &mut siglen,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(siglen, testcase.result.len() as CK_ULONG);
+
+    /* sign the same message multiple times under the same init */
+    for _ in 0..3 {
+        let mut signature = vec![0u8; siglen as usize];
+        ret = fn_sign_message(
+            session,
+            std::ptr::null_mut(),
+            0,
+            testcase.value.as_mut_ptr(),
+            testcase.value.len() as CK_ULONG,
+            signature.as_mut_ptr(),
+            &mut siglen,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(signature, testcase.result);
+    }
+
+    /* multi part message */
+    let half = testcase.value.len() / 2;
+    ret = fn_sign_message_begin(session, std::ptr::null_mut(), 0);
+    assert_eq!(ret, CKR_OK);
+    ret = fn_sign_message_begin(session, std::ptr::null_mut(), 0);
+    assert_eq!(ret, CKR_OPERATION_ACTIVE);
+    ret = fn_sign_message_next(
+        session,
+        std::ptr::null_mut(),
+        0,
+        testcase.value.as_mut_ptr(),
+        half as CK_ULONG,
+        std::ptr::null_mut(),
+        std::ptr::null_mut(),
+    );
+    assert_eq!(ret, CKR_OK);
+    let mut signature = vec![0u8; siglen as usize];
+    ret = fn_sign_message_next(
+        session,
+        std::ptr::null_mut(),
+        0,
+        testcase.value[half..].as_mut_ptr(),
+        (testcase.value.len() - half) as CK_ULONG,
+        signature.as_mut_ptr(),
+        &mut siglen,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(signature, testcase.result);
+
+    ret = fn_message_sign_final(session);
+    assert_eq!(ret, CKR_OK);
+    ret = fn_message_sign_final(session);
+    assert_eq!(ret, CKR_OPERATION_NOT_INITIALIZED);
+
+    ret = fn_message_verify_init(session, &mut mechanism, key_handle);
+    assert_eq!(ret, CKR_OK);
+    ret = fn_verify_message(
+        session,
+        std::ptr::null_mut(),
+        0,
+        testcase.value.as_mut_ptr(),
+        testcase.value.len() as CK_ULONG,
+        testcase.result.as_mut_ptr(),
+        testcase.result.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    ret = fn_verify_message_begin(session, std::ptr::null_mut(), 0);
+    assert_eq!(ret, CKR_OK);
+    ret = fn_verify_message_next(
+        session,
+        std::ptr::null_mut(),
+        0,
+        testcase.value.as_mut_ptr(),
+        half as CK_ULONG,
+        std::ptr::null_mut(),
+        0,
+    );
+    assert_eq!(ret, CKR_OK);
+    ret = fn_verify_message_next(
+        session,
+        std::ptr::null_mut(),
+        0,
+        testcase.value[half..].as_mut_ptr(),
+        (testcase.value.len() - half) as CK_ULONG,
+        testcase.result.as_mut_ptr(),
+        testcase.result.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* a bad signature fails only the current message */
+    let mut badsig = testcase.result.clone();
+    badsig[0] ^= 0xff;
+    ret = fn_verify_message(
+        session,
+        std::ptr::null_mut(),
+        0,
+        testcase.value.as_mut_ptr(),
+        testcase.value.len() as CK_ULONG,
+        badsig.as_mut_ptr(),
+        badsig.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_SIGNATURE_INVALID);
+    ret = fn_verify_message(
+        session,
+        std::ptr::null_mut(),
+        0,
+        testcase.value.as_mut_ptr(),
+        testcase.value.len() as CK_ULONG,
+        testcase.result.as_mut_ptr(),
+        testcase.result.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    ret = fn_message_verify_final(session);
+    assert_eq!(ret, CKR_OK);
+
     /* ### SHA384 HMAC */
 
     /* get test data */
@@ -2502,3 +3022,5868 @@ fn test_keygen() {
 
     testdata.finalize();
 }
+
+#[test]
+fn test_eddsa() {
+    let mut testdata = TestData::new("testdata/test_eddsa.json");
+    testdata.setup_db();
+
+    let mut args = testdata.make_init_args();
+    let args_ptr = &mut args as *mut CK_C_INITIALIZE_ARGS;
+    let mut ret = fn_initialize(args_ptr as *mut std::ffi::c_void);
+    assert_eq!(ret, CKR_OK);
+    let mut session: CK_SESSION_HANDLE = CK_UNAVAILABLE_INFORMATION;
+    ret = fn_open_session(
+        testdata.get_slot(),
+        CKF_SERIAL_SESSION | CKF_RW_SESSION,
+        std::ptr::null_mut(),
+        None,
+        &mut session,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* login */
+    let pin = "12345678";
+    ret = fn_login(
+        session,
+        CKU_USER,
+        pin.as_ptr() as *mut _,
+        pin.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* Test vector from RFC 8032, Section 7.1, TEST 2 */
+    let mut truebool = CK_TRUE;
+    let mut class = CKO_PRIVATE_KEY;
+    let mut ktype = CKK_EC_EDWARDS;
+    /* PrintableString("edwards25519") */
+    let ec_params = hex::decode("130c656477617264733235353139")
+        .expect("Failed to decode ec params");
+    let value = hex::decode(
+        "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+    )
+    .expect("Failed to decode private key");
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_SIGN, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(
+            CKA_EC_PARAMS,
+            ec_params.as_ptr() as *mut std::ffi::c_void,
+            ec_params.len()
+        ),
+        make_attribute!(
+            CKA_VALUE,
+            value.as_ptr() as *mut std::ffi::c_void,
+            value.len()
+        ),
+    ];
+    let mut prikey = CK_INVALID_HANDLE;
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut prikey,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* raw encoding of the public point */
+    class = CKO_PUBLIC_KEY;
+    let point = hex::decode(
+        "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+    )
+    .expect("Failed to decode public key");
+    template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_VERIFY, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(
+            CKA_EC_PARAMS,
+            ec_params.as_ptr() as *mut std::ffi::c_void,
+            ec_params.len()
+        ),
+        make_attribute!(
+            CKA_EC_POINT,
+            point.as_ptr() as *mut std::ffi::c_void,
+            point.len()
+        ),
+    ];
+    let mut pubkey = CK_INVALID_HANDLE;
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut pubkey,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_EDDSA,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let mut data = hex::decode("72").expect("Failed to decode data");
+    let mut signature = hex::decode("92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00").expect("Failed to decode signature");
+    sig_and_check(session, prikey, &mut data, &mut signature, &mut mechanism);
+    sig_verify(session, pubkey, &mut data, &mut signature, &mut mechanism);
+
+    /* a tampered signature must fail */
+    signature[0] ^= 0xff;
+    ret = fn_verify_init(session, &mut mechanism, pubkey);
+    assert_eq!(ret, CKR_OK);
+    ret = fn_verify(
+        session,
+        data.as_mut_ptr(),
+        data.len() as CK_ULONG,
+        signature.as_mut_ptr(),
+        signature.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_SIGNATURE_INVALID);
+
+    /* Ed25519ctx requires a non empty context */
+    let mut params = CK_EDDSA_PARAMS {
+        phFlag: CK_FALSE,
+        ulContextDataLen: 0,
+        pContextData: std::ptr::null_mut(),
+    };
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_EDDSA,
+        pParameter: &mut params as *mut CK_EDDSA_PARAMS as *mut _,
+        ulParameterLen: std::mem::size_of::<CK_EDDSA_PARAMS>() as CK_ULONG,
+    };
+    ret = fn_sign_init(session, &mut mechanism, prikey);
+    assert_eq!(ret, CKR_MECHANISM_PARAM_INVALID);
+
+    /* Ed448 key pair, EC params as DER encoded OID */
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_EC_EDWARDS_KEY_PAIR_GEN,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let ec_params =
+        hex::decode("06032b6571").expect("Failed to decode ec params");
+    let mut pub_template = vec![
+        make_attribute!(CKA_VERIFY, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(
+            CKA_EC_PARAMS,
+            ec_params.as_ptr() as *mut std::ffi::c_void,
+            ec_params.len()
+        ),
+    ];
+    let mut pri_template = vec![
+        make_attribute!(CKA_SENSITIVE, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_SIGN, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    ret = fn_generate_key_pair(
+        session,
+        &mut mechanism,
+        pub_template.as_mut_ptr(),
+        pub_template.len() as CK_ULONG,
+        pri_template.as_mut_ptr(),
+        pri_template.len() as CK_ULONG,
+        &mut pubkey,
+        &mut prikey,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* Ed448 with a context string */
+    let mut context = "kryoptic".as_bytes().to_vec();
+    let mut params = CK_EDDSA_PARAMS {
+        phFlag: CK_FALSE,
+        ulContextDataLen: context.len() as CK_ULONG,
+        pContextData: context.as_mut_ptr(),
+    };
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_EDDSA,
+        pParameter: &mut params as *mut CK_EDDSA_PARAMS as *mut _,
+        ulParameterLen: std::mem::size_of::<CK_EDDSA_PARAMS>() as CK_ULONG,
+    };
+    ret = fn_sign_init(session, &mut mechanism, prikey);
+    assert_eq!(ret, CKR_OK);
+    let mut data = "plaintext".as_bytes().to_vec();
+    let mut siglen: CK_ULONG = 0;
+    ret = fn_sign(
+        session,
+        data.as_mut_ptr(),
+        data.len() as CK_ULONG,
+        std::ptr::null_mut(),
+        &mut siglen,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(siglen, 114);
+    let mut signature: Vec<u8> = vec![0; siglen as usize];
+    ret = fn_sign(
+        session,
+        data.as_mut_ptr(),
+        data.len() as CK_ULONG,
+        signature.as_mut_ptr(),
+        &mut siglen,
+    );
+    assert_eq!(ret, CKR_OK);
+    sig_verify(session, pubkey, &mut data, &mut signature, &mut mechanism);
+
+    ret = fn_close_session(session);
+    assert_eq!(ret, CKR_OK);
+
+    testdata.finalize();
+}
+
+#[test]
+fn test_montgomery() {
+    let mut testdata = TestData::new("testdata/test_montgomery.json");
+    testdata.setup_db();
+
+    let mut args = testdata.make_init_args();
+    let args_ptr = &mut args as *mut CK_C_INITIALIZE_ARGS;
+    let mut ret = fn_initialize(args_ptr as *mut std::ffi::c_void);
+    assert_eq!(ret, CKR_OK);
+    let mut session: CK_SESSION_HANDLE = CK_UNAVAILABLE_INFORMATION;
+    ret = fn_open_session(
+        testdata.get_slot(),
+        CKF_SERIAL_SESSION | CKF_RW_SESSION,
+        std::ptr::null_mut(),
+        None,
+        &mut session,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* login */
+    let pin = "12345678";
+    ret = fn_login(
+        session,
+        CKU_USER,
+        pin.as_ptr() as *mut _,
+        pin.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* two X25519 key pairs, EC params as DER encoded OID */
+    let mut truebool = CK_TRUE;
+    let mut falsebool = CK_FALSE;
+    let ec_params =
+        hex::decode("06032b656e").expect("Failed to decode ec params");
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_EC_MONTGOMERY_KEY_PAIR_GEN,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let mut pubkeys = [CK_INVALID_HANDLE; 2];
+    let mut prikeys = [CK_INVALID_HANDLE; 2];
+    for i in 0..2 {
+        let mut pub_template = vec![make_attribute!(
+            CKA_EC_PARAMS,
+            ec_params.as_ptr() as *mut std::ffi::c_void,
+            ec_params.len()
+        )];
+        let mut pri_template = vec![
+            make_attribute!(
+                CKA_SENSITIVE,
+                &mut truebool as *mut _,
+                CK_BBOOL_SIZE
+            ),
+            make_attribute!(CKA_DERIVE, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        ];
+        ret = fn_generate_key_pair(
+            session,
+            &mut mechanism,
+            pub_template.as_mut_ptr(),
+            pub_template.len() as CK_ULONG,
+            pri_template.as_mut_ptr(),
+            pri_template.len() as CK_ULONG,
+            &mut pubkeys[i],
+            &mut prikeys[i],
+        );
+        assert_eq!(ret, CKR_OK);
+    }
+
+    /* fetch the public points */
+    let mut points: Vec<Vec<u8>> = Vec::new();
+    for i in 0..2 {
+        let mut point = vec![0u8; 64];
+        let mut template = vec![make_attribute!(
+            CKA_EC_POINT,
+            point.as_mut_ptr() as *mut std::ffi::c_void,
+            point.len()
+        )];
+        ret = fn_get_attribute_value(
+            session,
+            pubkeys[i],
+            template.as_mut_ptr(),
+            1,
+        );
+        assert_eq!(ret, CKR_OK);
+        /* DER encoded OCTET STRING */
+        assert_eq!(template[0].ulValueLen, 34);
+        point.resize(template[0].ulValueLen as usize, 0);
+        points.push(point);
+    }
+
+    /* derive the shared secret on both sides */
+    let mut class = CKO_SECRET_KEY;
+    let mut ktype = CKK_GENERIC_SECRET;
+    let mut values: Vec<Vec<u8>> = Vec::new();
+    for i in 0..2 {
+        let mut params = CK_ECDH1_DERIVE_PARAMS {
+            kdf: CKD_NULL,
+            ulSharedDataLen: 0,
+            pSharedData: std::ptr::null_mut(),
+            ulPublicDataLen: points[1 - i].len() as CK_ULONG,
+            pPublicData: points[1 - i].as_mut_ptr(),
+        };
+        let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+            mechanism: CKM_ECDH1_DERIVE,
+            pParameter: &mut params as *mut CK_ECDH1_DERIVE_PARAMS as *mut _,
+            ulParameterLen: std::mem::size_of::<CK_ECDH1_DERIVE_PARAMS>()
+                as CK_ULONG,
+        };
+        let mut template = vec![
+            make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+            make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+            make_attribute!(
+                CKA_EXTRACTABLE,
+                &mut truebool as *mut _,
+                CK_BBOOL_SIZE
+            ),
+            make_attribute!(
+                CKA_SENSITIVE,
+                &mut falsebool as *mut _,
+                CK_BBOOL_SIZE
+            ),
+        ];
+        let mut handle = CK_INVALID_HANDLE;
+        ret = fn_derive_key(
+            session,
+            &mut mechanism,
+            prikeys[i],
+            template.as_mut_ptr(),
+            template.len() as CK_ULONG,
+            &mut handle,
+        );
+        assert_eq!(ret, CKR_OK);
+
+        let mut value = vec![0u8; 32];
+        let mut template = vec![make_attribute!(
+            CKA_VALUE,
+            value.as_mut_ptr() as *mut std::ffi::c_void,
+            value.len()
+        )];
+        ret = fn_get_attribute_value(session, handle, template.as_mut_ptr(), 1);
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(template[0].ulValueLen, 32);
+        values.push(value);
+    }
+    assert_eq!(values[0], values[1]);
+
+    /* AES keys must have a valid length */
+    let mut params = CK_ECDH1_DERIVE_PARAMS {
+        kdf: CKD_NULL,
+        ulSharedDataLen: 0,
+        pSharedData: std::ptr::null_mut(),
+        ulPublicDataLen: points[1].len() as CK_ULONG,
+        pPublicData: points[1].as_mut_ptr(),
+    };
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_ECDH1_DERIVE,
+        pParameter: &mut params as *mut CK_ECDH1_DERIVE_PARAMS as *mut _,
+        ulParameterLen: std::mem::size_of::<CK_ECDH1_DERIVE_PARAMS>()
+            as CK_ULONG,
+    };
+    let mut aestype = CKK_AES;
+    let mut keylen: CK_ULONG = 20;
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut aestype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_VALUE_LEN, &mut keylen as *mut _, CK_ULONG_SIZE),
+    ];
+    let mut handle = CK_INVALID_HANDLE;
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        prikeys[0],
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_TEMPLATE_INCONSISTENT);
+    keylen = 16;
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        prikeys[0],
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    ret = fn_derive_key(
+        session,
+        std::ptr::null_mut(),
+        prikeys[0],
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_ARGUMENTS_BAD);
+
+    /* the derived key handle can't be discarded */
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        prikeys[0],
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        std::ptr::null_mut(),
+    );
+    assert_eq!(ret, CKR_ARGUMENTS_BAD);
+
+    /* low order points must be rejected */
+    let mut zero_point = vec![0u8; 32];
+    let mut params = CK_ECDH1_DERIVE_PARAMS {
+        kdf: CKD_NULL,
+        ulSharedDataLen: 0,
+        pSharedData: std::ptr::null_mut(),
+        ulPublicDataLen: zero_point.len() as CK_ULONG,
+        pPublicData: zero_point.as_mut_ptr(),
+    };
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_ECDH1_DERIVE,
+        pParameter: &mut params as *mut CK_ECDH1_DERIVE_PARAMS as *mut _,
+        ulParameterLen: std::mem::size_of::<CK_ECDH1_DERIVE_PARAMS>()
+            as CK_ULONG,
+    };
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+    ];
+    let mut handle = CK_INVALID_HANDLE;
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        prikeys[0],
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_MECHANISM_PARAM_INVALID);
+
+    let mut pubclass = CKO_PUBLIC_KEY;
+    let mut pubktype = CKK_EC_MONTGOMERY;
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut pubclass as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut pubktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_EC_PARAMS,
+            ec_params.as_ptr() as *mut std::ffi::c_void,
+            ec_params.len()
+        ),
+        make_attribute!(
+            CKA_EC_POINT,
+            zero_point.as_mut_ptr() as *mut std::ffi::c_void,
+            zero_point.len()
+        ),
+    ];
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_ATTRIBUTE_VALUE_INVALID);
+
+    ret = fn_close_session(session);
+    assert_eq!(ret, CKR_OK);
+
+    testdata.finalize();
+}
+
+#[test]
+fn test_mlkem() {
+    let mut testdata = TestData::new("testdata/test_mlkem.json");
+    testdata.setup_db();
+
+    let mut args = testdata.make_init_args();
+    let args_ptr = &mut args as *mut CK_C_INITIALIZE_ARGS;
+    let mut ret = fn_initialize(args_ptr as *mut std::ffi::c_void);
+    assert_eq!(ret, CKR_OK);
+    let mut session: CK_SESSION_HANDLE = CK_UNAVAILABLE_INFORMATION;
+    ret = fn_open_session(
+        testdata.get_slot(),
+        CKF_SERIAL_SESSION | CKF_RW_SESSION,
+        std::ptr::null_mut(),
+        None,
+        &mut session,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* login */
+    let pin = "12345678";
+    ret = fn_login(
+        session,
+        CKU_USER,
+        pin.as_ptr() as *mut _,
+        pin.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut truebool = CK_TRUE;
+    let mut falsebool = CK_FALSE;
+    let mut class = CKO_SECRET_KEY;
+    let mut ktype = CKK_GENERIC_SECRET;
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_ML_KEM_KEY_PAIR_GEN,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let mut kem: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_ML_KEM,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+
+    /* the parameter set is required */
+    let mut pub_template = vec![make_attribute!(
+        CKA_ENCAPSULATE,
+        &mut truebool as *mut _,
+        CK_BBOOL_SIZE
+    )];
+    let mut pri_template = vec![make_attribute!(
+        CKA_DECAPSULATE,
+        &mut truebool as *mut _,
+        CK_BBOOL_SIZE
+    )];
+    let mut pubkey = CK_INVALID_HANDLE;
+    let mut prikey = CK_INVALID_HANDLE;
+    ret = fn_generate_key_pair(
+        session,
+        &mut mechanism,
+        pub_template.as_mut_ptr(),
+        pub_template.len() as CK_ULONG,
+        pri_template.as_mut_ptr(),
+        pri_template.len() as CK_ULONG,
+        &mut pubkey,
+        &mut prikey,
+    );
+    assert_eq!(ret, CKR_TEMPLATE_INCOMPLETE);
+
+    for (set, ek_len, ct_len) in [
+        (CKP_ML_KEM_512, 800, 768),
+        (CKP_ML_KEM_768, 1184, 1088),
+        (CKP_ML_KEM_1024, 1568, 1568),
+    ] {
+        let mut param_set = set;
+        let mut pub_template = vec![
+            make_attribute!(
+                CKA_PARAMETER_SET,
+                &mut param_set as *mut _,
+                CK_ULONG_SIZE
+            ),
+            make_attribute!(
+                CKA_ENCAPSULATE,
+                &mut truebool as *mut _,
+                CK_BBOOL_SIZE
+            ),
+        ];
+        let mut pri_template = vec![
+            make_attribute!(
+                CKA_SENSITIVE,
+                &mut truebool as *mut _,
+                CK_BBOOL_SIZE
+            ),
+            make_attribute!(
+                CKA_DECAPSULATE,
+                &mut truebool as *mut _,
+                CK_BBOOL_SIZE
+            ),
+        ];
+        ret = fn_generate_key_pair(
+            session,
+            &mut mechanism,
+            pub_template.as_mut_ptr(),
+            pub_template.len() as CK_ULONG,
+            pri_template.as_mut_ptr(),
+            pri_template.len() as CK_ULONG,
+            &mut pubkey,
+            &mut prikey,
+        );
+        assert_eq!(ret, CKR_OK);
+
+        let mut value = vec![0u8; 2048];
+        let mut template = vec![make_attribute!(
+            CKA_VALUE,
+            value.as_mut_ptr() as *mut std::ffi::c_void,
+            value.len()
+        )];
+        ret = fn_get_attribute_value(session, pubkey, template.as_mut_ptr(), 1);
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(template[0].ulValueLen, ek_len);
+
+        /* the private key parameter set is copied from the public key */
+        let mut pri_set: CK_ULONG = 0;
+        let mut template = vec![make_attribute!(
+            CKA_PARAMETER_SET,
+            &mut pri_set as *mut _,
+            CK_ULONG_SIZE
+        )];
+        ret = fn_get_attribute_value(session, prikey, template.as_mut_ptr(), 1);
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(pri_set, set);
+
+        let mut secret_template = vec![
+            make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+            make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+            make_attribute!(
+                CKA_EXTRACTABLE,
+                &mut truebool as *mut _,
+                CK_BBOOL_SIZE
+            ),
+            make_attribute!(
+                CKA_SENSITIVE,
+                &mut falsebool as *mut _,
+                CK_BBOOL_SIZE
+            ),
+        ];
+
+        /* a mechanism is required */
+        let mut ciphertext_len: CK_ULONG = 0;
+        let mut handle = CK_INVALID_HANDLE;
+        ret = C_EncapsulateKey(
+            session,
+            std::ptr::null_mut(),
+            pubkey,
+            secret_template.as_mut_ptr(),
+            secret_template.len() as CK_ULONG,
+            std::ptr::null_mut(),
+            &mut ciphertext_len,
+            &mut handle,
+        );
+        assert_eq!(ret, CKR_ARGUMENTS_BAD);
+
+        /* size query */
+        ret = C_EncapsulateKey(
+            session,
+            &mut kem,
+            pubkey,
+            secret_template.as_mut_ptr(),
+            secret_template.len() as CK_ULONG,
+            std::ptr::null_mut(),
+            &mut ciphertext_len,
+            &mut handle,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(ciphertext_len, ct_len);
+
+        let mut ciphertext = vec![0u8; ciphertext_len as usize];
+        ret = C_EncapsulateKey(
+            session,
+            &mut kem,
+            pubkey,
+            secret_template.as_mut_ptr(),
+            secret_template.len() as CK_ULONG,
+            ciphertext.as_mut_ptr(),
+            &mut ciphertext_len,
+            &mut handle,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(ciphertext_len, ct_len);
+
+        let mut handles = [handle, CK_INVALID_HANDLE, CK_INVALID_HANDLE];
+        ret = C_DecapsulateKey(
+            session,
+            std::ptr::null_mut(),
+            prikey,
+            secret_template.as_mut_ptr(),
+            secret_template.len() as CK_ULONG,
+            ciphertext.as_mut_ptr(),
+            ciphertext_len,
+            &mut handles[1],
+        );
+        assert_eq!(ret, CKR_ARGUMENTS_BAD);
+        ret = C_DecapsulateKey(
+            session,
+            &mut kem,
+            prikey,
+            secret_template.as_mut_ptr(),
+            secret_template.len() as CK_ULONG,
+            ciphertext.as_mut_ptr(),
+            ciphertext_len,
+            &mut handles[1],
+        );
+        assert_eq!(ret, CKR_OK);
+
+        /* a modified ciphertext is implicitly rejected */
+        ciphertext[0] ^= 0x01;
+        ret = C_DecapsulateKey(
+            session,
+            &mut kem,
+            prikey,
+            secret_template.as_mut_ptr(),
+            secret_template.len() as CK_ULONG,
+            ciphertext.as_mut_ptr(),
+            ciphertext_len,
+            &mut handles[2],
+        );
+        assert_eq!(ret, CKR_OK);
+
+        let mut values: Vec<Vec<u8>> = Vec::new();
+        for h in handles {
+            let mut value = vec![0u8; 32];
+            let mut template = vec![make_attribute!(
+                CKA_VALUE,
+                value.as_mut_ptr() as *mut std::ffi::c_void,
+                value.len()
+            )];
+            ret = fn_get_attribute_value(session, h, template.as_mut_ptr(), 1);
+            assert_eq!(ret, CKR_OK);
+            assert_eq!(template[0].ulValueLen, 32);
+            values.push(value);
+        }
+        assert_eq!(values[0], values[1]);
+        assert_ne!(values[0], values[2]);
+
+        ret = C_DecapsulateKey(
+            session,
+            &mut kem,
+            prikey,
+            secret_template.as_mut_ptr(),
+            secret_template.len() as CK_ULONG,
+            ciphertext.as_mut_ptr(),
+            ciphertext_len - 1,
+            &mut handle,
+        );
+        assert_eq!(ret, CKR_WRAPPED_KEY_LEN_RANGE);
+    }
+
+    /* keys without CKA_ENCAPSULATE can't be used */
+    let mut param_set = CKP_ML_KEM_768;
+    let mut pub_template = vec![make_attribute!(
+        CKA_PARAMETER_SET,
+        &mut param_set as *mut _,
+        CK_ULONG_SIZE
+    )];
+    let mut pri_template = vec![make_attribute!(
+        CKA_DECAPSULATE,
+        &mut truebool as *mut _,
+        CK_BBOOL_SIZE
+    )];
+    ret = fn_generate_key_pair(
+        session,
+        &mut mechanism,
+        pub_template.as_mut_ptr(),
+        pub_template.len() as CK_ULONG,
+        pri_template.as_mut_ptr(),
+        pri_template.len() as CK_ULONG,
+        &mut pubkey,
+        &mut prikey,
+    );
+    assert_eq!(ret, CKR_OK);
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+    ];
+    let mut ciphertext = vec![0u8; 1088];
+    let mut ciphertext_len = ciphertext.len() as CK_ULONG;
+    let mut handle = CK_INVALID_HANDLE;
+    ret = C_EncapsulateKey(
+        session,
+        &mut kem,
+        pubkey,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        ciphertext.as_mut_ptr(),
+        &mut ciphertext_len,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_KEY_FUNCTION_NOT_PERMITTED);
+
+    ret = fn_close_session(session);
+    assert_eq!(ret, CKR_OK);
+
+    testdata.finalize();
+}
+
+#[test]
+fn test_mldsa() {
+    let mut testdata = TestData::new("testdata/test_mldsa.json");
+    testdata.setup_db();
+
+    let mut args = testdata.make_init_args();
+    let args_ptr = &mut args as *mut CK_C_INITIALIZE_ARGS;
+    let mut ret = fn_initialize(args_ptr as *mut std::ffi::c_void);
+    assert_eq!(ret, CKR_OK);
+    let mut session: CK_SESSION_HANDLE = CK_UNAVAILABLE_INFORMATION;
+    ret = fn_open_session(
+        testdata.get_slot(),
+        CKF_SERIAL_SESSION | CKF_RW_SESSION,
+        std::ptr::null_mut(),
+        None,
+        &mut session,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* login */
+    let pin = "12345678";
+    ret = fn_login(
+        session,
+        CKU_USER,
+        pin.as_ptr() as *mut _,
+        pin.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut truebool = CK_TRUE;
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_ML_DSA_KEY_PAIR_GEN,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let mut pubkey = CK_INVALID_HANDLE;
+    let mut prikey = CK_INVALID_HANDLE;
+
+    for (set, pk_len, sig_len) in [
+        (CKP_ML_DSA_44, 1312, 2420),
+        (CKP_ML_DSA_65, 1952, 3309),
+        (CKP_ML_DSA_87, 2592, 4627),
+    ] {
+        let mut param_set = set;
+        let mut pub_template = vec![
+            make_attribute!(
+                CKA_PARAMETER_SET,
+                &mut param_set as *mut _,
+                CK_ULONG_SIZE
+            ),
+            make_attribute!(CKA_VERIFY, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        ];
+        let mut pri_template = vec![
+            make_attribute!(
+                CKA_SENSITIVE,
+                &mut truebool as *mut _,
+                CK_BBOOL_SIZE
+            ),
+            make_attribute!(CKA_SIGN, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        ];
+        ret = fn_generate_key_pair(
+            session,
+            &mut mechanism,
+            pub_template.as_mut_ptr(),
+            pub_template.len() as CK_ULONG,
+            pri_template.as_mut_ptr(),
+            pri_template.len() as CK_ULONG,
+            &mut pubkey,
+            &mut prikey,
+        );
+        assert_eq!(ret, CKR_OK);
+
+        let mut value = vec![0u8; 4096];
+        let mut template = vec![make_attribute!(
+            CKA_VALUE,
+            value.as_mut_ptr() as *mut std::ffi::c_void,
+            value.len()
+        )];
+        ret = fn_get_attribute_value(session, pubkey, template.as_mut_ptr(), 1);
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(template[0].ulValueLen, pk_len);
+
+        /* pure ML-DSA with a context string */
+        let mut context = "kryoptic".as_bytes().to_vec();
+        let mut params = CK_SIGN_ADDITIONAL_CONTEXT {
+            hedgeVariant: CKH_HEDGE_PREFERRED,
+            pContext: context.as_mut_ptr(),
+            ulContextLen: context.len() as CK_ULONG,
+        };
+        let mut sign_mech: CK_MECHANISM = CK_MECHANISM {
+            mechanism: CKM_ML_DSA,
+            pParameter: &mut params as *mut CK_SIGN_ADDITIONAL_CONTEXT
+                as *mut _,
+            ulParameterLen: std::mem::size_of::<CK_SIGN_ADDITIONAL_CONTEXT>()
+                as CK_ULONG,
+        };
+        ret = fn_sign_init(session, &mut sign_mech, prikey);
+        assert_eq!(ret, CKR_OK);
+        let mut data = "plaintext".as_bytes().to_vec();
+        let mut siglen: CK_ULONG = 0;
+        ret = fn_sign(
+            session,
+            data.as_mut_ptr(),
+            data.len() as CK_ULONG,
+            std::ptr::null_mut(),
+            &mut siglen,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(siglen, sig_len);
+        let mut signature: Vec<u8> = vec![0; siglen as usize];
+        ret = fn_sign(
+            session,
+            data.as_mut_ptr(),
+            data.len() as CK_ULONG,
+            signature.as_mut_ptr(),
+            &mut siglen,
+        );
+        assert_eq!(ret, CKR_OK);
+        sig_verify(session, pubkey, &mut data, &mut signature, &mut sign_mech);
+
+        /* the context is bound to the signature */
+        let mut other = "other".as_bytes().to_vec();
+        params.pContext = other.as_mut_ptr();
+        params.ulContextLen = other.len() as CK_ULONG;
+        ret = fn_verify_init(session, &mut sign_mech, pubkey);
+        assert_eq!(ret, CKR_OK);
+        ret = fn_verify(
+            session,
+            data.as_mut_ptr(),
+            data.len() as CK_ULONG,
+            signature.as_mut_ptr(),
+            signature.len() as CK_ULONG,
+        );
+        assert_eq!(ret, CKR_SIGNATURE_INVALID);
+
+        /* deterministic signatures are reproducible */
+        params.hedgeVariant = CKH_DETERMINISTIC_REQUIRED;
+        params.pContext = context.as_mut_ptr();
+        params.ulContextLen = context.len() as CK_ULONG;
+        let mut signature = vec![0u8; sig_len as usize];
+        ret = fn_sign_init(session, &mut sign_mech, prikey);
+        assert_eq!(ret, CKR_OK);
+        ret = fn_sign(
+            session,
+            data.as_mut_ptr(),
+            data.len() as CK_ULONG,
+            signature.as_mut_ptr(),
+            &mut siglen,
+        );
+        assert_eq!(ret, CKR_OK);
+        sig_and_check(
+            session,
+            prikey,
+            &mut data,
+            &mut signature,
+            &mut sign_mech,
+        );
+        sig_verify(session, pubkey, &mut data, &mut signature, &mut sign_mech);
+    }
+
+    /* an oversized context is rejected */
+    let mut context = vec![0u8; 256];
+    let mut params = CK_SIGN_ADDITIONAL_CONTEXT {
+        hedgeVariant: CKH_HEDGE_PREFERRED,
+        pContext: context.as_mut_ptr(),
+        ulContextLen: context.len() as CK_ULONG,
+    };
+    let mut sign_mech: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_ML_DSA,
+        pParameter: &mut params as *mut CK_SIGN_ADDITIONAL_CONTEXT as *mut _,
+        ulParameterLen: std::mem::size_of::<CK_SIGN_ADDITIONAL_CONTEXT>()
+            as CK_ULONG,
+    };
+    ret = fn_sign_init(session, &mut sign_mech, prikey);
+    assert_eq!(ret, CKR_MECHANISM_PARAM_INVALID);
+
+    /* HashML-DSA, the token hashes the data */
+    let mut sign_mech: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_HASH_ML_DSA_SHA256,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let mut data = "plaintext".as_bytes().to_vec();
+    ret = fn_sign_init(session, &mut sign_mech, prikey);
+    assert_eq!(ret, CKR_OK);
+    let mut siglen: CK_ULONG = 4627;
+    let mut signature = vec![0u8; siglen as usize];
+    ret = fn_sign(
+        session,
+        data.as_mut_ptr(),
+        data.len() as CK_ULONG,
+        signature.as_mut_ptr(),
+        &mut siglen,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* the same signature verifies over the precomputed digest */
+    let mut params = CK_HASH_SIGN_ADDITIONAL_CONTEXT {
+        hedgeVariant: CKH_HEDGE_PREFERRED,
+        pContext: std::ptr::null_mut(),
+        ulContextLen: 0,
+        hash: CKM_SHA256,
+    };
+    let mut hash_mech: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_HASH_ML_DSA,
+        pParameter: &mut params as *mut CK_HASH_SIGN_ADDITIONAL_CONTEXT
+            as *mut _,
+        ulParameterLen: std::mem::size_of::<CK_HASH_SIGN_ADDITIONAL_CONTEXT>()
+            as CK_ULONG,
+    };
+    let mut digest = vec![0u8; 32];
+    let mut hash_op: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_SHA256,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    ret = fn_digest_init(session, &mut hash_op);
+    assert_eq!(ret, CKR_OK);
+    let mut digest_len = digest.len() as CK_ULONG;
+    ret = fn_digest(
+        session,
+        data.as_mut_ptr(),
+        data.len() as CK_ULONG,
+        digest.as_mut_ptr(),
+        &mut digest_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    sig_verify(session, pubkey, &mut digest, &mut signature, &mut hash_mech);
+
+    /* the input must be a digest of the expected size */
+    ret = fn_verify_init(session, &mut hash_mech, pubkey);
+    assert_eq!(ret, CKR_OK);
+    ret = fn_verify(
+        session,
+        data.as_mut_ptr(),
+        data.len() as CK_ULONG,
+        signature.as_mut_ptr(),
+        signature.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_DATA_LEN_RANGE);
+
+    /* PKCS#8 round trip of an ML-DSA-44 key */
+    let mut class = CKO_SECRET_KEY;
+    let mut aestype = CKK_AES;
+    let kek = hex::decode("000102030405060708090a0b0c0d0e0f")
+        .expect("Failed to decode kek");
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut aestype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            kek.as_ptr() as *mut std::ffi::c_void,
+            kek.len()
+        ),
+        make_attribute!(CKA_DECRYPT, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_WRAP, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_UNWRAP, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut kek_handle = CK_INVALID_HANDLE;
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut kek_handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut param_set = CKP_ML_DSA_44;
+    let mut pub_template = vec![
+        make_attribute!(
+            CKA_PARAMETER_SET,
+            &mut param_set as *mut _,
+            CK_ULONG_SIZE
+        ),
+        make_attribute!(CKA_VERIFY, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut pri_template = vec![
+        make_attribute!(CKA_SIGN, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(
+            CKA_EXTRACTABLE,
+            &mut truebool as *mut _,
+            CK_BBOOL_SIZE
+        ),
+    ];
+    ret = fn_generate_key_pair(
+        session,
+        &mut mechanism,
+        pub_template.as_mut_ptr(),
+        pub_template.len() as CK_ULONG,
+        pri_template.as_mut_ptr(),
+        pri_template.len() as CK_ULONG,
+        &mut pubkey,
+        &mut prikey,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut iv = [0u8; 16];
+    let mut wrap_mech: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_AES_CBC_PAD,
+        pParameter: iv.as_mut_ptr() as CK_VOID_PTR,
+        ulParameterLen: iv.len() as CK_ULONG,
+    };
+    let mut wrapped = vec![0u8; 4096];
+    let mut wrapped_len = wrapped.len() as CK_ULONG;
+    ret = fn_wrap_key(
+        session,
+        std::ptr::null_mut(),
+        kek_handle,
+        prikey,
+        wrapped.as_mut_ptr(),
+        &mut wrapped_len,
+    );
+    assert_eq!(ret, CKR_ARGUMENTS_BAD);
+    ret = fn_wrap_key(
+        session,
+        &mut wrap_mech,
+        kek_handle,
+        prikey,
+        wrapped.as_mut_ptr(),
+        &mut wrapped_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    wrapped.resize(wrapped_len as usize, 0);
+
+    ret = fn_decrypt_init(session, &mut wrap_mech, kek_handle);
+    assert_eq!(ret, CKR_OK);
+    let mut pkcs8 = vec![0u8; wrapped.len()];
+    let mut pkcs8_len = pkcs8.len() as CK_ULONG;
+    ret = fn_decrypt(
+        session,
+        wrapped.as_mut_ptr(),
+        wrapped.len() as CK_ULONG,
+        pkcs8.as_mut_ptr(),
+        &mut pkcs8_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(pkcs8_len, 2588);
+    let prefix = hex::decode(
+        "30820a18020100300b0609608648016503040311\
+         04820a0404820a00",
+    )
+    .expect("Failed to decode PKCS#8 prefix");
+    assert_eq!(pkcs8[..prefix.len()], prefix[..]);
+
+    /* the key type and parameter set come from the AlgorithmIdentifier */
+    class = CKO_PRIVATE_KEY;
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_SIGN, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut unwrapped = CK_INVALID_HANDLE;
+    ret = fn_unwrap_key(
+        session,
+        &mut wrap_mech,
+        kek_handle,
+        wrapped.as_mut_ptr(),
+        wrapped.len() as CK_ULONG,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut unwrapped,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut unwrapped_set: CK_ULONG = 0;
+    let mut template = vec![make_attribute!(
+        CKA_PARAMETER_SET,
+        &mut unwrapped_set as *mut _,
+        CK_ULONG_SIZE
+    )];
+    ret = fn_get_attribute_value(session, unwrapped, template.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(unwrapped_set, CKP_ML_DSA_44);
+
+    let mut sign_mech: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_ML_DSA,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let mut data = "plaintext".as_bytes().to_vec();
+    ret = fn_sign_init(session, &mut sign_mech, unwrapped);
+    assert_eq!(ret, CKR_OK);
+    let mut siglen: CK_ULONG = 2420;
+    let mut signature = vec![0u8; siglen as usize];
+    ret = fn_sign(
+        session,
+        data.as_mut_ptr(),
+        data.len() as CK_ULONG,
+        signature.as_mut_ptr(),
+        &mut siglen,
+    );
+    assert_eq!(ret, CKR_OK);
+    sig_verify(session, pubkey, &mut data, &mut signature, &mut sign_mech);
+
+    ret = fn_close_session(session);
+    assert_eq!(ret, CKR_OK);
+
+    testdata.finalize();
+}
+
+#[test]
+fn test_slhdsa() {
+    let mut testdata = TestData::new("testdata/test_slhdsa.json");
+    testdata.setup_db();
+
+    let mut args = testdata.make_init_args();
+    let args_ptr = &mut args as *mut CK_C_INITIALIZE_ARGS;
+    let mut ret = fn_initialize(args_ptr as *mut std::ffi::c_void);
+    assert_eq!(ret, CKR_OK);
+    let mut session: CK_SESSION_HANDLE = CK_UNAVAILABLE_INFORMATION;
+    ret = fn_open_session(
+        testdata.get_slot(),
+        CKF_SERIAL_SESSION | CKF_RW_SESSION,
+        std::ptr::null_mut(),
+        None,
+        &mut session,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* login */
+    let pin = "12345678";
+    ret = fn_login(
+        session,
+        CKU_USER,
+        pin.as_ptr() as *mut _,
+        pin.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut truebool = CK_TRUE;
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_SLH_DSA_KEY_PAIR_GEN,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let mut pubkey = CK_INVALID_HANDLE;
+    let mut prikey = CK_INVALID_HANDLE;
+
+    /* key generation from a seed is deterministic, and the public key
+     * starts with PK.seed */
+    for (set, n) in [
+        (CKP_SLH_DSA_SHA2_128S, 16),
+        (CKP_SLH_DSA_SHAKE_128S, 16),
+        (CKP_SLH_DSA_SHA2_128F, 16),
+        (CKP_SLH_DSA_SHAKE_128F, 16),
+        (CKP_SLH_DSA_SHA2_192S, 24),
+        (CKP_SLH_DSA_SHAKE_192S, 24),
+        (CKP_SLH_DSA_SHA2_192F, 24),
+        (CKP_SLH_DSA_SHAKE_192F, 24),
+        (CKP_SLH_DSA_SHA2_256S, 32),
+        (CKP_SLH_DSA_SHAKE_256S, 32),
+        (CKP_SLH_DSA_SHA2_256F, 32),
+        (CKP_SLH_DSA_SHAKE_256F, 32),
+    ] {
+        let mut param_set = set;
+        let mut seed: Vec<u8> = (0..(3 * n) as u8).collect();
+        let mut pub_template = vec![
+            make_attribute!(
+                CKA_PARAMETER_SET,
+                &mut param_set as *mut _,
+                CK_ULONG_SIZE
+            ),
+            make_attribute!(CKA_VERIFY, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        ];
+        let mut pri_template = vec![
+            make_attribute!(CKA_SIGN, &mut truebool as *mut _, CK_BBOOL_SIZE),
+            make_attribute!(
+                CKA_SEED,
+                seed.as_mut_ptr() as *mut std::ffi::c_void,
+                seed.len()
+            ),
+        ];
+        let mut values: Vec<Vec<u8>> = Vec::new();
+        for _ in 0..2 {
+            ret = fn_generate_key_pair(
+                session,
+                &mut mechanism,
+                pub_template.as_mut_ptr(),
+                pub_template.len() as CK_ULONG,
+                pri_template.as_mut_ptr(),
+                pri_template.len() as CK_ULONG,
+                &mut pubkey,
+                &mut prikey,
+            );
+            assert_eq!(ret, CKR_OK);
+
+            let mut value = vec![0u8; 64];
+            let mut template = vec![make_attribute!(
+                CKA_VALUE,
+                value.as_mut_ptr() as *mut std::ffi::c_void,
+                value.len()
+            )];
+            ret = fn_get_attribute_value(
+                session,
+                pubkey,
+                template.as_mut_ptr(),
+                1,
+            );
+            assert_eq!(ret, CKR_OK);
+            assert_eq!(template[0].ulValueLen as usize, 2 * n);
+            value.resize(2 * n, 0);
+            assert_eq!(value[..n], seed[2 * n..]);
+            values.push(value);
+        }
+        assert_eq!(values[0], values[1]);
+
+        /* the seed must be 3n bytes long */
+        seed.pop();
+        pri_template[1].ulValueLen = seed.len() as CK_ULONG;
+        ret = fn_generate_key_pair(
+            session,
+            &mut mechanism,
+            pub_template.as_mut_ptr(),
+            pub_template.len() as CK_ULONG,
+            pri_template.as_mut_ptr(),
+            pri_template.len() as CK_ULONG,
+            &mut pubkey,
+            &mut prikey,
+        );
+        assert_eq!(ret, CKR_ATTRIBUTE_VALUE_INVALID);
+    }
+
+    for (set, sig_len) in [
+        (CKP_SLH_DSA_SHA2_128F, 17088),
+        (CKP_SLH_DSA_SHAKE_128F, 17088),
+    ] {
+        let mut param_set = set;
+        let mut pub_template = vec![
+            make_attribute!(
+                CKA_PARAMETER_SET,
+                &mut param_set as *mut _,
+                CK_ULONG_SIZE
+            ),
+            make_attribute!(CKA_VERIFY, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        ];
+        let mut pri_template = vec![
+            make_attribute!(
+                CKA_SENSITIVE,
+                &mut truebool as *mut _,
+                CK_BBOOL_SIZE
+            ),
+            make_attribute!(CKA_SIGN, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        ];
+        ret = fn_generate_key_pair(
+            session,
+            &mut mechanism,
+            pub_template.as_mut_ptr(),
+            pub_template.len() as CK_ULONG,
+            pri_template.as_mut_ptr(),
+            pri_template.len() as CK_ULONG,
+            &mut pubkey,
+            &mut prikey,
+        );
+        assert_eq!(ret, CKR_OK);
+
+        let mut context = "kryoptic".as_bytes().to_vec();
+        let mut params = CK_SIGN_ADDITIONAL_CONTEXT {
+            hedgeVariant: CKH_HEDGE_PREFERRED,
+            pContext: context.as_mut_ptr(),
+            ulContextLen: context.len() as CK_ULONG,
+        };
+        let mut sign_mech: CK_MECHANISM = CK_MECHANISM {
+            mechanism: CKM_SLH_DSA,
+            pParameter: &mut params as *mut CK_SIGN_ADDITIONAL_CONTEXT
+                as *mut _,
+            ulParameterLen: std::mem::size_of::<CK_SIGN_ADDITIONAL_CONTEXT>()
+                as CK_ULONG,
+        };
+        ret = fn_sign_init(session, &mut sign_mech, prikey);
+        assert_eq!(ret, CKR_OK);
+        let mut data = "plaintext".as_bytes().to_vec();
+        let mut siglen: CK_ULONG = 0;
+        ret = fn_sign(
+            session,
+            data.as_mut_ptr(),
+            data.len() as CK_ULONG,
+            std::ptr::null_mut(),
+            &mut siglen,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(siglen, sig_len);
+        let mut signature: Vec<u8> = vec![0; siglen as usize];
+        ret = fn_sign(
+            session,
+            data.as_mut_ptr(),
+            data.len() as CK_ULONG,
+            signature.as_mut_ptr(),
+            &mut siglen,
+        );
+        assert_eq!(ret, CKR_OK);
+        sig_verify(session, pubkey, &mut data, &mut signature, &mut sign_mech);
+
+        /* the context is bound to the signature */
+        let mut other = "other".as_bytes().to_vec();
+        params.pContext = other.as_mut_ptr();
+        params.ulContextLen = other.len() as CK_ULONG;
+        ret = fn_verify_init(session, &mut sign_mech, pubkey);
+        assert_eq!(ret, CKR_OK);
+        ret = fn_verify(
+            session,
+            data.as_mut_ptr(),
+            data.len() as CK_ULONG,
+            signature.as_mut_ptr(),
+            signature.len() as CK_ULONG,
+        );
+        assert_eq!(ret, CKR_SIGNATURE_INVALID);
+
+        /* deterministic signatures are reproducible */
+        params.hedgeVariant = CKH_DETERMINISTIC_REQUIRED;
+        params.pContext = context.as_mut_ptr();
+        params.ulContextLen = context.len() as CK_ULONG;
+        ret = fn_sign_init(session, &mut sign_mech, prikey);
+        assert_eq!(ret, CKR_OK);
+        ret = fn_sign(
+            session,
+            data.as_mut_ptr(),
+            data.len() as CK_ULONG,
+            signature.as_mut_ptr(),
+            &mut siglen,
+        );
+        assert_eq!(ret, CKR_OK);
+        sig_and_check(
+            session,
+            prikey,
+            &mut data,
+            &mut signature,
+            &mut sign_mech,
+        );
+        sig_verify(session, pubkey, &mut data, &mut signature, &mut sign_mech);
+
+        /* only single-part signing is supported */
+        ret = fn_sign_init(session, &mut sign_mech, prikey);
+        assert_eq!(ret, CKR_OK);
+        ret =
+            fn_sign_update(session, data.as_mut_ptr(), data.len() as CK_ULONG);
+        assert_eq!(ret, CKR_OPERATION_NOT_INITIALIZED);
+    }
+
+    ret = fn_close_session(session);
+    assert_eq!(ret, CKR_OK);
+
+    testdata.finalize();
+}
+
+#[test]
+fn test_key_wrap() {
+    let mut testdata = TestData::new("testdata/test_key_wrap.json");
+    testdata.setup_db();
+
+    let mut args = testdata.make_init_args();
+    let args_ptr = &mut args as *mut CK_C_INITIALIZE_ARGS;
+    let mut ret = fn_initialize(args_ptr as *mut std::ffi::c_void);
+    assert_eq!(ret, CKR_OK);
+    let mut session: CK_SESSION_HANDLE = CK_UNAVAILABLE_INFORMATION;
+    ret = fn_open_session(
+        testdata.get_slot(),
+        CKF_SERIAL_SESSION | CKF_RW_SESSION,
+        std::ptr::null_mut(),
+        None,
+        &mut session,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* login */
+    let pin = "12345678";
+    ret = fn_login(
+        session,
+        CKU_USER,
+        pin.as_ptr() as *mut _,
+        pin.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut class = CKO_SECRET_KEY;
+    let mut aestype = CKK_AES;
+    let mut generictype = CKK_GENERIC_SECRET;
+    let mut truebool = CK_TRUE;
+    let mut falsebool = CK_FALSE;
+
+    /* RFC 3394 4.1: Wrap 128 bits of Key Data with a 128-bit KEK */
+    let kek = hex::decode("000102030405060708090A0B0C0D0E0F")
+        .expect("Failed to decode kek");
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut aestype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            kek.as_ptr() as *mut std::ffi::c_void,
+            kek.len()
+        ),
+        make_attribute!(CKA_WRAP, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_UNWRAP, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut kek_handle = CK_INVALID_HANDLE;
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut kek_handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let keydata = hex::decode("00112233445566778899AABBCCDDEEFF")
+        .expect("Failed to decode key data");
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut aestype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            keydata.as_ptr() as *mut std::ffi::c_void,
+            keydata.len()
+        ),
+        make_attribute!(
+            CKA_EXTRACTABLE,
+            &mut truebool as *mut _,
+            CK_BBOOL_SIZE
+        ),
+    ];
+    let mut key_handle = CK_INVALID_HANDLE;
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut key_handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_AES_KEY_WRAP,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+
+    /* length query */
+    let mut wrapped_len: CK_ULONG = 0;
+    ret = fn_wrap_key(
+        session,
+        &mut mechanism,
+        kek_handle,
+        key_handle,
+        std::ptr::null_mut(),
+        &mut wrapped_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(wrapped_len, 24);
+
+    let mut wrapped = vec![0u8; wrapped_len as usize];
+    ret = fn_wrap_key(
+        session,
+        &mut mechanism,
+        kek_handle,
+        key_handle,
+        wrapped.as_mut_ptr(),
+        &mut wrapped_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(
+        wrapped,
+        hex::decode("1FA68B0A8112B447AEF34BD8FB5A7B829D3E862371D2CFE5")
+            .expect("Failed to decode wrapped key")
+    );
+
+    /* the kek itself is not extractable */
+    ret = fn_wrap_key(
+        session,
+        &mut mechanism,
+        kek_handle,
+        kek_handle,
+        wrapped.as_mut_ptr(),
+        &mut wrapped_len,
+    );
+    assert_eq!(ret, CKR_KEY_UNEXTRACTABLE);
+
+    /* the key to wrap can't be used as a wrapping key */
+    ret = fn_wrap_key(
+        session,
+        &mut mechanism,
+        key_handle,
+        key_handle,
+        wrapped.as_mut_ptr(),
+        &mut wrapped_len,
+    );
+    assert_eq!(ret, CKR_KEY_FUNCTION_NOT_PERMITTED);
+
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut aestype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_EXTRACTABLE,
+            &mut truebool as *mut _,
+            CK_BBOOL_SIZE
+        ),
+        make_attribute!(CKA_SENSITIVE, &mut falsebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut unwrapped_handle = CK_INVALID_HANDLE;
+    ret = fn_unwrap_key(
+        session,
+        &mut mechanism,
+        kek_handle,
+        wrapped.as_mut_ptr(),
+        wrapped.len() as CK_ULONG,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut unwrapped_handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut value = vec![0u8; 32];
+    let mut always_sensitive = CK_TRUE;
+    let mut extract_template = vec![
+        make_attribute!(
+            CKA_VALUE,
+            value.as_mut_ptr() as *mut std::ffi::c_void,
+            value.len()
+        ),
+        make_attribute!(
+            CKA_ALWAYS_SENSITIVE,
+            &mut always_sensitive as *mut _,
+            CK_BBOOL_SIZE
+        ),
+    ];
+    ret = fn_get_attribute_value(
+        session,
+        unwrapped_handle,
+        extract_template.as_mut_ptr(),
+        extract_template.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+    value.resize(extract_template[0].ulValueLen as usize, 0);
+    assert_eq!(value, keydata);
+    assert_eq!(always_sensitive, CK_FALSE);
+
+    /* the value can't be provided in the template */
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut aestype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            keydata.as_ptr() as *mut std::ffi::c_void,
+            keydata.len()
+        ),
+    ];
+    ret = fn_unwrap_key(
+        session,
+        &mut mechanism,
+        kek_handle,
+        wrapped.as_mut_ptr(),
+        wrapped.len() as CK_ULONG,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut unwrapped_handle,
+    );
+    assert_eq!(ret, CKR_ATTRIBUTE_TYPE_INVALID);
+
+    /* tampered data must fail the integrity check */
+    wrapped[0] ^= 0x01;
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut aestype as *mut _, CK_ULONG_SIZE),
+    ];
+    ret = fn_unwrap_key(
+        session,
+        &mut mechanism,
+        kek_handle,
+        wrapped.as_mut_ptr(),
+        wrapped.len() as CK_ULONG,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut unwrapped_handle,
+    );
+    assert_eq!(ret, CKR_WRAPPED_KEY_INVALID);
+
+    /* RFC 5649 6: Wrap 7 octets of Key Data with a 192-bit KEK */
+    let kek = hex::decode("5840df6e29b02af1ab493b705bf16ea1ae8338f4dcc176a8")
+        .expect("Failed to decode kek");
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut aestype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            kek.as_ptr() as *mut std::ffi::c_void,
+            kek.len()
+        ),
+        make_attribute!(CKA_WRAP, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_UNWRAP, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut kek_handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let keydata =
+        hex::decode("466f7250617369").expect("Failed to decode key data");
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_KEY_TYPE,
+            &mut generictype as *mut _,
+            CK_ULONG_SIZE
+        ),
+        make_attribute!(
+            CKA_VALUE,
+            keydata.as_ptr() as *mut std::ffi::c_void,
+            keydata.len()
+        ),
+        make_attribute!(
+            CKA_EXTRACTABLE,
+            &mut truebool as *mut _,
+            CK_BBOOL_SIZE
+        ),
+    ];
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut key_handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_AES_KEY_WRAP_KWP,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+
+    let mut wrapped = vec![0u8; 16];
+    let mut wrapped_len = wrapped.len() as CK_ULONG;
+    ret = fn_wrap_key(
+        session,
+        &mut mechanism,
+        kek_handle,
+        key_handle,
+        wrapped.as_mut_ptr(),
+        &mut wrapped_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(
+        wrapped,
+        hex::decode("afbeb0f07dfbf5419200f2ccb50bb24f")
+            .expect("Failed to decode wrapped key")
+    );
+
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_KEY_TYPE,
+            &mut generictype as *mut _,
+            CK_ULONG_SIZE
+        ),
+        make_attribute!(
+            CKA_EXTRACTABLE,
+            &mut truebool as *mut _,
+            CK_BBOOL_SIZE
+        ),
+        make_attribute!(CKA_SENSITIVE, &mut falsebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    ret = fn_unwrap_key(
+        session,
+        &mut mechanism,
+        kek_handle,
+        wrapped.as_mut_ptr(),
+        wrapped.len() as CK_ULONG,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut unwrapped_handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut value = vec![0u8; 32];
+    let mut extract_template = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(
+        session,
+        unwrapped_handle,
+        extract_template.as_mut_ptr(),
+        1,
+    );
+    assert_eq!(ret, CKR_OK);
+    value.resize(extract_template[0].ulValueLen as usize, 0);
+    assert_eq!(value, keydata);
+
+    /* keys marked CKA_WRAP_WITH_TRUSTED need a trusted wrapping key,
+     * use public session objects so that they survive the logouts */
+    let kek = hex::decode("00112233445566778899AABBCCDDEEFF")
+        .expect("Failed to decode kek");
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut aestype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            kek.as_ptr() as *mut std::ffi::c_void,
+            kek.len()
+        ),
+        make_attribute!(CKA_PRIVATE, &mut falsebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_WRAP, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_UNWRAP, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut kek_handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let keydata = hex::decode("000102030405060708090A0B0C0D0E0F")
+        .expect("Failed to decode key data");
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut aestype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            keydata.as_ptr() as *mut std::ffi::c_void,
+            keydata.len()
+        ),
+        make_attribute!(CKA_PRIVATE, &mut falsebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(
+            CKA_EXTRACTABLE,
+            &mut truebool as *mut _,
+            CK_BBOOL_SIZE
+        ),
+        make_attribute!(
+            CKA_WRAP_WITH_TRUSTED,
+            &mut truebool as *mut _,
+            CK_BBOOL_SIZE
+        ),
+    ];
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut key_handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_AES_KEY_WRAP,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let mut wrapped = vec![0u8; 24];
+    let mut wrapped_len = wrapped.len() as CK_ULONG;
+    ret = fn_wrap_key(
+        session,
+        &mut mechanism,
+        kek_handle,
+        key_handle,
+        wrapped.as_mut_ptr(),
+        &mut wrapped_len,
+    );
+    assert_eq!(ret, CKR_KEY_NOT_WRAPPABLE);
+
+    /* only the SO can mark the wrapping key as trusted */
+    let mut template = vec![make_attribute!(
+        CKA_TRUSTED,
+        &mut truebool as *mut _,
+        CK_BBOOL_SIZE
+    )];
+    ret = fn_set_attribute_value(session, kek_handle, template.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_ATTRIBUTE_READ_ONLY);
+
+    ret = fn_logout(session);
+    assert_eq!(ret, CKR_OK);
+    ret = fn_login(
+        session,
+        CKU_SO,
+        pin.as_ptr() as *mut _,
+        pin.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+    ret = fn_set_attribute_value(session, kek_handle, template.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    ret = fn_logout(session);
+    assert_eq!(ret, CKR_OK);
+    ret = fn_login(
+        session,
+        CKU_USER,
+        pin.as_ptr() as *mut _,
+        pin.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    ret = fn_wrap_key(
+        session,
+        &mut mechanism,
+        kek_handle,
+        key_handle,
+        wrapped.as_mut_ptr(),
+        &mut wrapped_len,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* the wrap template restricts the keys that can be wrapped */
+    let mut wrap_template = vec![make_attribute!(
+        CKA_KEY_TYPE,
+        &mut generictype as *mut _,
+        CK_ULONG_SIZE
+    )];
+    let mut template = vec![make_attribute!(
+        CKA_WRAP_TEMPLATE,
+        wrap_template.as_mut_ptr() as *mut std::ffi::c_void,
+        wrap_template.len() * std::mem::size_of::<CK_ATTRIBUTE>()
+    )];
+    ret = fn_set_attribute_value(session, kek_handle, template.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+
+    let mut rewrapped = vec![0u8; 24];
+    let mut rewrapped_len = rewrapped.len() as CK_ULONG;
+    ret = fn_wrap_key(
+        session,
+        &mut mechanism,
+        kek_handle,
+        key_handle,
+        rewrapped.as_mut_ptr(),
+        &mut rewrapped_len,
+    );
+    assert_eq!(ret, CKR_KEY_NOT_WRAPPABLE);
+
+    /* the unwrap template is forced on unwrapped keys */
+    let mut unwrap_template = vec![make_attribute!(
+        CKA_EXTRACTABLE,
+        &mut falsebool as *mut _,
+        CK_BBOOL_SIZE
+    )];
+    let mut template = vec![make_attribute!(
+        CKA_UNWRAP_TEMPLATE,
+        unwrap_template.as_mut_ptr() as *mut std::ffi::c_void,
+        unwrap_template.len() * std::mem::size_of::<CK_ATTRIBUTE>()
+    )];
+    ret = fn_set_attribute_value(session, kek_handle, template.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+
+    /* and can be read back as an attribute array */
+    let mut extractable = CK_TRUE;
+    let mut read_template = vec![make_attribute!(
+        CKA_EXTRACTABLE,
+        &mut extractable as *mut _,
+        CK_BBOOL_SIZE
+    )];
+    let mut template = vec![make_attribute!(
+        CKA_UNWRAP_TEMPLATE,
+        read_template.as_mut_ptr() as *mut std::ffi::c_void,
+        read_template.len() * std::mem::size_of::<CK_ATTRIBUTE>()
+    )];
+    ret = fn_get_attribute_value(session, kek_handle, template.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(read_template[0].type_, CKA_EXTRACTABLE);
+    assert_eq!(extractable, CK_FALSE);
+
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut aestype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_EXTRACTABLE,
+            &mut truebool as *mut _,
+            CK_BBOOL_SIZE
+        ),
+    ];
+    ret = fn_unwrap_key(
+        session,
+        &mut mechanism,
+        kek_handle,
+        wrapped.as_mut_ptr(),
+        wrapped.len() as CK_ULONG,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut unwrapped_handle,
+    );
+    assert_eq!(ret, CKR_TEMPLATE_INCONSISTENT);
+
+    ret = fn_unwrap_key(
+        session,
+        &mut mechanism,
+        kek_handle,
+        wrapped.as_mut_ptr(),
+        wrapped.len() as CK_ULONG,
+        template.as_mut_ptr(),
+        2,
+        &mut unwrapped_handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    extractable = CK_TRUE;
+    let mut template = vec![make_attribute!(
+        CKA_EXTRACTABLE,
+        &mut extractable as *mut _,
+        CK_BBOOL_SIZE
+    )];
+    ret = fn_get_attribute_value(
+        session,
+        unwrapped_handle,
+        template.as_mut_ptr(),
+        1,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extractable, CK_FALSE);
+
+    ret = fn_close_session(session);
+    assert_eq!(ret, CKR_OK);
+
+    testdata.finalize();
+}
+
+#[test]
+fn test_private_key_wrap() {
+    let mut testdata = TestData::new("testdata/test_private_key_wrap.json");
+    testdata.setup_db();
+
+    let mut args = testdata.make_init_args();
+    let args_ptr = &mut args as *mut CK_C_INITIALIZE_ARGS;
+    let mut ret = fn_initialize(args_ptr as *mut std::ffi::c_void);
+    assert_eq!(ret, CKR_OK);
+    let mut session: CK_SESSION_HANDLE = CK_UNAVAILABLE_INFORMATION;
+    ret = fn_open_session(
+        testdata.get_slot(),
+        CKF_SERIAL_SESSION | CKF_RW_SESSION,
+        std::ptr::null_mut(),
+        None,
+        &mut session,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* login */
+    let pin = "12345678";
+    ret = fn_login(
+        session,
+        CKU_USER,
+        pin.as_ptr() as *mut _,
+        pin.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut truebool = CK_TRUE;
+    let mut class = CKO_SECRET_KEY;
+    let mut aestype = CKK_AES;
+    let kek = hex::decode("000102030405060708090A0B0C0D0E0F")
+        .expect("Failed to decode kek");
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut aestype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            kek.as_ptr() as *mut std::ffi::c_void,
+            kek.len()
+        ),
+        make_attribute!(CKA_DECRYPT, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_WRAP, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_UNWRAP, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut kek_handle = CK_INVALID_HANDLE;
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut kek_handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* RFC 8032, Section 7.1, TEST 2 key */
+    class = CKO_PRIVATE_KEY;
+    let mut ktype = CKK_EC_EDWARDS;
+    let ec_params =
+        hex::decode("06032b6570").expect("Failed to decode ec params");
+    let value = hex::decode(
+        "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+    )
+    .expect("Failed to decode private key");
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_SIGN, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(
+            CKA_EC_PARAMS,
+            ec_params.as_ptr() as *mut std::ffi::c_void,
+            ec_params.len()
+        ),
+        make_attribute!(
+            CKA_VALUE,
+            value.as_ptr() as *mut std::ffi::c_void,
+            value.len()
+        ),
+        make_attribute!(
+            CKA_EXTRACTABLE,
+            &mut truebool as *mut _,
+            CK_BBOOL_SIZE
+        ),
+    ];
+    let mut edkey = CK_INVALID_HANDLE;
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut edkey,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* wrap with CBC-PAD and decrypt to check the PKCS#8 encoding */
+    let mut iv = [0u8; 16];
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_AES_CBC_PAD,
+        pParameter: iv.as_mut_ptr() as CK_VOID_PTR,
+        ulParameterLen: iv.len() as CK_ULONG,
+    };
+    let mut wrapped = vec![0u8; 128];
+    let mut wrapped_len = wrapped.len() as CK_ULONG;
+    ret = fn_wrap_key(
+        session,
+        &mut mechanism,
+        kek_handle,
+        edkey,
+        wrapped.as_mut_ptr(),
+        &mut wrapped_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(wrapped_len, 64);
+    wrapped.resize(wrapped_len as usize, 0);
+
+    ret = fn_decrypt_init(session, &mut mechanism, kek_handle);
+    assert_eq!(ret, CKR_OK);
+    let mut pkcs8 = vec![0u8; wrapped.len()];
+    let mut pkcs8_len = pkcs8.len() as CK_ULONG;
+    ret = fn_decrypt(
+        session,
+        wrapped.as_mut_ptr(),
+        wrapped.len() as CK_ULONG,
+        pkcs8.as_mut_ptr(),
+        &mut pkcs8_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    pkcs8.resize(pkcs8_len as usize, 0);
+    let expected = hex::decode(
+        "302e020100300506032b657004220420\
+         4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+    )
+    .expect("Failed to decode PKCS#8 key");
+    assert_eq!(pkcs8, expected);
+
+    /* the key type comes from the AlgorithmIdentifier */
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_SIGN, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut unwrapped = CK_INVALID_HANDLE;
+    ret = fn_unwrap_key(
+        session,
+        &mut mechanism,
+        kek_handle,
+        wrapped.as_mut_ptr(),
+        wrapped.len() as CK_ULONG,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut unwrapped,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut unwrapped_type: CK_ULONG = 0;
+    let mut template = vec![make_attribute!(
+        CKA_KEY_TYPE,
+        &mut unwrapped_type as *mut _,
+        CK_ULONG_SIZE
+    )];
+    ret = fn_get_attribute_value(session, unwrapped, template.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(unwrapped_type, CKK_EC_EDWARDS);
+
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_EDDSA,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let mut data = hex::decode("72").expect("Failed to decode data");
+    let mut signature = hex::decode("92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00").expect("Failed to decode signature");
+    sig_and_check(
+        session,
+        unwrapped,
+        &mut data,
+        &mut signature,
+        &mut mechanism,
+    );
+
+    /* RSA key pair, round trip through AES-KWP */
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_RSA_PKCS_KEY_PAIR_GEN,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let mut len: CK_ULONG = 2048;
+    let mut pub_template = vec![
+        make_attribute!(CKA_VERIFY, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_MODULUS_BITS, &mut len as *mut _, CK_ULONG_SIZE),
+    ];
+    let mut pri_template = vec![
+        make_attribute!(CKA_SIGN, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(
+            CKA_EXTRACTABLE,
+            &mut truebool as *mut _,
+            CK_BBOOL_SIZE
+        ),
+    ];
+    let mut pubkey = CK_INVALID_HANDLE;
+    let mut prikey = CK_INVALID_HANDLE;
+    ret = fn_generate_key_pair(
+        session,
+        &mut mechanism,
+        pub_template.as_mut_ptr(),
+        pub_template.len() as CK_ULONG,
+        pri_template.as_mut_ptr(),
+        pri_template.len() as CK_ULONG,
+        &mut pubkey,
+        &mut prikey,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_AES_KEY_WRAP_KWP,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let mut wrapped_len: CK_ULONG = 0;
+    ret = fn_wrap_key(
+        session,
+        &mut mechanism,
+        kek_handle,
+        prikey,
+        std::ptr::null_mut(),
+        &mut wrapped_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    let mut wrapped = vec![0u8; wrapped_len as usize];
+    ret = fn_wrap_key(
+        session,
+        &mut mechanism,
+        kek_handle,
+        prikey,
+        wrapped.as_mut_ptr(),
+        &mut wrapped_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    wrapped.resize(wrapped_len as usize, 0);
+
+    /* a key type that does not match the AlgorithmIdentifier */
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_SIGN, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    ret = fn_unwrap_key(
+        session,
+        &mut mechanism,
+        kek_handle,
+        wrapped.as_mut_ptr(),
+        wrapped.len() as CK_ULONG,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut unwrapped,
+    );
+    assert_eq!(ret, CKR_TEMPLATE_INCONSISTENT);
+
+    ktype = CKK_RSA;
+    ret = fn_unwrap_key(
+        session,
+        &mut mechanism,
+        kek_handle,
+        wrapped.as_mut_ptr(),
+        wrapped.len() as CK_ULONG,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut unwrapped,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* PKCS#1 v1.5 signatures are deterministic */
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_SHA256_RSA_PKCS,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let mut data = "plaintext".as_bytes().to_vec();
+    ret = fn_sign_init(session, &mut mechanism, prikey);
+    assert_eq!(ret, CKR_OK);
+    let mut siglen: CK_ULONG = 256;
+    let mut signature = vec![0u8; siglen as usize];
+    ret = fn_sign(
+        session,
+        data.as_mut_ptr(),
+        data.len() as CK_ULONG,
+        signature.as_mut_ptr(),
+        &mut siglen,
+    );
+    assert_eq!(ret, CKR_OK);
+    sig_and_check(
+        session,
+        unwrapped,
+        &mut data,
+        &mut signature,
+        &mut mechanism,
+    );
+    sig_verify(session, pubkey, &mut data, &mut signature, &mut mechanism);
+
+    ret = fn_close_session(session);
+    assert_eq!(ret, CKR_OK);
+
+    testdata.finalize();
+}
+
+#[test]
+fn test_rsa_aes_key_wrap() {
+    let mut testdata = TestData::new("testdata/test_rsa_aes_key_wrap.json");
+    testdata.setup_db();
+
+    let mut args = testdata.make_init_args();
+    let args_ptr = &mut args as *mut CK_C_INITIALIZE_ARGS;
+    let mut ret = fn_initialize(args_ptr as *mut std::ffi::c_void);
+    assert_eq!(ret, CKR_OK);
+    let mut session: CK_SESSION_HANDLE = CK_UNAVAILABLE_INFORMATION;
+    ret = fn_open_session(
+        testdata.get_slot(),
+        CKF_SERIAL_SESSION | CKF_RW_SESSION,
+        std::ptr::null_mut(),
+        None,
+        &mut session,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* login */
+    let pin = "12345678";
+    ret = fn_login(
+        session,
+        CKU_USER,
+        pin.as_ptr() as *mut _,
+        pin.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut truebool = CK_TRUE;
+    let mut falsebool = CK_FALSE;
+
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_RSA_PKCS_KEY_PAIR_GEN,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let mut len: CK_ULONG = 2048;
+    let mut pub_template = vec![
+        make_attribute!(CKA_WRAP, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_MODULUS_BITS, &mut len as *mut _, CK_ULONG_SIZE),
+    ];
+    let mut pri_template = vec![make_attribute!(
+        CKA_UNWRAP,
+        &mut truebool as *mut _,
+        CK_BBOOL_SIZE
+    )];
+    let mut pubkey = CK_INVALID_HANDLE;
+    let mut prikey = CK_INVALID_HANDLE;
+    ret = fn_generate_key_pair(
+        session,
+        &mut mechanism,
+        pub_template.as_mut_ptr(),
+        pub_template.len() as CK_ULONG,
+        pri_template.as_mut_ptr(),
+        pri_template.len() as CK_ULONG,
+        &mut pubkey,
+        &mut prikey,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut class = CKO_SECRET_KEY;
+    let mut aestype = CKK_AES;
+    let keydata = hex::decode("00112233445566778899AABBCCDDEEFF")
+        .expect("Failed to decode key data");
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut aestype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            keydata.as_ptr() as *mut std::ffi::c_void,
+            keydata.len()
+        ),
+        make_attribute!(
+            CKA_EXTRACTABLE,
+            &mut truebool as *mut _,
+            CK_BBOOL_SIZE
+        ),
+    ];
+    let mut key_handle = CK_INVALID_HANDLE;
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut key_handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut oaep_params = CK_RSA_PKCS_OAEP_PARAMS {
+        hashAlg: CKM_SHA256,
+        mgf: CKG_MGF1_SHA256,
+        source: CKZ_DATA_SPECIFIED,
+        pSourceData: std::ptr::null_mut(),
+        ulSourceDataLen: 0,
+    };
+    let mut params = CK_RSA_AES_KEY_WRAP_PARAMS {
+        ulAESKeyBits: 256,
+        pOAEPParams: &mut oaep_params,
+    };
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_RSA_AES_KEY_WRAP,
+        pParameter: &mut params as *mut CK_RSA_AES_KEY_WRAP_PARAMS as *mut _,
+        ulParameterLen: std::mem::size_of::<CK_RSA_AES_KEY_WRAP_PARAMS>()
+            as CK_ULONG,
+    };
+
+    /* the RSA-OAEP encrypted AES key followed by the AES-KWP blob */
+    let mut wrapped_len: CK_ULONG = 0;
+    ret = fn_wrap_key(
+        session,
+        &mut mechanism,
+        pubkey,
+        key_handle,
+        std::ptr::null_mut(),
+        &mut wrapped_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(wrapped_len, 256 + 24);
+    let mut wrapped = vec![0u8; wrapped_len as usize];
+    ret = fn_wrap_key(
+        session,
+        &mut mechanism,
+        pubkey,
+        key_handle,
+        wrapped.as_mut_ptr(),
+        &mut wrapped_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(wrapped_len, 256 + 24);
+
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut aestype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_SENSITIVE, &mut falsebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut unwrapped = CK_INVALID_HANDLE;
+    ret = fn_unwrap_key(
+        session,
+        &mut mechanism,
+        prikey,
+        wrapped.as_mut_ptr(),
+        wrapped.len() as CK_ULONG,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut unwrapped,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut value = vec![0u8; keydata.len()];
+    let mut template = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(session, unwrapped, template.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(value, keydata);
+
+    /* the OAEP parameters must match */
+    oaep_params.hashAlg = CKM_SHA384;
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut aestype as *mut _, CK_ULONG_SIZE),
+    ];
+    ret = fn_unwrap_key(
+        session,
+        &mut mechanism,
+        prikey,
+        wrapped.as_mut_ptr(),
+        wrapped.len() as CK_ULONG,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut unwrapped,
+    );
+    assert_eq!(ret, CKR_WRAPPED_KEY_INVALID);
+
+    /* a short blob cannot even hold the encrypted AES key */
+    oaep_params.hashAlg = CKM_SHA256;
+    ret = fn_unwrap_key(
+        session,
+        &mut mechanism,
+        prikey,
+        wrapped.as_mut_ptr(),
+        200,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut unwrapped,
+    );
+    assert_eq!(ret, CKR_WRAPPED_KEY_LEN_RANGE);
+
+    ret = fn_close_session(session);
+    assert_eq!(ret, CKR_OK);
+
+    testdata.finalize();
+}
+
+#[test]
+fn test_aes_mac() {
+    let mut testdata = TestData::new("testdata/test_aes_mac.json");
+    testdata.setup_db();
+
+    let mut args = testdata.make_init_args();
+    let args_ptr = &mut args as *mut CK_C_INITIALIZE_ARGS;
+    let mut ret = fn_initialize(args_ptr as *mut std::ffi::c_void);
+    assert_eq!(ret, CKR_OK);
+    let mut session: CK_SESSION_HANDLE = CK_UNAVAILABLE_INFORMATION;
+    ret = fn_open_session(
+        testdata.get_slot(),
+        CKF_SERIAL_SESSION | CKF_RW_SESSION,
+        std::ptr::null_mut(),
+        None,
+        &mut session,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* login */
+    let pin = "12345678";
+    ret = fn_login(
+        session,
+        CKU_USER,
+        pin.as_ptr() as *mut _,
+        pin.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut class = CKO_SECRET_KEY;
+    let mut ktype = CKK_AES;
+    let mut truebool = CK_TRUE;
+
+    /* RFC 4493, Section 4 */
+    let value = hex::decode("2b7e151628aed2a6abf7158809cf4f3c")
+        .expect("Failed to decode key");
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            value.as_ptr() as *mut std::ffi::c_void,
+            value.len()
+        ),
+        make_attribute!(CKA_SIGN, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_VERIFY, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut handle = CK_INVALID_HANDLE;
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_AES_CMAC,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+
+    /* Example 2 */
+    let mut data = hex::decode("6bc1bee22e409f96e93d7e117393172a")
+        .expect("Failed to decode data");
+    let mut mac = hex::decode("070a16b46b4d4144f79bdd9dd04a287c")
+        .expect("Failed to decode mac");
+    sig_and_check(session, handle, &mut data, &mut mac, &mut mechanism);
+    sig_verify(session, handle, &mut data, &mut mac, &mut mechanism);
+
+    /* Example 3, in two parts */
+    let data = hex::decode(
+        "6bc1bee22e409f96e93d7e117393172a\
+         ae2d8a571e03ac9c9eb76fac45af8e5130c81c46a35ce411",
+    )
+    .expect("Failed to decode data");
+    let mac = hex::decode("dfa66747de9ae63030ca32611497c827")
+        .expect("Failed to decode mac");
+    ret = fn_sign_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_OK);
+    ret = fn_sign_update(session, data[..20].as_ptr() as *mut _, 20);
+    assert_eq!(ret, CKR_OK);
+    ret = fn_sign_update(
+        session,
+        data[20..].as_ptr() as *mut _,
+        (data.len() - 20) as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+    let mut computed = vec![0u8; mac.len()];
+    let mut computed_len = computed.len() as CK_ULONG;
+    ret = fn_sign_final(session, computed.as_mut_ptr(), &mut computed_len);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(computed, mac);
+
+    /* truncated MAC with the _GENERAL variant */
+    let mut maclen: CK_MAC_GENERAL_PARAMS = 8;
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_AES_CMAC_GENERAL,
+        pParameter: &mut maclen as *mut CK_MAC_GENERAL_PARAMS as *mut _,
+        ulParameterLen: CK_ULONG_SIZE as CK_ULONG,
+    };
+    let mut data = hex::decode("6bc1bee22e409f96e93d7e117393172a")
+        .expect("Failed to decode data");
+    let mut mac =
+        hex::decode("070a16b46b4d4144").expect("Failed to decode mac");
+    sig_and_check(session, handle, &mut data, &mut mac, &mut mechanism);
+    sig_verify(session, handle, &mut data, &mut mac, &mut mechanism);
+
+    maclen = 17;
+    ret = fn_sign_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_MECHANISM_PARAM_INVALID);
+
+    /* GMAC, NIST GCM test vectors with an empty plaintext */
+    let value = hex::decode("77be63708971c4e240d1cb79e8d77feb")
+        .expect("Failed to decode key");
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            value.as_ptr() as *mut std::ffi::c_void,
+            value.len()
+        ),
+        make_attribute!(CKA_SIGN, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_VERIFY, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut iv =
+        hex::decode("e0e00f19fed7ba0136a797f3").expect("Failed to decode iv");
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_AES_GMAC,
+        pParameter: iv.as_mut_ptr() as CK_VOID_PTR,
+        ulParameterLen: iv.len() as CK_ULONG,
+    };
+    let mut data = hex::decode("7a43ec1d9c0a5a78a0b16533a6213cab")
+        .expect("Failed to decode data");
+    let mut mac = hex::decode("209fcc8d3675ed938e9c7166709dd946")
+        .expect("Failed to decode mac");
+    sig_and_check(session, handle, &mut data, &mut mac, &mut mechanism);
+    sig_verify(session, handle, &mut data, &mut mac, &mut mechanism);
+
+    /* a tampered MAC must fail */
+    mac[0] ^= 0xff;
+    ret = fn_verify_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_OK);
+    ret = fn_verify(
+        session,
+        data.as_mut_ptr(),
+        data.len() as CK_ULONG,
+        mac.as_mut_ptr(),
+        mac.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_SIGNATURE_INVALID);
+
+    ret = fn_close_session(session);
+    assert_eq!(ret, CKR_OK);
+
+    testdata.finalize();
+}
+
+#[cfg(not(feature = "fips"))]
+#[test]
+fn test_chacha20() {
+    let mut testdata = TestData::new("testdata/test_chacha20.json");
+    testdata.setup_db();
+
+    let mut args = testdata.make_init_args();
+    let args_ptr = &mut args as *mut CK_C_INITIALIZE_ARGS;
+    let mut ret = fn_initialize(args_ptr as *mut std::ffi::c_void);
+    assert_eq!(ret, CKR_OK);
+    let mut session: CK_SESSION_HANDLE = CK_UNAVAILABLE_INFORMATION;
+    ret = fn_open_session(
+        testdata.get_slot(),
+        CKF_SERIAL_SESSION | CKF_RW_SESSION,
+        std::ptr::null_mut(),
+        None,
+        &mut session,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* login */
+    let pin = "12345678";
+    ret = fn_login(
+        session,
+        CKU_USER,
+        pin.as_ptr() as *mut _,
+        pin.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut class = CKO_SECRET_KEY;
+    let mut ktype = CKK_CHACHA20;
+    let mut truebool = CK_TRUE;
+
+    /* RFC 8439, 2.4.2 */
+    let value: Vec<u8> = (0u8..32).collect();
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            value.as_ptr() as *mut std::ffi::c_void,
+            value.len()
+        ),
+        make_attribute!(CKA_ENCRYPT, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_DECRYPT, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut handle = CK_INVALID_HANDLE;
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let plain = b"Ladies and Gentlemen of the class of '99: If I could \
+                  offer you only one tip for the future, sunscreen would \
+                  be it.";
+    let expect = hex::decode(
+        "6e2e359a2568f98041ba0728dd0d6981e97e7aec1d4360c20a27afccfd9fae0b\
+         f91b65c5524733ab8f593dabcd62b3571639d624e65152ab8f530c359f0861d8\
+         07ca0dbf500d6a6156a38e088a22b65e52bc514d16ccf806818ce91ab7793736\
+         5af90bbf74a35be6b40b8eedf2785e42874d",
+    )
+    .expect("Failed to decode ciphertext");
+
+    let mut counter: [u8; 4] = [1, 0, 0, 0];
+    let mut nonce =
+        hex::decode("000000000000004a00000000").expect("Failed to decode");
+    let mut params = CK_CHACHA20_PARAMS {
+        pBlockCounter: counter.as_mut_ptr(),
+        blockCounterBits: 32,
+        pNonce: nonce.as_mut_ptr(),
+        ulNonceBits: 96,
+    };
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_CHACHA20,
+        pParameter: &mut params as *mut CK_CHACHA20_PARAMS as CK_VOID_PTR,
+        ulParameterLen: std::mem::size_of::<CK_CHACHA20_PARAMS>() as CK_ULONG,
+    };
+
+    ret = fn_encrypt_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_OK);
+    let mut enc = vec![0u8; plain.len()];
+    let mut enc_len = enc.len() as CK_ULONG;
+    ret = fn_encrypt(
+        session,
+        plain.as_ptr() as *mut u8,
+        plain.len() as CK_ULONG,
+        enc.as_mut_ptr(),
+        &mut enc_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(enc_len as usize, plain.len());
+    assert_eq!(enc, expect);
+
+    /* stream cipher, so arbitrary part sizes */
+    ret = fn_decrypt_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_OK);
+    let mut dec = vec![0u8; plain.len()];
+    let mut dec_len = 33 as CK_ULONG;
+    ret = fn_decrypt_update(
+        session,
+        enc.as_mut_ptr(),
+        33,
+        dec.as_mut_ptr(),
+        &mut dec_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(dec_len, 33);
+    let mut dec_len2 = (plain.len() - 33) as CK_ULONG;
+    ret = fn_decrypt_update(
+        session,
+        enc[33..].as_mut_ptr(),
+        (plain.len() - 33) as CK_ULONG,
+        dec[33..].as_mut_ptr(),
+        &mut dec_len2,
+    );
+    assert_eq!(ret, CKR_OK);
+    let mut dec_len3: CK_ULONG = 0;
+    ret = fn_decrypt_final(session, dec[..0].as_mut_ptr(), &mut dec_len3);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(dec_len3, 0);
+    assert_eq!(&dec, plain);
+
+    /* the original construction with a 64 bit counter and nonce */
+    let mut counter: [u8; 8] = [1, 0, 0, 0, 0, 0, 0, 0];
+    let mut nonce = hex::decode("0001020304050607").expect("Failed to decode");
+    let mut params = CK_CHACHA20_PARAMS {
+        pBlockCounter: counter.as_mut_ptr(),
+        blockCounterBits: 64,
+        pNonce: nonce.as_mut_ptr(),
+        ulNonceBits: 64,
+    };
+    mechanism.pParameter =
+        &mut params as *mut CK_CHACHA20_PARAMS as CK_VOID_PTR;
+    let expect = hex::decode(
+        "7461eff343cf15f57040645019e4aa0aecb3f04869e9bf78fd9305c82545a8ce\
+         e97aa47ae13f876f",
+    )
+    .expect("Failed to decode ciphertext");
+    ret = fn_encrypt_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_OK);
+    let mut enc = vec![0u8; expect.len()];
+    let mut enc_len = enc.len() as CK_ULONG;
+    ret = fn_encrypt(
+        session,
+        plain.as_ptr() as *mut u8,
+        expect.len() as CK_ULONG,
+        enc.as_mut_ptr(),
+        &mut enc_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(enc, expect);
+
+    /* mismatched counter and nonce sizes */
+    params.ulNonceBits = 96;
+    ret = fn_encrypt_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_MECHANISM_PARAM_INVALID);
+
+    /* RFC 8439, 2.8.2 */
+    let value: Vec<u8> = (0x80u8..0xa0).collect();
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            value.as_ptr() as *mut std::ffi::c_void,
+            value.len()
+        ),
+        make_attribute!(CKA_ENCRYPT, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_DECRYPT, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut nonce =
+        hex::decode("070000004041424344454647").expect("Failed to decode");
+    let mut aad =
+        hex::decode("50515253c0c1c2c3c4c5c6c7").expect("Failed to decode");
+    let mut params = CK_SALSA20_CHACHA20_POLY1305_PARAMS {
+        pNonce: nonce.as_mut_ptr(),
+        ulNonceLen: nonce.len() as CK_ULONG,
+        pAAD: aad.as_mut_ptr(),
+        ulAADLen: aad.len() as CK_ULONG,
+    };
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_CHACHA20_POLY1305,
+        pParameter: &mut params as *mut CK_SALSA20_CHACHA20_POLY1305_PARAMS
+            as CK_VOID_PTR,
+        ulParameterLen: std::mem::size_of::<CK_SALSA20_CHACHA20_POLY1305_PARAMS>(
+        ) as CK_ULONG,
+    };
+    let expect = hex::decode(
+        "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d6\
+         3dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b36\
+         92ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc\
+         3ff4def08e4b7a9de576d26586cec64b6116\
+         1ae10b594f09e26a7e902ecbd0600691",
+    )
+    .expect("Failed to decode ciphertext");
+
+    ret = fn_encrypt_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_OK);
+    let mut enc_len: CK_ULONG = 0;
+    ret = fn_encrypt(
+        session,
+        plain.as_ptr() as *mut u8,
+        plain.len() as CK_ULONG,
+        std::ptr::null_mut(),
+        &mut enc_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(enc_len as usize, expect.len());
+    let mut enc = vec![0u8; enc_len as usize];
+    ret = fn_encrypt(
+        session,
+        plain.as_ptr() as *mut u8,
+        plain.len() as CK_ULONG,
+        enc.as_mut_ptr(),
+        &mut enc_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(enc, expect);
+
+    ret = fn_decrypt_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_OK);
+    let mut dec = vec![0u8; plain.len()];
+    let mut dec_len = dec.len() as CK_ULONG;
+    ret = fn_decrypt(
+        session,
+        enc.as_mut_ptr(),
+        enc.len() as CK_ULONG,
+        dec.as_mut_ptr(),
+        &mut dec_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(dec_len as usize, plain.len());
+    assert_eq!(&dec, plain);
+
+    /* a modified tag must be rejected */
+    let last = enc.len() - 1;
+    enc[last] ^= 0x01;
+    ret = fn_decrypt_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_OK);
+    let mut dec_len = dec.len() as CK_ULONG;
+    ret = fn_decrypt(
+        session,
+        enc.as_mut_ptr(),
+        enc.len() as CK_ULONG,
+        dec.as_mut_ptr(),
+        &mut dec_len,
+    );
+    assert_eq!(ret, CKR_ENCRYPTED_DATA_INVALID);
+
+    /* message based encryption, the nonce and tag are per message */
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_CHACHA20_POLY1305,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    ret = fn_message_encrypt_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_OK);
+    let mut tag: [u8; 16] = [0; 16];
+    let mut param = CK_SALSA20_CHACHA20_POLY1305_MSG_PARAMS {
+        pNonce: nonce.as_mut_ptr(),
+        ulNonceLen: nonce.len() as CK_ULONG,
+        pTag: tag.as_mut_ptr(),
+    };
+    let mut data = plain.to_vec();
+    let mut menc = vec![0u8; data.len()];
+    let mut menc_len = menc.len() as CK_ULONG;
+    ret = fn_encrypt_message(
+        session,
+        &mut param as *mut CK_SALSA20_CHACHA20_POLY1305_MSG_PARAMS
+            as CK_VOID_PTR,
+        std::mem::size_of::<CK_SALSA20_CHACHA20_POLY1305_MSG_PARAMS>()
+            as CK_ULONG,
+        aad.as_mut_ptr(),
+        aad.len() as CK_ULONG,
+        data.as_mut_ptr(),
+        data.len() as CK_ULONG,
+        menc.as_mut_ptr(),
+        &mut menc_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(menc_len as usize, data.len());
+    assert_eq!(&menc, &expect[..data.len()]);
+    assert_eq!(&tag, &expect[data.len()..]);
+    ret = fn_message_encrypt_final(session);
+    assert_eq!(ret, CKR_OK);
+
+    /* decrypt in parts */
+    ret = fn_message_decrypt_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_OK);
+    ret = fn_decrypt_message_begin(
+        session,
+        &mut param as *mut CK_SALSA20_CHACHA20_POLY1305_MSG_PARAMS
+            as CK_VOID_PTR,
+        std::mem::size_of::<CK_SALSA20_CHACHA20_POLY1305_MSG_PARAMS>()
+            as CK_ULONG,
+        aad.as_mut_ptr(),
+        aad.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+    let mut dec = vec![0u8; data.len()];
+    let mut dec_len: CK_ULONG = dec.len() as CK_ULONG;
+    ret = fn_decrypt_message_next(
+        session,
+        &mut param as *mut CK_SALSA20_CHACHA20_POLY1305_MSG_PARAMS
+            as CK_VOID_PTR,
+        std::mem::size_of::<CK_SALSA20_CHACHA20_POLY1305_MSG_PARAMS>()
+            as CK_ULONG,
+        menc.as_mut_ptr(),
+        10,
+        dec.as_mut_ptr(),
+        &mut dec_len,
+        0,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(dec_len, 10);
+    let mut part_len: CK_ULONG = (dec.len() - 10) as CK_ULONG;
+    ret = fn_decrypt_message_next(
+        session,
+        &mut param as *mut CK_SALSA20_CHACHA20_POLY1305_MSG_PARAMS
+            as CK_VOID_PTR,
+        std::mem::size_of::<CK_SALSA20_CHACHA20_POLY1305_MSG_PARAMS>()
+            as CK_ULONG,
+        unsafe { menc.as_mut_ptr().add(10) },
+        (menc.len() - 10) as CK_ULONG,
+        unsafe { dec.as_mut_ptr().add(10) },
+        &mut part_len,
+        CKF_END_OF_MESSAGE,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(part_len as usize, menc.len() - 10);
+    assert_eq!(&dec, &data);
+
+    /* a corrupted tag must be detected */
+    tag[0] ^= 0xff;
+    dec_len = dec.len() as CK_ULONG;
+    ret = fn_decrypt_message(
+        session,
+        &mut param as *mut CK_SALSA20_CHACHA20_POLY1305_MSG_PARAMS
+            as CK_VOID_PTR,
+        std::mem::size_of::<CK_SALSA20_CHACHA20_POLY1305_MSG_PARAMS>()
+            as CK_ULONG,
+        aad.as_mut_ptr(),
+        aad.len() as CK_ULONG,
+        menc.as_mut_ptr(),
+        menc.len() as CK_ULONG,
+        dec.as_mut_ptr(),
+        &mut dec_len,
+    );
+    assert_eq!(ret, CKR_AEAD_DECRYPT_FAILED);
+    ret = fn_message_decrypt_final(session);
+    assert_eq!(ret, CKR_OK);
+
+    /* ChaCha20 keys can not be used with Poly1305 */
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_POLY1305,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    ret = fn_sign_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_KEY_TYPE_INCONSISTENT);
+
+    /* RFC 8439, 2.5.2 */
+    let mut ktype = CKK_POLY1305;
+    let value = hex::decode(
+        "85d6be7857556d337f4452fe42d506a80103808afb0db2fd4abff6af4149f51b",
+    )
+    .expect("Failed to decode key");
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            value.as_ptr() as *mut std::ffi::c_void,
+            value.len()
+        ),
+        make_attribute!(CKA_SIGN, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_VERIFY, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut data = b"Cryptographic Forum Research Group".to_vec();
+    let mut mac = hex::decode("a8061dc1305136c6c22b8baf0c0127a9")
+        .expect("Failed to decode mac");
+    sig_and_check(session, handle, &mut data, &mut mac, &mut mechanism);
+    sig_verify(session, handle, &mut data, &mut mac, &mut mechanism);
+
+    /* key generation */
+    let mut template = vec![
+        make_attribute!(CKA_ENCRYPT, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_DECRYPT, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_CHACHA20_KEY_GEN,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    ret = fn_generate_key(
+        session,
+        &mut mechanism,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut keytype: CK_KEY_TYPE = 0;
+    let mut template = vec![make_attribute!(
+        CKA_KEY_TYPE,
+        &mut keytype as *mut _,
+        CK_ULONG_SIZE
+    )];
+    ret = fn_get_attribute_value(
+        session,
+        handle,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(keytype, CKK_CHACHA20);
+
+    ret = fn_close_session(session);
+    assert_eq!(ret, CKR_OK);
+
+    testdata.finalize();
+}
+
+#[test]
+fn test_aes_xts() {
+    let mut testdata = TestData::new("testdata/test_aes_xts.json");
+    testdata.setup_db();
+
+    let mut args = testdata.make_init_args();
+    let args_ptr = &mut args as *mut CK_C_INITIALIZE_ARGS;
+    let mut ret = fn_initialize(args_ptr as *mut std::ffi::c_void);
+    assert_eq!(ret, CKR_OK);
+    let mut session: CK_SESSION_HANDLE = CK_UNAVAILABLE_INFORMATION;
+    ret = fn_open_session(
+        testdata.get_slot(),
+        CKF_SERIAL_SESSION | CKF_RW_SESSION,
+        std::ptr::null_mut(),
+        None,
+        &mut session,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* login */
+    let pin = "12345678";
+    ret = fn_login(
+        session,
+        CKU_USER,
+        pin.as_ptr() as *mut _,
+        pin.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut class = CKO_SECRET_KEY;
+    let mut ktype = CKK_AES_XTS;
+    let mut truebool = CK_TRUE;
+
+    /* FIPS requires the two halves of the key to differ */
+    let value = [0x11u8; 32];
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            value.as_ptr() as *mut std::ffi::c_void,
+            value.len()
+        ),
+        make_attribute!(CKA_ENCRYPT, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_DECRYPT, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut handle = CK_INVALID_HANDLE;
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_ATTRIBUTE_VALUE_INVALID);
+
+    /* IEEE 1619-2007, Vector 2 */
+    let mut value = [0x11u8; 32];
+    value[16..].copy_from_slice(&[0x22u8; 16]);
+    template[2] = make_attribute!(
+        CKA_VALUE,
+        value.as_ptr() as *mut std::ffi::c_void,
+        value.len()
+    );
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut tweak = [0u8; 16];
+    tweak[..5].copy_from_slice(&[0x33u8; 5]);
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_AES_XTS,
+        pParameter: tweak.as_mut_ptr() as CK_VOID_PTR,
+        ulParameterLen: tweak.len() as CK_ULONG,
+    };
+    let plain = [0x44u8; 32];
+    let expect = hex::decode(
+        "c454185e6a16936e39334038acef838bfb186fff7480adc4289382ecd6d394f0",
+    )
+    .expect("Failed to decode ciphertext");
+
+    ret = fn_encrypt_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_OK);
+    let mut enc = vec![0u8; plain.len()];
+    let mut enc_len = enc.len() as CK_ULONG;
+    ret = fn_encrypt(
+        session,
+        plain.as_ptr() as *mut u8,
+        plain.len() as CK_ULONG,
+        enc.as_mut_ptr(),
+        &mut enc_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(enc, expect);
+
+    ret = fn_decrypt_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_OK);
+    let mut dec = vec![0u8; enc.len()];
+    let mut dec_len = dec.len() as CK_ULONG;
+    ret = fn_decrypt(
+        session,
+        enc.as_mut_ptr(),
+        enc.len() as CK_ULONG,
+        dec.as_mut_ptr(),
+        &mut dec_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(dec, plain);
+
+    /* less than a block of data */
+    ret = fn_encrypt_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_OK);
+    let mut enc_len = enc.len() as CK_ULONG;
+    ret = fn_encrypt(
+        session,
+        plain.as_ptr() as *mut u8,
+        15,
+        enc.as_mut_ptr(),
+        &mut enc_len,
+    );
+    assert_eq!(ret, CKR_DATA_LEN_RANGE);
+
+    /* multi part, one sector per part with the tweak incremented between
+     * sectors, including the carry */
+    let value: Vec<u8> = (0u8..64).collect();
+    template[2] = make_attribute!(
+        CKA_VALUE,
+        value.as_ptr() as *mut std::ffi::c_void,
+        value.len()
+    );
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut tweak = [0u8; 16];
+    tweak[0] = 0xff;
+    mechanism.pParameter = tweak.as_mut_ptr() as CK_VOID_PTR;
+    let plain: Vec<u8> = (0..1024).map(|i| (i % 256) as u8).collect();
+    let expect1 = hex::decode(
+        "75de381013f2a09b6655cf5e407ca71ccb3623e1ed6ffb5447b872c185f29eac",
+    )
+    .expect("Failed to decode ciphertext");
+    let expect2 = hex::decode(
+        "60cddc0ab876191f16b139f372f052114562e3ba83ed341f501b07b556edcae6",
+    )
+    .expect("Failed to decode ciphertext");
+
+    ret = fn_encrypt_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_OK);
+    let mut enc = vec![0u8; plain.len()];
+    for sector in 0..2 {
+        let off = sector * 512;
+        let mut enc_len: CK_ULONG = 512;
+        ret = fn_encrypt_update(
+            session,
+            plain[off..].as_ptr() as *mut u8,
+            512,
+            enc[off..].as_mut_ptr(),
+            &mut enc_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(enc_len, 512);
+    }
+    let mut enc_len: CK_ULONG = 0;
+    ret = fn_encrypt_final(session, enc.as_mut_ptr(), &mut enc_len);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(enc_len, 0);
+    assert_eq!(&enc[..32], expect1.as_slice());
+    assert_eq!(&enc[512..544], expect2.as_slice());
+
+    ret = fn_decrypt_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_OK);
+    let mut dec = vec![0u8; enc.len()];
+    for sector in 0..2 {
+        let off = sector * 512;
+        let mut dec_len: CK_ULONG = 512;
+        ret = fn_decrypt_update(
+            session,
+            enc[off..].as_mut_ptr(),
+            512,
+            dec[off..].as_mut_ptr(),
+            &mut dec_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(dec_len, 512);
+    }
+    let mut dec_len: CK_ULONG = 0;
+    ret = fn_decrypt_final(session, dec.as_mut_ptr(), &mut dec_len);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(dec, plain);
+
+    /* key generation */
+    let mut len: CK_ULONG = 64;
+    let mut template = vec![
+        make_attribute!(CKA_VALUE_LEN, &mut len as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_ENCRYPT, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_DECRYPT, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_AES_XTS_KEY_GEN,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    ret = fn_generate_key(
+        session,
+        &mut mechanism,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    len = 48;
+    ret = fn_generate_key(
+        session,
+        &mut mechanism,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_KEY_SIZE_RANGE);
+
+    /* XTS keys can not be used with other AES modes */
+    let mut iv = [0u8; 16];
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_AES_CBC,
+        pParameter: iv.as_mut_ptr() as CK_VOID_PTR,
+        ulParameterLen: iv.len() as CK_ULONG,
+    };
+    ret = fn_encrypt_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_KEY_TYPE_INCONSISTENT);
+
+    ret = fn_close_session(session);
+    assert_eq!(ret, CKR_OK);
+
+    testdata.finalize();
+}
+
+#[test]
+fn test_aes_cts() {
+    let mut testdata = TestData::new("testdata/test_aes_cts.json");
+    testdata.setup_db();
+
+    let mut args = testdata.make_init_args();
+    let args_ptr = &mut args as *mut CK_C_INITIALIZE_ARGS;
+    let mut ret = fn_initialize(args_ptr as *mut std::ffi::c_void);
+    assert_eq!(ret, CKR_OK);
+    let mut session: CK_SESSION_HANDLE = CK_UNAVAILABLE_INFORMATION;
+    ret = fn_open_session(
+        testdata.get_slot(),
+        CKF_SERIAL_SESSION | CKF_RW_SESSION,
+        std::ptr::null_mut(),
+        None,
+        &mut session,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* login */
+    let pin = "12345678";
+    ret = fn_login(
+        session,
+        CKU_USER,
+        pin.as_ptr() as *mut _,
+        pin.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* RFC 3962, Appendix B */
+    let mut class = CKO_SECRET_KEY;
+    let mut ktype = CKK_AES;
+    let mut truebool = CK_TRUE;
+    let value = hex::decode("636869636b656e207465726979616b69")
+        .expect("Failed to decode key");
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            value.as_ptr() as *mut std::ffi::c_void,
+            value.len()
+        ),
+        make_attribute!(CKA_ENCRYPT, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_DECRYPT, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut handle = CK_INVALID_HANDLE;
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let plain =
+        b"I would like the General Gau's Chicken, please, and wonton soup.";
+    let mut iv = [0u8; 16];
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_AES_CTS,
+        pParameter: iv.as_mut_ptr() as CK_VOID_PTR,
+        ulParameterLen: iv.len() as CK_ULONG,
+    };
+
+    let vectors = [
+        (17, "c6353568f2bf8cb4d8a580362da7ff7f97"),
+        (
+            31,
+            "fc00783e0efdb2c1d445d4c8eff7ed2297687268d6ecccc0c07b25e25ecfe5",
+        ),
+        (
+            32,
+            "39312523a78662d5be7fcbcc98ebf5a897687268d6ecccc0c07b25e25ecfe584",
+        ),
+        (
+            47,
+            "97687268d6ecccc0c07b25e25ecfe584b3fffd940c16a18c1b5549d2f838029e\
+             39312523a78662d5be7fcbcc98ebf5",
+        ),
+        (
+            48,
+            "97687268d6ecccc0c07b25e25ecfe5849dad8bbb96c4cdc03bc103e1a194bbd8\
+             39312523a78662d5be7fcbcc98ebf5a8",
+        ),
+        (
+            64,
+            "97687268d6ecccc0c07b25e25ecfe58439312523a78662d5be7fcbcc98ebf5a8\
+             4807efe836ee89a526730dbc2f7bc8409dad8bbb96c4cdc03bc103e1a194bbd8",
+        ),
+    ];
+    for (len, hexenc) in vectors {
+        let expect = hex::decode(hexenc).expect("Failed to decode ciphertext");
+
+        ret = fn_encrypt_init(session, &mut mechanism, handle);
+        assert_eq!(ret, CKR_OK);
+        let mut enc = vec![0u8; len];
+        let mut enc_len = enc.len() as CK_ULONG;
+        ret = fn_encrypt(
+            session,
+            plain.as_ptr() as *mut u8,
+            len as CK_ULONG,
+            enc.as_mut_ptr(),
+            &mut enc_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(enc_len as usize, len);
+        assert_eq!(enc, expect);
+
+        ret = fn_decrypt_init(session, &mut mechanism, handle);
+        assert_eq!(ret, CKR_OK);
+        let mut dec = vec![0u8; len];
+        let mut dec_len = dec.len() as CK_ULONG;
+        ret = fn_decrypt(
+            session,
+            enc.as_mut_ptr(),
+            enc.len() as CK_ULONG,
+            dec.as_mut_ptr(),
+            &mut dec_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        assert_eq!(dec_len as usize, len);
+        assert_eq!(dec.as_slice(), &plain[..len]);
+    }
+
+    /* multi part, the last two blocks are held back until final */
+    let (_, hexenc) = vectors[5];
+    let expect = hex::decode(hexenc).expect("Failed to decode ciphertext");
+    ret = fn_encrypt_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_OK);
+    let mut enc = vec![0u8; plain.len()];
+    let mut enc_off = 0;
+    for part in plain.chunks(10) {
+        let mut enc_len = (enc.len() - enc_off) as CK_ULONG;
+        ret = fn_encrypt_update(
+            session,
+            part.as_ptr() as *mut u8,
+            part.len() as CK_ULONG,
+            enc[enc_off..].as_mut_ptr(),
+            &mut enc_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        enc_off += enc_len as usize;
+    }
+    assert_eq!(enc_off, 32);
+    let mut enc_len = (enc.len() - enc_off) as CK_ULONG;
+    ret = fn_encrypt_final(session, enc[enc_off..].as_mut_ptr(), &mut enc_len);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(enc_len, 32);
+    assert_eq!(enc, expect);
+
+    ret = fn_decrypt_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_OK);
+    let mut dec = vec![0u8; enc.len()];
+    let mut dec_off = 0;
+    for part in enc.chunks(20) {
+        let mut dec_len = (dec.len() - dec_off) as CK_ULONG;
+        ret = fn_decrypt_update(
+            session,
+            part.as_ptr() as *mut u8,
+            part.len() as CK_ULONG,
+            dec[dec_off..].as_mut_ptr(),
+            &mut dec_len,
+        );
+        assert_eq!(ret, CKR_OK);
+        dec_off += dec_len as usize;
+    }
+    let mut dec_len = (dec.len() - dec_off) as CK_ULONG;
+    ret = fn_decrypt_final(session, dec[dec_off..].as_mut_ptr(), &mut dec_len);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(dec_off + dec_len as usize, plain.len());
+    assert_eq!(dec.as_slice(), plain);
+
+    /* less than a block of data */
+    ret = fn_encrypt_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_OK);
+    let mut enc_len = enc.len() as CK_ULONG;
+    ret = fn_encrypt(
+        session,
+        plain.as_ptr() as *mut u8,
+        15,
+        enc.as_mut_ptr(),
+        &mut enc_len,
+    );
+    assert_eq!(ret, CKR_DATA_LEN_RANGE);
+
+    /* CS1 keeps the partial block last */
+    mechanism.mechanism = KRYMECH_AES_CTS_CS1;
+    let expect = hex::decode(
+        "97687268d6ecccc0c07b25e25ecfe58439312523a78662d5be7fcbcc98ebf5\
+         b3fffd940c16a18c1b5549d2f838029e",
+    )
+    .expect("Failed to decode ciphertext");
+    ret = fn_encrypt_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_OK);
+    let mut enc = vec![0u8; 47];
+    let mut enc_len = enc.len() as CK_ULONG;
+    ret = fn_encrypt(
+        session,
+        plain.as_ptr() as *mut u8,
+        47,
+        enc.as_mut_ptr(),
+        &mut enc_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(enc, expect);
+
+    /* CS2 does not swap full blocks, so it is plain CBC on 48 bytes */
+    mechanism.mechanism = KRYMECH_AES_CTS_CS2;
+    let expect = hex::decode(
+        "97687268d6ecccc0c07b25e25ecfe58439312523a78662d5be7fcbcc98ebf5a8\
+         9dad8bbb96c4cdc03bc103e1a194bbd8",
+    )
+    .expect("Failed to decode ciphertext");
+    ret = fn_encrypt_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_OK);
+    let mut enc = vec![0u8; 48];
+    let mut enc_len = enc.len() as CK_ULONG;
+    ret = fn_encrypt(
+        session,
+        plain.as_ptr() as *mut u8,
+        48,
+        enc.as_mut_ptr(),
+        &mut enc_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(enc, expect);
+
+    ret = fn_decrypt_init(session, &mut mechanism, handle);
+    assert_eq!(ret, CKR_OK);
+    let mut dec = vec![0u8; enc.len()];
+    let mut dec_len = dec.len() as CK_ULONG;
+    ret = fn_decrypt(
+        session,
+        enc.as_mut_ptr(),
+        enc.len() as CK_ULONG,
+        dec.as_mut_ptr(),
+        &mut dec_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(dec.as_slice(), &plain[..48]);
+
+    ret = fn_close_session(session);
+    assert_eq!(ret, CKR_OK);
+
+    testdata.finalize();
+}
+
+#[test]
+fn test_aes_encrypt_data_derive() {
+    let mut testdata =
+        TestData::new("testdata/test_aes_encrypt_data_derive.json");
+    testdata.setup_db();
+
+    let mut args = testdata.make_init_args();
+    let args_ptr = &mut args as *mut CK_C_INITIALIZE_ARGS;
+    let mut ret = fn_initialize(args_ptr as *mut std::ffi::c_void);
+    assert_eq!(ret, CKR_OK);
+    let mut session: CK_SESSION_HANDLE = CK_UNAVAILABLE_INFORMATION;
+    ret = fn_open_session(
+        testdata.get_slot(),
+        CKF_SERIAL_SESSION | CKF_RW_SESSION,
+        std::ptr::null_mut(),
+        None,
+        &mut session,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* login */
+    let pin = "12345678";
+    ret = fn_login(
+        session,
+        CKU_USER,
+        pin.as_ptr() as *mut _,
+        pin.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* NIST SP 800-38A, F.1.1 and F.2.1 */
+    let mut class = CKO_SECRET_KEY;
+    let mut ktype = CKK_AES;
+    let mut truebool = CK_TRUE;
+    let mut falsebool = CK_FALSE;
+    let value = hex::decode("2b7e151628aed2a6abf7158809cf4f3c")
+        .expect("Failed to decode key");
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            value.as_ptr() as *mut std::ffi::c_void,
+            value.len()
+        ),
+        make_attribute!(CKA_DERIVE, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut base_key = CK_INVALID_HANDLE;
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut base_key,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut data = hex::decode(
+        "6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51",
+    )
+    .expect("Failed to decode data");
+
+    /* ECB, the key length defaults to the data length */
+    let mut params = CK_KEY_DERIVATION_STRING_DATA {
+        pData: data.as_mut_ptr(),
+        ulLen: 16,
+    };
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_AES_ECB_ENCRYPT_DATA,
+        pParameter: &mut params as *mut CK_KEY_DERIVATION_STRING_DATA as *mut _,
+        ulParameterLen: std::mem::size_of::<CK_KEY_DERIVATION_STRING_DATA>()
+            as CK_ULONG,
+    };
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_EXTRACTABLE,
+            &mut truebool as *mut _,
+            CK_BBOOL_SIZE
+        ),
+        make_attribute!(CKA_SENSITIVE, &mut falsebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut handle = CK_INVALID_HANDLE;
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        base_key,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut value = vec![0u8; 32];
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(session, handle, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extract[0].ulValueLen, 16);
+    assert_eq!(
+        &value[..16],
+        hex::decode("3ad77bb40d7a3660a89ecaf32466ef97")
+            .expect("Failed to decode value")
+            .as_slice()
+    );
+
+    /* data must be a multiple of the block size */
+    params.ulLen = 15;
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        base_key,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_MECHANISM_PARAM_INVALID);
+
+    /* CBC with an explicit key length */
+    let mut cbc_params = CK_AES_CBC_ENCRYPT_DATA_PARAMS {
+        iv: [0u8; 16],
+        pData: data.as_mut_ptr(),
+        length: data.len() as CK_ULONG,
+    };
+    for (i, b) in cbc_params.iv.iter_mut().enumerate() {
+        *b = i as u8;
+    }
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_AES_CBC_ENCRYPT_DATA,
+        pParameter: &mut cbc_params as *mut CK_AES_CBC_ENCRYPT_DATA_PARAMS
+            as *mut _,
+        ulParameterLen: std::mem::size_of::<CK_AES_CBC_ENCRYPT_DATA_PARAMS>()
+            as CK_ULONG,
+    };
+    let mut len: CK_ULONG = 32;
+    template.push(make_attribute!(
+        CKA_VALUE_LEN,
+        &mut len as *mut _,
+        CK_ULONG_SIZE
+    ));
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        base_key,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(session, handle, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extract[0].ulValueLen, 32);
+    assert_eq!(
+        value,
+        hex::decode(
+            "7649abac8119b246cee98e9b12e9197d\
+             5086cb9b507219ee95db113a917678b2"
+        )
+        .expect("Failed to decode value")
+    );
+
+    /* AES keys must still have a valid length */
+    len = 20;
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        base_key,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_TEMPLATE_INCONSISTENT);
+
+    /* the base key must allow derivation */
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            value.as_ptr() as *mut std::ffi::c_void,
+            value.len()
+        ),
+        make_attribute!(CKA_DERIVE, &mut falsebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut base_key,
+    );
+    assert_eq!(ret, CKR_OK);
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+    ];
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        base_key,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_KEY_FUNCTION_NOT_PERMITTED);
+
+    ret = fn_close_session(session);
+    assert_eq!(ret, CKR_OK);
+
+    testdata.finalize();
+}
+
+#[test]
+fn test_hkdf() {
+    let mut testdata = TestData::new("testdata/test_hkdf.json");
+    testdata.setup_db();
+
+    let mut args = testdata.make_init_args();
+    let args_ptr = &mut args as *mut CK_C_INITIALIZE_ARGS;
+    let mut ret = fn_initialize(args_ptr as *mut std::ffi::c_void);
+    assert_eq!(ret, CKR_OK);
+    let mut session: CK_SESSION_HANDLE = CK_UNAVAILABLE_INFORMATION;
+    ret = fn_open_session(
+        testdata.get_slot(),
+        CKF_SERIAL_SESSION | CKF_RW_SESSION,
+        std::ptr::null_mut(),
+        None,
+        &mut session,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* login */
+    let pin = "12345678";
+    ret = fn_login(
+        session,
+        CKU_USER,
+        pin.as_ptr() as *mut _,
+        pin.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* RFC 5869, A.1 */
+    let mut class = CKO_SECRET_KEY;
+    let mut ktype = CKK_GENERIC_SECRET;
+    let mut truebool = CK_TRUE;
+    let mut falsebool = CK_FALSE;
+    let ikm = [0x0bu8; 22];
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            ikm.as_ptr() as *mut std::ffi::c_void,
+            ikm.len()
+        ),
+        make_attribute!(CKA_DERIVE, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut base_key = CK_INVALID_HANDLE;
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut base_key,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut salt: Vec<u8> = (0u8..13).collect();
+    let mut info: Vec<u8> = (0xf0u8..0xfa).collect();
+    let prk = hex::decode(
+        "077709362c2e32df0ddc3f0dc47bba6390b6c73bb50f9c3122ec844ad7c2b3e5",
+    )
+    .expect("Failed to decode prk");
+    let okm = hex::decode(
+        "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf\
+         34007208d5b887185865",
+    )
+    .expect("Failed to decode okm");
+
+    let mut params = CK_HKDF_PARAMS {
+        bExtract: CK_TRUE,
+        bExpand: CK_TRUE,
+        prfHashMechanism: CKM_SHA256,
+        ulSaltType: CKF_HKDF_SALT_DATA,
+        pSalt: salt.as_mut_ptr(),
+        ulSaltLen: salt.len() as CK_ULONG,
+        hSaltKey: CK_INVALID_HANDLE,
+        pInfo: info.as_mut_ptr(),
+        ulInfoLen: info.len() as CK_ULONG,
+    };
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_HKDF_DERIVE,
+        pParameter: &mut params as *mut CK_HKDF_PARAMS as *mut _,
+        ulParameterLen: std::mem::size_of::<CK_HKDF_PARAMS>() as CK_ULONG,
+    };
+    let mut len: CK_ULONG = okm.len() as CK_ULONG;
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_EXTRACTABLE,
+            &mut truebool as *mut _,
+            CK_BBOOL_SIZE
+        ),
+        make_attribute!(CKA_SENSITIVE, &mut falsebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_VALUE_LEN, &mut len as *mut _, CK_ULONG_SIZE),
+    ];
+    let mut handle = CK_INVALID_HANDLE;
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        base_key,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut value = vec![0u8; 64];
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(session, handle, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extract[0].ulValueLen as usize, okm.len());
+    assert_eq!(&value[..okm.len()], okm.as_slice());
+
+    /* expand requires an explicit length */
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        base_key,
+        template.as_mut_ptr(),
+        4,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_TEMPLATE_INCOMPLETE);
+
+    /* extract only returns the PRK */
+    params.bExpand = CK_FALSE;
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        base_key,
+        template.as_mut_ptr(),
+        4,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(session, handle, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extract[0].ulValueLen as usize, prk.len());
+    assert_eq!(&value[..prk.len()], prk.as_slice());
+
+    /* the same derivation with the salt in a key object */
+    let mut salt_template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            salt.as_ptr() as *mut std::ffi::c_void,
+            salt.len()
+        ),
+    ];
+    let mut salt_key = CK_INVALID_HANDLE;
+    ret = fn_create_object(
+        session,
+        salt_template.as_mut_ptr(),
+        salt_template.len() as CK_ULONG,
+        &mut salt_key,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    params.bExpand = CK_TRUE;
+    params.ulSaltType = CKF_HKDF_SALT_KEY;
+    params.pSalt = std::ptr::null_mut();
+    params.ulSaltLen = 0;
+    params.hSaltKey = salt_key;
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        base_key,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(session, handle, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(&value[..okm.len()], okm.as_slice());
+
+    /* RFC 5869, A.3, output to a data object */
+    let okm = hex::decode(
+        "8da4e775a563c18f715f802a063c5a31b8a11f5c5ee1879ec3454e5f3c738d2d\
+         9d201395faa4b61a96c8",
+    )
+    .expect("Failed to decode okm");
+    params.ulSaltType = CKF_HKDF_SALT_NULL;
+    params.hSaltKey = CK_INVALID_HANDLE;
+    params.pInfo = std::ptr::null_mut();
+    params.ulInfoLen = 0;
+    mechanism.mechanism = CKM_HKDF_DATA;
+    let mut dataclass = CKO_DATA;
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut dataclass as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_VALUE_LEN, &mut len as *mut _, CK_ULONG_SIZE),
+    ];
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        base_key,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(session, handle, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extract[0].ulValueLen as usize, okm.len());
+    assert_eq!(&value[..okm.len()], okm.as_slice());
+
+    /* HKDF keys */
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_HKDF_KEY_GEN,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    len = 32;
+    let mut template = vec![
+        make_attribute!(CKA_VALUE_LEN, &mut len as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_DERIVE, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    ret = fn_generate_key(
+        session,
+        &mut mechanism,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut keytype: CK_KEY_TYPE = 0;
+    let mut extract = vec![make_attribute!(
+        CKA_KEY_TYPE,
+        &mut keytype as *mut _,
+        CK_ULONG_SIZE
+    )];
+    ret = fn_get_attribute_value(session, handle, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(keytype, CKK_HKDF);
+
+    ret = fn_close_session(session);
+    assert_eq!(ret, CKR_OK);
+
+    testdata.finalize();
+}
+
+#[test]
+fn test_kbkdf() {
+    let mut testdata = TestData::new("testdata/test_kbkdf.json");
+    testdata.setup_db();
+
+    let mut args = testdata.make_init_args();
+    let args_ptr = &mut args as *mut CK_C_INITIALIZE_ARGS;
+    let mut ret = fn_initialize(args_ptr as *mut std::ffi::c_void);
+    assert_eq!(ret, CKR_OK);
+    let mut session: CK_SESSION_HANDLE = CK_UNAVAILABLE_INFORMATION;
+    ret = fn_open_session(
+        testdata.get_slot(),
+        CKF_SERIAL_SESSION | CKF_RW_SESSION,
+        std::ptr::null_mut(),
+        None,
+        &mut session,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* login */
+    let pin = "12345678";
+    ret = fn_login(
+        session,
+        CKU_USER,
+        pin.as_ptr() as *mut _,
+        pin.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut class = CKO_SECRET_KEY;
+    let mut ktype = CKK_GENERIC_SECRET;
+    let mut aestype = CKK_AES;
+    let mut truebool = CK_TRUE;
+    let mut falsebool = CK_FALSE;
+    let hmac_ki: Vec<u8> = (0u8..32).collect();
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            hmac_ki.as_ptr() as *mut std::ffi::c_void,
+            hmac_ki.len()
+        ),
+        make_attribute!(CKA_DERIVE, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut hmac_key = CK_INVALID_HANDLE;
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut hmac_key,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let cmac_ki: Vec<u8> = (0u8..16).collect();
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut aestype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            cmac_ki.as_ptr() as *mut std::ffi::c_void,
+            cmac_ki.len()
+        ),
+        make_attribute!(CKA_DERIVE, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut cmac_key = CK_INVALID_HANDLE;
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut cmac_key,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* Label || 0x00 || Context */
+    let mut fixed: Vec<u8> = Vec::new();
+    fixed.extend_from_slice(b"kbkdf label");
+    fixed.push(0);
+    fixed.extend_from_slice(b"tenant context");
+
+    let mut counter_format = CK_SP800_108_COUNTER_FORMAT {
+        bLittleEndian: CK_FALSE,
+        ulWidthInBits: 32,
+    };
+    let mut dkm_format = CK_SP800_108_DKM_LENGTH_FORMAT {
+        dkmLengthMethod: CK_SP800_108_DKM_LENGTH_SUM_OF_KEYS,
+        bLittleEndian: CK_FALSE,
+        ulWidthInBits: 32,
+    };
+    let mut data_params = vec![
+        CK_PRF_DATA_PARAM {
+            type_: CK_SP800_108_ITERATION_VARIABLE,
+            pValue: &mut counter_format as *mut _ as CK_VOID_PTR,
+            ulValueLen: std::mem::size_of::<CK_SP800_108_COUNTER_FORMAT>()
+                as CK_ULONG,
+        },
+        CK_PRF_DATA_PARAM {
+            type_: CK_SP800_108_BYTE_ARRAY,
+            pValue: fixed.as_mut_ptr() as CK_VOID_PTR,
+            ulValueLen: fixed.len() as CK_ULONG,
+        },
+        CK_PRF_DATA_PARAM {
+            type_: CK_SP800_108_DKM_LENGTH,
+            pValue: &mut dkm_format as *mut _ as CK_VOID_PTR,
+            ulValueLen: std::mem::size_of::<CK_SP800_108_DKM_LENGTH_FORMAT>()
+                as CK_ULONG,
+        },
+    ];
+
+    /* counter mode with HMAC, the primary key and an additional key are
+     * cut from the same key stream */
+    let dkm = hex::decode(
+        "f0aa61e61050814f2c53acb16a261f78d1ccaa677611658b48f301103a93b97b\
+         e9c15cd01b61ba6af350",
+    )
+    .expect("Failed to decode dkm");
+    let mut len1: CK_ULONG = 16;
+    let mut len2: CK_ULONG = 26;
+    let mut add_template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_EXTRACTABLE,
+            &mut truebool as *mut _,
+            CK_BBOOL_SIZE
+        ),
+        make_attribute!(CKA_SENSITIVE, &mut falsebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_VALUE_LEN, &mut len2 as *mut _, CK_ULONG_SIZE),
+    ];
+    let mut add_handle = CK_INVALID_HANDLE;
+    let mut add_keys = vec![CK_DERIVED_KEY {
+        pTemplate: add_template.as_mut_ptr(),
+        ulAttributeCount: add_template.len() as CK_ULONG,
+        phKey: &mut add_handle,
+    }];
+    let mut params = CK_SP800_108_KDF_PARAMS {
+        prfType: CKM_SHA256_HMAC,
+        ulNumberOfDataParams: data_params.len() as CK_ULONG,
+        pDataParams: data_params.as_mut_ptr(),
+        ulAdditionalDerivedKeys: add_keys.len() as CK_ULONG,
+        pAdditionalDerivedKeys: add_keys.as_mut_ptr(),
+    };
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_SP800_108_COUNTER_KDF,
+        pParameter: &mut params as *mut CK_SP800_108_KDF_PARAMS as *mut _,
+        ulParameterLen: std::mem::size_of::<CK_SP800_108_KDF_PARAMS>()
+            as CK_ULONG,
+    };
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_EXTRACTABLE,
+            &mut truebool as *mut _,
+            CK_BBOOL_SIZE
+        ),
+        make_attribute!(CKA_SENSITIVE, &mut falsebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_VALUE_LEN, &mut len1 as *mut _, CK_ULONG_SIZE),
+    ];
+    let mut handle = CK_INVALID_HANDLE;
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        hmac_key,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_ne!(add_handle, CK_INVALID_HANDLE);
+
+    let mut value = vec![0u8; 64];
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(session, handle, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extract[0].ulValueLen, len1);
+    assert_eq!(&value[..16], &dkm[..16]);
+
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(session, add_handle, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extract[0].ulValueLen, len2);
+    assert_eq!(&value[..26], &dkm[16..]);
+
+    /* the key length is mandatory */
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        hmac_key,
+        template.as_mut_ptr(),
+        4,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_TEMPLATE_INCOMPLETE);
+
+    /* counter mode with AES-CMAC */
+    params.prfType = CKM_AES_CMAC;
+    params.ulAdditionalDerivedKeys = 0;
+    params.pAdditionalDerivedKeys = std::ptr::null_mut();
+    len1 = 32;
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        cmac_key,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let dkm = hex::decode(
+        "bb316df2244315823dffb3ab46477c977076fe8f9774369e46367f41015b318a",
+    )
+    .expect("Failed to decode dkm");
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(session, handle, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extract[0].ulValueLen, len1);
+    assert_eq!(&value[..32], dkm.as_slice());
+
+    /* the PRF must match the base key type */
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        hmac_key,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_KEY_TYPE_INCONSISTENT);
+
+    /* feedback mode, with an optional 8 bit counter after the iteration
+     * variable */
+    let mut iv: Vec<u8> = (0xa0u8..0xb0).collect();
+    let mut counter_format = CK_SP800_108_COUNTER_FORMAT {
+        bLittleEndian: CK_FALSE,
+        ulWidthInBits: 8,
+    };
+    let mut data_params = vec![
+        CK_PRF_DATA_PARAM {
+            type_: CK_SP800_108_ITERATION_VARIABLE,
+            pValue: std::ptr::null_mut(),
+            ulValueLen: 0,
+        },
+        CK_PRF_DATA_PARAM {
+            type_: CK_SP800_108_OPTIONAL_COUNTER,
+            pValue: &mut counter_format as *mut _ as CK_VOID_PTR,
+            ulValueLen: std::mem::size_of::<CK_SP800_108_COUNTER_FORMAT>()
+                as CK_ULONG,
+        },
+        CK_PRF_DATA_PARAM {
+            type_: CK_SP800_108_BYTE_ARRAY,
+            pValue: fixed.as_mut_ptr() as CK_VOID_PTR,
+            ulValueLen: fixed.len() as CK_ULONG,
+        },
+    ];
+    let mut fb_params = CK_SP800_108_FEEDBACK_KDF_PARAMS {
+        prfType: CKM_SHA256_HMAC,
+        ulNumberOfDataParams: data_params.len() as CK_ULONG,
+        pDataParams: data_params.as_mut_ptr(),
+        ulIVLen: iv.len() as CK_ULONG,
+        pIV: iv.as_mut_ptr(),
+        ulAdditionalDerivedKeys: 0,
+        pAdditionalDerivedKeys: std::ptr::null_mut(),
+    };
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_SP800_108_FEEDBACK_KDF,
+        pParameter: &mut fb_params as *mut CK_SP800_108_FEEDBACK_KDF_PARAMS
+            as *mut _,
+        ulParameterLen: std::mem::size_of::<CK_SP800_108_FEEDBACK_KDF_PARAMS>()
+            as CK_ULONG,
+    };
+    len1 = 40;
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        hmac_key,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let dkm = hex::decode(
+        "dfd90cb1f52deb9c7827872b92a0818d87aedf283f8f1ffb67b5e8d383781054\
+         761b3cc2fd93570f",
+    )
+    .expect("Failed to decode dkm");
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(session, handle, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extract[0].ulValueLen, len1);
+    assert_eq!(&value[..40], dkm.as_slice());
+
+    /* double pipeline mode, with the length of all the PRF output */
+    let mut dkm_format = CK_SP800_108_DKM_LENGTH_FORMAT {
+        dkmLengthMethod: CK_SP800_108_DKM_LENGTH_SUM_OF_SEGMENTS,
+        bLittleEndian: CK_FALSE,
+        ulWidthInBits: 16,
+    };
+    let mut data_params = vec![
+        CK_PRF_DATA_PARAM {
+            type_: CK_SP800_108_ITERATION_VARIABLE,
+            pValue: std::ptr::null_mut(),
+            ulValueLen: 0,
+        },
+        CK_PRF_DATA_PARAM {
+            type_: CK_SP800_108_BYTE_ARRAY,
+            pValue: fixed.as_mut_ptr() as CK_VOID_PTR,
+            ulValueLen: fixed.len() as CK_ULONG,
+        },
+        CK_PRF_DATA_PARAM {
+            type_: CK_SP800_108_DKM_LENGTH,
+            pValue: &mut dkm_format as *mut _ as CK_VOID_PTR,
+            ulValueLen: std::mem::size_of::<CK_SP800_108_DKM_LENGTH_FORMAT>()
+                as CK_ULONG,
+        },
+    ];
+    let mut params = CK_SP800_108_KDF_PARAMS {
+        prfType: CKM_SHA256_HMAC,
+        ulNumberOfDataParams: data_params.len() as CK_ULONG,
+        pDataParams: data_params.as_mut_ptr(),
+        ulAdditionalDerivedKeys: 0,
+        pAdditionalDerivedKeys: std::ptr::null_mut(),
+    };
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_SP800_108_DOUBLE_PIPELINE_KDF,
+        pParameter: &mut params as *mut CK_SP800_108_KDF_PARAMS as *mut _,
+        ulParameterLen: std::mem::size_of::<CK_SP800_108_KDF_PARAMS>()
+            as CK_ULONG,
+    };
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        hmac_key,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let dkm = hex::decode(
+        "0bbeafa8c407184ebae82629ebc1edb6f48e774aed5b1e3d083bc432289b25ca\
+         0f4e8de205e0ccdd",
+    )
+    .expect("Failed to decode dkm");
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(session, handle, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extract[0].ulValueLen, len1);
+    assert_eq!(&value[..40], dkm.as_slice());
+
+    /* a counter is only optional in feedback and double pipeline mode */
+    data_params[0] = CK_PRF_DATA_PARAM {
+        type_: CK_SP800_108_OPTIONAL_COUNTER,
+        pValue: &mut counter_format as *mut _ as CK_VOID_PTR,
+        ulValueLen: std::mem::size_of::<CK_SP800_108_COUNTER_FORMAT>()
+            as CK_ULONG,
+    };
+    mechanism.mechanism = CKM_SP800_108_COUNTER_KDF;
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        hmac_key,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_MECHANISM_PARAM_INVALID);
+
+    ret = fn_close_session(session);
+    assert_eq!(ret, CKR_OK);
+
+    testdata.finalize();
+}
+
+#[test]
+fn test_pbkdf2() {
+    let mut testdata = TestData::new("testdata/test_pbkdf2.json");
+    testdata.setup_db();
+
+    let mut args = testdata.make_init_args();
+    let args_ptr = &mut args as *mut CK_C_INITIALIZE_ARGS;
+    let mut ret = fn_initialize(args_ptr as *mut std::ffi::c_void);
+    assert_eq!(ret, CKR_OK);
+    let mut session: CK_SESSION_HANDLE = CK_UNAVAILABLE_INFORMATION;
+    ret = fn_open_session(
+        testdata.get_slot(),
+        CKF_SERIAL_SESSION | CKF_RW_SESSION,
+        std::ptr::null_mut(),
+        None,
+        &mut session,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* login */
+    let pin = "12345678";
+    ret = fn_login(
+        session,
+        CKU_USER,
+        pin.as_ptr() as *mut _,
+        pin.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* RFC 6070 */
+    let mut password = b"passwordPASSWORDpassword".to_vec();
+    let mut salt = b"saltSALTsaltSALTsaltSALTsaltSALTsalt".to_vec();
+    let dk = hex::decode("3d2eec4fe41c849b80c8d83662c0e44a8b291a964cf2f07038")
+        .expect("Failed to decode dk");
+
+    let mut params = CK_PKCS5_PBKD2_PARAMS2 {
+        saltSource: CKZ_SALT_SPECIFIED,
+        pSaltSourceData: salt.as_mut_ptr() as CK_VOID_PTR,
+        ulSaltSourceDataLen: salt.len() as CK_ULONG,
+        iterations: 4096,
+        prf: CKP_PKCS5_PBKD2_HMAC_SHA1,
+        pPrfData: std::ptr::null_mut(),
+        ulPrfDataLen: 0,
+        pPassword: password.as_mut_ptr(),
+        ulPasswordLen: password.len() as CK_ULONG,
+    };
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_PKCS5_PBKD2,
+        pParameter: &mut params as *mut CK_PKCS5_PBKD2_PARAMS2 as *mut _,
+        ulParameterLen: std::mem::size_of::<CK_PKCS5_PBKD2_PARAMS2>()
+            as CK_ULONG,
+    };
+    let mut class = CKO_SECRET_KEY;
+    let mut ktype = CKK_GENERIC_SECRET;
+    let mut len: CK_ULONG = dk.len() as CK_ULONG;
+    let mut truebool = CK_TRUE;
+    let mut falsebool = CK_FALSE;
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_VALUE_LEN, &mut len as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_EXTRACTABLE,
+            &mut truebool as *mut _,
+            CK_BBOOL_SIZE
+        ),
+        make_attribute!(CKA_SENSITIVE, &mut falsebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut handle = CK_INVALID_HANDLE;
+    ret = fn_generate_key(
+        session,
+        &mut mechanism,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut value = vec![0u8; 64];
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(session, handle, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extract[0].ulValueLen as usize, dk.len());
+    assert_eq!(&value[..dk.len()], dk.as_slice());
+
+    /* an AES key with a SHA256 based PRF */
+    let dk = hex::decode(
+        "4610df202292270a7613e4723f6e8d1e513fb62caba8fb8a0168293411f2896c",
+    )
+    .expect("Failed to decode dk");
+    params.iterations = 1000;
+    params.prf = CKP_PKCS5_PBKD2_HMAC_SHA256;
+    ktype = CKK_AES;
+    len = dk.len() as CK_ULONG;
+    ret = fn_generate_key(
+        session,
+        &mut mechanism,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(session, handle, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extract[0].ulValueLen as usize, dk.len());
+    assert_eq!(&value[..dk.len()], dk.as_slice());
+
+    /* AES key sizes are still enforced */
+    len = 20;
+    ret = fn_generate_key(
+        session,
+        &mut mechanism,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_KEY_SIZE_RANGE);
+
+    /* unsupported PRF */
+    len = 32;
+    params.prf = CKP_PKCS5_PBKD2_HMAC_GOSTR3411;
+    ret = fn_generate_key(
+        session,
+        &mut mechanism,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_MECHANISM_PARAM_INVALID);
+
+    /* short salts are rejected in FIPS mode */
+    #[cfg(feature = "fips")]
+    {
+        params.prf = CKP_PKCS5_PBKD2_HMAC_SHA256;
+        params.ulSaltSourceDataLen = 8;
+        ret = fn_generate_key(
+            session,
+            &mut mechanism,
+            template.as_mut_ptr(),
+            template.len() as CK_ULONG,
+            &mut handle,
+        );
+        assert_eq!(ret, CKR_MECHANISM_PARAM_INVALID);
+    }
+
+    ret = fn_close_session(session);
+    assert_eq!(ret, CKR_OK);
+
+    testdata.finalize();
+}
+
+#[test]
+fn test_tls() {
+    let mut testdata = TestData::new("testdata/test_tls.json");
+    testdata.setup_db();
+
+    let mut args = testdata.make_init_args();
+    let args_ptr = &mut args as *mut CK_C_INITIALIZE_ARGS;
+    let mut ret = fn_initialize(args_ptr as *mut std::ffi::c_void);
+    assert_eq!(ret, CKR_OK);
+    let mut session: CK_SESSION_HANDLE = CK_UNAVAILABLE_INFORMATION;
+    ret = fn_open_session(
+        testdata.get_slot(),
+        CKF_SERIAL_SESSION | CKF_RW_SESSION,
+        std::ptr::null_mut(),
+        None,
+        &mut session,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* login */
+    let pin = "12345678";
+    ret = fn_login(
+        session,
+        CKU_USER,
+        pin.as_ptr() as *mut _,
+        pin.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut class = CKO_SECRET_KEY;
+    let mut ktype = CKK_GENERIC_SECRET;
+    let mut aestype = CKK_AES;
+    let mut truebool = CK_TRUE;
+    let mut falsebool = CK_FALSE;
+
+    /* pre-master secret, starts with the client version */
+    let mut pms: Vec<u8> = vec![3, 3];
+    pms.extend(2u8..48);
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            pms.as_ptr() as *mut std::ffi::c_void,
+            pms.len()
+        ),
+        make_attribute!(CKA_DERIVE, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut pms_key = CK_INVALID_HANDLE;
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut pms_key,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut client_random: Vec<u8> = (0x10u8..0x30).collect();
+    let mut server_random: Vec<u8> = (0x30u8..0x50).collect();
+    let mut version = CK_VERSION { major: 0, minor: 0 };
+    let mut params = CK_TLS12_MASTER_KEY_DERIVE_PARAMS {
+        RandomInfo: CK_SSL3_RANDOM_DATA {
+            pClientRandom: client_random.as_mut_ptr(),
+            ulClientRandomLen: client_random.len() as CK_ULONG,
+            pServerRandom: server_random.as_mut_ptr(),
+            ulServerRandomLen: server_random.len() as CK_ULONG,
+        },
+        pVersion: &mut version,
+        prfHashMechanism: CKM_SHA256,
+    };
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_TLS12_MASTER_KEY_DERIVE,
+        pParameter: &mut params as *mut CK_TLS12_MASTER_KEY_DERIVE_PARAMS
+            as *mut _,
+        ulParameterLen: std::mem::size_of::<CK_TLS12_MASTER_KEY_DERIVE_PARAMS>()
+            as CK_ULONG,
+    };
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_EXTRACTABLE,
+            &mut truebool as *mut _,
+            CK_BBOOL_SIZE
+        ),
+        make_attribute!(CKA_SENSITIVE, &mut falsebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_DERIVE, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_SIGN, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_VERIFY, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut master_key = CK_INVALID_HANDLE;
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        pms_key,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut master_key,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(version.major, 3);
+    assert_eq!(version.minor, 3);
+
+    let ms = hex::decode(
+        "8beb5449e24a299bbf2b069dd6d7e8fba6cd0defe447f26ede0dc8cc9e221d03\
+         7283663b2ad6dc15dcc92f9462a56b49",
+    )
+    .expect("Failed to decode master secret");
+    let mut value = vec![0u8; 128];
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(session, master_key, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extract[0].ulValueLen as usize, ms.len());
+    assert_eq!(&value[..ms.len()], ms.as_slice());
+
+    /* the DH variant does not return a version */
+    mechanism.mechanism = CKM_TLS12_MASTER_KEY_DERIVE_DH;
+    let mut handle = CK_INVALID_HANDLE;
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        pms_key,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_MECHANISM_PARAM_INVALID);
+
+    /* extended master secret */
+    let mut session_hash: Vec<u8> = (0x60u8..0x80).collect();
+    let mut ems_params = CK_NSS_TLS_EXTENDED_MASTER_KEY_DERIVE_PARAMS {
+        prfHashMechanism: CKM_SHA256,
+        pSessionHash: session_hash.as_mut_ptr(),
+        ulSessionHashLen: session_hash.len() as CK_ULONG,
+        pVersion: std::ptr::null_mut(),
+    };
+    let mut ems_mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_NSS_TLS_EXTENDED_MASTER_KEY_DERIVE,
+        pParameter: &mut ems_params
+            as *mut CK_NSS_TLS_EXTENDED_MASTER_KEY_DERIVE_PARAMS
+            as *mut _,
+        ulParameterLen: std::mem::size_of::<
+            CK_NSS_TLS_EXTENDED_MASTER_KEY_DERIVE_PARAMS,
+        >() as CK_ULONG,
+    };
+    ret = fn_derive_key(
+        session,
+        &mut ems_mechanism,
+        pms_key,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let ems = hex::decode(
+        "32b63f922cf90224e1907b9213fade9069087623a77f6e2278ff8068623842b4\
+         1cd255a9190cf85a3d9ec9ec66af0c37",
+    )
+    .expect("Failed to decode extended master secret");
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(session, handle, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extract[0].ulValueLen as usize, ems.len());
+    assert_eq!(&value[..ems.len()], ems.as_slice());
+
+    /* key expansion, all keys are returned in the key material */
+    let mut iv_client = vec![0u8; 16];
+    let mut iv_server = vec![0u8; 16];
+    let mut keymat = CK_SSL3_KEY_MAT_OUT {
+        hClientMacSecret: CK_INVALID_HANDLE,
+        hServerMacSecret: CK_INVALID_HANDLE,
+        hClientKey: CK_INVALID_HANDLE,
+        hServerKey: CK_INVALID_HANDLE,
+        pIVClient: iv_client.as_mut_ptr(),
+        pIVServer: iv_server.as_mut_ptr(),
+    };
+    let mut km_params = CK_TLS12_KEY_MAT_PARAMS {
+        ulMacSizeInBits: 256,
+        ulKeySizeInBits: 128,
+        ulIVSizeInBits: 128,
+        bIsExport: CK_FALSE,
+        RandomInfo: CK_SSL3_RANDOM_DATA {
+            pClientRandom: client_random.as_mut_ptr(),
+            ulClientRandomLen: client_random.len() as CK_ULONG,
+            pServerRandom: server_random.as_mut_ptr(),
+            ulServerRandomLen: server_random.len() as CK_ULONG,
+        },
+        pReturnedKeyMaterial: &mut keymat,
+        prfHashMechanism: CKM_SHA256,
+    };
+    let mut km_mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_TLS12_KEY_AND_MAC_DERIVE,
+        pParameter: &mut km_params as *mut CK_TLS12_KEY_MAT_PARAMS as *mut _,
+        ulParameterLen: std::mem::size_of::<CK_TLS12_KEY_MAT_PARAMS>()
+            as CK_ULONG,
+    };
+    let mut km_template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut aestype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_EXTRACTABLE,
+            &mut truebool as *mut _,
+            CK_BBOOL_SIZE
+        ),
+        make_attribute!(CKA_SENSITIVE, &mut falsebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_ENCRYPT, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    ret = fn_derive_key(
+        session,
+        &mut km_mechanism,
+        master_key,
+        km_template.as_mut_ptr(),
+        km_template.len() as CK_ULONG,
+        std::ptr::null_mut(),
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let smac = hex::decode(
+        "d9d638235283d62ebbce59da377c9c0d715e1cfe2d7f739fc63aed85e6955c8b",
+    )
+    .expect("Failed to decode server mac secret");
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(
+        session,
+        keymat.hServerMacSecret,
+        extract.as_mut_ptr(),
+        1,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extract[0].ulValueLen as usize, smac.len());
+    assert_eq!(&value[..smac.len()], smac.as_slice());
+
+    let ckey = hex::decode("654468302ae594718be8ef2eb381552c")
+        .expect("Failed to decode client key");
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(
+        session,
+        keymat.hClientKey,
+        extract.as_mut_ptr(),
+        1,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extract[0].ulValueLen as usize, ckey.len());
+    assert_eq!(&value[..ckey.len()], ckey.as_slice());
+    assert_ne!(keymat.hServerKey, CK_INVALID_HANDLE);
+    assert_ne!(keymat.hClientMacSecret, CK_INVALID_HANDLE);
+
+    assert_eq!(
+        iv_client,
+        hex::decode("1b578b8fcc54d98edacc4ba04da57a00")
+            .expect("Failed to decode client iv")
+    );
+    assert_eq!(
+        iv_server,
+        hex::decode("805a568df556308f281fb010598ef30b")
+            .expect("Failed to decode server iv")
+    );
+
+    /* export ciphers are not supported */
+    km_params.bIsExport = CK_TRUE;
+    ret = fn_derive_key(
+        session,
+        &mut km_mechanism,
+        master_key,
+        km_template.as_mut_ptr(),
+        km_template.len() as CK_ULONG,
+        std::ptr::null_mut(),
+    );
+    assert_eq!(ret, CKR_MECHANISM_PARAM_INVALID);
+
+    /* generic TLS KDF, the published TLS 1.2 PRF test vector */
+    let secret = hex::decode("9bbe436ba940f017b17652849a71db35")
+        .expect("Failed to decode secret");
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            secret.as_ptr() as *mut std::ffi::c_void,
+            secret.len()
+        ),
+        make_attribute!(CKA_DERIVE, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut secret_key = CK_INVALID_HANDLE;
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut secret_key,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut seed = hex::decode("a0ba9f936cda311827a6f796ffd5198c")
+        .expect("Failed to decode seed");
+    let mut label = b"test label".to_vec();
+    let mut kdf_params = CK_TLS_KDF_PARAMS {
+        prfMechanism: CKM_SHA256,
+        pLabel: label.as_mut_ptr(),
+        ulLabelLength: label.len() as CK_ULONG,
+        RandomInfo: CK_SSL3_RANDOM_DATA {
+            pClientRandom: seed.as_mut_ptr(),
+            ulClientRandomLen: 8,
+            pServerRandom: unsafe { seed.as_mut_ptr().add(8) },
+            ulServerRandomLen: 8,
+        },
+        pContextData: std::ptr::null_mut(),
+        ulContextDataLength: 0,
+    };
+    let mut kdf_mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_TLS12_KDF,
+        pParameter: &mut kdf_params as *mut CK_TLS_KDF_PARAMS as *mut _,
+        ulParameterLen: std::mem::size_of::<CK_TLS_KDF_PARAMS>() as CK_ULONG,
+    };
+    let mut len: CK_ULONG = 100;
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_EXTRACTABLE,
+            &mut truebool as *mut _,
+            CK_BBOOL_SIZE
+        ),
+        make_attribute!(CKA_SENSITIVE, &mut falsebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_VALUE_LEN, &mut len as *mut _, CK_ULONG_SIZE),
+    ];
+    ret = fn_derive_key(
+        session,
+        &mut kdf_mechanism,
+        secret_key,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let output = hex::decode(
+        "e3f229ba727be17b8d122620557cd453c2aab21d07c3d495329b52d4e61edb5a\
+         6b301791e90d35c9c9a46b4e14baf9af0fa022f7077def17abfd3797c0564bab\
+         4fbc91666e9def9b97fce34f796789baa48082d122ee42c5a72e5a5110fff701\
+         87347b66",
+    )
+    .expect("Failed to decode output");
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(session, handle, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extract[0].ulValueLen as usize, output.len());
+    assert_eq!(&value[..output.len()], output.as_slice());
+
+    /* the key length is mandatory */
+    ret = fn_derive_key(
+        session,
+        &mut kdf_mechanism,
+        secret_key,
+        template.as_mut_ptr(),
+        3,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_TEMPLATE_INCOMPLETE);
+
+    /* keying material exporter with a context */
+    let mut exp_label = b"EXPORTER label".to_vec();
+    let mut context = b"exporter context".to_vec();
+    kdf_params.pLabel = exp_label.as_mut_ptr();
+    kdf_params.ulLabelLength = exp_label.len() as CK_ULONG;
+    kdf_params.RandomInfo.pClientRandom = client_random.as_mut_ptr();
+    kdf_params.RandomInfo.ulClientRandomLen = client_random.len() as CK_ULONG;
+    kdf_params.RandomInfo.pServerRandom = server_random.as_mut_ptr();
+    kdf_params.RandomInfo.ulServerRandomLen = server_random.len() as CK_ULONG;
+    kdf_params.pContextData = context.as_mut_ptr();
+    kdf_params.ulContextDataLength = context.len() as CK_ULONG;
+    len = 32;
+    ret = fn_derive_key(
+        session,
+        &mut kdf_mechanism,
+        master_key,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let output = hex::decode(
+        "e78517fc1dec34117e93c5f8bb4dfc1d7d4c20d7d74630df405a7f81dba59a5f",
+    )
+    .expect("Failed to decode output");
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(session, handle, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extract[0].ulValueLen as usize, output.len());
+    assert_eq!(&value[..output.len()], output.as_slice());
+
+    /* finished message MAC */
+    let mut mac_params = CK_TLS_MAC_PARAMS {
+        prfHashMechanism: CKM_SHA256,
+        ulMacLength: 12,
+        ulServerOrClient: 2,
+    };
+    let mut mac_mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_TLS_MAC,
+        pParameter: &mut mac_params as *mut CK_TLS_MAC_PARAMS as *mut _,
+        ulParameterLen: std::mem::size_of::<CK_TLS_MAC_PARAMS>() as CK_ULONG,
+    };
+    let data = b"handshake messages hash";
+    let verify_data = hex::decode("2463b537aa77c45c5a0cf4a7")
+        .expect("Failed to decode verify data");
+
+    ret = fn_sign_init(session, &mut mac_mechanism, master_key);
+    assert_eq!(ret, CKR_OK);
+    let mut signature = vec![0u8; 12];
+    let mut siglen: CK_ULONG = signature.len() as CK_ULONG;
+    ret = fn_sign(
+        session,
+        data.as_ptr() as *mut u8,
+        data.len() as CK_ULONG,
+        signature.as_mut_ptr(),
+        &mut siglen,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(signature, verify_data);
+
+    ret = fn_verify_init(session, &mut mac_mechanism, master_key);
+    assert_eq!(ret, CKR_OK);
+    ret = fn_verify(
+        session,
+        data.as_ptr() as *mut u8,
+        data.len() as CK_ULONG,
+        signature.as_mut_ptr(),
+        siglen,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* the server label gives a different result */
+    mac_params.ulServerOrClient = 1;
+    ret = fn_verify_init(session, &mut mac_mechanism, master_key);
+    assert_eq!(ret, CKR_OK);
+    ret = fn_verify(
+        session,
+        data.as_ptr() as *mut u8,
+        data.len() as CK_ULONG,
+        signature.as_mut_ptr(),
+        siglen,
+    );
+    assert_eq!(ret, CKR_SIGNATURE_INVALID);
+
+    ret = fn_close_session(session);
+    assert_eq!(ret, CKR_OK);
+
+    testdata.finalize();
+}
+
+#[test]
+fn test_sshkdf() {
+    let mut testdata = TestData::new("testdata/test_sshkdf.json");
+    testdata.setup_db();
+
+    let mut args = testdata.make_init_args();
+    let args_ptr = &mut args as *mut CK_C_INITIALIZE_ARGS;
+    let mut ret = fn_initialize(args_ptr as *mut std::ffi::c_void);
+    assert_eq!(ret, CKR_OK);
+    let mut session: CK_SESSION_HANDLE = CK_UNAVAILABLE_INFORMATION;
+    ret = fn_open_session(
+        testdata.get_slot(),
+        CKF_SERIAL_SESSION | CKF_RW_SESSION,
+        std::ptr::null_mut(),
+        None,
+        &mut session,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* login */
+    let pin = "12345678";
+    ret = fn_login(
+        session,
+        CKU_USER,
+        pin.as_ptr() as *mut _,
+        pin.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut class = CKO_SECRET_KEY;
+    let mut ktype = CKK_GENERIC_SECRET;
+    let mut aestype = CKK_AES;
+    let mut truebool = CK_TRUE;
+    let mut falsebool = CK_FALSE;
+
+    /* shared secret encoded as an mpint */
+    let secret = hex::decode(
+        "0000002100808182838485868788898a8b8c8d8e8f909192939495969798999a\
+         9b9c9d9e9f",
+    )
+    .expect("Failed to decode secret");
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            secret.as_ptr() as *mut std::ffi::c_void,
+            secret.len()
+        ),
+        make_attribute!(CKA_DERIVE, &mut truebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut secret_key = CK_INVALID_HANDLE;
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut secret_key,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut exchange_hash = hex::decode(
+        "7bde03b6174b06ac22d902ada587e02f46b15e82e96fcc34d951b86efe0642f8",
+    )
+    .expect("Failed to decode exchange hash");
+    let mut session_id = hex::decode(
+        "1cc1c70c03d3fa98125ac304150c6b2ab44b1a308f55aab9e46e6de9f92ae871",
+    )
+    .expect("Failed to decode session id");
+    let mut params = CK_SSHKDF_PARAMS {
+        prfHashMechanism: CKM_SHA256,
+        derivedKeyType: CKF_SSHKDF_ENCRYPTION_KEY_CLIENT_TO_SERVER,
+        pExchangeHash: exchange_hash.as_mut_ptr(),
+        ulExchangeHashLen: exchange_hash.len() as CK_ULONG,
+        pSessionId: session_id.as_mut_ptr(),
+        ulSessionIdLen: session_id.len() as CK_ULONG,
+    };
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_SSHKDF_DERIVE,
+        pParameter: &mut params as *mut CK_SSHKDF_PARAMS as *mut _,
+        ulParameterLen: std::mem::size_of::<CK_SSHKDF_PARAMS>() as CK_ULONG,
+    };
+
+    /* encryption key */
+    let mut len: CK_ULONG = 32;
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut aestype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_EXTRACTABLE,
+            &mut truebool as *mut _,
+            CK_BBOOL_SIZE
+        ),
+        make_attribute!(CKA_SENSITIVE, &mut falsebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_VALUE_LEN, &mut len as *mut _, CK_ULONG_SIZE),
+    ];
+    let mut handle = CK_INVALID_HANDLE;
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        secret_key,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let dk = hex::decode(
+        "5fca2c06dce6e078771840e75b83d673fed120e3a5099386e7c5503602664cf9",
+    )
+    .expect("Failed to decode dk");
+    let mut value = vec![0u8; 64];
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(session, handle, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extract[0].ulValueLen as usize, dk.len());
+    assert_eq!(&value[..dk.len()], dk.as_slice());
+
+    /* integrity key, longer than the hash output */
+    params.derivedKeyType = CKF_SSHKDF_INTEGRITY_KEY_CLIENT_TO_SERVER;
+    len = 40;
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_EXTRACTABLE,
+            &mut truebool as *mut _,
+            CK_BBOOL_SIZE
+        ),
+        make_attribute!(CKA_SENSITIVE, &mut falsebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_VALUE_LEN, &mut len as *mut _, CK_ULONG_SIZE),
+    ];
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        secret_key,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let dk = hex::decode(
+        "535885905e3b7cf7a8db025a042c02031f9aba60dddee1f68457b82e7d9fe362\
+         b83f9a72b82530fc",
+    )
+    .expect("Failed to decode dk");
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(session, handle, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extract[0].ulValueLen as usize, dk.len());
+    assert_eq!(&value[..dk.len()], dk.as_slice());
+
+    /* the key length is mandatory */
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        secret_key,
+        template.as_mut_ptr(),
+        4,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_TEMPLATE_INCOMPLETE);
+
+    /* unknown key type letter */
+    params.derivedKeyType = 0x47;
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        secret_key,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_MECHANISM_PARAM_INVALID);
+
+    ret = fn_close_session(session);
+    assert_eq!(ret, CKR_OK);
+
+    testdata.finalize();
+}
+
+#[test]
+fn test_simplekdf() {
+    let mut testdata = TestData::new("testdata/test_simplekdf.json");
+    testdata.setup_db();
+
+    let mut args = testdata.make_init_args();
+    let args_ptr = &mut args as *mut CK_C_INITIALIZE_ARGS;
+    let mut ret = fn_initialize(args_ptr as *mut std::ffi::c_void);
+    assert_eq!(ret, CKR_OK);
+    let mut session: CK_SESSION_HANDLE = CK_UNAVAILABLE_INFORMATION;
+    ret = fn_open_session(
+        testdata.get_slot(),
+        CKF_SERIAL_SESSION | CKF_RW_SESSION,
+        std::ptr::null_mut(),
+        None,
+        &mut session,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* login */
+    let pin = "12345678";
+    ret = fn_login(
+        session,
+        CKU_USER,
+        pin.as_ptr() as *mut _,
+        pin.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut class = CKO_SECRET_KEY;
+    let mut ktype = CKK_GENERIC_SECRET;
+    let mut aestype = CKK_AES;
+    let mut truebool = CK_TRUE;
+    let mut falsebool = CK_FALSE;
+
+    let mut handles: Vec<CK_OBJECT_HANDLE> = Vec::new();
+    let values: Vec<Vec<u8>> = vec![
+        (0u8..16).collect(),
+        (16u8..32).collect(),
+        (32u8..48).collect(),
+        hex::decode("329f84a9").expect("Failed to decode value"),
+    ];
+    for (i, v) in values.iter().enumerate() {
+        /* the third key is sensitive */
+        let mut sensitive = if i == 2 { CK_TRUE } else { CK_FALSE };
+        let mut template = vec![
+            make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+            make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+            make_attribute!(
+                CKA_VALUE,
+                v.as_ptr() as *mut std::ffi::c_void,
+                v.len()
+            ),
+            make_attribute!(CKA_DERIVE, &mut truebool as *mut _, CK_BBOOL_SIZE),
+            make_attribute!(
+                CKA_EXTRACTABLE,
+                &mut truebool as *mut _,
+                CK_BBOOL_SIZE
+            ),
+            make_attribute!(
+                CKA_SENSITIVE,
+                &mut sensitive as *mut _,
+                CK_BBOOL_SIZE
+            ),
+        ];
+        let mut handle = CK_INVALID_HANDLE;
+        ret = fn_create_object(
+            session,
+            template.as_mut_ptr(),
+            template.len() as CK_ULONG,
+            &mut handle,
+        );
+        assert_eq!(ret, CKR_OK);
+        handles.push(handle);
+    }
+
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_EXTRACTABLE,
+            &mut truebool as *mut _,
+            CK_BBOOL_SIZE
+        ),
+        make_attribute!(CKA_SENSITIVE, &mut falsebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut value = vec![0u8; 64];
+
+    /* concatenate base and key, defaults to the full length */
+    let mut other = handles[1];
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_CONCATENATE_BASE_AND_KEY,
+        pParameter: &mut other as *mut CK_OBJECT_HANDLE as *mut _,
+        ulParameterLen: CK_ULONG_SIZE as CK_ULONG,
+    };
+    let mut handle = CK_INVALID_HANDLE;
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        handles[0],
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(session, handle, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extract[0].ulValueLen, 32);
+    let expect: Vec<u8> = (0u8..32).collect();
+    assert_eq!(&value[..32], expect.as_slice());
+
+    /* a sensitive component makes the derived key sensitive, asking for
+     * a non sensitive key is an error */
+    other = handles[2];
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        handles[0],
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_TEMPLATE_INCONSISTENT);
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        handles[0],
+        template.as_mut_ptr(),
+        2,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut sensitive = CK_FALSE;
+    let mut extract = vec![make_attribute!(
+        CKA_SENSITIVE,
+        &mut sensitive as *mut _,
+        CK_BBOOL_SIZE
+    )];
+    ret = fn_get_attribute_value(session, handle, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(sensitive, CK_TRUE);
+
+    /* cannot be longer than the concatenation */
+    let mut len: CK_ULONG = 33;
+    let mut len_template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_EXTRACTABLE,
+            &mut truebool as *mut _,
+            CK_BBOOL_SIZE
+        ),
+        make_attribute!(CKA_SENSITIVE, &mut falsebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_VALUE_LEN, &mut len as *mut _, CK_ULONG_SIZE),
+    ];
+    other = handles[1];
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        handles[0],
+        len_template.as_mut_ptr(),
+        len_template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_TEMPLATE_INCONSISTENT);
+
+    /* concatenate data and base, truncated to an AES key */
+    let mut data = b"datadata".to_vec();
+    let mut params = CK_KEY_DERIVATION_STRING_DATA {
+        pData: data.as_mut_ptr(),
+        ulLen: data.len() as CK_ULONG,
+    };
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_CONCATENATE_DATA_AND_BASE,
+        pParameter: &mut params as *mut CK_KEY_DERIVATION_STRING_DATA as *mut _,
+        ulParameterLen: std::mem::size_of::<CK_KEY_DERIVATION_STRING_DATA>()
+            as CK_ULONG,
+    };
+    len = 16;
+    len_template[1] =
+        make_attribute!(CKA_KEY_TYPE, &mut aestype as *mut _, CK_ULONG_SIZE);
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        handles[0],
+        len_template.as_mut_ptr(),
+        len_template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(session, handle, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extract[0].ulValueLen, 16);
+    let mut expect = data.clone();
+    expect.extend(0u8..8);
+    assert_eq!(&value[..16], expect.as_slice());
+
+    /* concatenate base and data */
+    mechanism.mechanism = CKM_CONCATENATE_BASE_AND_DATA;
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        handles[1],
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(session, handle, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extract[0].ulValueLen, 24);
+    let mut expect: Vec<u8> = (16u8..32).collect();
+    expect.extend_from_slice(data.as_slice());
+    assert_eq!(&value[..24], expect.as_slice());
+
+    /* xor base and data */
+    let mut data = vec![0xffu8; 16];
+    params.pData = data.as_mut_ptr();
+    params.ulLen = data.len() as CK_ULONG;
+    mechanism.mechanism = CKM_XOR_BASE_AND_DATA;
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        handles[0],
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(session, handle, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extract[0].ulValueLen, 16);
+    let expect: Vec<u8> = (0u8..16).map(|b| b ^ 0xff).collect();
+    assert_eq!(&value[..16], expect.as_slice());
+
+    /* extract key from key, the example from the spec */
+    let mut index: CK_EXTRACT_PARAMS = 21;
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_EXTRACT_KEY_FROM_KEY,
+        pParameter: &mut index as *mut CK_EXTRACT_PARAMS as *mut _,
+        ulParameterLen: CK_ULONG_SIZE as CK_ULONG,
+    };
+    len = 2;
+    len_template[1] =
+        make_attribute!(CKA_KEY_TYPE, &mut ktype as *mut _, CK_ULONG_SIZE);
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        handles[3],
+        len_template.as_mut_ptr(),
+        len_template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut extract = vec![make_attribute!(
+        CKA_VALUE,
+        value.as_mut_ptr() as *mut std::ffi::c_void,
+        value.len()
+    )];
+    ret = fn_get_attribute_value(session, handle, extract.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(extract[0].ulValueLen, 2);
+    assert_eq!(&value[..2], &[0x95, 0x26]);
+
+    /* the bit index must fall within the base key */
+    index = 32;
+    ret = fn_derive_key(
+        session,
+        &mut mechanism,
+        handles[3],
+        len_template.as_mut_ptr(),
+        len_template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_MECHANISM_PARAM_INVALID);
+
+    ret = fn_close_session(session);
+    assert_eq!(ret, CKR_OK);
+
+    testdata.finalize();
+}
+
+#[test]
+fn test_shamir() {
+    let mut testdata = TestData::new("testdata/test_shamir.json");
+    testdata.setup_db();
+
+    let mut args = testdata.make_init_args();
+    let args_ptr = &mut args as *mut CK_C_INITIALIZE_ARGS;
+    let mut ret = fn_initialize(args_ptr as *mut std::ffi::c_void);
+    assert_eq!(ret, CKR_OK);
+    let mut session: CK_SESSION_HANDLE = CK_UNAVAILABLE_INFORMATION;
+    ret = fn_open_session(
+        testdata.get_slot(),
+        CKF_SERIAL_SESSION | CKF_RW_SESSION,
+        std::ptr::null_mut(),
+        None,
+        &mut session,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* login */
+    let pin = "12345678";
+    ret = fn_login(
+        session,
+        CKU_USER,
+        pin.as_ptr() as *mut _,
+        pin.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut truebool = CK_TRUE;
+    let mut falsebool = CK_FALSE;
+
+    /* custodian keys */
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: CKM_RSA_PKCS_KEY_PAIR_GEN,
+        pParameter: std::ptr::null_mut(),
+        ulParameterLen: 0,
+    };
+    let mut bits: CK_ULONG = 2048;
+    let mut pub_template = vec![
+        make_attribute!(CKA_WRAP, &mut truebool as *mut _, CK_BBOOL_SIZE),
+        make_attribute!(CKA_MODULUS_BITS, &mut bits as *mut _, CK_ULONG_SIZE),
+    ];
+    let mut pri_template = vec![make_attribute!(
+        CKA_UNWRAP,
+        &mut truebool as *mut _,
+        CK_BBOOL_SIZE
+    )];
+    let mut pubkeys: Vec<CK_OBJECT_HANDLE> = Vec::new();
+    let mut prikeys: Vec<CK_OBJECT_HANDLE> = Vec::new();
+    for _ in 0..3 {
+        let mut pubkey = CK_INVALID_HANDLE;
+        let mut prikey = CK_INVALID_HANDLE;
+        ret = fn_generate_key_pair(
+            session,
+            &mut mechanism,
+            pub_template.as_mut_ptr(),
+            pub_template.len() as CK_ULONG,
+            pri_template.as_mut_ptr(),
+            pri_template.len() as CK_ULONG,
+            &mut pubkey,
+            &mut prikey,
+        );
+        assert_eq!(ret, CKR_OK);
+        pubkeys.push(pubkey);
+        prikeys.push(prikey);
+    }
+
+    let mut class = CKO_SECRET_KEY;
+    let mut aestype = CKK_AES;
+    let keydata = hex::decode(
+        "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+    )
+    .expect("Failed to decode key data");
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut aestype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_VALUE,
+            keydata.as_ptr() as *mut std::ffi::c_void,
+            keydata.len()
+        ),
+        make_attribute!(
+            CKA_EXTRACTABLE,
+            &mut truebool as *mut _,
+            CK_BBOOL_SIZE
+        ),
+    ];
+    let mut key_handle = CK_INVALID_HANDLE;
+    ret = fn_create_object(
+        session,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut key_handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* 2 of 3, the wrapping key is the first custodian */
+    let mut custodians = vec![pubkeys[1], pubkeys[2]];
+    let mut split_params = KRY_SHAMIR_SPLIT_PARAMS {
+        ulThreshold: 2,
+        ulCustodianKeys: custodians.len() as CK_ULONG,
+        phCustodianKeys: custodians.as_mut_ptr(),
+    };
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: KRYMECH_SHAMIR_SPLIT,
+        pParameter: &mut split_params as *mut KRY_SHAMIR_SPLIT_PARAMS as *mut _,
+        ulParameterLen: std::mem::size_of::<KRY_SHAMIR_SPLIT_PARAMS>()
+            as CK_ULONG,
+    };
+    let mut wrapped_len: CK_ULONG = 0;
+    ret = fn_wrap_key(
+        session,
+        &mut mechanism,
+        pubkeys[0],
+        key_handle,
+        std::ptr::null_mut(),
+        &mut wrapped_len,
+    );
+    assert_eq!(ret, CKR_OK);
+    let mut wrapped = vec![0u8; wrapped_len as usize];
+    ret = fn_wrap_key(
+        session,
+        &mut mechanism,
+        pubkeys[0],
+        key_handle,
+        wrapped.as_mut_ptr(),
+        &mut wrapped_len,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    /* one length prefixed blob per custodian */
+    let mut blobs: Vec<Vec<u8>> = Vec::new();
+    let mut offset = 0;
+    while offset < wrapped_len as usize {
+        let len = u32::from_be_bytes(
+            wrapped[offset..(offset + 4)].try_into().unwrap(),
+        ) as usize;
+        blobs.push(wrapped[offset..(offset + 4 + len)].to_vec());
+        offset += 4 + len;
+    }
+    assert_eq!(offset, wrapped_len as usize);
+    assert_eq!(blobs.len(), 3);
+
+    /* recombine from the first and third shares */
+    let mut shares = blobs[0].clone();
+    shares.extend_from_slice(blobs[2].as_slice());
+    let mut custodians = vec![prikeys[2]];
+    let mut combine_params = KRY_SHAMIR_COMBINE_PARAMS {
+        ulCustodianKeys: custodians.len() as CK_ULONG,
+        phCustodianKeys: custodians.as_mut_ptr(),
+    };
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: KRYMECH_SHAMIR_COMBINE,
+        pParameter: &mut combine_params as *mut KRY_SHAMIR_COMBINE_PARAMS
+            as *mut _,
+        ulParameterLen: std::mem::size_of::<KRY_SHAMIR_COMBINE_PARAMS>()
+            as CK_ULONG,
+    };
+    let mut template = vec![
+        make_attribute!(CKA_CLASS, &mut class as *mut _, CK_ULONG_SIZE),
+        make_attribute!(CKA_KEY_TYPE, &mut aestype as *mut _, CK_ULONG_SIZE),
+        make_attribute!(
+            CKA_EXTRACTABLE,
+            &mut truebool as *mut _,
+            CK_BBOOL_SIZE
+        ),
+        make_attribute!(CKA_SENSITIVE, &mut falsebool as *mut _, CK_BBOOL_SIZE),
+    ];
+    let mut handle = CK_INVALID_HANDLE;
+    ret = fn_unwrap_key(
+        session,
+        &mut mechanism,
+        prikeys[0],
+        shares.as_mut_ptr(),
+        shares.len() as CK_ULONG,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_OK);
+
+    let mut value = vec![0u8; 32];
+    let mut local = CK_TRUE;
+    let mut extract = vec![
+        make_attribute!(
+            CKA_VALUE,
+            value.as_mut_ptr() as *mut std::ffi::c_void,
+            value.len()
+        ),
+        make_attribute!(CKA_LOCAL, &mut local as *mut _, CK_BBOOL_SIZE),
+    ];
+    ret = fn_get_attribute_value(
+        session,
+        handle,
+        extract.as_mut_ptr(),
+        extract.len() as CK_ULONG,
+    );
+    assert_eq!(ret, CKR_OK);
+    assert_eq!(value, keydata);
+    assert_eq!(local, CK_FALSE);
+
+    /* a single share is not enough */
+    let mut shares = blobs[1].clone();
+    combine_params.ulCustodianKeys = 0;
+    combine_params.phCustodianKeys = std::ptr::null_mut();
+    ret = fn_unwrap_key(
+        session,
+        &mut mechanism,
+        prikeys[1],
+        shares.as_mut_ptr(),
+        shares.len() as CK_ULONG,
+        template.as_mut_ptr(),
+        template.len() as CK_ULONG,
+        &mut handle,
+    );
+    assert_eq!(ret, CKR_WRAPPED_KEY_INVALID);
+
+    /* the threshold must be at least 2 */
+    let mut mechanism: CK_MECHANISM = CK_MECHANISM {
+        mechanism: KRYMECH_SHAMIR_SPLIT,
+        pParameter: &mut split_params as *mut KRY_SHAMIR_SPLIT_PARAMS as *mut _,
+        ulParameterLen: std::mem::size_of::<KRY_SHAMIR_SPLIT_PARAMS>()
+            as CK_ULONG,
+    };
+    split_params.ulThreshold = 1;
+    ret = fn_wrap_key(
+        session,
+        &mut mechanism,
+        pubkeys[0],
+        key_handle,
+        std::ptr::null_mut(),
+        &mut wrapped_len,
+    );
+    assert_eq!(ret, CKR_MECHANISM_PARAM_INVALID);
+
+    /* the wrap template of every custodian key is enforced */
+    let mut generictype = CKK_GENERIC_SECRET;
+    let mut wrap_template = vec![make_attribute!(
+        CKA_KEY_TYPE,
+        &mut generictype as *mut _,
+        CK_ULONG_SIZE
+    )];
+    let mut template = vec![make_attribute!(
+        CKA_WRAP_TEMPLATE,
+        wrap_template.as_mut_ptr() as *mut std::ffi::c_void,
+        wrap_template.len() * std::mem::size_of::<CK_ATTRIBUTE>()
+    )];
+    ret = fn_set_attribute_value(session, pubkeys[2], template.as_mut_ptr(), 1);
+    assert_eq!(ret, CKR_OK);
+    split_params.ulThreshold = 2;
+    ret = fn_wrap_key(
+        session,
+        &mut mechanism,
+        pubkeys[0],
+        key_handle,
+        std::ptr::null_mut(),
+        &mut wrapped_len,
+    );
+    assert_eq!(ret, CKR_KEY_NOT_WRAPPABLE);
+
+    ret = fn_close_session(session);
+    assert_eq!(ret, CKR_OK);
+
+    testdata.finalize();
+}
@@ -0,0 +1,778 @@
+// Copyright 2024 Simo Sorce
+// See LICENSE.txt file for terms
+
+use super::attribute;
+use super::err_rv;
+use super::error;
+use super::hmac;
+use super::interface;
+use super::mechanism;
+use super::object;
+
+use attribute::from_bytes;
+use error::{KError, KResult};
+use interface::*;
+use mechanism::*;
+use object::{Object, ObjectTemplates};
+
+use std::fmt::Debug;
+use zeroize::Zeroize;
+
+const TLS_MASTER_SECRET_SIZE: usize = 48;
+
+const TLS_MASTER_SECRET_LABEL: &[u8] = b"master secret";
+const TLS_EXTENDED_MASTER_SECRET_LABEL: &[u8] = b"extended master secret";
+const TLS_KEY_EXPANSION_LABEL: &[u8] = b"key expansion";
+const TLS_SERVER_FINISHED_LABEL: &[u8] = b"server finished";
+const TLS_CLIENT_FINISHED_LABEL: &[u8] = b"client finished";
+
+fn check_key_object(key: &Object, op: CK_ULONG) -> KResult<()> {
+    match key.get_attr_as_ulong(CKA_CLASS)? {
+        CKO_SECRET_KEY => {
+            if key.get_attr_as_ulong(CKA_KEY_TYPE)? != CKK_GENERIC_SECRET {
+                return err_rv!(CKR_KEY_TYPE_INCONSISTENT);
+            }
+        }
+        _ => return err_rv!(CKR_KEY_TYPE_INCONSISTENT),
+    }
+    match key.get_attr_as_bool(op) {
+        Ok(avail) => {
+            if !avail {
+                return err_rv!(CKR_KEY_FUNCTION_NOT_PERMITTED);
+            }
+        }
+        Err(_) => return err_rv!(CKR_KEY_FUNCTION_NOT_PERMITTED),
+    }
+    Ok(())
+}
+
+fn param_bytes<'a>(ptr: *const u8, len: CK_ULONG) -> KResult<&'a [u8]> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+    if ptr.is_null() {
+        return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+    }
+    Ok(unsafe { std::slice::from_raw_parts(ptr, len as usize) })
+}
+
+fn random_data(info: &CK_SSL3_RANDOM_DATA) -> KResult<(&[u8], &[u8])> {
+    let client = param_bytes(info.pClientRandom, info.ulClientRandomLen)?;
+    let server = param_bytes(info.pServerRandom, info.ulServerRandomLen)?;
+    Ok((client, server))
+}
+
+/* RFC 5246, 5 */
+fn tls12_prf(
+    hash: CK_MECHANISM_TYPE,
+    secret: &[u8],
+    label: &[u8],
+    seed: &[&[u8]],
+    len: usize,
+) -> KResult<Vec<u8>> {
+    let mut lseed: Vec<u8> = label.to_vec();
+    for s in seed {
+        lseed.extend_from_slice(s);
+    }
+    let mut out: Vec<u8> = Vec::new();
+    let mut a = hmac::hmac(hash, secret, &[lseed.as_slice()])?;
+    loop {
+        out.extend_from_slice(
+            hmac::hmac(hash, secret, &[a.as_slice(), lseed.as_slice()])?
+                .as_slice(),
+        );
+        if out.len() >= len {
+            break;
+        }
+        let next = hmac::hmac(hash, secret, &[a.as_slice()])?;
+        a.zeroize();
+        a = next;
+    }
+    a.zeroize();
+    out[len..].zeroize();
+    out.truncate(len);
+    Ok(out)
+}
+
+/* Secrets derived by the TLS mechanisms default to generic secret keys,
+ * the length is fixed by the mechanism, or must be in the template */
+fn secret_key(
+    key: &Object,
+    template: &[CK_ATTRIBUTE],
+    objtemplates: &ObjectTemplates,
+    len: Option<usize>,
+) -> KResult<(Object, usize)> {
+    let ktype: CK_KEY_TYPE = CKK_GENERIC_SECRET;
+    let mut tmpl: Vec<CK_ATTRIBUTE> = template.to_vec();
+    if !template.iter().any(|a| a.type_ == CKA_KEY_TYPE) {
+        tmpl.push(CK_ATTRIBUTE {
+            type_: CKA_KEY_TYPE,
+            pValue: &ktype as *const CK_KEY_TYPE as CK_VOID_PTR,
+            ulValueLen: ::std::mem::size_of::<CK_KEY_TYPE>() as CK_ULONG,
+        });
+    }
+    let mut obj = objtemplates.derive_key_from_template(key, &tmpl)?;
+    let keylen = match obj.get_attr_as_ulong(CKA_VALUE_LEN) {
+        Ok(n) => match len {
+            Some(l) => {
+                if n as usize != l {
+                    return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+                }
+                l
+            }
+            None => n as usize,
+        },
+        Err(e) => match e {
+            KError::NotFound(_) => match len {
+                Some(l) => l,
+                None => return err_rv!(CKR_TEMPLATE_INCOMPLETE),
+            },
+            _ => return Err(e),
+        },
+    };
+    if keylen == 0 {
+        return err_rv!(CKR_TEMPLATE_INCONSISTENT);
+    }
+    if obj.get_attr_as_ulong(CKA_KEY_TYPE)? == CKK_AES {
+        match keylen {
+            16 | 24 | 32 => (),
+            _ => return err_rv!(CKR_TEMPLATE_INCONSISTENT),
+        }
+    }
+    obj.del_attr(CKA_VALUE_LEN);
+    Ok((obj, keylen))
+}
+
+/* MAC secrets are always generic secrets that can be used to sign, verify
+ * and derive, the other attributes come from the template */
+fn mac_key(
+    key: &Object,
+    template: &[CK_ATTRIBUTE],
+    objtemplates: &ObjectTemplates,
+) -> KResult<Object> {
+    let ktype: CK_KEY_TYPE = CKK_GENERIC_SECRET;
+    let truebool: CK_BBOOL = CK_TRUE;
+    let mut tmpl: Vec<CK_ATTRIBUTE> = Vec::with_capacity(template.len() + 4);
+    for attr in template {
+        match attr.type_ {
+            CKA_KEY_TYPE | CKA_VALUE_LEN | CKA_SIGN | CKA_VERIFY
+            | CKA_DERIVE | CKA_ENCRYPT | CKA_DECRYPT => (),
+            _ => tmpl.push(*attr),
+        }
+    }
+    tmpl.push(CK_ATTRIBUTE {
+        type_: CKA_KEY_TYPE,
+        pValue: &ktype as *const CK_KEY_TYPE as CK_VOID_PTR,
+        ulValueLen: ::std::mem::size_of::<CK_KEY_TYPE>() as CK_ULONG,
+    });
+    for atype in [CKA_SIGN, CKA_VERIFY, CKA_DERIVE] {
+        tmpl.push(CK_ATTRIBUTE {
+            type_: atype,
+            pValue: &truebool as *const CK_BBOOL as CK_VOID_PTR,
+            ulValueLen: ::std::mem::size_of::<CK_BBOOL>() as CK_ULONG,
+        });
+    }
+    objtemplates.derive_key_from_template(key, &tmpl)
+}
+
+#[derive(Debug)]
+struct TLSMechanism {
+    info: CK_MECHANISM_INFO,
+}
+
+impl TLSMechanism {
+    fn master_secret(
+        mech: &CK_MECHANISM,
+        key: &Object,
+        template: &[CK_ATTRIBUTE],
+        objtemplates: &ObjectTemplates,
+    ) -> KResult<Object> {
+        let (prf, label, seed, version): (_, _, Vec<&[u8]>, _) = match mech
+            .mechanism
+        {
+            CKM_TLS12_MASTER_KEY_DERIVE | CKM_TLS12_MASTER_KEY_DERIVE_DH => {
+                if mech.pParameter.is_null()
+                    || mech.ulParameterLen as usize
+                        != ::std::mem::size_of::<
+                            CK_TLS12_MASTER_KEY_DERIVE_PARAMS,
+                        >()
+                {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+                let params = unsafe {
+                    &*(mech.pParameter
+                        as *const CK_TLS12_MASTER_KEY_DERIVE_PARAMS)
+                };
+                let (client, server) = random_data(&params.RandomInfo)?;
+                (
+                    params.prfHashMechanism,
+                    TLS_MASTER_SECRET_LABEL,
+                    vec![client, server],
+                    params.pVersion,
+                )
+            }
+            CKM_NSS_TLS_EXTENDED_MASTER_KEY_DERIVE
+            | CKM_NSS_TLS_EXTENDED_MASTER_KEY_DERIVE_DH => {
+                if mech.pParameter.is_null()
+                    || mech.ulParameterLen as usize
+                        != ::std::mem::size_of::<
+                            CK_NSS_TLS_EXTENDED_MASTER_KEY_DERIVE_PARAMS,
+                        >()
+                {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+                let params = unsafe {
+                    &*(mech.pParameter
+                        as *const CK_NSS_TLS_EXTENDED_MASTER_KEY_DERIVE_PARAMS)
+                };
+                let hash =
+                    param_bytes(params.pSessionHash, params.ulSessionHashLen)?;
+                if hash.is_empty() {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+                (
+                    params.prfHashMechanism,
+                    TLS_EXTENDED_MASTER_SECRET_LABEL,
+                    vec![hash],
+                    params.pVersion,
+                )
+            }
+            _ => return err_rv!(CKR_MECHANISM_INVALID),
+        };
+        hmac::hash_mech_to_hmac_mech(prf)?;
+
+        let pms = key.get_attr_as_bytes(CKA_VALUE)?;
+        /* a DH derived pre-master secret carries no version */
+        match mech.mechanism {
+            CKM_TLS12_MASTER_KEY_DERIVE_DH
+            | CKM_NSS_TLS_EXTENDED_MASTER_KEY_DERIVE_DH => {
+                if !version.is_null() {
+                    return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+                }
+            }
+            _ => {
+                if pms.len() != TLS_MASTER_SECRET_SIZE {
+                    return err_rv!(CKR_KEY_SIZE_RANGE);
+                }
+            }
+        }
+
+        let (mut obj, _) = secret_key(
+            key,
+            template,
+            objtemplates,
+            Some(TLS_MASTER_SECRET_SIZE),
+        )?;
+        let secret = tls12_prf(
+            prf,
+            pms.as_slice(),
+            label,
+            seed.as_slice(),
+            TLS_MASTER_SECRET_SIZE,
+        )?;
+        obj.set_attr(from_bytes(CKA_VALUE, secret))?;
+
+        if !version.is_null() {
+            unsafe {
+                core::ptr::write(
+                    version,
+                    CK_VERSION {
+                        major: pms[0],
+                        minor: pms[1],
+                    },
+                );
+            }
+        }
+        Ok(obj)
+    }
+
+    fn key_mat_params(
+        mech: &CK_MECHANISM,
+    ) -> KResult<&CK_TLS12_KEY_MAT_PARAMS> {
+        if mech.pParameter.is_null()
+            || mech.ulParameterLen as usize
+                != ::std::mem::size_of::<CK_TLS12_KEY_MAT_PARAMS>()
+        {
+            return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+        }
+        let params =
+            unsafe { &*(mech.pParameter as *const CK_TLS12_KEY_MAT_PARAMS) };
+        if params.bIsExport != CK_FALSE
+            || params.pReturnedKeyMaterial.is_null()
+            || params.ulMacSizeInBits % 8 != 0
+            || params.ulKeySizeInBits % 8 != 0
+            || params.ulIVSizeInBits % 8 != 0
+        {
+            return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+        }
+        if params.ulMacSizeInBits == 0 && params.ulKeySizeInBits == 0 {
+            return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+        }
+        if params.ulIVSizeInBits != 0 {
+            let keymat = unsafe { &*params.pReturnedKeyMaterial };
+            if keymat.pIVClient.is_null() || keymat.pIVServer.is_null() {
+                return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+            }
+        }
+        hmac::hash_mech_to_hmac_mech(params.prfHashMechanism)?;
+        Ok(params)
+    }
+
+    /* Returns client and server MAC keys and cipher keys, in this order,
+     * keys with a zero size are not created, IVs are returned directly in
+     * the mechanism parameters */
+    fn key_and_mac(
+        mech: &CK_MECHANISM,
+        key: &Object,
+        template: &[CK_ATTRIBUTE],
+        objtemplates: &ObjectTemplates,
+    ) -> KResult<Vec<Object>> {
+        let params = Self::key_mat_params(mech)?;
+        let maclen = params.ulMacSizeInBits as usize / 8;
+        let keylen = params.ulKeySizeInBits as usize / 8;
+        let ivlen = params.ulIVSizeInBits as usize / 8;
+        let (client, server) = random_data(&params.RandomInfo)?;
+
+        let mut objs: Vec<Object> = Vec::with_capacity(4);
+        if maclen > 0 {
+            objs.push(mac_key(key, template, objtemplates)?);
+            objs.push(mac_key(key, template, objtemplates)?);
+        }
+        if keylen > 0 {
+            objs.push(secret_key(key, template, objtemplates, Some(keylen))?.0);
+            objs.push(secret_key(key, template, objtemplates, Some(keylen))?.0);
+        }
+
+        let master = key.get_attr_as_bytes(CKA_VALUE)?;
+        let mut block = tls12_prf(
+            params.prfHashMechanism,
+            master.as_slice(),
+            TLS_KEY_EXPANSION_LABEL,
+            &[server, client],
+            2 * (maclen + keylen + ivlen),
+        )?;
+        let mut sizes: Vec<usize> = Vec::with_capacity(4);
+        if maclen > 0 {
+            sizes.extend_from_slice(&[maclen, maclen]);
+        }
+        if keylen > 0 {
+            sizes.extend_from_slice(&[keylen, keylen]);
+        }
+        let mut offset = 0;
+        for (obj, len) in objs.iter_mut().zip(sizes.iter()) {
+            let value = block[offset..(offset + len)].to_vec();
+            offset += len;
+            if let Err(e) = obj.set_attr(from_bytes(CKA_VALUE, value)) {
+                block.zeroize();
+                return Err(e);
+            }
+        }
+        if ivlen > 0 {
+            let keymat = unsafe { &*params.pReturnedKeyMaterial };
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    block[offset..].as_ptr(),
+                    keymat.pIVClient,
+                    ivlen,
+                );
+                std::ptr::copy_nonoverlapping(
+                    block[(offset + ivlen)..].as_ptr(),
+                    keymat.pIVServer,
+                    ivlen,
+                );
+            }
+        }
+        block.zeroize();
+
+        Ok(objs)
+    }
+
+    fn kdf(
+        mech: &CK_MECHANISM,
+        key: &Object,
+        template: &[CK_ATTRIBUTE],
+        objtemplates: &ObjectTemplates,
+    ) -> KResult<Object> {
+        if mech.pParameter.is_null()
+            || mech.ulParameterLen as usize
+                != ::std::mem::size_of::<CK_TLS_KDF_PARAMS>()
+        {
+            return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+        }
+        let params = unsafe { &*(mech.pParameter as *const CK_TLS_KDF_PARAMS) };
+        hmac::hash_mech_to_hmac_mech(params.prfMechanism)?;
+        let label = param_bytes(params.pLabel, params.ulLabelLength)?;
+        if label.is_empty() {
+            return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+        }
+        let (client, server) = random_data(&params.RandomInfo)?;
+        /* RFC 5705, the context is prefixed by its length when present */
+        let mut context: Vec<u8> = Vec::new();
+        if !params.pContextData.is_null() {
+            if params.ulContextDataLength > u16::MAX as CK_ULONG {
+                return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+            }
+            context.extend_from_slice(
+                &(params.ulContextDataLength as u16).to_be_bytes(),
+            );
+            context.extend_from_slice(param_bytes(
+                params.pContextData,
+                params.ulContextDataLength,
+            )?);
+        }
+
+        let (mut obj, keylen) = secret_key(key, template, objtemplates, None)?;
+        let secret = tls12_prf(
+            params.prfMechanism,
+            key.get_attr_as_bytes(CKA_VALUE)?.as_slice(),
+            label,
+            &[client, server, context.as_slice()],
+            keylen,
+        )?;
+        obj.set_attr(from_bytes(CKA_VALUE, secret))?;
+        Ok(obj)
+    }
+}
+
+impl Mechanism for TLSMechanism {
+    fn info(&self) -> &CK_MECHANISM_INFO {
+        &self.info
+    }
+
+    fn sign_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> KResult<Box<dyn Sign>> {
+        if self.info.flags & CKF_SIGN != CKF_SIGN {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        match check_key_object(key, CKA_SIGN) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        Ok(Box::new(TLSMACOperation::init(mech, key)?))
+    }
+
+    fn verify_new(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+    ) -> KResult<Box<dyn Verify>> {
+        if self.info.flags & CKF_VERIFY != CKF_VERIFY {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        match check_key_object(key, CKA_VERIFY) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        Ok(Box::new(TLSMACOperation::init(mech, key)?))
+    }
+
+    fn derive_keys(
+        &self,
+        mech: &CK_MECHANISM,
+        key: &Object,
+        _: &[&Object],
+        template: &[CK_ATTRIBUTE],
+        objtemplates: &ObjectTemplates,
+    ) -> KResult<Vec<Object>> {
+        if self.info.flags & CKF_DERIVE != CKF_DERIVE {
+            return err_rv!(CKR_MECHANISM_INVALID);
+        }
+        match check_key_object(key, CKA_DERIVE) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        match mech.mechanism {
+            CKM_TLS12_MASTER_KEY_DERIVE
+            | CKM_TLS12_MASTER_KEY_DERIVE_DH
+            | CKM_NSS_TLS_EXTENDED_MASTER_KEY_DERIVE
+            | CKM_NSS_TLS_EXTENDED_MASTER_KEY_DERIVE_DH => {
+                Ok(vec![Self::master_secret(
+                    mech,
+                    key,
+                    template,
+                    objtemplates,
+                )?])
+            }
+            CKM_TLS12_KEY_AND_MAC_DERIVE => {
+                Self::key_and_mac(mech, key, template, objtemplates)
+            }
+            CKM_TLS12_KDF => {
+                Ok(vec![Self::kdf(mech, key, template, objtemplates)?])
+            }
+            _ => err_rv!(CKR_MECHANISM_INVALID),
+        }
+    }
+
+    fn derive_key_handles(
+        &self,
+        mech: &CK_MECHANISM,
+        handles: &[CK_OBJECT_HANDLE],
+    ) -> KResult<CK_OBJECT_HANDLE> {
+        if mech.mechanism != CKM_TLS12_KEY_AND_MAC_DERIVE {
+            return match handles.first() {
+                Some(h) => Ok(*h),
+                None => err_rv!(CKR_GENERAL_ERROR),
+            };
+        }
+        /* all the keys are returned in the key material structure */
+        let params = Self::key_mat_params(mech)?;
+        let keymat = unsafe { &mut *params.pReturnedKeyMaterial };
+        let mut iter = handles.iter();
+        let mut next = || match iter.next() {
+            Some(h) => Ok(*h),
+            None => err_rv!(CKR_GENERAL_ERROR),
+        };
+        if params.ulMacSizeInBits != 0 {
+            keymat.hClientMacSecret = next()?;
+            keymat.hServerMacSecret = next()?;
+        } else {
+            keymat.hClientMacSecret = CK_INVALID_HANDLE;
+            keymat.hServerMacSecret = CK_INVALID_HANDLE;
+        }
+        if params.ulKeySizeInBits != 0 {
+            keymat.hClientKey = next()?;
+            keymat.hServerKey = next()?;
+        } else {
+            keymat.hClientKey = CK_INVALID_HANDLE;
+            keymat.hServerKey = CK_INVALID_HANDLE;
+        }
+        Ok(CK_INVALID_HANDLE)
+    }
+}
+
+#[derive(Debug)]
+struct TLSMACOperation {
+    mech: CK_MECHANISM_TYPE,
+    prf: CK_MECHANISM_TYPE,
+    secret: Vec<u8>,
+    label: &'static [u8],
+    maclen: usize,
+    data: Vec<u8>,
+    finalized: bool,
+    in_use: bool,
+}
+
+impl Drop for TLSMACOperation {
+    fn drop(&mut self) {
+        self.secret.zeroize();
+    }
+}
+
+impl TLSMACOperation {
+    fn init(mech: &CK_MECHANISM, key: &Object) -> KResult<TLSMACOperation> {
+        if mech.pParameter.is_null()
+            || mech.ulParameterLen as usize
+                != ::std::mem::size_of::<CK_TLS_MAC_PARAMS>()
+        {
+            return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+        }
+        let params = unsafe { &*(mech.pParameter as *const CK_TLS_MAC_PARAMS) };
+        hmac::hash_mech_to_hmac_mech(params.prfHashMechanism)?;
+        let label = match params.ulServerOrClient {
+            1 => TLS_SERVER_FINISHED_LABEL,
+            2 => TLS_CLIENT_FINISHED_LABEL,
+            _ => return err_rv!(CKR_MECHANISM_PARAM_INVALID),
+        };
+        if params.ulMacLength == 0 {
+            return err_rv!(CKR_MECHANISM_PARAM_INVALID);
+        }
+        Ok(TLSMACOperation {
+            mech: mech.mechanism,
+            prf: params.prfHashMechanism,
+            secret: key.get_attr_as_bytes(CKA_VALUE)?.clone(),
+            label: label,
+            maclen: params.ulMacLength as usize,
+            data: Vec::new(),
+            finalized: false,
+            in_use: false,
+        })
+    }
+
+    fn finalize(&mut self) -> KResult<Vec<u8>> {
+        tls12_prf(
+            self.prf,
+            self.secret.as_slice(),
+            self.label,
+            &[self.data.as_slice()],
+            self.maclen,
+        )
+    }
+}
+
+impl MechOperation for TLSMACOperation {
+    fn mechanism(&self) -> CK_MECHANISM_TYPE {
+        self.mech
+    }
+    fn in_use(&self) -> bool {
+        self.in_use
+    }
+    fn finalized(&self) -> bool {
+        self.finalized
+    }
+}
+
+impl Sign for TLSMACOperation {
+    fn sign(&mut self, data: &[u8], signature: &mut [u8]) -> KResult<()> {
+        if self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        match self.sign_update(data) {
+            Err(e) => {
+                self.finalized = true;
+                return Err(e);
+            }
+            Ok(()) => (),
+        }
+        self.sign_final(signature)
+    }
+
+    fn sign_update(&mut self, data: &[u8]) -> KResult<()> {
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        self.in_use = true;
+        self.data.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn sign_final(&mut self, signature: &mut [u8]) -> KResult<()> {
+        if !self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        self.finalized = true;
+        if signature.len() != self.maclen {
+            return err_rv!(CKR_GENERAL_ERROR);
+        }
+        signature.copy_from_slice(self.finalize()?.as_slice());
+        Ok(())
+    }
+
+    fn signature_len(&self) -> KResult<usize> {
+        Ok(self.maclen)
+    }
+}
+
+impl Verify for TLSMACOperation {
+    fn verify(&mut self, data: &[u8], signature: &[u8]) -> KResult<()> {
+        if self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        match self.verify_update(data) {
+            Err(e) => {
+                self.finalized = true;
+                return Err(e);
+            }
+            Ok(()) => (),
+        }
+        self.verify_final(signature)
+    }
+
+    fn verify_update(&mut self, data: &[u8]) -> KResult<()> {
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        self.in_use = true;
+        self.data.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn verify_final(&mut self, signature: &[u8]) -> KResult<()> {
+        if !self.in_use {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        if self.finalized {
+            return err_rv!(CKR_OPERATION_NOT_INITIALIZED);
+        }
+        self.finalized = true;
+        if signature.len() != self.maclen {
+            return err_rv!(CKR_GENERAL_ERROR);
+        }
+        if self.finalize()? != signature {
+            return err_rv!(CKR_SIGNATURE_INVALID);
+        }
+        Ok(())
+    }
+
+    fn signature_len(&self) -> KResult<usize> {
+        Ok(self.maclen)
+    }
+}
+
+pub fn register(mechs: &mut Mechanisms, _: &mut ObjectTemplates) {
+    mechs.add_mechanism(
+        CKM_TLS12_MASTER_KEY_DERIVE,
+        Box::new(TLSMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_DERIVE,
+            },
+        }),
+    );
+    mechs.add_mechanism(
+        CKM_TLS12_MASTER_KEY_DERIVE_DH,
+        Box::new(TLSMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_DERIVE,
+            },
+        }),
+    );
+    mechs.add_mechanism(
+        CKM_NSS_TLS_EXTENDED_MASTER_KEY_DERIVE,
+        Box::new(TLSMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_DERIVE,
+            },
+        }),
+    );
+    mechs.add_mechanism(
+        CKM_NSS_TLS_EXTENDED_MASTER_KEY_DERIVE_DH,
+        Box::new(TLSMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_DERIVE,
+            },
+        }),
+    );
+    mechs.add_mechanism(
+        CKM_TLS12_KEY_AND_MAC_DERIVE,
+        Box::new(TLSMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_DERIVE,
+            },
+        }),
+    );
+    mechs.add_mechanism(
+        CKM_TLS12_KDF,
+        Box::new(TLSMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_DERIVE,
+            },
+        }),
+    );
+    mechs.add_mechanism(
+        CKM_TLS_MAC,
+        Box::new(TLSMechanism {
+            info: CK_MECHANISM_INFO {
+                ulMinKeySize: 0,
+                ulMaxKeySize: 0,
+                flags: CKF_SIGN | CKF_VERIFY,
+            },
+        }),
+    );
+}
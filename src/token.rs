@@ -9,13 +9,27 @@ use serde_json;
 
 use super::aes;
 use super::attribute;
+#[cfg(not(feature = "fips"))]
+use super::chacha20;
+use super::eddsa;
 use super::error;
 use super::hash;
+use super::hkdf;
 use super::hmac;
 use super::interface;
+use super::kbkdf;
 use super::mechanism;
+use super::mldsa;
+use super::mlkem;
+use super::montgomery;
 use super::object;
+use super::pbkdf2;
 use super::rsa;
+use super::shamir;
+use super::simplekdf;
+use super::slhdsa;
+use super::sshkdf;
+use super::tls;
 
 use super::{err_not_found, err_rv};
 use error::{KError, KResult};
@@ -351,8 +365,25 @@ impl Token {
         object::register(&mut token.mechanisms, &mut token.object_templates);
         aes::register(&mut token.mechanisms, &mut token.object_templates);
         rsa::register(&mut token.mechanisms, &mut token.object_templates);
+        shamir::register(&mut token.mechanisms, &mut token.object_templates);
+        eddsa::register(&mut token.mechanisms, &mut token.object_templates);
+        montgomery::register(
+            &mut token.mechanisms,
+            &mut token.object_templates,
+        );
+        mlkem::register(&mut token.mechanisms, &mut token.object_templates);
+        mldsa::register(&mut token.mechanisms, &mut token.object_templates);
+        slhdsa::register(&mut token.mechanisms, &mut token.object_templates);
         hash::register(&mut token.mechanisms, &mut token.object_templates);
         hmac::register(&mut token.mechanisms, &mut token.object_templates);
+        hkdf::register(&mut token.mechanisms, &mut token.object_templates);
+        kbkdf::register(&mut token.mechanisms, &mut token.object_templates);
+        pbkdf2::register(&mut token.mechanisms, &mut token.object_templates);
+        simplekdf::register(&mut token.mechanisms, &mut token.object_templates);
+        sshkdf::register(&mut token.mechanisms, &mut token.object_templates);
+        tls::register(&mut token.mechanisms, &mut token.object_templates);
+        #[cfg(not(feature = "fips"))]
+        chacha20::register(&mut token.mechanisms, &mut token.object_templates);
 
         token
     }
@@ -695,6 +726,12 @@ impl Token {
         mut obj: Object,
     ) -> KResult<CK_OBJECT_HANDLE> {
         let uid = obj.get_attr_as_string(CKA_UNIQUE_ID)?;
+        /* only the SO can mark objects as trusted */
+        if obj.get_attr_as_bool(CKA_TRUSTED).unwrap_or(false)
+            && !self.is_logged_in(CKU_SO)
+        {
+            return err_rv!(CKR_ATTRIBUTE_READ_ONLY);
+        }
         let is_token = match obj.get_attr_as_bool(CKA_TOKEN) {
             Ok(t) => t,
             Err(_) => return err_rv!(CKR_GENERAL_ERROR),
@@ -768,6 +805,14 @@ impl Token {
         handle: CK_OBJECT_HANDLE,
         template: &mut [CK_ATTRIBUTE],
     ) -> KResult<()> {
+        for ck_attr in template.iter() {
+            if ck_attr.type_ == CKA_TRUSTED
+                && ck_attr.to_bool()?
+                && !self.is_logged_in(CKU_SO)
+            {
+                return err_rv!(CKR_ATTRIBUTE_READ_ONLY);
+            }
+        }
         let obj = match self.objects.get_by_handle_mut(handle) {
             Ok(o) => o,
             Err(e) => return Err(e),
@@ -867,4 +912,8 @@ impl Token {
     ) -> KResult<&Box<dyn mechanism::Mechanism>> {
         self.mechanisms.get(mech_type)
     }
+
+    pub fn get_object_templates(&self) -> &ObjectTemplates {
+        &self.object_templates
+    }
 }